@@ -0,0 +1,86 @@
+//! frame 读取路径的 benchmark
+//!
+//! `read_frame` + `decode(&[u8])` 是以前 `ProstStream` 的读取方式（已经从库中删除，保留在这里对比）：每个 frame 复制到
+//! 读缓存，binary 再复制一次；`FrameDecoder` 直接从读缓存切出 payload，binary 不复制，
+//! 解压缩的缓存也会复用。
+
+use bytes::{BufMut, Bytes, BytesMut};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use kv6::{CommandResponse, CompressionCodec, FrameCoder, FrameDecoder, Value, MAX_FRAME};
+use prost::Message;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    runtime::{Builder, Runtime},
+};
+use tokio_util::codec::Decoder;
+
+const FRAMES: usize = 64;
+
+fn frames(size: usize, codec: CompressionCodec) -> Bytes {
+    let value: Value = Bytes::from(vec![42u8; size]).into();
+    let res: CommandResponse = value.into();
+    let mut buf = BytesMut::new();
+    for _ in 0..FRAMES {
+        res.encode_frame_with(&mut buf, codec, 1436).unwrap();
+    }
+    buf.freeze()
+}
+
+// 以前的读取方式：把整个 frame 复制到 buf 中
+async fn read_frame<S>(stream: &mut S, buf: &mut BytesMut)
+where
+    S: AsyncRead + Unpin,
+{
+    let header = stream.read_u32().await.unwrap();
+    let len = header as usize & MAX_FRAME;
+    buf.put_u32(header);
+    let start = buf.len();
+    buf.resize(start + len, 0);
+    stream.read_exact(&mut buf[start..]).await.unwrap();
+}
+
+fn read_frame_decode(rt: &Runtime, data: &[u8]) {
+    rt.block_on(async {
+        let mut reader = data;
+        for _ in 0..FRAMES {
+            let mut buf = BytesMut::new();
+            read_frame(&mut reader, &mut buf).await;
+            let res = CommandResponse::decode_frame(&mut BytesMut::from(&buf[..])).unwrap();
+            criterion::black_box(res);
+        }
+    });
+}
+
+fn frame_decoder_decode(data: &[u8]) {
+    let mut decoder = FrameDecoder::default();
+    // 模拟每次从 socket 读到 16k 数据
+    let mut src = BytesMut::with_capacity(16 * 1024);
+    for chunk in data.chunks(16 * 1024) {
+        src.extend_from_slice(chunk);
+        while let Some(payload) = decoder.decode(&mut src).unwrap() {
+            criterion::black_box(CommandResponse::decode(payload).unwrap());
+        }
+    }
+}
+
+fn decode_benchmark(c: &mut Criterion) {
+    let rt = Builder::new_current_thread().build().unwrap();
+    let mut group = c.benchmark_group("frame_decode");
+    for codec in [CompressionCodec::None, CompressionCodec::Gzip, CompressionCodec::Lz4] {
+        for size in [1024, 64 * 1024] {
+            let data = frames(size, codec);
+            let id = format!("{}/{}", codec, size);
+            group.throughput(Throughput::Bytes((size * FRAMES) as u64));
+            group.bench_with_input(BenchmarkId::new("read_frame", &id), &data, |b, data| {
+                b.iter(|| read_frame_decode(&rt, data))
+            });
+            group.bench_with_input(BenchmarkId::new("frame_decoder", &id), &data, |b, data| {
+                b.iter(|| frame_decoder_decode(data))
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, decode_benchmark);
+criterion_main!(benches);
@@ -1,6 +1,8 @@
 fn main() {
     let mut config = prost_build::Config::new();
     config.bytes(&["."]);
+    // HashMap 没有实现 PartialOrd
+    config.btree_map(["."]);
     config.type_attribute(".", "#[derive(PartialOrd)]");
     config
         .out_dir("src/pb")
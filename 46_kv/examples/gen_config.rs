@@ -12,16 +12,18 @@ fn main() -> Result<()> {
 
     let general_config = GeneralConfig{
         addr: "127.0.0.1:9527".into(),
+        ..Default::default()
     };
 
     let server_config = ServerConfig{
         storage: StorageConfig::SledDb("./tmp/kv_server".into()),
         general: general_config.clone(),
-        tls: ServerTlsConfig { cert: SERVER_CERT.into(), key: SERVER_KEY.into(), ca: None },
+        tls: ServerTlsConfig { cert: SERVER_CERT.into(), key: SERVER_KEY.into(), ..Default::default() },
         log: LogConfig {
             path: "./tmp/kv-log".into(),
             rotation: RotationConfig::Daily,
         },
+        ..Default::default()
     };
 
     fs::write("fixtures/server.conf", toml::to_string_pretty(&server_config)?)?;
@@ -29,6 +31,7 @@ fn main() -> Result<()> {
     let client_config = ClientConfig {
         general: general_config,
         tls: ClientTlsConfig { domain: "kvserver.acme.inc".into(), identity: None, ca: Some(CA_CERT.into()) },
+        ..Default::default()
     };
 
     fs::write("fixtures/client.conf", toml::to_string_pretty(&client_config)?)?;
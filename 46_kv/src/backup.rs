@@ -0,0 +1,545 @@
+//! 备份文件格式（所有整数都是大端）：
+//!
+//! ```text
+//! | "KVBK" | version: u16 |
+//! | kind: u8 | len: u32 | payload | ...    kind 1: BackupTable, 2: Kvpair, 3: 过期时间, 4: Zadd
+//! | kind: u8 = 0 |                          结束标记
+//! | pairs: u64 | crc32: u32 |               crc32 覆盖 header 之后到结束标记的所有字节
+//! ```
+//!
+//! 每个 table 先写一个 BackupTable，随后是这个 table 下所有的 Kvpair。
+//! 有过期时间的 Kvpair 后面紧跟一条过期时间记录，payload 是 unix 毫秒（u64）。
+//! 随后每个 sorted set 是一条 Zadd，它的 table 为空。
+//! 版本 1 没有过期时间记录，恢复时使用 table 的 default_ttl。
+
+use crate::{
+    now_ms, script::check_deadline, BackupTable, KvError, Kvpair, ScoredMember, Storage, TableSnapshot, Zadd, MAX_FRAME,
+};
+use crc32fast::Hasher;
+use prost::Message;
+use std::{
+    fs::File,
+    convert::TryInto,
+    io::{BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+const MAGIC: &[u8; 4] = b"KVBK";
+/// 当前备份文件的版本
+pub const BACKUP_VERSION: u16 = 2;
+/// 单条记录的最大长度，存储中的数据都来自 frame，不会超过它
+const MAX_RECORD_SIZE: usize = MAX_FRAME;
+/// 每处理这么多个 kv pair 汇报一次进度
+const PROGRESS_INTERVAL: u64 = 10_000;
+
+const RECORD_END: u8 = 0;
+const RECORD_TABLE: u8 = 1;
+const RECORD_PAIR: u8 = 2;
+const RECORD_EXPIRE: u8 = 3;
+const RECORD_ZSET: u8 = 4;
+
+/// 备份 / 恢复了多少数据
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BackupStats {
+    pub tables: u64,
+    pub pairs: u64,
+    pub zsets: u64,
+}
+
+enum Record {
+    Table(BackupTable),
+    // 过期时间：None 表示备份中没有记录（版本 1），Some(None) 表示不过期
+    Pair(Kvpair, Option<Option<u64>>),
+    ZSet(Zadd),
+}
+
+struct ChecksumWriter<W> {
+    inner: W,
+    hasher: Hasher,
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+struct ChecksumReader<R> {
+    inner: R,
+    hasher: Hasher,
+}
+
+impl<R: Read> Read for ChecksumReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+fn write_record(w: &mut impl Write, kind: u8, msg: &impl Message) -> Result<(), KvError> {
+    w.write_all(&[kind])?;
+    w.write_all(&(msg.encoded_len() as u32).to_be_bytes())?;
+    w.write_all(&msg.encode_to_vec())?;
+    Ok(())
+}
+
+fn read_u8(r: &mut impl Read) -> Result<u8, KvError> {
+    let mut buf = [0u8; 1];
+    r.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u32(r: &mut impl Read) -> Result<u32, KvError> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+fn read_u64(r: &mut impl Read) -> Result<u64, KvError> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_be_bytes(buf))
+}
+
+/// 把 store 中所有的 table 写入 writer，包括 key 的过期时间
+///
+/// 备份期间不会暂停 store 的写入，每个 table 是导出它时的快照，不同的 table 可能来自不同的时间点。
+/// 在 `with_deadline` 中执行时，超过 deadline 后停止备份并返回 `KvError::Timeout`
+pub fn backup(
+    store: &impl Storage,
+    writer: impl Write,
+    mut progress: impl FnMut(&BackupStats),
+) -> Result<BackupStats, KvError> {
+    let mut writer = BufWriter::new(writer);
+    writer.write_all(MAGIC)?;
+    writer.write_all(&BACKUP_VERSION.to_be_bytes())?;
+
+    let mut w = ChecksumWriter {
+        inner: writer,
+        hasher: Hasher::new(),
+    };
+    let mut stats = BackupStats::default();
+    let mut tables = store.list_tables()?;
+    tables.sort();
+
+    for name in tables {
+        let TableSnapshot { options, pairs, zsets } = store.export_table(&name)?;
+        write_record(&mut w, RECORD_TABLE, &BackupTable { name, options })?;
+        stats.tables += 1;
+
+        for item in pairs {
+            check_deadline()?;
+            let (pair, expire_at) = item?;
+            write_record(&mut w, RECORD_PAIR, &pair)?;
+            if let Some(at) = expire_at {
+                w.write_all(&[RECORD_EXPIRE])?;
+                w.write_all(&8u32.to_be_bytes())?;
+                w.write_all(&at.to_be_bytes())?;
+            }
+            stats.pairs += 1;
+            if stats.pairs % PROGRESS_INTERVAL == 0 {
+                progress(&stats);
+            }
+        }
+
+        for (key, members) in zsets {
+            check_deadline()?;
+            let members = members
+                .into_iter()
+                .map(|pair| {
+                    let score = pair.value.unwrap_or_default().try_into()?;
+                    Ok(ScoredMember { member: pair.key, score })
+                })
+                .collect::<Result<_, KvError>>()?;
+            let zset = Zadd { table: String::new(), key, members };
+            write_record(&mut w, RECORD_ZSET, &zset)?;
+            stats.zsets += 1;
+        }
+    }
+    w.write_all(&[RECORD_END])?;
+
+    let ChecksumWriter { inner: mut writer, hasher } = w;
+    writer.write_all(&stats.pairs.to_be_bytes())?;
+    writer.write_all(&hasher.finalize().to_be_bytes())?;
+    writer.flush()?;
+
+    progress(&stats);
+    Ok(stats)
+}
+
+// 读取整个备份文件，每读到一条记录就调用 f，最后检查数量和校验和
+fn read_backup(
+    reader: impl Read,
+    mut f: impl FnMut(Record) -> Result<(), KvError>,
+) -> Result<BackupStats, KvError> {
+    let mut reader = BufReader::new(reader);
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(KvError::InvalidBackup("not a kv backup file".into()));
+    }
+    let mut version = [0u8; 2];
+    reader.read_exact(&mut version)?;
+    let version = u16::from_be_bytes(version);
+    if version == 0 || version > BACKUP_VERSION {
+        return Err(KvError::InvalidBackup(format!(
+            "unsupported version {}",
+            version
+        )));
+    }
+
+    let mut r = ChecksumReader {
+        inner: reader,
+        hasher: Hasher::new(),
+    };
+    let mut stats = BackupStats::default();
+    let mut buf = Vec::new();
+    // 读到 Kvpair 时先不处理，等看到下一条记录才知道它有没有过期时间
+    let mut pending: Option<Kvpair> = None;
+    let no_expire = (version >= 2).then_some(None);
+    loop {
+        let kind = read_u8(&mut r)?;
+        if kind != RECORD_EXPIRE {
+            if let Some(pair) = pending.take() {
+                f(Record::Pair(pair, no_expire))?;
+            }
+        }
+        if kind == RECORD_END {
+            break;
+        }
+        let len = read_u32(&mut r)? as usize;
+        if len > MAX_RECORD_SIZE {
+            return Err(KvError::InvalidBackup(format!(
+                "record too large: {} bytes",
+                len
+            )));
+        }
+        // 按实际读到的数据分配内存，不相信校验之前的长度
+        buf.clear();
+        (&mut r).take(len as u64).read_to_end(&mut buf)?;
+        if buf.len() != len {
+            return Err(KvError::InvalidBackup("unexpected end of file".into()));
+        }
+
+        match kind {
+            RECORD_TABLE => {
+                stats.tables += 1;
+                f(Record::Table(BackupTable::decode(&buf[..])?))?
+            }
+            RECORD_PAIR => {
+                stats.pairs += 1;
+                pending = Some(Kvpair::decode(&buf[..])?);
+            }
+            RECORD_EXPIRE => {
+                let (pair, at) = match (pending.take(), buf[..].try_into()) {
+                    (Some(pair), Ok(at)) => (pair, u64::from_be_bytes(at)),
+                    _ => return Err(KvError::InvalidBackup("invalid expire record".into())),
+                };
+                f(Record::Pair(pair, Some(Some(at))))?
+            }
+            RECORD_ZSET => {
+                stats.zsets += 1;
+                f(Record::ZSet(Zadd::decode(&buf[..])?))?
+            }
+            _ => {
+                return Err(KvError::InvalidBackup(format!(
+                    "unknown record type {}",
+                    kind
+                )))
+            }
+        }
+    }
+
+    let ChecksumReader {
+        inner: mut reader,
+        hasher,
+    } = r;
+    let pairs = read_u64(&mut reader)?;
+    let checksum = read_u32(&mut reader)?;
+    if pairs != stats.pairs {
+        return Err(KvError::InvalidBackup(format!(
+            "expect {} pairs, got {}",
+            pairs, stats.pairs
+        )));
+    }
+    if checksum != hasher.finalize() {
+        return Err(KvError::InvalidBackup("checksum mismatch".into()));
+    }
+    Ok(stats)
+}
+
+/// 只检查备份文件的完整性，不写入数据
+pub fn verify(reader: impl Read) -> Result<BackupStats, KvError> {
+    read_backup(reader, |_| Ok(()))
+}
+
+/// 把备份中的数据写入 store，key 的过期时间和备份时一致，已经过期的 key 会被跳过
+///
+/// 数据边读边写，如果备份文件损坏或者超过了 deadline，错误之前的数据已经写入了。
+/// 需要先检查完整性可以使用 `restore_file`
+pub fn restore(
+    store: &impl Storage,
+    reader: impl Read,
+    mut progress: impl FnMut(&BackupStats),
+) -> Result<BackupStats, KvError> {
+    let mut table = String::new();
+    let mut stats = BackupStats::default();
+    read_backup(reader, |record| {
+        check_deadline()?;
+        match record {
+            Record::Table(t) => {
+                if let Some(options) = t.options {
+                    match store.create_table(&t.name, options) {
+                        Ok(()) | Err(KvError::AlreadyExists(_)) => {}
+                        Err(e) => return Err(e),
+                    }
+                }
+                table = t.name;
+                stats.tables += 1;
+            }
+            Record::Pair(pair, expire_at) => {
+                if matches!(expire_at, Some(Some(at)) if at <= now_ms()) {
+                    return Ok(());
+                }
+                let key = pair.key;
+                store.set(&table, key.clone(), pair.value.unwrap_or_default())?;
+                // set 使用的是 table 的 default_ttl，改成备份中的过期时间
+                if let Some(at) = expire_at {
+                    store.set_expire_at(&table, &key, at)?;
+                }
+                stats.pairs += 1;
+                if stats.pairs % PROGRESS_INTERVAL == 0 {
+                    progress(&stats);
+                }
+            }
+            Record::ZSet(zset) => {
+                let members = zset.members.into_iter().map(|m| (m.member, m.score)).collect();
+                store.zadd(&table, &zset.key, members)?;
+                stats.zsets += 1;
+            }
+        }
+        Ok(())
+    })?;
+
+    progress(&stats);
+    Ok(stats)
+}
+
+/// 备份到文件，失败（包括超过 deadline）时删除写了一半的文件
+pub fn backup_file(
+    store: &impl Storage,
+    path: impl AsRef<Path>,
+    progress: impl FnMut(&BackupStats),
+) -> Result<BackupStats, KvError> {
+    let path = path.as_ref();
+    let result = backup(store, File::create(path)?, progress);
+    if result.is_err() {
+        let _ = std::fs::remove_file(path);
+    }
+    result
+}
+
+/// 先检查备份文件的完整性，再把数据恢复到 store
+pub fn restore_file(
+    store: &impl Storage,
+    path: impl AsRef<Path>,
+    progress: impl FnMut(&BackupStats),
+) -> Result<BackupStats, KvError> {
+    let path = path.as_ref();
+    verify(File::open(path)?)?;
+    restore(store, File::open(path)?, progress)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MemTable, SledDb, TableOptions};
+    use std::{sync::mpsc, thread, time::Duration};
+    use tempfile::tempdir;
+
+    // 每次写入备份数据时调用 f
+    struct HookWriter<F> {
+        buf: Vec<u8>,
+        f: F,
+    }
+
+    impl<F: FnMut()> Write for HookWriter<F> {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            (self.f)();
+            self.buf.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // 备份的过程中，另一个线程的写入可以完成
+    fn test_backup_with_concurrent_writes(store: impl Storage) {
+        // 数据超过 BufWriter 的缓冲区，遍历 table 的过程中就会写入
+        for i in 0..2000 {
+            store.set("t1", format!("k{}", i), "v".repeat(64).into()).unwrap();
+        }
+        let written = thread::scope(|s| {
+            let mut written = 0;
+            let writer = HookWriter {
+                buf: Vec::new(),
+                f: || {
+                    let (tx, rx) = mpsc::channel();
+                    let store = &store;
+                    s.spawn(move || {
+                        store.set("t1", format!("new{}", written), 1.into()).unwrap();
+                        store.zadd("t2", "z1", vec![(format!("m{}", written), 1.0)]).unwrap();
+                        tx.send(()).unwrap();
+                    });
+                    rx.recv_timeout(Duration::from_secs(5))
+                        .expect("write should not be blocked by backup");
+                    written += 1;
+                },
+            };
+            let stats = backup(&store, writer, |_| {}).unwrap();
+            assert!(stats.pairs >= 2000);
+            written
+        });
+        assert!(written > 1);
+        assert_eq!(store.count("t1").unwrap(), 2000 + written);
+    }
+
+    #[test]
+    fn memtable_backup_should_not_block_writes() {
+        test_backup_with_concurrent_writes(MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_backup_should_not_block_writes() {
+        let dir = tempdir().unwrap();
+        test_backup_with_concurrent_writes(SledDb::new(dir));
+    }
+
+    fn prepare(store: &impl Storage) {
+        let options = TableOptions {
+            value_type: "string".into(),
+            ..Default::default()
+        };
+        store.create_table("t1", options).unwrap();
+        store.set("t1", "k1".into(), "v1".into()).unwrap();
+        store.set("t1", "k2".into(), "v2".into()).unwrap();
+        store.set("t2", "k1".into(), 10.into()).unwrap();
+        let members = vec![("a".into(), 2.0), ("b".into(), 1.0)];
+        store.zadd("t3", "z1", members).unwrap();
+    }
+
+    #[test]
+    fn backup_and_restore_should_work() {
+        let store = MemTable::new();
+        prepare(&store);
+
+        let mut buf = Vec::new();
+        let stats = backup(&store, &mut buf, |_| {}).unwrap();
+        assert_eq!(stats, BackupStats { tables: 3, pairs: 3, zsets: 1 });
+        assert_eq!(verify(&buf[..]).unwrap(), stats);
+
+        // 恢复到另一种存储
+        let dir = tempdir().unwrap();
+        let target = SledDb::new(dir);
+        let mut reported = BackupStats::default();
+        let restored = restore(&target, &buf[..], |s| reported = s.clone()).unwrap();
+        assert_eq!(restored, stats);
+        assert_eq!(reported, stats);
+
+        assert_eq!(target.get("t1", "k2").unwrap(), Some("v2".into()));
+        assert_eq!(target.get("t2", "k1").unwrap(), Some(10.into()));
+        let members = vec![Kvpair::new("b", 1.0.into()), Kvpair::new("a", 2.0.into())];
+        assert_eq!(target.zrange("t3", "z1", 0, -1).unwrap(), members);
+        assert!(target.table_options("t1").unwrap().is_some());
+    }
+
+    #[test]
+    fn backup_should_keep_expire_time() {
+        let store = MemTable::new();
+        let options = TableOptions {
+            default_ttl: 3600,
+            ..Default::default()
+        };
+        store.create_table("t1", options).unwrap();
+        store.set("t1", "k1".into(), "v1".into()).unwrap();
+        store.set("t1", "k2".into(), "v2".into()).unwrap();
+        store.set("t1", "k3".into(), "v3".into()).unwrap();
+        let at = now_ms() + 10_000;
+        store.set_expire_at("t1", "k1", Some(at)).unwrap();
+        store.set_expire_at("t1", "k2", None).unwrap();
+        store.set_expire_at("t1", "k3", Some(now_ms() - 1)).unwrap();
+
+        let mut buf = Vec::new();
+        let stats = backup(&store, &mut buf, |_| {}).unwrap();
+        assert_eq!(stats, BackupStats { tables: 1, pairs: 2, zsets: 0 });
+
+        let dir = tempdir().unwrap();
+        let target = SledDb::new(dir);
+        restore(&target, &buf[..], |_| {}).unwrap();
+        assert_eq!(target.expire_at("t1", "k1").unwrap(), Some(at));
+        assert_eq!(target.expire_at("t1", "k2").unwrap(), None);
+        assert_eq!(target.get("t1", "k3").unwrap(), None);
+    }
+
+    #[test]
+    fn oversized_record_should_be_rejected() {
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&BACKUP_VERSION.to_be_bytes());
+        data.push(RECORD_PAIR);
+        data.extend_from_slice(&u32::MAX.to_be_bytes());
+        let err = verify(&data[..]).unwrap_err();
+        assert!(matches!(err, KvError::InvalidBackup(_)));
+
+        // 长度在范围内，但是数据不够
+        data.truncate(data.len() - 4);
+        data.extend_from_slice(&1024u32.to_be_bytes());
+        data.extend_from_slice(&[0; 10]);
+        let err = verify(&data[..]).unwrap_err();
+        assert!(matches!(err, KvError::InvalidBackup(_)));
+    }
+
+    #[test]
+    fn corrupted_backup_should_be_rejected() {
+        let store = MemTable::new();
+        prepare(&store);
+        let mut buf = Vec::new();
+        backup(&store, &mut buf, |_| {}).unwrap();
+
+        let mut corrupted = buf.clone();
+        let pos = corrupted.len() - 20;
+        corrupted[pos] ^= 0xff;
+        assert!(verify(&corrupted[..]).is_err());
+
+        let truncated = &buf[..buf.len() - 4];
+        assert!(verify(truncated).is_err());
+
+        assert!(verify(&b"hello world"[..]).is_err());
+    }
+
+    #[test]
+    fn restore_file_should_check_integrity_first() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("kv.backup");
+        let store = MemTable::new();
+        prepare(&store);
+        backup_file(&store, &path, |_| {}).unwrap();
+
+        let mut data = std::fs::read(&path).unwrap();
+        let len = data.len();
+        data[len - 1] ^= 0xff;
+        std::fs::write(&path, data).unwrap();
+
+        let target = MemTable::new();
+        assert!(restore_file(&target, &path, |_| {}).is_err());
+        assert!(target.list_tables().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,421 @@
+use std::env;
+use anyhow::Result;
+use clap::Parser;
+use kv6::{
+    start_client_with_config, start_quic_client_with_config, start_server_with_config, CertPem,
+    CertSubject, ClientConfig, GeneralConfig, ProstClientStream, QuicCtrl, ServerConfig,
+    ServerTlsConfig, StorageConfig, TransportConfig, YamuxCtrl,
+};
+use rand::{rngs::StdRng, SeedableRng};
+use std::{
+    net::{TcpListener, UdpSocket},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+    sync::Barrier,
+    task::JoinHandle,
+    time,
+};
+use tokio_rustls::client::TlsStream;
+use tracing::warn;
+use workload::{KeyDistribution, Latencies, OpKind, ValueSize, Workload};
+
+#[path = "bench/workload.rs"]
+mod workload;
+
+/// prefill 时每次 pipeline 写入的 key 数量
+const PREFILL_BATCH: u64 = 1000;
+/// 等待进程内服务器启动的次数和间隔
+const READY_RETRIES: usize = 50;
+const READY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// 对 kvs 运行可配置的负载，输出吞吐和延迟的百分位
+#[derive(Parser, Debug)]
+#[clap(name = "kv-bench", version, about = "KV load generator")]
+struct Args {
+    /// 客户端配置文件路径，也可以通过 KV_CLIENT_CONFIG 指定
+    #[clap(short, long)]
+    config: Option<String>,
+    /// 服务器地址
+    #[clap(long)]
+    addr: Option<String>,
+    /// 在进程内启动一个服务器（使用临时生成的证书），忽略 --addr
+    #[clap(long)]
+    in_process: bool,
+    /// 进程内服务器的存储：memtable 或者 sled:<path>
+    #[clap(long, default_value = "memtable")]
+    storage: StorageConfig,
+    /// 连接的数量
+    #[clap(long, default_value_t = 1)]
+    connections: usize,
+    /// 每个连接上的 stream 数量，每个 stream 独立地发送命令
+    #[clap(long, default_value_t = 4)]
+    streams: usize,
+    /// 每个 stream 一次发送多少个命令再等待它们的响应
+    #[clap(long, default_value_t = 1)]
+    pipeline: usize,
+    /// 命令的总数
+    #[clap(short = 'n', long, default_value_t = 100_000)]
+    requests: u64,
+    /// 运行的秒数，设置之后不限制命令的总数
+    #[clap(short, long)]
+    duration: Option<u64>,
+    /// 读命令（hget）的比例，其余是写命令（hset）
+    #[clap(long, default_value_t = 0.9)]
+    read_ratio: f64,
+    /// key 的数量
+    #[clap(long, default_value_t = 10_000)]
+    keys: u64,
+    /// key 的分布：uniform、zipfian 或者 zipfian:<theta>
+    #[clap(long, default_value = "uniform")]
+    distribution: KeyDistribution,
+    /// value 的字节数：<n> 或者 <min>-<max>
+    #[clap(long, default_value = "100")]
+    value_size: ValueSize,
+    /// 使用的表
+    #[clap(long, default_value = "bench")]
+    table: String,
+    /// 开始之前不写入所有的 key，读取不存在的 key 返回 404
+    #[clap(long)]
+    no_prefill: bool,
+    /// 随机数的 seed，相同的 seed 生成相同的命令序列
+    #[clap(long, default_value_t = 0)]
+    seed: u64,
+}
+
+/// 所有 worker 共享的状态
+struct Bench {
+    workload: Workload,
+    pipeline: usize,
+    /// 剩余的命令数量
+    remaining: AtomicU64,
+    duration: Option<Duration>,
+    /// 所有 worker 准备好之后同时开始
+    start: Barrier,
+}
+
+/// worker 的任务
+#[derive(Debug, Clone, Copy)]
+enum Task {
+    /// 写入所有的 key
+    Prefill,
+    /// 运行负载，参数是随机数的 seed
+    Run(u64),
+}
+
+/// 一个 worker 的结果，404 的读命令也算成功
+#[derive(Debug, Default)]
+struct Stats {
+    reads: Latencies,
+    writes: Latencies,
+    errors: u64,
+}
+
+enum Connection {
+    Tcp(YamuxCtrl<TlsStream<TcpStream>>),
+    Quic(QuicCtrl),
+}
+
+impl Bench {
+    // 取出最多 pipeline 个命令，返回取到的数量
+    fn take(&self) -> usize {
+        let n = self.pipeline as u64;
+        self.remaining
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |r| {
+                (r > 0).then(|| r.saturating_sub(n))
+            })
+            .map_or(0, |r| r.min(n) as usize)
+    }
+}
+
+impl Stats {
+    fn record(&mut self, kind: OpKind, status: u32, latency: Duration) {
+        match (kind, status) {
+            (OpKind::Read, 200 | 404) => self.reads.record(latency),
+            (OpKind::Write, 200) => self.writes.record(latency),
+            _ => self.errors += 1,
+        }
+    }
+
+    fn merge(&mut self, other: Stats) {
+        self.reads.merge(other.reads);
+        self.writes.merge(other.writes);
+        self.errors += other.errors;
+    }
+}
+
+impl Connection {
+    async fn connect(config: &ClientConfig) -> Result<Self> {
+        Ok(match config.general.transport {
+            TransportConfig::Tcp => Self::Tcp(start_client_with_config(config).await?),
+            TransportConfig::Quic => Self::Quic(start_quic_client_with_config(config).await?),
+        })
+    }
+
+    /// 打开一个 stream，完成 hello 之后在上面运行 task
+    async fn spawn(
+        &mut self,
+        config: &ClientConfig,
+        bench: Arc<Bench>,
+        task: Task,
+    ) -> Result<JoinHandle<Result<Stats>>> {
+        Ok(match self {
+            Self::Tcp(ctrl) => {
+                let stream = hello(ctrl.open_stream().await?, config).await?;
+                tokio::spawn(run(stream, bench, task))
+            }
+            Self::Quic(ctrl) => {
+                let stream = hello(ctrl.open_stream().await?, config).await?;
+                tokio::spawn(run(stream, bench, task))
+            }
+        })
+    }
+}
+
+async fn hello<S>(
+    stream: ProstClientStream<S>,
+    config: &ClientConfig,
+) -> Result<ProstClientStream<S>>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let mut stream = stream
+        .with_max_frame(config.general.max_frame)
+        .with_timeout(config.timeout.request());
+    stream.hello(&config.compression).await?;
+    Ok(stream)
+}
+
+async fn run<S>(mut stream: ProstClientStream<S>, bench: Arc<Bench>, task: Task) -> Result<Stats>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let seed = match task {
+        Task::Prefill => return prefill(&mut stream, &bench).await,
+        Task::Run(seed) => seed,
+    };
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut stats = Stats::default();
+    bench.start.wait().await;
+    let deadline = bench.duration.map(|d| Instant::now() + d);
+
+    loop {
+        if matches!(deadline, Some(deadline) if Instant::now() >= deadline) {
+            break;
+        }
+        let n = bench.take();
+        if n == 0 {
+            break;
+        }
+        let (kinds, cmds): (Vec<_>, Vec<_>) =
+            (0..n).map(|_| bench.workload.next(&mut rng)).unzip();
+
+        // pipeline 中每个命令的延迟都是整批命令完成的时间
+        let start = Instant::now();
+        match stream.execute_pipeline(&cmds).await {
+            Ok(res) => {
+                let latency = start.elapsed();
+                for (kind, res) in kinds.into_iter().zip(res) {
+                    stats.record(kind, res.status, latency);
+                }
+            }
+            Err(e) => {
+                // 出错之后 stream 的状态不确定，不能再使用
+                warn!("Stream failed: {}", e);
+                stats.errors += n as u64;
+                break;
+            }
+        }
+    }
+    Ok(stats)
+}
+
+// 写入所有的 key，让读命令都能读到数据
+async fn prefill<S>(stream: &mut ProstClientStream<S>, bench: &Bench) -> Result<Stats>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let workload = &bench.workload;
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut stats = Stats::default();
+    for start in (0..workload.keys()).step_by(PREFILL_BATCH as usize) {
+        let end = (start + PREFILL_BATCH).min(workload.keys());
+        let cmds: Vec<_> = (start..end).map(|i| workload.write(i, &mut rng)).collect();
+        let now = Instant::now();
+        for res in stream.execute_pipeline(&cmds).await? {
+            stats.record(OpKind::Write, res.status, now.elapsed());
+        }
+    }
+    Ok(stats)
+}
+
+// 用临时生成的证书在本地空闲的端口上启动服务器，并让客户端配置连接它
+async fn start_in_process(config: &mut ClientConfig, storage: StorageConfig) -> Result<()> {
+    let domain = config.tls.domain.clone();
+    let ca = CertPem::new_ca(&CertSubject::new("kv-bench CA", 1).with_domains(&[&domain]))?;
+    let subject = CertSubject::new("kv-bench server", 1).with_domains(&[&domain]);
+    let cert = CertPem::new_server(&ca, &subject)?;
+
+    let addr = match config.general.transport {
+        TransportConfig::Tcp => TcpListener::bind("127.0.0.1:0")?.local_addr()?,
+        TransportConfig::Quic => UdpSocket::bind("127.0.0.1:0")?.local_addr()?,
+    };
+    let server = ServerConfig {
+        general: GeneralConfig {
+            addr: addr.to_string(),
+            ..config.general.clone()
+        },
+        storage,
+        tls: ServerTlsConfig {
+            cert: cert.cert,
+            key: cert.key,
+            ..Default::default()
+        },
+        compression: config.compression.clone(),
+        ..Default::default()
+    };
+    tokio::spawn(async move {
+        if let Err(e) = start_server_with_config(&server).await {
+            warn!("In-process server exited: {:?}", e);
+        }
+    });
+
+    config.general.addr = addr.to_string();
+    config.tls.ca = Some(ca.cert);
+    config.tls.identity = None;
+
+    // 等待服务器开始监听
+    for _ in 0..READY_RETRIES {
+        if let Ok(Ok(_)) = time::timeout(READY_INTERVAL, Connection::connect(config)).await {
+            return Ok(());
+        }
+        time::sleep(READY_INTERVAL).await;
+    }
+    anyhow::bail!("in-process server didn't start on {}", addr)
+}
+
+fn report(args: &Args, config: &ClientConfig, stats: Stats, elapsed: Duration) {
+    let ops = stats.reads.len() + stats.writes.len();
+    println!(
+        "{:?} {}, {} connection(s) x {} stream(s), pipeline {}",
+        config.general.transport, config.general.addr, args.connections, args.streams, args.pipeline
+    );
+    println!(
+        "workload: {:.0}% reads, {} keys ({}), value size {}",
+        args.read_ratio * 100.0,
+        args.keys,
+        args.distribution,
+        args.value_size
+    );
+    println!(
+        "ops: {} (reads {}, writes {}), errors: {}",
+        ops,
+        stats.reads.len(),
+        stats.writes.len(),
+        stats.errors
+    );
+    println!(
+        "elapsed: {:.2}s, throughput: {:.0} ops/s",
+        elapsed.as_secs_f64(),
+        ops as f64 / elapsed.as_secs_f64()
+    );
+    println!();
+
+    let mut all = stats.reads.clone();
+    all.merge(stats.writes.clone());
+    println!(
+        "{:<12}{:>10}{:>10}{:>10}{:>10}{:>10}",
+        "latency(us)", "p50", "p90", "p99", "p99.9", "max"
+    );
+    for (name, latencies) in [
+        ("read", &stats.reads),
+        ("write", &stats.writes),
+        ("all", &all),
+    ] {
+        if latencies.is_empty() {
+            continue;
+        }
+        println!(
+            "{:<12}{:>10}{:>10}{:>10}{:>10}{:>10}",
+            name,
+            latencies.percentile(50.0),
+            latencies.percentile(90.0),
+            latencies.percentile(99.0),
+            latencies.percentile(99.9),
+            latencies.percentile(100.0)
+        );
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    tracing_subscriber::fmt::init();
+    let args = Args::parse();
+    if args.connections == 0 || args.streams == 0 || args.pipeline == 0 {
+        anyhow::bail!("connections, streams and pipeline must be positive");
+    }
+
+    // 默认值 -> 配置文件 -> 环境变量 -> 命令行参数
+    let path = args.config.clone().or_else(|| env::var("KV_CLIENT_CONFIG").ok());
+    let mut config = ClientConfig::load_layered(path.as_deref())?;
+    if let Some(addr) = &args.addr {
+        config.general.addr = addr.clone();
+    }
+    if args.in_process {
+        start_in_process(&mut config, args.storage.clone()).await?;
+    }
+
+    let workers = args.connections * args.streams;
+    let bench = Arc::new(Bench {
+        workload: Workload::new(
+            &args.table,
+            args.read_ratio,
+            args.keys,
+            args.distribution,
+            args.value_size,
+        )?,
+        pipeline: args.pipeline,
+        remaining: AtomicU64::new(match args.duration {
+            Some(_) => u64::MAX,
+            None => args.requests,
+        }),
+        duration: args.duration.map(Duration::from_secs),
+        start: Barrier::new(workers + 1),
+    });
+
+    // 连接要一直保持到所有 worker 结束
+    let mut conns = Vec::with_capacity(args.connections);
+    for _ in 0..args.connections {
+        conns.push(Connection::connect(&config).await?);
+    }
+    if !args.no_prefill && args.read_ratio > 0.0 {
+        let prefill = conns[0].spawn(&config, bench.clone(), Task::Prefill).await?;
+        let stats = prefill.await??;
+        if stats.errors > 0 {
+            anyhow::bail!("prefill failed with {} errors", stats.errors);
+        }
+    }
+
+    let mut handles = Vec::with_capacity(workers);
+    for (i, conn) in conns.iter_mut().enumerate() {
+        for j in 0..args.streams {
+            let seed = args.seed.wrapping_add((i * args.streams + j) as u64);
+            handles.push(conn.spawn(&config, bench.clone(), Task::Run(seed)).await?);
+        }
+    }
+
+    bench.start.wait().await;
+    let start = Instant::now();
+    let mut stats = Stats::default();
+    for handle in handles {
+        stats.merge(handle.await??);
+    }
+    report(&args, &config, stats, start.elapsed());
+    Ok(())
+}
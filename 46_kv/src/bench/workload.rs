@@ -0,0 +1,411 @@
+//! kv-bench 使用的负载：按读写比例、key 的分布和 value 的大小生成命令，统计延迟。
+
+use kv6::{CommandRequest, KvError, Value};
+use bytes::Bytes;
+use rand::Rng;
+use std::{fmt, str::FromStr, time::Duration};
+
+/// zipfian 默认的偏斜程度（和 YCSB 相同）
+pub const DEFAULT_ZIPF_THETA: f64 = 0.99;
+
+/// key 的分布
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyDistribution {
+    /// 每个 key 的概率相同
+    Uniform,
+    /// 第 i 个 key 的概率和 1 / i^theta 成正比，theta 在 (0, 1) 之间
+    Zipfian(f64),
+}
+
+/// value 的大小范围（字节），每次写入在 [min, max] 中随机选择
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ValueSize {
+    pub min: usize,
+    pub max: usize,
+}
+
+/// 命令的类型
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OpKind {
+    Read,
+    Write,
+}
+
+/// 一个负载：在 table 中的 keys 个 key 上随机读写
+#[derive(Debug, Clone)]
+pub struct Workload {
+    table: String,
+    read_ratio: f64,
+    keys: KeyGenerator,
+    value_size: ValueSize,
+    // 写入的 value 是它的一部分，避免每次生成随机数据
+    payload: Bytes,
+}
+
+/// 生成 [0, n) 中的 key 序号
+#[derive(Debug, Clone)]
+enum KeyGenerator {
+    Uniform(u64),
+    Zipfian(Zipf),
+}
+
+/// Gray 等人的 zipfian 生成器（YCSB 使用的算法），序号越小越热
+#[derive(Debug, Clone)]
+struct Zipf {
+    n: u64,
+    theta: f64,
+    alpha: f64,
+    zetan: f64,
+    eta: f64,
+}
+
+/// 每个 2 的幂次的区间分成多少个桶的 bit 数，误差不超过 1 / 2^(SUB_BUCKET_BITS - 1)
+const SUB_BUCKET_BITS: u32 = 8;
+const HALF_SUB_BUCKETS: u64 = 1 << (SUB_BUCKET_BITS - 1);
+/// 覆盖整个 u64 需要的桶数
+const BUCKETS: usize = ((66 - SUB_BUCKET_BITS) as u64 * HALF_SUB_BUCKETS) as usize;
+
+/// 延迟的统计，单位是微秒
+///
+/// 使用固定大小的对数直方图（类似 HdrHistogram），内存不随样本数量增长，
+/// 小于 2^SUB_BUCKET_BITS 的值是精确的，更大的值误差不超过 1%
+#[derive(Debug, Clone)]
+pub struct Latencies {
+    counts: Box<[u64]>,
+    len: u64,
+    min: u64,
+    max: u64,
+}
+
+impl FromStr for KeyDistribution {
+    type Err = KvError;
+
+    /// uniform、zipfian 或者 zipfian:<theta>
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, theta) = match s.split_once(':') {
+            Some((name, theta)) => (name, Some(theta)),
+            None => (s, None),
+        };
+        let invalid = || KvError::InvalidConfig(format!("unknown key distribution: {}", s));
+        match (name.to_ascii_lowercase().as_str(), theta) {
+            ("uniform", None) => Ok(Self::Uniform),
+            ("zipfian", None) => Ok(Self::Zipfian(DEFAULT_ZIPF_THETA)),
+            ("zipfian", Some(theta)) => match theta.parse() {
+                Ok(theta) if theta > 0.0 && theta < 1.0 => Ok(Self::Zipfian(theta)),
+                _ => Err(invalid()),
+            },
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for KeyDistribution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Uniform => write!(f, "uniform"),
+            Self::Zipfian(theta) => write!(f, "zipfian:{}", theta),
+        }
+    }
+}
+
+impl FromStr for ValueSize {
+    type Err = KvError;
+
+    /// 固定的大小 <n>，或者范围 <min>-<max>
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || KvError::InvalidConfig(format!("invalid value size: {}", s));
+        let parse = |v: &str| v.trim().parse::<usize>().map_err(|_| invalid());
+        let (min, max) = match s.split_once('-') {
+            Some((min, max)) => (parse(min)?, parse(max)?),
+            None => (parse(s)?, parse(s)?),
+        };
+        if min > max {
+            return Err(invalid());
+        }
+        Ok(Self { min, max })
+    }
+}
+
+impl fmt::Display for ValueSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.min == self.max {
+            true => write!(f, "{}", self.min),
+            false => write!(f, "{}-{}", self.min, self.max),
+        }
+    }
+}
+
+impl Workload {
+    pub fn new(
+        table: impl Into<String>,
+        read_ratio: f64,
+        keys: u64,
+        distribution: KeyDistribution,
+        value_size: ValueSize,
+    ) -> Result<Self, KvError> {
+        if !(0.0..=1.0).contains(&read_ratio) {
+            return Err(KvError::InvalidConfig(format!(
+                "read ratio must be in [0, 1]: {}",
+                read_ratio
+            )));
+        }
+        if keys == 0 {
+            return Err(KvError::InvalidConfig("workload needs at least one key".into()));
+        }
+        let keys = match distribution {
+            KeyDistribution::Uniform => KeyGenerator::Uniform(keys),
+            KeyDistribution::Zipfian(theta) => KeyGenerator::Zipfian(Zipf::new(keys, theta)),
+        };
+        let mut payload = vec![0; value_size.max];
+        rand::thread_rng().fill(&mut payload[..]);
+        Ok(Self {
+            table: table.into(),
+            read_ratio,
+            keys,
+            value_size,
+            payload: payload.into(),
+        })
+    }
+
+    /// key 的数量
+    pub fn keys(&self) -> u64 {
+        match &self.keys {
+            KeyGenerator::Uniform(n) => *n,
+            KeyGenerator::Zipfian(zipf) => zipf.n,
+        }
+    }
+
+    /// 第 i 个 key
+    pub fn key(&self, i: u64) -> String {
+        format!("key{:010}", i)
+    }
+
+    /// 写入第 i 个 key 的命令
+    pub fn write(&self, i: u64, rng: &mut impl Rng) -> CommandRequest {
+        let size = rng.gen_range(self.value_size.min..=self.value_size.max);
+        let value: Value = self.payload.slice(..size).into();
+        CommandRequest::new_hset(&self.table, self.key(i), value)
+    }
+
+    /// 按读写比例和 key 的分布随机生成一个命令
+    pub fn next(&self, rng: &mut impl Rng) -> (OpKind, CommandRequest) {
+        let i = match &self.keys {
+            KeyGenerator::Uniform(n) => rng.gen_range(0..*n),
+            KeyGenerator::Zipfian(zipf) => zipf.sample(rng),
+        };
+        if rng.gen_bool(self.read_ratio) {
+            (OpKind::Read, CommandRequest::new_hget(&self.table, self.key(i)))
+        } else {
+            (OpKind::Write, self.write(i, rng))
+        }
+    }
+}
+
+impl Zipf {
+    fn new(n: u64, theta: f64) -> Self {
+        let zetan = zeta(n, theta);
+        let eta = (1.0 - (2.0 / n as f64).powf(1.0 - theta)) / (1.0 - zeta(2, theta) / zetan);
+        Self {
+            n,
+            theta,
+            alpha: 1.0 / (1.0 - theta),
+            zetan,
+            eta,
+        }
+    }
+
+    fn sample(&self, rng: &mut impl Rng) -> u64 {
+        let u: f64 = rng.gen();
+        let uz = u * self.zetan;
+        if uz < 1.0 {
+            return 0;
+        }
+        if uz < 1.0 + 0.5f64.powf(self.theta) {
+            return 1.min(self.n - 1);
+        }
+        let i = self.n as f64 * (self.eta * u - self.eta + 1.0).powf(self.alpha);
+        (i as u64).min(self.n - 1)
+    }
+}
+
+// 1 / 1^theta + 1 / 2^theta + ... + 1 / n^theta
+fn zeta(n: u64, theta: f64) -> f64 {
+    (1..=n).map(|i| 1.0 / (i as f64).powf(theta)).sum()
+}
+
+impl Default for Latencies {
+    fn default() -> Self {
+        Self {
+            counts: vec![0; BUCKETS].into_boxed_slice(),
+            len: 0,
+            min: u64::MAX,
+            max: 0,
+        }
+    }
+}
+
+impl Latencies {
+    pub fn record(&mut self, latency: Duration) {
+        let v = latency.as_micros().min(u64::MAX as u128) as u64;
+        self.counts[bucket_index(v)] += 1;
+        self.len += 1;
+        self.min = self.min.min(v);
+        self.max = self.max.max(v);
+    }
+
+    pub fn merge(&mut self, other: Latencies) {
+        for (count, other) in self.counts.iter_mut().zip(other.counts.iter()) {
+            *count += other;
+        }
+        self.len += other.len;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// 第 p 百分位的延迟（微秒），p 在 [0, 100] 之间，没有样本时返回 0
+    ///
+    /// 返回样本所在桶的上界，但不会超出记录到的最小值和最大值
+    pub fn percentile(&self, p: f64) -> u64 {
+        if self.len == 0 {
+            return 0;
+        }
+        // nearest-rank，减去一个很小的数避免浮点误差让 99.9% * 1000 变成 1000
+        let rank = (p / 100.0 * self.len as f64 - 1e-9).ceil() as u64;
+        let rank = rank.clamp(1, self.len);
+        let mut seen = 0;
+        for (i, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return bucket_high(i).clamp(self.min, self.max);
+            }
+        }
+        self.max
+    }
+}
+
+// 值 v 所在的桶：v 的最高位在第 m + SUB_BUCKET_BITS - 1 位时，右移 m 位之后剩下的
+// 高位在 [HALF_SUB_BUCKETS, 2 * HALF_SUB_BUCKETS) 中，每个 m 占用 HALF_SUB_BUCKETS 个桶
+fn bucket_index(v: u64) -> usize {
+    let m = (64 - v.leading_zeros()).saturating_sub(SUB_BUCKET_BITS);
+    (m as u64 * HALF_SUB_BUCKETS + (v >> m)) as usize
+}
+
+// 桶中最大的值
+fn bucket_high(i: usize) -> u64 {
+    let i = i as u64;
+    let m = (i / HALF_SUB_BUCKETS).saturating_sub(1);
+    let top = i - m * HALF_SUB_BUCKETS;
+    ((top + 1) << m).wrapping_sub(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kv6::command_request::RequestData;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn distribution_and_value_size_should_parse() {
+        assert_eq!("uniform".parse::<KeyDistribution>().unwrap(), KeyDistribution::Uniform);
+        assert_eq!(
+            "zipfian".parse::<KeyDistribution>().unwrap(),
+            KeyDistribution::Zipfian(DEFAULT_ZIPF_THETA)
+        );
+        assert_eq!(
+            "zipfian:0.5".parse::<KeyDistribution>().unwrap(),
+            KeyDistribution::Zipfian(0.5)
+        );
+        assert!("zipfian:1.5".parse::<KeyDistribution>().is_err());
+        assert!("normal".parse::<KeyDistribution>().is_err());
+
+        assert_eq!("128".parse::<ValueSize>().unwrap(), ValueSize { min: 128, max: 128 });
+        assert_eq!("16-1024".parse::<ValueSize>().unwrap(), ValueSize { min: 16, max: 1024 });
+        assert!("1024-16".parse::<ValueSize>().is_err());
+        assert!("big".parse::<ValueSize>().is_err());
+    }
+
+    #[test]
+    fn zipfian_should_prefer_hot_keys() {
+        let workload = Workload::new("t", 1.0, 1000, "zipfian".parse().unwrap(), "1".parse().unwrap());
+        let workload = workload.unwrap();
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut counts = vec![0u32; 1000];
+        for _ in 0..100_000 {
+            let i = match &workload.keys {
+                KeyGenerator::Zipfian(zipf) => zipf.sample(&mut rng),
+                _ => unreachable!(),
+            };
+            counts[i as usize] += 1;
+        }
+        // theta = 0.99 时最热的 key 大约占 13%，远多于最后 10% 的 key 的总和
+        assert!(counts[0] > 10_000);
+        assert!(counts[0] > counts[900..].iter().sum::<u32>());
+        assert!(counts[0] > counts[1] && counts[1] > counts[10]);
+    }
+
+    #[test]
+    fn workload_should_follow_read_ratio_and_value_size() {
+        let size = ValueSize { min: 10, max: 20 };
+        let workload = Workload::new("t", 0.8, 100, KeyDistribution::Uniform, size).unwrap();
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut reads = 0;
+        for _ in 0..10_000 {
+            match workload.next(&mut rng) {
+                (OpKind::Read, _) => reads += 1,
+                (OpKind::Write, cmd) => match cmd.request_data {
+                    Some(RequestData::Hset(v)) => {
+                        let value: Bytes = v.pair.unwrap().value.unwrap().try_into().unwrap();
+                        assert!((10..=20).contains(&value.len()));
+                    }
+                    _ => panic!("unexpected command: {:?}", cmd),
+                },
+            }
+        }
+        assert!((7_500..8_500).contains(&reads));
+        assert!(Workload::new("t", 1.5, 100, KeyDistribution::Uniform, size).is_err());
+    }
+
+    #[test]
+    fn percentiles_should_work() {
+        let mut latencies = Latencies::default();
+        assert_eq!(latencies.percentile(99.0), 0);
+        for i in (1..=1000).rev() {
+            latencies.record(Duration::from_micros(i));
+        }
+        // 小的值是精确的，大的值误差不超过 1%
+        assert_eq!(latencies.percentile(0.0), 1);
+        assert_eq!(latencies.percentile(10.0), 100);
+        for (p, expected) in [(50.0, 500), (99.0, 990), (99.9, 999)] {
+            let v = latencies.percentile(p);
+            assert!(v >= expected && v <= expected + expected / 100, "p{}: {}", p, v);
+        }
+        assert_eq!(latencies.percentile(100.0), 1000);
+
+        let mut other = Latencies::default();
+        other.record(Duration::from_secs(3600));
+        latencies.merge(other);
+        assert_eq!(latencies.len(), 1001);
+        assert_eq!(latencies.percentile(100.0), 3_600_000_000);
+    }
+
+    #[test]
+    fn buckets_should_cover_all_values() {
+        for v in [0, 1, 255, 256, 257, 1000, 123_456_789, u64::MAX - 1, u64::MAX] {
+            let i = bucket_index(v);
+            assert!(i < BUCKETS);
+            assert!(bucket_high(i) >= v);
+            assert!(bucket_high(i) - v <= v / HALF_SUB_BUCKETS, "{}", v);
+            if i > 0 {
+                assert!(bucket_high(i - 1) < v);
+            }
+        }
+    }
+}
@@ -0,0 +1,234 @@
+//! 证书生成：创建 CA，用 CA 签发服务器证书和客户端证书。
+//!
+//! 客户端证书的 CN 就是客户端的身份，ACL 中的 admins 使用它。
+
+use crate::{network::cert_identity, KvError};
+use certify::{generate_ca, generate_cert, load_ca};
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+use tokio_rustls::rustls::internal::pemfile;
+
+/// 证书的主体信息和有效期
+#[derive(Debug, Clone, PartialEq)]
+pub struct CertSubject {
+    pub country: String,
+    pub org: String,
+    pub cn: String,
+    /// 证书的 SAN，服务器证书必须包含客户端连接时使用的域名
+    pub domains: Vec<String>,
+    /// 有效期（天）
+    pub days: i64,
+}
+
+/// PEM 格式的证书和私钥
+#[derive(Debug, Clone, PartialEq)]
+pub struct CertPem {
+    pub cert: String,
+    pub key: String,
+}
+
+impl CertSubject {
+    pub fn new(cn: impl Into<String>, days: i64) -> Self {
+        Self {
+            country: "CN".into(),
+            org: "Acme Inc.".into(),
+            cn: cn.into(),
+            domains: vec![],
+            days,
+        }
+    }
+
+    pub fn with_domains(mut self, domains: &[&str]) -> Self {
+        self.domains = domains.iter().map(|d| d.to_string()).collect();
+        self
+    }
+
+    fn domains(&self) -> Vec<&str> {
+        self.domains.iter().map(|d| d.as_str()).collect()
+    }
+}
+
+impl CertPem {
+    /// 创建自签名的 CA 证书
+    pub fn new_ca(subject: &CertSubject) -> Result<Self, KvError> {
+        let (cert, key) = generate_ca(
+            subject.domains(),
+            &subject.country,
+            &subject.org,
+            &subject.cn,
+            None,
+            Some(subject.days),
+        )
+        .map_err(|e| KvError::CertificateGenerationError(e.to_string()))?;
+        Ok(Self { cert, key })
+    }
+
+    /// 用 CA 签发服务器证书
+    pub fn new_server(ca: &CertPem, subject: &CertSubject) -> Result<Self, KvError> {
+        if subject.domains.is_empty() {
+            return Err(KvError::CertificateGenerationError(
+                "server cert needs at least one domain".into(),
+            ));
+        }
+        ca.issue(subject, false)
+    }
+
+    /// 用 CA 签发客户端证书，subject 的 CN 是客户端的身份
+    pub fn new_client(ca: &CertPem, subject: &CertSubject) -> Result<Self, KvError> {
+        ca.issue(subject, true)
+    }
+
+    /// 从 dir 下读取 name.cert 和 name.key
+    pub fn read(dir: impl AsRef<Path>, name: &str) -> Result<Self, KvError> {
+        let (cert, key) = Self::paths(dir, name);
+        Ok(Self {
+            cert: fs::read_to_string(cert)?,
+            key: fs::read_to_string(key)?,
+        })
+    }
+
+    /// 写入到 dir 下的 name.cert 和 name.key，返回这两个文件的路径
+    ///
+    /// 文件已经存在时，overwrite 为 false 则报错。私钥文件只有所有者可以读写
+    pub fn write(
+        &self,
+        dir: impl AsRef<Path>,
+        name: &str,
+        overwrite: bool,
+    ) -> Result<(PathBuf, PathBuf), KvError> {
+        fs::create_dir_all(dir.as_ref())?;
+        let (cert, key) = Self::paths(dir, name);
+        if !overwrite {
+            if let Some(path) = [&cert, &key].into_iter().find(|p| p.exists()) {
+                return Err(KvError::AlreadyExists(path.display().to_string()));
+            }
+        }
+        write_file(&cert, &self.cert, 0o644)?;
+        write_file(&key, &self.key, 0o600)?;
+        Ok((cert, key))
+    }
+
+    /// dir 下 name 对应的证书和私钥文件
+    pub fn paths(dir: impl AsRef<Path>, name: &str) -> (PathBuf, PathBuf) {
+        let dir = dir.as_ref();
+        (
+            dir.join(format!("{}.cert", name)),
+            dir.join(format!("{}.key", name)),
+        )
+    }
+
+    /// 证书的 CN，对于客户端证书就是服务器看到的身份
+    pub fn identity(&self) -> Option<String> {
+        let certs = pemfile::certs(&mut self.cert.as_bytes()).ok()?;
+        cert_identity(&certs.first()?.0)
+    }
+
+    // 用这个 CA 签发证书
+    fn issue(&self, subject: &CertSubject, is_client: bool) -> Result<Self, KvError> {
+        let ca = load_ca(&self.cert, &self.key)
+            .map_err(|e| KvError::CertificateGenerationError(e.to_string()))?;
+        let (cert, key) = generate_cert(
+            &ca,
+            subject.domains(),
+            &subject.country,
+            &subject.org,
+            &subject.cn,
+            None,
+            is_client,
+            Some(subject.days),
+        )
+        .map_err(|e| KvError::CertificateGenerationError(e.to_string()))?;
+        Ok(Self { cert, key })
+    }
+}
+
+// 写入文件并设置权限，已经存在的文件也会被修改成 mode。mode 只在 unix 上生效
+fn write_file(path: &Path, content: &str, mode: u32) -> Result<(), KvError> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(mode);
+        // 先收紧已有文件的权限，再写入内容
+        if path.exists() {
+            fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+    options.open(path)?.write_all(content.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{peer_identity, TlsClientConnector, TlsServerAcceptor};
+    use anyhow::Result;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    #[tokio::test]
+    async fn generated_certs_should_work_with_tls() -> Result<()> {
+        let ca = CertPem::new_ca(&CertSubject::new("Acme CA", 365).with_domains(&["acme.inc"]))?;
+        let subject = CertSubject::new("Acme KV server", 30).with_domains(&["kv.acme.inc"]);
+        let server = CertPem::new_server(&ca, &subject)?;
+        let client = CertPem::new_client(&ca, &CertSubject::new("device-1", 30))?;
+        assert_eq!(client.identity().as_deref(), Some("device-1"));
+
+        let acceptor = TlsServerAcceptor::new(&server.cert, &server.key, Some(&ca.cert))?;
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let handle = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = acceptor.accept(stream).await.unwrap();
+            stream.write_all(b"ok").await.unwrap();
+            peer_identity(&stream)
+        });
+
+        let identity = Some((client.cert.as_str(), client.key.as_str()));
+        let connector = TlsClientConnector::new("kv.acme.inc", identity, Some(&ca.cert))?;
+        let mut stream = connector.connect(TcpStream::connect(addr).await?).await?;
+        let mut buf = [0; 2];
+        stream.read_exact(&mut buf).await?;
+        assert_eq!(&buf, b"ok");
+        assert_eq!(handle.await?.as_deref(), Some("device-1"));
+        Ok(())
+    }
+
+    #[test]
+    fn server_cert_should_have_domains() {
+        let ca = CertPem::new_ca(&CertSubject::new("Acme CA", 365)).unwrap();
+        let result = CertPem::new_server(&ca, &CertSubject::new("Acme KV server", 30));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn certs_should_be_written_and_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let ca = CertPem::new_ca(&CertSubject::new("Acme CA", 365)).unwrap();
+        let (cert, key) = ca.write(dir.path().join("certs"), "ca", false).unwrap();
+        assert!(cert.ends_with("certs/ca.cert") && key.ends_with("certs/ca.key"));
+        assert_eq!(CertPem::read(dir.path().join("certs"), "ca").unwrap(), ca);
+
+        // 默认不覆盖已有的证书
+        let other = CertPem::new_ca(&CertSubject::new("Other CA", 365)).unwrap();
+        assert!(other.write(dir.path().join("certs"), "ca", false).is_err());
+        assert_eq!(CertPem::read(dir.path().join("certs"), "ca").unwrap(), ca);
+        other.write(dir.path().join("certs"), "ca", true).unwrap();
+        assert_eq!(CertPem::read(dir.path().join("certs"), "ca").unwrap(), other);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&key).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+}
@@ -1,26 +1,98 @@
+use std::env;
 use anyhow::Result;
-use kv6::{CommandRequest, ProstClientStream, TlsClientConnector};
-use tokio::net::TcpStream;
+use clap::Parser;
+use kv6::{
+    start_client_with_config, start_quic_client_with_config, ClientConfig, CommandRequest,
+    TransportConfig,
+};
 use tracing::info;
 
+/// 命令行参数，优先级高于配置文件和环境变量
+#[derive(Parser, Debug)]
+#[clap(name = "kvc", version, about = "KV client")]
+struct Args {
+    /// 配置文件路径，也可以通过 KV_CLIENT_CONFIG 指定
+    #[clap(short, long)]
+    config: Option<String>,
+    /// 服务器地址
+    #[clap(long)]
+    addr: Option<String>,
+    /// 服务器证书里的域名
+    #[clap(long)]
+    domain: Option<String>,
+    /// 签发服务器证书的 CA（PEM 文件路径）
+    #[clap(long)]
+    ca: Option<String>,
+    /// 客户端证书（PEM 文件路径），需要和 --key 一起使用
+    #[clap(long, requires = "key")]
+    cert: Option<String>,
+    /// 客户端私钥（PEM 文件路径）
+    #[clap(long, requires = "cert")]
+    key: Option<String>,
+    /// 打印最终生效的配置（隐藏私钥）后退出
+    #[clap(long)]
+    check_config: bool,
+}
+
+impl Args {
+    fn apply(self, config: &mut ClientConfig) {
+        if let Some(v) = self.addr {
+            config.general.addr = v;
+        }
+        if let Some(v) = self.domain {
+            config.tls.domain = v;
+        }
+        if let Some(v) = self.ca {
+            config.tls.ca = Some(v);
+        }
+        if let (Some(cert), Some(key)) = (self.cert, self.key) {
+            config.tls.identity = Some((cert, key));
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
+    let args = Args::parse();
 
-    let ca_cert = include_str!("../fixtures/ca.cert");
-
-    let addr = "127.0.0.1:9527";
-    let connector = TlsClientConnector::new("kvserver.acme.inc", None, Some(ca_cert))?;
-    let stream = TcpStream::connect(addr).await?;
-    let stream = connector.connect(stream).await?;
+    // 默认值 -> 配置文件 -> 环境变量 -> 命令行参数
+    let path = args.config.clone().or_else(|| env::var("KV_CLIENT_CONFIG").ok());
+    let mut config = ClientConfig::load_layered(path.as_deref())?;
+    let check_config = args.check_config;
+    args.apply(&mut config);
 
-    let mut client = ProstClientStream::new(stream);
+    if check_config {
+        print!("{}", toml::to_string_pretty(&config.redacted())?);
+        return Ok(());
+    }
 
     // 生成一个HSET命令
     let cmd = CommandRequest::new_hset("table1", "hello", "world".to_string().into());
 
     // 发送命令
-    let data = client.execute(cmd).await?;
+    let data = match config.general.transport {
+        TransportConfig::Tcp => {
+            let mut ctrl = start_client_with_config(&config).await?;
+            let mut stream = ctrl
+                .open_stream()
+                .await?
+                .with_max_frame(config.general.max_frame)
+                .with_timeout(config.timeout.request());
+            stream.hello(&config.compression).await?;
+            stream.execute_unary(&cmd).await?
+        }
+        TransportConfig::Quic => {
+            let ctrl = start_quic_client_with_config(&config).await?;
+            let mut stream = ctrl
+                .open_stream()
+                .await?
+                .with_max_frame(config.general.max_frame)
+                .with_timeout(config.timeout.request());
+            stream.hello(&config.compression).await?;
+            stream.execute_unary(&cmd).await?
+        }
+    };
     info!("Got response {:?}", data);
 
     Ok(())
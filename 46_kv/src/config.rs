@@ -1,68 +1,447 @@
-use crate::KvError;
+use crate::{CompressionCodec, KvError, COMPRESSION_LIMIT, DEFAULT_MAX_FRAME, MAX_FRAME};
 use serde::{Deserialize, Serialize};
-use std::fs;
+use std::{fs, str::FromStr, time::Duration};
 
+/// 脱敏后显示的内容
+const REDACTED: &str = "<redacted>";
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
 pub struct ServerConfig {
     pub general: GeneralConfig,
     pub storage: StorageConfig,
     pub tls: ServerTlsConfig,
     pub log: LogConfig,
+    pub metrics: MetricsConfig,
+    pub http: HttpConfig,
+    pub resp: RespConfig,
+    pub ws: WsConfig,
+    pub compression: CompressionConfig,
+    pub timeout: TimeoutConfig,
+    pub acl: AclConfig,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
 pub struct ClientConfig {
     pub general: GeneralConfig,
     pub tls: ClientTlsConfig,
+    pub compression: CompressionConfig,
+    pub timeout: TimeoutConfig,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
 pub struct GeneralConfig {
     pub addr: String,
+    pub transport: TransportConfig,
+    /// 能接收的最大 frame（字节），不能超过协议允许的 512M
+    pub max_frame: usize,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
 pub struct LogConfig {
     pub path: String,
     pub rotation: RotationConfig,
 }
 
+/// Prometheus metrics 的 HTTP 端口
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct MetricsConfig {
+    pub enabled: bool,
+    pub addr: String,
+}
+
+/// HTTP/JSON 网关，和原生的端口使用同样的 TLS 配置和 ACL
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct HttpConfig {
+    pub enabled: bool,
+    pub addr: String,
+}
+
+/// RESP 端口，redis 客户端可以直接连接，不使用 TLS，以匿名客户端的身份执行命令
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct RespConfig {
+    pub enabled: bool,
+    pub addr: String,
+}
+
+/// WebSocket 端口，每个 binary message 是一个 frame，使用原生端口的 TLS 配置和 ACL
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct WsConfig {
+    pub enabled: bool,
+    pub addr: String,
+}
+
+/// frame 的压缩，超过 limit 字节的 frame 使用 codec 压缩。
+/// 客户端在 stream 开始时发送自己的 codec 协商，服务器的 codec 优先
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct CompressionConfig {
+    pub codec: CompressionCodec,
+    pub limit: usize,
+}
+
+/// 连接和请求的超时，单位是毫秒，0 表示不超时
+///
+/// 服务器使用 idle_ms / stream_idle_ms，客户端使用 keepalive_ms / request_ms
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct TimeoutConfig {
+    /// 连接上没有任何 stream 的时间超过 idle_ms 时关闭连接
+    pub idle_ms: u64,
+    /// stream 上超过 stream_idle_ms 没有收到命令时关闭 stream
+    pub stream_idle_ms: u64,
+    /// 客户端每隔 keepalive_ms 在连接上打开一个空的 stream，保持连接活跃
+    pub keepalive_ms: u64,
+    /// 客户端请求默认的 deadline
+    pub request_ms: u64,
+}
+
+/// 访问控制，admins 是拥有 admin 角色的客户端证书 CN
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct AclConfig {
+    pub admins: Vec<String>,
+}
+
+/// 原生端口使用的传输协议：TLS + yamux，或者 QUIC（addr 是 UDP 地址）
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+pub enum TransportConfig {
+    #[default]
+    Tcp,
+    Quic,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
 pub enum RotationConfig {
     Hourly,
+    #[default]
     Daily,
     Never,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
 #[serde(tag = "type", content = "args")]
 pub enum StorageConfig {
+    #[default]
     MemTable,
     SledDb(String),
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+/// TLS 相关的 cert / key / ca / crl 可以是 PEM 字符串，也可以是 PEM 文件的路径
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
 pub struct ServerTlsConfig {
+    /// 客户端没有发送 SNI，或者 SNI 没有匹配的证书时使用的服务器证书
     pub cert: String,
     pub key: String,
     pub ca: Option<String>,
+    /// 额外信任的客户端 CA，和 ca 一起验证客户端证书
+    pub client_cas: Vec<String>,
+    /// 客户端证书的吊销列表
+    pub crls: Vec<String>,
+    /// 根据 SNI 选择的服务器证书
+    pub sni: Vec<SniCertConfig>,
+    /// 检查证书文件是否变化的间隔，0 表示不重新加载
+    pub reload_ms: u64,
 }
 
+/// SNI 为 name 时使用的服务器证书
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct SniCertConfig {
+    pub name: String,
+    pub cert: String,
+    pub key: String,
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
 pub struct ClientTlsConfig {
     pub domain: String,
     pub identity: Option<(String, String)>,
     pub ca: Option<String>,
 }
 
+impl Default for GeneralConfig {
+    fn default() -> Self {
+        Self {
+            addr: "127.0.0.1:9527".into(),
+            transport: TransportConfig::Tcp,
+            max_frame: DEFAULT_MAX_FRAME,
+        }
+    }
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            path: "/tmp/kv-log".into(),
+            rotation: RotationConfig::Daily,
+        }
+    }
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            addr: "127.0.0.1:9528".into(),
+        }
+    }
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            addr: "127.0.0.1:9529".into(),
+        }
+    }
+}
+
+impl Default for RespConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            addr: "127.0.0.1:6380".into(),
+        }
+    }
+}
+
+impl Default for WsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            addr: "127.0.0.1:9530".into(),
+        }
+    }
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            codec: CompressionCodec::Gzip,
+            limit: COMPRESSION_LIMIT,
+        }
+    }
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        Self {
+            idle_ms: 600_000,
+            stream_idle_ms: 600_000,
+            keepalive_ms: 30_000,
+            request_ms: 30_000,
+        }
+    }
+}
+
+impl TimeoutConfig {
+    pub fn idle(&self) -> Option<Duration> {
+        millis(self.idle_ms)
+    }
+
+    pub fn stream_idle(&self) -> Option<Duration> {
+        millis(self.stream_idle_ms)
+    }
+
+    pub fn keepalive(&self) -> Option<Duration> {
+        millis(self.keepalive_ms)
+    }
+
+    pub fn request(&self) -> Option<Duration> {
+        millis(self.request_ms)
+    }
+}
+
+fn millis(ms: u64) -> Option<Duration> {
+    (ms > 0).then(|| Duration::from_millis(ms))
+}
+
+fn parse_millis(name: &str, v: &str) -> Result<u64, KvError> {
+    v.parse()
+        .map_err(|_| KvError::InvalidConfig(format!("invalid {}: {}", name, v)))
+}
+
+impl Default for ClientTlsConfig {
+    fn default() -> Self {
+        Self {
+            domain: "localhost".into(),
+            identity: None,
+            ca: None,
+        }
+    }
+}
+
+impl FromStr for RotationConfig {
+    type Err = KvError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "hourly" => Ok(RotationConfig::Hourly),
+            "daily" => Ok(RotationConfig::Daily),
+            "never" => Ok(RotationConfig::Never),
+            _ => Err(KvError::InvalidConfig(format!("unknown log rotation: {}", s))),
+        }
+    }
+}
+
+impl FromStr for TransportConfig {
+    type Err = KvError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "tcp" => Ok(TransportConfig::Tcp),
+            "quic" => Ok(TransportConfig::Quic),
+            _ => Err(KvError::InvalidConfig(format!("unknown transport: {}", s))),
+        }
+    }
+}
+
+/// 支持 `memtable` 或者 `sled:<path>` 两种写法
+impl FromStr for StorageConfig {
+    type Err = KvError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("sled", path)) | Some(("sleddb", path)) if !path.is_empty() => {
+                Ok(StorageConfig::SledDb(path.into()))
+            }
+            _ if s.eq_ignore_ascii_case("memtable") => Ok(StorageConfig::MemTable),
+            _ => Err(KvError::InvalidConfig(format!("unknown storage: {}", s))),
+        }
+    }
+}
+
 impl ServerConfig {
     pub fn load(path: &str) -> Result<Self, KvError> {
         let config = fs::read_to_string(path)?;
         let config: Self = toml::from_str(&config)?;
         Ok(config)
     }
+
+    /// 按 默认值 -> 配置文件 -> KV_* 环境变量 的顺序加载配置
+    pub fn load_layered(path: Option<&str>) -> Result<Self, KvError> {
+        let mut config = match path {
+            Some(path) => Self::load(path)?,
+            None => Self::default(),
+        };
+        config.apply_env(|name| std::env::var(name).ok())?;
+        Ok(config)
+    }
+
+    /// 用环境变量覆盖配置，`vars` 根据变量名返回变量的值
+    pub fn apply_env(&mut self, vars: impl Fn(&str) -> Option<String>) -> Result<(), KvError> {
+        if let Some(v) = vars("KV_ADDR") {
+            self.general.addr = v;
+        }
+        if let Some(v) = vars("KV_TRANSPORT") {
+            self.general.transport = v.parse()?;
+        }
+        if let Some(v) = vars("KV_COMPRESSION") {
+            self.compression.codec = v.parse()?;
+        }
+        if let Some(v) = vars("KV_MAX_FRAME") {
+            self.general.max_frame = v
+                .parse()
+                .map_err(|_| KvError::InvalidConfig(format!("invalid max frame: {}", v)))?;
+        }
+        if let Some(v) = vars("KV_IDLE_TIMEOUT_MS") {
+            self.timeout.idle_ms = parse_millis("idle timeout", &v)?;
+        }
+        if let Some(v) = vars("KV_STREAM_IDLE_TIMEOUT_MS") {
+            self.timeout.stream_idle_ms = parse_millis("stream idle timeout", &v)?;
+        }
+        if let Some(v) = vars("KV_STORAGE") {
+            self.storage = v.parse()?;
+        }
+        if let Some(v) = vars("KV_TLS_CERT") {
+            self.tls.cert = v;
+        }
+        if let Some(v) = vars("KV_TLS_KEY") {
+            self.tls.key = v;
+        }
+        if let Some(v) = vars("KV_TLS_CA") {
+            self.tls.ca = Some(v);
+        }
+        if let Some(v) = vars("KV_TLS_CRLS") {
+            self.tls.crls = v.split(',').map(|s| s.trim().to_string()).collect();
+        }
+        if let Some(v) = vars("KV_TLS_RELOAD_MS") {
+            self.tls.reload_ms = parse_millis("tls reload interval", &v)?;
+        }
+        if let Some(v) = vars("KV_LOG_PATH") {
+            self.log.path = v;
+        }
+        if let Some(v) = vars("KV_LOG_ROTATION") {
+            self.log.rotation = v.parse()?;
+        }
+        if let Some(v) = vars("KV_ADMINS") {
+            self.acl.admins = v.split(',').map(|s| s.trim().to_string()).collect();
+        }
+        if let Some(v) = vars("KV_METRICS_ADDR") {
+            self.metrics.enabled = true;
+            self.metrics.addr = v;
+        }
+        if let Some(v) = vars("KV_HTTP_ADDR") {
+            self.http.enabled = true;
+            self.http.addr = v;
+        }
+        if let Some(v) = vars("KV_RESP_ADDR") {
+            self.resp.enabled = true;
+            self.resp.addr = v;
+        }
+        if let Some(v) = vars("KV_WS_ADDR") {
+            self.ws.enabled = true;
+            self.ws.addr = v;
+        }
+        Ok(())
+    }
+
+    /// 检查配置是否完整
+    pub fn validate(&self) -> Result<(), KvError> {
+        if self.tls.cert.is_empty() || self.tls.key.is_empty() {
+            return Err(KvError::InvalidConfig(
+                "tls cert and key must be provided".into(),
+            ));
+        }
+        if self.tls.sni.iter().any(|c| c.name.is_empty()) {
+            return Err(KvError::InvalidConfig("sni name must be provided".into()));
+        }
+        // QUIC 使用 rustls 0.20 单独加载证书，只支持 cert / key 和客户端 CA
+        let tls = &self.tls;
+        if self.general.transport == TransportConfig::Quic
+            && (!tls.crls.is_empty() || !tls.sni.is_empty() || tls.reload_ms > 0)
+        {
+            return Err(KvError::InvalidConfig(
+                "quic transport does not support tls crls, sni or reload".into(),
+            ));
+        }
+        if self.general.max_frame == 0 || self.general.max_frame > MAX_FRAME {
+            return Err(KvError::InvalidConfig(format!(
+                "max frame must be between 1 and {}",
+                MAX_FRAME
+            )));
+        }
+        Ok(())
+    }
+
+    /// 返回隐藏了私钥的配置，用于打印
+    pub fn redacted(&self) -> Self {
+        let mut config = self.clone();
+        config.tls.key = redact_pem(&config.tls.key);
+        for sni in config.tls.sni.iter_mut() {
+            sni.key = redact_pem(&sni.key);
+        }
+        config
+    }
 }
 
 impl ClientConfig {
@@ -71,11 +450,151 @@ impl ClientConfig {
         let config: Self = toml::from_str(&config)?;
         Ok(config)
     }
+
+    /// 按 默认值 -> 配置文件 -> KV_* 环境变量 的顺序加载配置
+    pub fn load_layered(path: Option<&str>) -> Result<Self, KvError> {
+        let mut config = match path {
+            Some(path) => Self::load(path)?,
+            None => Self::default(),
+        };
+        config.apply_env(|name| std::env::var(name).ok())?;
+        Ok(config)
+    }
+
+    /// 用环境变量覆盖配置，`vars` 根据变量名返回变量的值
+    pub fn apply_env(&mut self, vars: impl Fn(&str) -> Option<String>) -> Result<(), KvError> {
+        if let Some(v) = vars("KV_ADDR") {
+            self.general.addr = v;
+        }
+        if let Some(v) = vars("KV_TRANSPORT") {
+            self.general.transport = v.parse()?;
+        }
+        if let Some(v) = vars("KV_COMPRESSION") {
+            self.compression.codec = v.parse()?;
+        }
+        if let Some(v) = vars("KV_MAX_FRAME") {
+            self.general.max_frame = v
+                .parse()
+                .map_err(|_| KvError::InvalidConfig(format!("invalid max frame: {}", v)))?;
+        }
+        if let Some(v) = vars("KV_KEEPALIVE_MS") {
+            self.timeout.keepalive_ms = parse_millis("keepalive", &v)?;
+        }
+        if let Some(v) = vars("KV_REQUEST_TIMEOUT_MS") {
+            self.timeout.request_ms = parse_millis("request timeout", &v)?;
+        }
+        if let Some(v) = vars("KV_TLS_DOMAIN") {
+            self.tls.domain = v;
+        }
+        if let Some(v) = vars("KV_TLS_CA") {
+            self.tls.ca = Some(v);
+        }
+        // 客户端证书需要 cert 和 key 同时提供
+        if let (Some(cert), Some(key)) = (vars("KV_TLS_CERT"), vars("KV_TLS_KEY")) {
+            self.tls.identity = Some((cert, key));
+        }
+        Ok(())
+    }
+
+    /// 返回隐藏了私钥的配置，用于打印
+    pub fn redacted(&self) -> Self {
+        let mut config = self.clone();
+        if let Some((_, key)) = config.tls.identity.as_mut() {
+            *key = redact_pem(key);
+        }
+        config
+    }
+}
+
+impl ServerTlsConfig {
+    /// 把文件路径形式的 cert / key / ca / crl 读取成 PEM 字符串
+    pub fn resolve(&self) -> Result<Self, KvError> {
+        let sni = self
+            .sni
+            .iter()
+            .map(|c| {
+                Ok(SniCertConfig {
+                    name: c.name.clone(),
+                    cert: load_pem(&c.cert)?,
+                    key: load_pem(&c.key)?,
+                })
+            })
+            .collect::<Result<_, KvError>>()?;
+        Ok(Self {
+            cert: load_pem(&self.cert)?,
+            key: load_pem(&self.key)?,
+            ca: self.ca.as_deref().map(load_pem).transpose()?,
+            client_cas: self.client_cas.iter().map(|s| load_pem(s)).collect::<Result<_, _>>()?,
+            crls: self.crls.iter().map(|s| load_pem(s)).collect::<Result<_, _>>()?,
+            sni,
+            reload_ms: self.reload_ms,
+        })
+    }
+
+    /// 所有信任的客户端 CA
+    pub fn client_cas(&self) -> impl Iterator<Item = &str> {
+        self.ca.iter().chain(self.client_cas.iter()).map(|s| s.as_str())
+    }
+
+    /// 以文件路径形式配置的证书文件，重新加载时检查它们是否变化
+    pub fn files(&self) -> Vec<&str> {
+        let sni = self.sni.iter().flat_map(|c| [c.cert.as_str(), c.key.as_str()]);
+        [self.cert.as_str(), self.key.as_str()]
+            .into_iter()
+            .chain(self.client_cas())
+            .chain(self.crls.iter().map(|s| s.as_str()))
+            .chain(sni)
+            .filter(|s| !s.is_empty() && !is_pem(s))
+            .collect()
+    }
+
+    /// 重新加载证书的间隔
+    pub fn reload(&self) -> Option<Duration> {
+        millis(self.reload_ms)
+    }
+}
+
+impl ClientTlsConfig {
+    /// 把文件路径形式的 identity / ca 读取成 PEM 字符串
+    pub fn resolve(&self) -> Result<Self, KvError> {
+        let identity = match &self.identity {
+            Some((cert, key)) => Some((load_pem(cert)?, load_pem(key)?)),
+            None => None,
+        };
+        Ok(Self {
+            domain: self.domain.clone(),
+            identity,
+            ca: self.ca.as_deref().map(load_pem).transpose()?,
+        })
+    }
+}
+
+fn is_pem(s: &str) -> bool {
+    s.trim_start().starts_with("-----BEGIN")
+}
+
+// 如果是 PEM 字符串直接返回，否则当作文件路径读取
+fn load_pem(s: &str) -> Result<String, KvError> {
+    if is_pem(s) {
+        Ok(s.to_string())
+    } else {
+        Ok(fs::read_to_string(s)?)
+    }
+}
+
+// 文件路径不是敏感信息，保留下来便于排查问题
+fn redact_pem(s: &str) -> String {
+    if is_pem(s) {
+        REDACTED.into()
+    } else {
+        s.into()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::HashMap;
 
     #[test]
     fn server_config_should_be_loaded() {
@@ -88,4 +607,90 @@ mod tests {
         let result: Result<ClientConfig, toml::de::Error> = toml::from_str(include_str!("../fixtures/client.conf"));
         assert!(result.is_ok());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn partial_config_should_use_defaults() {
+        let config: ServerConfig = toml::from_str("[general]\naddr = '0.0.0.0:1234'").unwrap();
+        assert_eq!(config.general.addr, "0.0.0.0:1234");
+        assert_eq!(config.storage, StorageConfig::MemTable);
+        assert_eq!(config.log, LogConfig::default());
+        assert_eq!(config.general.transport, TransportConfig::Tcp);
+    }
+
+    #[test]
+    fn env_should_override_config() {
+        let mut config: ServerConfig = toml::from_str(include_str!("../fixtures/server.conf")).unwrap();
+        let vars: HashMap<&str, &str> = [
+            ("KV_ADDR", "0.0.0.0:9999"),
+            ("KV_STORAGE", "memtable"),
+            ("KV_LOG_ROTATION", "hourly"),
+            ("KV_TRANSPORT", "quic"),
+            ("KV_COMPRESSION", "zstd"),
+            ("KV_MAX_FRAME", "1048576"),
+            ("KV_IDLE_TIMEOUT_MS", "0"),
+        ]
+        .into_iter()
+        .collect();
+        config.apply_env(|k| vars.get(k).map(|v| v.to_string())).unwrap();
+
+        assert_eq!(config.general.addr, "0.0.0.0:9999");
+        assert_eq!(config.storage, StorageConfig::MemTable);
+        assert_eq!(config.log.rotation, RotationConfig::Hourly);
+        assert_eq!(config.general.transport, TransportConfig::Quic);
+        assert_eq!(config.compression.codec, CompressionCodec::Zstd);
+        assert_eq!(config.general.max_frame, 1024 * 1024);
+        assert_eq!(config.timeout.idle(), None);
+        assert_eq!(config.timeout.stream_idle(), Some(Duration::from_secs(600)));
+        assert!(config.validate().is_ok());
+        config.general.max_frame = MAX_FRAME + 1;
+        assert!(config.validate().is_err());
+
+        config.general.max_frame = MAX_FRAME;
+        config.tls.reload_ms = 1000;
+        assert!(config.validate().is_err());
+        config.general.transport = TransportConfig::Tcp;
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn storage_config_should_be_parsed() {
+        assert_eq!("MemTable".parse::<StorageConfig>().unwrap(), StorageConfig::MemTable);
+        assert_eq!(
+            "sled:/tmp/kv".parse::<StorageConfig>().unwrap(),
+            StorageConfig::SledDb("/tmp/kv".into())
+        );
+        assert!("redis".parse::<StorageConfig>().is_err());
+    }
+
+    #[test]
+    fn redacted_config_should_hide_private_key() {
+        let config: ServerConfig = toml::from_str(include_str!("../fixtures/server.conf")).unwrap();
+        let output = toml::to_string_pretty(&config.redacted()).unwrap();
+        assert!(!output.contains("PRIVATE KEY"));
+        assert!(output.contains(REDACTED));
+    }
+
+    #[test]
+    fn tls_config_should_load_pem_from_file() {
+        let config = ServerTlsConfig {
+            cert: "fixtures/server.cert".into(),
+            key: "fixtures/server.key".into(),
+            crls: vec!["fixtures/client.crl".into()],
+            sni: vec![SniCertConfig {
+                name: "kvadmin.acme.inc".into(),
+                cert: "fixtures/admin.cert".into(),
+                key: include_str!("../fixtures/admin.key").into(),
+            }],
+            ..Default::default()
+        };
+        assert_eq!(
+            config.files(),
+            ["fixtures/server.cert", "fixtures/server.key", "fixtures/client.crl", "fixtures/admin.cert"]
+        );
+        let config = config.resolve().unwrap();
+        assert_eq!(config.cert, include_str!("../fixtures/server.cert"));
+        assert_eq!(config.key, include_str!("../fixtures/server.key"));
+        assert_eq!(config.crls, [include_str!("../fixtures/client.crl")]);
+        assert_eq!(config.sni[0].cert, include_str!("../fixtures/admin.cert"));
+    }
+}
@@ -8,12 +8,32 @@ pub enum KvError {
     FrameError,
     #[error("Command is invalid: `{0}`")]
     InvalidCommand(String),
+    #[error("Permission denied: {0}")]
+    PermissionDenied(String),
+    #[error("Already exists: {0}")]
+    AlreadyExists(String),
+    #[error("Constraint violation: {0}")]
+    ConstraintViolation(String),
     #[error("Cannot convert value {:0} to {1}")]
     ConvertError(String, &'static str),
     #[error("Cannot process command {0} with table: {1}, key: {2}. Error: {}")]
     StorageError(&'static str, String, String, String),
+    #[error("Invalid config: {0}")]
+    InvalidConfig(String),
+    #[error("Invalid backup: {0}")]
+    InvalidBackup(String),
+    #[error("{0}")]
+    IncompatibleProtocol(String),
+    #[error("Request timed out")]
+    Timeout,
+    #[error("Script error: {0}")]
+    ScriptError(String),
     #[error("Certificate parse error: error to load {0} {0}")]
     CertificateParseError(&'static str, &'static str),
+    #[error("Certificate generation error: {0}")]
+    CertificateGenerationError(String),
+    #[error("Serialization error: {0}")]
+    SerializationError(String),
 
     #[error("Failed to encode protobuf message")]
     EncodeError(#[from] prost::EncodeError),
@@ -27,6 +47,8 @@ pub enum KvError {
     TlsError(#[from] tokio_rustls::rustls::TLSError),
     #[error("Parse config error")]
     YamuxConnectionError(#[from] yamux::ConnectionError),
+    #[error("QUIC connection error")]
+    QuicConnectionError(#[from] quinn::ConnectionError),
     #[error("Parse config error")]
     ConfigError(#[from] toml::de::Error),
 
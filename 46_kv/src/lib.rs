@@ -1,37 +1,59 @@
+mod backup;
+mod cert;
 mod error;
 mod network;
 mod pb;
+mod script;
+mod sdk;
 mod service;
 mod storage;
 mod config;
+mod metrics;
 
 use std::time::Duration;
 
+pub use backup::*;
+pub use cert::*;
 pub use error::KvError;
 pub use network::*;
 pub use pb::abi::*;
+pub use pb::MAX_REQUEST_TIMEOUT;
+pub use script::*;
+pub use sdk::*;
 pub use service::*;
 pub use storage::*;
 pub use config::*;
+pub use metrics::{gather_metrics, start_metrics_server};
 
 use anyhow::Result;
 use tokio::net::{TcpListener, TcpStream};
 use tokio_rustls::client;
 use tokio_util::compat::FuturesAsyncReadCompatExt;
-use tracing::{info, instrument, span};
+use tracing::{info, instrument, warn};
 use tokio::time;
 
 
 /// 通过配置创建KV服务
 #[instrument(skip_all)]
 pub async fn start_server_with_config(config: &ServerConfig) -> Result<()> {
-    let acceptor =
-        TlsServerAcceptor::new(&config.tls.cert, &config.tls.key, config.tls.ca.as_deref())?;
+    config.validate()?;
+    let acceptor = TlsServerAcceptor::from_config(&config.tls)?;
+    if let Some(interval) = config.tls.reload() {
+        acceptor.watch(config.tls.clone(), interval);
+    }
+
+    if config.metrics.enabled {
+        let metrics_addr = config.metrics.addr.clone();
+        tokio::spawn(async move {
+            if let Err(e) = start_metrics_server(&metrics_addr).await {
+                warn!("Metrics server exited: {:?}", e);
+            }
+        });
+    }
 
-    let addr = &config.general.addr;
     match &config.storage {
-        StorageConfig::MemTable => start_tls_server(addr, MemTable::new(), acceptor).await?,
-        StorageConfig::SledDb(path) => start_tls_server(addr, SledDb::new(path), acceptor).await?,
+        StorageConfig::MemTable => start_tls_server(config, MemTable::new(), acceptor).await?,
+        StorageConfig::SledDb(path) => start_tls_server(config, SledDb::new(path), acceptor).await?,
     };
 
     Ok(())
@@ -41,41 +63,130 @@ pub async fn start_server_with_config(config: &ServerConfig) -> Result<()> {
 #[instrument(skip_all)]
 pub async fn start_client_with_config(config: &ClientConfig) -> Result<YamuxCtrl<client::TlsStream<TcpStream>>>{
     let addr = &config.general.addr;
-    let tls = &config.tls;
+    let tls = config.tls.resolve()?;
 
     let identity = tls.identity.as_ref().map(|(c, k)|(c.as_str(), k.as_str()));
     let connector = TlsClientConnector::new(&tls.domain, identity, tls.ca.as_deref())?;
     let stream = TcpStream::connect(addr).await?;
+    // 请求和 yamux 的控制 frame 都很小，不能等待 Nagle 合并，否则每个请求都会多出几十毫秒
+    stream.set_nodelay(true)?;
     let stream = connector.connect(stream).await?;
 
-    // 打开一个Stream
-    Ok(YamuxCtrl::new_client(stream, None))
+    let ctrl = YamuxCtrl::new_client(stream, None);
+    Ok(match config.timeout.keepalive() {
+        Some(interval) => ctrl.with_keepalive(interval),
+        None => ctrl,
+    })
+}
+
+/// 通过配置创建 QUIC 的 KV 客户端
+#[instrument(skip_all)]
+pub async fn start_quic_client_with_config(config: &ClientConfig) -> Result<QuicCtrl> {
+    let tls = config.tls.resolve()?;
+    let identity = tls.identity.as_ref().map(|(c, k)| (c.as_str(), k.as_str()));
+    let quic = quic_client_config(identity, tls.ca.as_deref())?;
+    Ok(QuicCtrl::connect(&config.general.addr, &tls.domain, quic).await?)
 }
 
 async fn start_tls_server<Store: Storage>(
-    addr: &str,
+    config: &ServerConfig,
     store: Store,
     acceptor: TlsServerAcceptor,
 ) -> Result<()>{
-    let service: Service<Store> = ServiceInner::new(store).into();
+    let acl = Acl::new(&config.acl.admins);
+    let service: Service<Store> = ServiceInner::new(store)
+        .acl(acl)
+        .compression(config.compression.clone())
+        .max_frame(config.general.max_frame)
+        .timeout(config.timeout.clone())
+        .into();
+    if config.http.enabled {
+        let (http, svc, acceptor) = (config.http.addr.clone(), service.clone(), acceptor.clone());
+        tokio::spawn(async move {
+            if let Err(e) = start_http_gateway(&http, svc, acceptor).await {
+                warn!("HTTP gateway exited: {:?}", e);
+            }
+        });
+    }
+    if config.resp.enabled {
+        let (resp, svc) = (config.resp.addr.clone(), service.clone());
+        tokio::spawn(async move {
+            if let Err(e) = start_resp_server(&resp, svc).await {
+                warn!("RESP server exited: {:?}", e);
+            }
+        });
+    }
+    if config.ws.enabled {
+        let (ws, svc, acceptor) = (config.ws.addr.clone(), service.clone(), acceptor.clone());
+        tokio::spawn(async move {
+            if let Err(e) = start_ws_server(&ws, svc, acceptor).await {
+                warn!("WebSocket server exited: {:?}", e);
+            }
+        });
+    }
+    let addr = &config.general.addr;
+    let idle_timeout = config.timeout.idle();
+    if config.general.transport == TransportConfig::Quic {
+        let tls = config.tls.resolve()?;
+        let client_cas: Vec<_> = tls.client_cas().collect();
+        let quic = quic_server_config(&tls.cert, &tls.key, &client_cas)?;
+        start_quic_server(addr, service, quic).await?;
+        return Ok(());
+    }
+
     let listener = TcpListener::bind(addr).await?;
     info!("Start listening on {}", addr);
     loop {
         let tls = acceptor.clone();
         let (stream, addr) = listener.accept().await?;
         info!("Client {:?} connected", addr);
+        if let Err(e) = stream.set_nodelay(true) {
+            warn!("Failed to set TCP_NODELAY: {:?}", e);
+        }
 
         let svc = service.clone();
         tokio::spawn(async move {
-            let stream = tls.accept(stream).await.unwrap();
-            YamuxCtrl::new_server(stream, None, move |stream| {
+            let stream = match tls.accept(stream).await {
+                Ok(stream) => stream,
+                Err(e) => return warn!("Failed to process TLS: {:?}", e),
+            };
+            let client = svc.register_client(Some(addr), peer_identity(&stream));
+            let info = client.info().clone();
+            let tracker = IdleTracker::default();
+            let streams = tracker.clone();
+            let mut ctrl = YamuxCtrl::new_server(stream, None, move |stream| {
                 let svc1 = svc.clone();
+                let info = info.clone();
+                let guard = streams.track();
                 async move {
-                    let stream = ProstServerStream::new(stream.compat(), svc1.clone());
-                    stream.process().await.unwrap();
+                    let _guard = guard;
+                    let stream = ProstServerStream::new(stream.compat(), svc1).with_client(info);
+                    if let Err(e) = stream.process().await {
+                        warn!("Failed to process stream: {:?}", e);
+                    }
                     Ok(())
                 }
             });
+            let idle = async {
+                match idle_timeout {
+                    Some(timeout) => tracker.idle(timeout).await,
+                    None => futures::future::pending().await,
+                }
+            };
+
+            // 连接正常结束，空闲超时，或者被管理命令 kill
+            tokio::select! {
+                _ = ctrl.closed() => {},
+                _ = idle => {
+                    info!("Client {:?} is idle, closing", addr);
+                    ctrl.abort();
+                }
+                _ = client.killed() => {
+                    info!("Client {:?} is killed", addr);
+                    ctrl.abort();
+                }
+            }
+            info!("Client {:?} disconnected", addr);
         });
     }
 }
\ No newline at end of file
@@ -0,0 +1,140 @@
+use crate::KvError;
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, Server,
+};
+use lazy_static::lazy_static;
+use prometheus::{
+    register_histogram, register_histogram_vec, register_int_counter, register_int_counter_vec,
+    register_int_gauge, Encoder, Histogram, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    TextEncoder,
+};
+use std::{convert::Infallible, net::SocketAddr};
+use tracing::info;
+
+lazy_static! {
+    /// 按命令类型和返回状态统计的命令数
+    pub(crate) static ref COMMANDS: IntCounterVec = register_int_counter_vec!(
+        "kv_commands_total",
+        "Number of commands executed, by command and status",
+        &["command", "status"]
+    )
+    .unwrap();
+    /// 每种命令的执行耗时
+    pub(crate) static ref COMMAND_DURATION: HistogramVec = register_histogram_vec!(
+        "kv_command_duration_seconds",
+        "Command execution latency in seconds",
+        &["command"]
+    )
+    .unwrap();
+    /// 当前活跃的连接数
+    pub(crate) static ref ACTIVE_CONNECTIONS: IntGauge =
+        register_int_gauge!("kv_active_connections", "Number of active connections").unwrap();
+    /// 当前活跃的 yamux stream 数
+    pub(crate) static ref ACTIVE_STREAMS: IntGauge =
+        register_int_gauge!("kv_active_streams", "Number of active yamux streams").unwrap();
+    /// Broadcaster 中的主题数
+    pub(crate) static ref TOPICS: IntGauge =
+        register_int_gauge!("kv_topics", "Number of topics in broadcaster").unwrap();
+    /// Broadcaster 中的订阅数
+    pub(crate) static ref SUBSCRIPTIONS: IntGauge =
+        register_int_gauge!("kv_subscriptions", "Number of subscriptions in broadcaster").unwrap();
+    /// 读取的字节数
+    pub(crate) static ref BYTES_IN: IntCounter =
+        register_int_counter!("kv_bytes_in_total", "Bytes read from frames").unwrap();
+    /// 写出的字节数
+    pub(crate) static ref BYTES_OUT: IntCounter =
+        register_int_counter!("kv_bytes_out_total", "Bytes written as frames").unwrap();
+    /// 被压缩的 frame 压缩后与压缩前的大小之比
+    pub(crate) static ref COMPRESSION_RATIO: Histogram = register_histogram!(
+        "kv_frame_compression_ratio",
+        "Compressed size / original size of compressed frames",
+        vec![0.01, 0.05, 0.1, 0.2, 0.3, 0.5, 0.7, 0.9, 1.0, 1.5]
+    )
+    .unwrap();
+}
+
+/// 创建时 +1，drop 时 -1 的 gauge
+pub(crate) struct GaugeGuard(&'static IntGauge);
+
+impl GaugeGuard {
+    pub(crate) fn new(gauge: &'static IntGauge) -> Self {
+        gauge.inc();
+        Self(gauge)
+    }
+}
+
+impl Drop for GaugeGuard {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+// lazy_static 在第一次使用时才会注册，这里提前注册所有的指标
+fn register_all() {
+    lazy_static::initialize(&COMMANDS);
+    lazy_static::initialize(&COMMAND_DURATION);
+    lazy_static::initialize(&ACTIVE_CONNECTIONS);
+    lazy_static::initialize(&ACTIVE_STREAMS);
+    lazy_static::initialize(&TOPICS);
+    lazy_static::initialize(&SUBSCRIPTIONS);
+    lazy_static::initialize(&BYTES_IN);
+    lazy_static::initialize(&BYTES_OUT);
+    lazy_static::initialize(&COMPRESSION_RATIO);
+}
+
+/// 把所有指标以 Prometheus text exposition 格式输出
+pub fn gather_metrics() -> Result<String, KvError> {
+    register_all();
+    let mut buf = Vec::new();
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buf)
+        .map_err(|e| KvError::Internal(e.to_string()))?;
+    String::from_utf8(buf).map_err(|e| KvError::Internal(e.to_string()))
+}
+
+async fn serve_metrics(_req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let res = match gather_metrics() {
+        Ok(body) => Response::builder()
+            .header(CONTENT_TYPE, TextEncoder::new().format_type())
+            .body(Body::from(body)),
+        Err(e) => Response::builder()
+            .status(http::StatusCode::INTERNAL_SERVER_ERROR)
+            .body(Body::from(e.to_string())),
+    };
+    Ok(res.unwrap())
+}
+
+/// 在单独的 HTTP 端口上提供 metrics
+pub async fn start_metrics_server(addr: &str) -> Result<(), KvError> {
+    let addr: SocketAddr = addr
+        .parse()
+        .map_err(|_| KvError::InvalidConfig(format!("invalid metrics addr: {}", addr)))?;
+    let make_svc =
+        make_service_fn(|_conn| async { Ok::<_, Infallible>(service_fn(serve_metrics)) });
+
+    info!("Metrics listening on {}", addr);
+    Server::try_bind(&addr)
+        .map_err(|e| KvError::Internal(e.to_string()))?
+        .serve(make_svc)
+        .await
+        .map_err(|e| KvError::Internal(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gather_metrics_should_work() {
+        COMMANDS.with_label_values(&["hget", "200"]).inc();
+        {
+            let _guard = GaugeGuard::new(&ACTIVE_STREAMS);
+            assert!(ACTIVE_STREAMS.get() >= 1);
+        }
+        let output = gather_metrics().unwrap();
+        assert!(output.contains("kv_commands_total{command=\"hget\",status=\"200\"}"));
+        assert!(output.contains("kv_active_streams"));
+    }
+}
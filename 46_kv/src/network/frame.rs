@@ -1,28 +1,180 @@
-use std::io::{Read, Write};
-
-use crate::{CommandRequest, CommandResponse, KvError};
-use bytes::{Buf, BufMut, BytesMut};
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    str::FromStr,
+};
+
+use crate::{metrics, CommandRequest, CommandResponse, KvError};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use flate2::{read::GzDecoder, write::GzEncoder, Compression};
 use prost::Message;
-use tokio::io::{AsyncRead, AsyncReadExt};
+use serde::{Deserialize, Serialize};
+use tokio_util::codec::Decoder;
 use tracing::debug;
 
 /// 长度整个占用 4 个字节
 pub const LEN_LEN: usize = 4;
-/// 长度占 31 bit，所以最大的 frame 是 2G
-const MAX_FRAME: usize = 2 * 1024 * 1024 * 1024;
+/// 长度占 29 bit，所以协议允许的最大的 frame 是 512M
+pub const MAX_FRAME: usize = LEN_MASK;
+/// 默认的最大 frame，可以在配置中修改，不能超过 MAX_FRAME
+pub const DEFAULT_MAX_FRAME: usize = 16 * 1024 * 1024;
 /// 如果 payload 超过了 1436 字节，就做压缩
-const COMPRESSION_LIMIT: usize = 1436;
+pub const COMPRESSION_LIMIT: usize = 1436;
 /// 代表压缩的 bit（整个长度 4 字节的最高位）
 const COMPRESSION_BIT: usize = 1 << 31;
+/// 压缩算法占压缩 bit 之后的 2 bit，0 是 gzip，这样以前只设置了压缩 bit 的 frame 仍然是 gzip
+const CODEC_SHIFT: usize = 29;
+const CODEC_MASK: usize = 0b11 << CODEC_SHIFT;
+const LEN_MASK: usize = (1 << CODEC_SHIFT) - 1;
+
+/// frame 的压缩算法
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CompressionCodec {
+    /// 不压缩
+    None,
+    #[default]
+    Gzip,
+    Zstd,
+    Lz4,
+}
+
+impl CompressionCodec {
+    /// 支持的所有压缩算法
+    pub const ALL: [CompressionCodec; 4] = [Self::Zstd, Self::Lz4, Self::Gzip, Self::None];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Gzip => "gzip",
+            Self::Zstd => "zstd",
+            Self::Lz4 => "lz4",
+        }
+    }
+
+    // header 中的编号，None 不会出现在 header 中
+    fn id(&self) -> usize {
+        match self {
+            Self::None | Self::Gzip => 0,
+            Self::Zstd => 1,
+            Self::Lz4 => 2,
+        }
+    }
+
+    fn from_id(id: usize) -> Result<Self, KvError> {
+        match id {
+            0 => Ok(Self::Gzip),
+            1 => Ok(Self::Zstd),
+            2 => Ok(Self::Lz4),
+            _ => Err(KvError::FrameError),
+        }
+    }
+
+    fn compress(&self, data: &[u8], buf: BytesMut) -> Result<BytesMut, KvError> {
+        match self {
+            Self::None => {
+                let mut buf = buf;
+                buf.extend_from_slice(data);
+                Ok(buf)
+            }
+            // 处理 gzip 压缩，具体可以参考 flate2 文档
+            Self::Gzip => {
+                let mut encoder = GzEncoder::new(buf.writer(), Compression::default());
+                encoder.write_all(data)?;
+                Ok(encoder.finish()?.into_inner())
+            }
+            Self::Zstd => {
+                let mut encoder = zstd::Encoder::new(buf.writer(), 0)?;
+                encoder.write_all(data)?;
+                Ok(encoder.finish()?.into_inner())
+            }
+            Self::Lz4 => {
+                let mut buf = buf;
+                buf.extend_from_slice(&lz4_flex::compress_prepend_size(data));
+                Ok(buf)
+            }
+        }
+    }
+
+    /// 把 data 解压缩，追加到 buf 中，解压缩之后超过 limit 字节时返回 FrameError
+    fn decompress_into(&self, data: &[u8], buf: &mut BytesMut, limit: usize) -> Result<(), KvError> {
+        let start = buf.len();
+        // 多读一个字节，用来判断是否超过 limit，避免 gzip bomb 一类的数据耗尽内存
+        let take = limit as u64 + 1;
+        match self {
+            Self::None => buf.extend_from_slice(&data[..data.len().min(limit + 1)]),
+            Self::Gzip => {
+                io::copy(&mut GzDecoder::new(data).take(take), &mut buf.writer())?;
+            }
+            Self::Zstd => {
+                io::copy(&mut zstd::Decoder::new(data)?.take(take), &mut buf.writer())?;
+            }
+            Self::Lz4 => {
+                let lz4_error = |e| KvError::Internal(format!("lz4 decompress error: {}", e));
+                let (size, data) = lz4_flex::block::uncompressed_size(data).map_err(lz4_error)?;
+                if size > limit {
+                    return Err(KvError::FrameError);
+                }
+                buf.resize(start + size, 0);
+                let n = lz4_flex::block::decompress_into(data, &mut buf[start..])
+                    .map_err(lz4_error)?;
+                buf.truncate(start + n);
+            }
+        }
+        if buf.len() - start > limit {
+            buf.truncate(start);
+            return Err(KvError::FrameError);
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for CompressionCodec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for CompressionCodec {
+    type Err = KvError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(Self::None),
+            "gzip" => Ok(Self::Gzip),
+            "zstd" => Ok(Self::Zstd),
+            "lz4" => Ok(Self::Lz4),
+            _ => Err(KvError::InvalidConfig(format!("unknown compression codec: {}", s))),
+        }
+    }
+}
+
+/// 在客户端支持的算法中选择一个：优先使用 preferred，否则使用客户端列出的第一个
+pub fn negotiate_codec(preferred: CompressionCodec, codecs: &[String]) -> CompressionCodec {
+    let codecs: Vec<CompressionCodec> = codecs.iter().filter_map(|c| c.parse().ok()).collect();
+    if codecs.contains(&preferred) {
+        preferred
+    } else {
+        codecs.first().copied().unwrap_or(CompressionCodec::None)
+    }
+}
 
 /// 处理 Frame 的 encode/decode
 pub trait FrameCoder
 where
     Self: Message + Sized + Default,
 {
-    /// 把一个 Message encode 成一个 frame
+    /// 把一个 Message encode 成一个 frame，超过 COMPRESSION_LIMIT 时使用 gzip 压缩
     fn encode_frame(&self, buf: &mut BytesMut) -> Result<(), KvError> {
+        self.encode_frame_with(buf, CompressionCodec::Gzip, COMPRESSION_LIMIT)
+    }
+
+    /// 把一个 Message encode 成一个 frame，超过 limit 时使用 codec 压缩
+    fn encode_frame_with(
+        &self,
+        buf: &mut BytesMut,
+        codec: CompressionCodec,
+        limit: usize,
+    ) -> Result<(), KvError> {
         let size = self.encoded_len();
 
         if size > MAX_FRAME {
@@ -30,27 +182,29 @@ where
         }
 
         // 我们先写入长度，如果需要压缩，再重写压缩后的长度
+        // buf 中可能已经有之前的 frame，所以从 start 开始
+        let start = buf.len();
         buf.put_u32(size as _);
 
-        if size > COMPRESSION_LIMIT {
+        if size > limit && codec != CompressionCodec::None {
             let mut buf1 = Vec::with_capacity(size);
             self.encode(&mut buf1)?;
 
             // BytesMut 支持逻辑上的 split（之后还能 unsplit）
-            // 所以我们先把长度这 4 字节拿走，清除
-            let payload = buf.split_off(LEN_LEN);
-            buf.clear();
-
-            // 处理 gzip 压缩，具体可以参考 flate2 文档
-            let mut encoder = GzEncoder::new(payload.writer(), Compression::default());
-            encoder.write_all(&buf1[..])?;
+            // 所以我们先把长度这 4 字节之后的部分拿走，再去掉长度
+            let payload = buf.split_off(start + LEN_LEN);
+            buf.truncate(start);
 
-            // 压缩完成后，从 gzip encoder 中把 BytesMut 再拿回来
-            let payload = encoder.finish()?.into_inner();
-            debug!("Encode a frame: size {}({})", size, payload.len());
+            // 压缩完成后，把 BytesMut 再拿回来
+            let payload = codec.compress(&buf1[..], payload)?;
+            debug!("Encode a frame: size {}({}), codec {}", size, payload.len(), codec);
+            metrics::COMPRESSION_RATIO.observe(payload.len() as f64 / size as f64);
+            if payload.len() > MAX_FRAME {
+                return Err(KvError::FrameError);
+            }
 
-            // 写入压缩后的长度
-            buf.put_u32((payload.len() | COMPRESSION_BIT) as _);
+            // 写入压缩后的长度和压缩算法
+            buf.put_u32((payload.len() | COMPRESSION_BIT | codec.id() << CODEC_SHIFT) as _);
 
             // 把 BytesMut 再合并回来
             buf.unsplit(payload);
@@ -63,24 +217,10 @@ where
     }
     /// 把一个完整的 frame decode 成一个 Message
     fn decode_frame(buf: &mut BytesMut) -> Result<Self, KvError> {
-        // 先取 4 字节，从中拿出长度和 compression bit
-        let header = buf.get_u32() as usize;
-        let (len, compressed) = decode_header(header);
-        debug!("Got a frame: msg len {}, compressed {}", len, compressed);
-
-        if compressed {
-            // 解压缩
-            let mut decoder = GzDecoder::new(&buf[..len]);
-            let mut buf1 = Vec::with_capacity(len * 2);
-            decoder.read_to_end(&mut buf1)?;
-            buf.advance(len);
-
-            // decode 成相应的消息
-            Ok(Self::decode(&buf1[..buf1.len()])?)
-        } else {
-            let msg = Self::decode(&buf[..len])?;
-            buf.advance(len);
-            Ok(msg)
+        let mut decoder = FrameDecoder::default();
+        match decoder.decode(buf)? {
+            Some(payload) => Ok(Self::decode(payload)?),
+            None => Err(KvError::FrameError),
         }
     }
 }
@@ -88,34 +228,101 @@ where
 impl FrameCoder for CommandRequest {}
 impl FrameCoder for CommandResponse {}
 
-fn decode_header(header: usize) -> (usize, bool) {
-    let len = header & !COMPRESSION_BIT;
-    let compressed = header & COMPRESSION_BIT == COMPRESSION_BIT;
-    (len, compressed)
+// 从 header 中拿出长度和压缩算法，没有压缩时压缩算法的 bit 必须为 0
+fn decode_header(header: usize) -> Result<(usize, Option<CompressionCodec>), KvError> {
+    let len = header & LEN_MASK;
+    let codec = (header & CODEC_MASK) >> CODEC_SHIFT;
+    match header & COMPRESSION_BIT == COMPRESSION_BIT {
+        true => Ok((len, Some(CompressionCodec::from_id(codec)?))),
+        false if codec == 0 => Ok((len, None)),
+        false => Err(KvError::FrameError),
+    }
 }
 
-/// 从 stream 中读取一个完整的 frame
-pub async fn read_frame<S>(stream: &mut S, buf: &mut BytesMut) -> Result<(), KvError>
-where
-    S: AsyncRead + Unpin + Send,
-{
-    let header = stream.read_u32().await? as usize;
-    let (len, _compressed) = decode_header(header);
-    // 如果没有这么大的内存，就分配至少一个 frame 的内存，保证它可用
-    buf.reserve(LEN_LEN + len);
-    buf.put_u32(header as _);
-    // advance_mut 是 unsafe 的原因是，从当前位置 pos 到 pos + len，
-    // 这段内存目前没有初始化。我们就是为了 reserve 这段内存，然后从 stream
-    // 里读取，读取完，它就是初始化的。所以，我们这么用是安全的
-    unsafe { buf.advance_mut(len) };
-    stream.read_exact(&mut buf[LEN_LEN..]).await?;
-    Ok(())
+/// frame 的解码器：从读缓存中切出一个完整的 frame，返回解压缩之后的 payload
+///
+/// 没有压缩的 payload 直接从读缓存中切出来，decode 出来的 binary `Value` 和读缓存共享内存，
+/// 不会复制；压缩的 payload 解压缩到 `scratch` 中，payload 被释放之后 `scratch` 的内存会被复用
+///
+/// header 中的长度和解压缩之后的长度都不能超过 `max_frame`，超过时返回 `KvError::FrameError`，
+/// 这样对端不能让我们分配超过 `max_frame` 的内存
+#[derive(Debug)]
+pub struct FrameDecoder {
+    // 已经读到 header，在等待 payload
+    head: Option<(usize, Option<CompressionCodec>)>,
+    // 解压缩的缓存
+    scratch: BytesMut,
+    max_frame: usize,
+}
+
+impl Default for FrameDecoder {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_FRAME)
+    }
+}
+
+impl FrameDecoder {
+    pub fn new(max_frame: usize) -> Self {
+        Self {
+            head: None,
+            scratch: BytesMut::new(),
+            max_frame,
+        }
+    }
+
+    pub fn max_frame(&self) -> usize {
+        self.max_frame
+    }
+
+    pub fn set_max_frame(&mut self, max_frame: usize) {
+        self.max_frame = max_frame;
+    }
+
+    /// 没有读了一半的 frame
+    pub fn is_idle(&self) -> bool {
+        self.head.is_none()
+    }
+}
+
+impl Decoder for FrameDecoder {
+    type Item = Bytes;
+    type Error = KvError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Bytes>, KvError> {
+        let (len, codec) = match self.head {
+            Some(head) => head,
+            None if src.len() < LEN_LEN => return Ok(None),
+            None => {
+                let head = decode_header(src.get_u32() as usize)?;
+                // 在分配内存之前检查长度
+                if head.0 > self.max_frame {
+                    return Err(KvError::FrameError);
+                }
+                self.head = Some(head);
+                head
+            }
+        };
+        if src.len() < len {
+            // 预留整个 frame 的空间，之后的读取不需要再分配内存
+            src.reserve(len - src.len());
+            return Ok(None);
+        }
+        self.head = None;
+        let payload = src.split_to(len);
+        debug!("Got a frame: msg len {}, codec {:?}", len, codec);
+        match codec {
+            Some(codec) => {
+                codec.decompress_into(&payload, &mut self.scratch, self.max_frame)?;
+                Ok(Some(self.scratch.split().freeze()))
+            }
+            None => Ok(Some(payload.freeze())),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::utils::DummyStream;
     use crate::Value;
     use bytes::Bytes;
 
@@ -163,18 +370,113 @@ mod tests {
         assert_eq!(res, res1);
     }
 
-    #[tokio::test]
-    async fn read_frame_should_work() {
+    #[test]
+    fn all_codecs_encode_decode_should_work() {
+        let value: Value = Bytes::from(vec![0u8; 4096]).into();
+        let res: CommandResponse = value.into();
+        for codec in CompressionCodec::ALL {
+            let mut buf = BytesMut::new();
+            res.encode_frame_with(&mut buf, codec, 1024).unwrap();
+            assert_eq!(is_compressed(&buf), codec != CompressionCodec::None);
+
+            let res1 = CommandResponse::decode_frame(&mut buf).unwrap();
+            assert_eq!(res, res1);
+        }
+    }
+
+    #[test]
+    fn legacy_gzip_frame_should_be_decoded() {
+        // 以前的 frame 只设置最高位，payload 是 gzip
+        let value: Value = Bytes::from(vec![1u8; 4096]).into();
+        let res: CommandResponse = value.into();
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&res.encode_to_vec()).unwrap();
+        let payload = encoder.finish().unwrap();
+
         let mut buf = BytesMut::new();
-        let cmd = CommandRequest::new_hdel("t1", "k1");
-        cmd.encode_frame(&mut buf).unwrap();
-        let mut stream = DummyStream { buf };
+        buf.put_u32((payload.len() | COMPRESSION_BIT) as _);
+        buf.extend_from_slice(&payload);
+        assert_eq!(CommandResponse::decode_frame(&mut buf).unwrap(), res);
+    }
+
+    #[test]
+    fn invalid_codec_should_be_rejected() {
+        let mut buf = BytesMut::new();
+        buf.put_u32((COMPRESSION_BIT | 3 << CODEC_SHIFT) as _);
+        assert!(matches!(
+            CommandResponse::decode_frame(&mut buf),
+            Err(KvError::FrameError)
+        ));
+    }
 
-        let mut data = BytesMut::new();
-        read_frame(&mut stream, &mut data).await.unwrap();
+    #[test]
+    fn negotiate_codec_should_work() {
+        let codecs = vec!["lz4".to_string(), "gzip".to_string()];
+        assert_eq!(negotiate_codec(CompressionCodec::Gzip, &codecs), CompressionCodec::Gzip);
+        assert_eq!(negotiate_codec(CompressionCodec::Zstd, &codecs), CompressionCodec::Lz4);
+        assert_eq!(negotiate_codec(CompressionCodec::Zstd, &[]), CompressionCodec::None);
+    }
 
-        let cmd1 = CommandRequest::decode_frame(&mut data).unwrap();
-        assert_eq!(cmd, cmd1);
+    #[test]
+    fn frame_decoder_should_handle_partial_frames() {
+        let value: Value = Bytes::from(vec![0u8; 4096]).into();
+        let res: CommandResponse = value.into();
+        let mut frames = BytesMut::new();
+        res.encode_frame_with(&mut frames, CompressionCodec::None, COMPRESSION_LIMIT)
+            .unwrap();
+        res.encode_frame_with(&mut frames, CompressionCodec::Lz4, COMPRESSION_LIMIT)
+            .unwrap();
+
+        // 每次只给解码器一部分数据
+        let mut decoder = FrameDecoder::default();
+        let mut src = BytesMut::new();
+        let mut decoded = Vec::new();
+        for chunk in frames.chunks(1000) {
+            src.extend_from_slice(chunk);
+            while let Some(payload) = decoder.decode(&mut src).unwrap() {
+                decoded.push(CommandResponse::decode(payload).unwrap());
+            }
+        }
+        assert_eq!(decoded, vec![res.clone(), res]);
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn uncompressed_binary_value_should_not_be_copied() {
+        let value: Value = Bytes::from(vec![7u8; 4096]).into();
+        let res: CommandResponse = value.into();
+        let mut buf = BytesMut::new();
+        res.encode_frame_with(&mut buf, CompressionCodec::None, 0).unwrap();
+        let range = buf.as_ptr_range();
+
+        let res1 = CommandResponse::decode_frame(&mut buf).unwrap();
+        let data = Bytes::try_from(res1.values[0].clone()).unwrap();
+        // binary 指向读缓存里的数据
+        assert!(range.contains(&data.as_ptr()));
+    }
+
+    #[test]
+    fn oversized_frame_should_be_rejected_before_allocation() {
+        let mut src = BytesMut::new();
+        src.put_u32(MAX_FRAME as _);
+        let mut decoder = FrameDecoder::new(1024);
+        assert!(matches!(decoder.decode(&mut src), Err(KvError::FrameError)));
+        // 没有为 frame 预留内存
+        assert!(src.capacity() < 1024);
+    }
+
+    #[test]
+    fn decompression_bomb_should_be_rejected() {
+        let value: Value = Bytes::from(vec![0u8; 1024 * 1024]).into();
+        let res: CommandResponse = value.into();
+        for codec in [CompressionCodec::Gzip, CompressionCodec::Zstd, CompressionCodec::Lz4] {
+            let mut buf = BytesMut::new();
+            res.encode_frame_with(&mut buf, codec, COMPRESSION_LIMIT).unwrap();
+            // 压缩之后很小，但解压缩之后超过了 max_frame
+            assert!(buf.len() < 64 * 1024);
+            let mut decoder = FrameDecoder::new(64 * 1024);
+            assert!(matches!(decoder.decode(&mut buf), Err(KvError::FrameError)));
+        }
     }
 
     fn is_compressed(data: &[u8]) -> bool {
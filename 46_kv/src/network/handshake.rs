@@ -0,0 +1,227 @@
+//! stream 开始时的握手
+//!
+//! 客户端发送 `Hello`，带上协议版本、会使用的命令、支持的压缩算法和能接收的最大 frame，
+//! 服务器检查版本之后，在响应的 pairs 中返回协商的结果。不发送 `Hello` 的客户端按照
+//! 最早的协议处理：gzip 压缩，最大 frame 为 `MAX_FRAME`。
+
+use crate::{
+    negotiate_codec, CommandResponse, CompressionCodec, CompressionConfig, Hello, KvError, Kvpair,
+    Value, MAX_FRAME,
+};
+
+/// 当前的协议版本，abi.proto 有不兼容的修改时增加
+pub const PROTOCOL_VERSION: u32 = 1;
+/// 能兼容的最低协议版本
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// 所有的命令，和 `CommandRequest::command_name` 一致，握手时交换
+pub const COMMANDS: &[&str] = &[
+    "hget",
+    "hgetall",
+    "hmget",
+    "hset",
+    "hmset",
+    "hsetnx",
+    "hcas",
+    "lock",
+    "unlock",
+    "renew_lock",
+    "hdel",
+    "hmdel",
+    "hexist",
+    "hmexist",
+    "hfind",
+    "lpush",
+    "rpush",
+    "lpop",
+    "rpop",
+    "lrange",
+    "fget",
+    "fset",
+    "fdel",
+    "zadd",
+    "zrem",
+    "zscore",
+    "zrank",
+    "zrange",
+    "zrangebyscore",
+    "eval",
+    "subscribe",
+    "unsubscribe",
+    "publish",
+    "list_tables",
+    "dbsize",
+    "info",
+    "client_list",
+    "client_kill",
+    "create_table",
+    "drop_table",
+    "rename_table",
+    "backup",
+    "restore",
+];
+
+/// 握手之后双方协商的能力
+#[derive(Debug, Clone, PartialEq)]
+pub struct Capabilities {
+    /// 协议版本，0 表示对端不支持握手
+    pub version: u32,
+    pub codec: CompressionCodec,
+    /// 双方都支持的命令
+    pub commands: Vec<String>,
+    pub max_frame: usize,
+}
+
+impl Capabilities {
+    /// 不支持握手的对端
+    pub fn legacy() -> Self {
+        Self {
+            version: 0,
+            codec: CompressionCodec::Gzip,
+            commands: COMMANDS.iter().map(|c| c.to_string()).collect(),
+            max_frame: MAX_FRAME,
+        }
+    }
+
+    /// 服务器根据客户端的 Hello 协商，max_frame 是服务器能接收的最大 frame，
+    /// 版本不兼容时返回错误
+    pub fn negotiate(
+        hello: &Hello,
+        config: &CompressionConfig,
+        max_frame: usize,
+    ) -> Result<Self, KvError> {
+        // 双方都使用较低的版本
+        let version = hello.version.min(PROTOCOL_VERSION);
+        if version < MIN_PROTOCOL_VERSION {
+            return Err(incompatible(hello.version));
+        }
+        let commands = COMMANDS
+            .iter()
+            .filter(|c| hello.commands.is_empty() || hello.commands.iter().any(|h| h == *c))
+            .map(|c| c.to_string())
+            .collect();
+        let max_frame = match hello.max_frame as usize {
+            0 => max_frame,
+            n => n.min(max_frame),
+        };
+        Ok(Self {
+            version,
+            codec: negotiate_codec(config.codec, &hello.codecs),
+            commands,
+            max_frame,
+        })
+    }
+
+    /// 对端是否支持这个命令
+    pub fn supports(&self, command: &str) -> bool {
+        self.commands.iter().any(|c| c == command)
+    }
+
+    /// 检查服务器返回的版本，客户端使用
+    pub fn check_version(&self) -> Result<(), KvError> {
+        match self.version {
+            0 => Ok(()),
+            v if (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&v) => Ok(()),
+            v => Err(incompatible(v)),
+        }
+    }
+}
+
+fn incompatible(version: u32) -> KvError {
+    KvError::IncompatibleProtocol(format!(
+        "incompatible protocol version {}, expect {}..={}",
+        version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+    ))
+}
+
+impl From<&Capabilities> for CommandResponse {
+    fn from(caps: &Capabilities) -> Self {
+        let commands: Vec<Value> = caps.commands.iter().map(|c| c.as_str().into()).collect();
+        vec![
+            Kvpair::new("version", (caps.version as i64).into()),
+            Kvpair::new("codec", caps.codec.as_str().into()),
+            Kvpair::new("commands", commands.into()),
+            Kvpair::new("max_frame", (caps.max_frame as i64).into()),
+        ]
+        .into()
+    }
+}
+
+impl TryFrom<CommandResponse> for Capabilities {
+    type Error = KvError;
+
+    fn try_from(res: CommandResponse) -> Result<Self, Self::Error> {
+        let mut caps = Self::legacy();
+        for pair in res.pairs {
+            let value = pair.value.unwrap_or_default();
+            match pair.key.as_str() {
+                "version" => caps.version = i64::try_from(value)? as _,
+                "codec" => caps.codec = String::try_from(value)?.parse()?,
+                "commands" => {
+                    caps.commands = Vec::<Value>::try_from(value)?
+                        .into_iter()
+                        .map(String::try_from)
+                        .collect::<Result<_, _>>()?
+                }
+                "max_frame" => caps.max_frame = i64::try_from(value)? as _,
+                // 新版本的服务器可能返回更多的字段
+                _ => {}
+            }
+        }
+        Ok(caps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hello(version: u32, commands: &[&str], max_frame: u64) -> Hello {
+        Hello {
+            codecs: vec!["lz4".into()],
+            version,
+            commands: commands.iter().map(|c| c.to_string()).collect(),
+            max_frame,
+        }
+    }
+
+    #[test]
+    fn negotiate_should_work() {
+        let config = CompressionConfig::default();
+        let caps = Capabilities::negotiate(&hello(1, &["hget", "xadd"], 1024), &config, MAX_FRAME).unwrap();
+        assert_eq!(caps.version, 1);
+        assert_eq!(caps.codec, CompressionCodec::Lz4);
+        assert_eq!(caps.commands, vec!["hget".to_string()]);
+        assert_eq!(caps.max_frame, 1024);
+
+        // 新版本的客户端降级到服务器的版本
+        let caps = Capabilities::negotiate(&hello(9, &[], 0), &config, MAX_FRAME).unwrap();
+        assert_eq!(caps.version, PROTOCOL_VERSION);
+        assert_eq!(caps.commands.len(), COMMANDS.len());
+        assert_eq!(caps.max_frame, MAX_FRAME);
+    }
+
+    #[test]
+    fn incompatible_version_should_be_rejected() {
+        let config = CompressionConfig::default();
+        let err = Capabilities::negotiate(&hello(0, &[], 0), &config, MAX_FRAME).unwrap_err();
+        let res: CommandResponse = err.into();
+        assert_eq!(res.status, 426);
+
+        let mut caps = Capabilities::legacy();
+        assert!(caps.check_version().is_ok());
+        caps.version = PROTOCOL_VERSION + 1;
+        assert!(caps.check_version().is_err());
+    }
+
+    #[test]
+    fn capabilities_response_should_round_trip() {
+        let config = CompressionConfig::default();
+        let caps = Capabilities::negotiate(&hello(1, &["hget", "hset"], 0), &config, MAX_FRAME).unwrap();
+        let res: CommandResponse = (&caps).into();
+        assert_eq!(res.status, 200);
+        assert_eq!(Capabilities::try_from(res).unwrap(), caps);
+        assert!(caps.supports("hset"));
+        assert!(!caps.supports("eval"));
+    }
+}
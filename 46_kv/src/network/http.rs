@@ -0,0 +1,464 @@
+//! HTTP/JSON 网关
+//!
+//! 把 REST 请求转换成 `CommandRequest`，通过同一个 `Service` 执行：
+//!
+//! ```text
+//! GET    /tables                 ListTables（需要 admin 角色）
+//! GET    /tables/:t/keys         Hgetall，返回 JSON object
+//! GET    /tables/:t/keys/:k      Hget
+//! PUT    /tables/:t/keys/:k      Hset，body 是 value 的 JSON，返回旧的 value
+//! DELETE /tables/:t/keys/:k      Hdel，返回被删除的 value
+//! POST   /topics/:name           Publish，body 是 value 的 JSON 数组
+//! GET    /topics/:name           Subscribe，以 Server-Sent Events 的方式推送
+//! ```
+//!
+//! Value 和 JSON 的对应关系：string / integer / float / bool / list / map 对应 JSON 中同样的类型，
+//! null 和没有设置的 value 对应 `null`，binary 表示为 `{"$binary": "<base64>"}`，
+//! timestamp 表示为 `{"$timestamp": <unix ms>}`。出错时返回 `{"error": "<message>"}`。
+
+use crate::{
+    peer_identity, value, ClientInfo, CommandRequest, CommandResponse, KvError, Service, Storage,
+    TlsServerAcceptor, Value,
+};
+use bytes::Bytes;
+use futures::StreamExt;
+use hyper::{
+    body::HttpBody,
+    header::{CACHE_CONTROL, CONTENT_TYPE},
+    server::conn::Http,
+    service::service_fn,
+    Body, Method, Request, Response, StatusCode,
+};
+use serde_json::{json, Map, Number};
+use std::{collections::BTreeMap, convert::Infallible, net::SocketAddr};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+};
+use tracing::{info, warn};
+
+/// HTTP 网关的 ALPN
+pub(crate) const ALPN_HTTP: &str = "http/1.1";
+/// 请求 body 的最大长度
+const MAX_BODY_SIZE: usize = 4 * 1024 * 1024;
+
+/// 把 Value 转换成 JSON
+pub fn value_to_json(v: &Value) -> serde_json::Value {
+    match &v.value {
+        None | Some(value::Value::Null(_)) => serde_json::Value::Null,
+        Some(value::Value::String(s)) => s.clone().into(),
+        Some(value::Value::Integer(i)) => (*i).into(),
+        Some(value::Value::Float(f)) => (*f).into(),
+        Some(value::Value::Bool(b)) => (*b).into(),
+        Some(value::Value::Binary(b)) => json!({ "$binary": base64::encode(b) }),
+        Some(value::Value::Timestamp(ms)) => json!({ "$timestamp": ms }),
+        Some(value::Value::List(list)) => list.values.iter().map(value_to_json).collect(),
+        Some(value::Value::Map(map)) => map
+            .fields
+            .iter()
+            .map(|(k, v)| (k.clone(), value_to_json(v)))
+            .collect::<Map<_, _>>()
+            .into(),
+    }
+}
+
+/// 把 JSON 转换成 Value
+pub fn json_to_value(v: serde_json::Value) -> Result<Value, KvError> {
+    use serde_json::Value as Json;
+    let invalid = |msg: &str| KvError::InvalidCommand(msg.into());
+    Ok(match v {
+        Json::Null => Value::null(),
+        Json::Bool(b) => b.into(),
+        Json::Number(n) => number_to_value(&n),
+        Json::String(s) => s.into(),
+        Json::Array(values) => values
+            .into_iter()
+            .map(json_to_value)
+            .collect::<Result<Vec<_>, _>>()?
+            .into(),
+        Json::Object(mut map) if map.len() == 1 && map.contains_key("$binary") => {
+            match map.remove("$binary") {
+                Some(Json::String(s)) => {
+                    let data = base64::decode(s).map_err(|_| invalid("$binary must be base64"))?;
+                    Bytes::from(data).into()
+                }
+                _ => return Err(invalid("$binary must be a string")),
+            }
+        }
+        Json::Object(mut map) if map.len() == 1 && map.contains_key("$timestamp") => {
+            match map.remove("$timestamp").and_then(|v| v.as_i64()) {
+                Some(ms) => Value::timestamp(ms),
+                None => return Err(invalid("$timestamp must be an integer")),
+            }
+        }
+        Json::Object(map) => map
+            .into_iter()
+            .map(|(k, v)| Ok((k, json_to_value(v)?)))
+            .collect::<Result<BTreeMap<_, _>, KvError>>()?
+            .into(),
+    })
+}
+
+fn number_to_value(n: &Number) -> Value {
+    match n.as_i64() {
+        Some(i) => i.into(),
+        None => n.as_f64().unwrap_or(f64::NAN).into(),
+    }
+}
+
+/// 成功的响应如何渲染成 JSON
+enum Render {
+    /// 第一个 value
+    Value,
+    /// 所有的 value 组成的数组
+    Values,
+    /// 所有的 kv pair 组成的 object
+    Pairs,
+}
+
+fn json_response(status: StatusCode, body: serde_json::Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+fn error_response(status: StatusCode, msg: impl Into<String>) -> Response<Body> {
+    json_response(status, json!({ "error": msg.into() }))
+}
+
+fn render(res: &CommandResponse, render: Render) -> Response<Body> {
+    let status = StatusCode::from_u16(res.status as u16).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    if status != StatusCode::OK {
+        return error_response(status, &res.message);
+    }
+    let body = match render {
+        Render::Value => res
+            .values
+            .first()
+            .map(value_to_json)
+            .unwrap_or(serde_json::Value::Null),
+        Render::Values => res.values.iter().map(value_to_json).collect(),
+        Render::Pairs => res
+            .pairs
+            .iter()
+            .map(|p| {
+                let v = p.value.as_ref().map(value_to_json).unwrap_or_default();
+                (p.key.clone(), v)
+            })
+            .collect::<Map<_, _>>()
+            .into(),
+    };
+    json_response(status, body)
+}
+
+// 读取 body 并解析成 JSON，body 超过 MAX_BODY_SIZE 时返回错误
+async fn read_json(mut body: Body) -> Result<serde_json::Value, KvError> {
+    let mut buf = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|e| KvError::Internal(e.to_string()))?;
+        if buf.len() + chunk.len() > MAX_BODY_SIZE {
+            return Err(KvError::InvalidCommand("request body is too large".into()));
+        }
+        buf.extend_from_slice(&chunk);
+    }
+    serde_json::from_slice(&buf).map_err(|e| KvError::InvalidCommand(e.to_string()))
+}
+
+// 路径中的 %XX 转义
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut buf = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(b)) => {
+                buf.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                buf.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&buf).into_owned()
+}
+
+async fn execute<Store: Storage>(
+    svc: &Service<Store>,
+    client: &ClientInfo,
+    cmd: CommandRequest,
+) -> CommandResponse {
+    let mut stream = svc.execute_as(cmd, Some(client));
+    match stream.next().await {
+        Some(res) => (*res).clone(),
+        None => KvError::Internal("no response".into()).into(),
+    }
+}
+
+// 订阅主题，第一个事件是 subscribed，data 是订阅的 id，之后每条消息是一个 message 事件
+fn subscribe<Store: Storage>(svc: &Service<Store>, client: &ClientInfo, topic: &str) -> Response<Body> {
+    let mut stream = svc.execute_as(CommandRequest::new_subscribe(topic), Some(client));
+    let (mut tx, body) = Body::channel();
+    tokio::spawn(async move {
+        let mut first = true;
+        while let Some(res) = stream.next().await {
+            let event = match (res.status, first) {
+                (200, true) => format!("event: subscribed\ndata: {}\n\n", render_values(&res)),
+                (200, false) => format!("event: message\ndata: {}\n\n", render_values(&res)),
+                _ => format!("event: error\ndata: {}\n\n", json!({ "error": res.message })),
+            };
+            first = false;
+            // 客户端断开后停止推送，订阅在下一次 publish 时被清理
+            if tx.send_data(event.into()).await.is_err() {
+                break;
+            }
+        }
+    });
+    Response::builder()
+        .header(CONTENT_TYPE, "text/event-stream")
+        .header(CACHE_CONTROL, "no-cache")
+        .body(body)
+        .unwrap()
+}
+
+// 只有一个 value 时直接输出这个 value，否则输出数组
+fn render_values(res: &CommandResponse) -> serde_json::Value {
+    match res.values.as_slice() {
+        [v] => value_to_json(v),
+        values => values.iter().map(value_to_json).collect(),
+    }
+}
+
+async fn handle<Store: Storage>(
+    req: Request<Body>,
+    svc: &Service<Store>,
+    client: &ClientInfo,
+) -> Response<Body> {
+    let path: Vec<String> = req
+        .uri()
+        .path()
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .map(percent_decode)
+        .collect();
+    let path: Vec<&str> = path.iter().map(|s| s.as_str()).collect();
+    let method = req.method().clone();
+
+    let (cmd, how) = match (&method, path.as_slice()) {
+        (&Method::GET, ["tables"]) => (CommandRequest::new_list_tables(), Render::Values),
+        (&Method::GET, ["tables", t, "keys"]) => (CommandRequest::new_hgetall(*t), Render::Pairs),
+        (&Method::GET, ["tables", t, "keys", k]) => (CommandRequest::new_hget(*t, *k), Render::Value),
+        (&Method::DELETE, ["tables", t, "keys", k]) => {
+            (CommandRequest::new_hdel(*t, *k), Render::Value)
+        }
+        (&Method::PUT, ["tables", t, "keys", k]) => {
+            let value = match read_json(req.into_body()).await.and_then(json_to_value) {
+                Ok(v) => v,
+                Err(e) => return render(&e.into(), Render::Value),
+            };
+            (CommandRequest::new_hset(*t, *k, value), Render::Value)
+        }
+        (&Method::POST, ["topics", topic]) => {
+            let values = match read_json(req.into_body()).await.and_then(json_to_value) {
+                Ok(Value {
+                    value: Some(value::Value::List(list)),
+                }) => list.values,
+                Ok(v) => vec![v],
+                Err(e) => return render(&e.into(), Render::Value),
+            };
+            (CommandRequest::new_publish(*topic, values), Render::Value)
+        }
+        (&Method::GET, ["topics", topic]) => return subscribe(svc, client, topic),
+        (_, ["tables", ..]) | (_, ["topics", ..]) => {
+            return error_response(StatusCode::METHOD_NOT_ALLOWED, "method not allowed")
+        }
+        _ => return error_response(StatusCode::NOT_FOUND, "no such route"),
+    };
+
+    render(&execute(svc, client, cmd).await, how)
+}
+
+/// 在一个连接上提供 HTTP 网关，连接上所有的请求都以同一个客户端的身份执行
+pub async fn serve_http<S, Store>(
+    stream: S,
+    svc: Service<Store>,
+    peer: Option<SocketAddr>,
+    identity: Option<String>,
+) -> Result<(), KvError>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    Store: Storage,
+{
+    let client = svc.register_client(peer, identity);
+    let info = client.info().clone();
+    let service = service_fn(move |req| {
+        let svc = svc.clone();
+        let info = info.clone();
+        async move { Ok::<_, Infallible>(handle(req, &svc, &info).await) }
+    });
+    let conn = Http::new().http1_only(true).serve_connection(stream, service);
+
+    // 连接正常结束，或者被管理命令 kill
+    tokio::select! {
+        result = conn => result.map_err(|e| KvError::Internal(e.to_string())),
+        _ = client.killed() => Ok(()),
+    }
+}
+
+/// 启动 HTTP 网关，和原生的监听端口使用同样的 TLS 证书和客户端认证
+pub async fn start_http_gateway<Store: Storage>(
+    addr: &str,
+    svc: Service<Store>,
+    acceptor: TlsServerAcceptor,
+) -> Result<(), KvError> {
+    let acceptor = acceptor.with_protocols(&[ALPN_HTTP]);
+    let listener = TcpListener::bind(addr).await?;
+    info!("HTTP gateway listening on {}", addr);
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let svc = svc.clone();
+        let acceptor = acceptor.clone();
+        tokio::spawn(async move {
+            let stream = match acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(e) => return warn!("Failed to process TLS: {:?}", e),
+            };
+            let identity = peer_identity(&stream);
+            if let Err(e) = serve_http(stream, svc, Some(peer), identity).await {
+                warn!("HTTP connection {} failed: {:?}", peer, e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Acl, MemTable, ServiceInner};
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt, DuplexStream};
+
+    fn value_json_pairs() -> Vec<(Value, serde_json::Value)> {
+        let mut map = BTreeMap::new();
+        map.insert("name".to_string(), Value::from("tyr"));
+        vec![
+            (Value::null(), json!(null)),
+            ("hello".into(), json!("hello")),
+            (10.into(), json!(10)),
+            (1.5.into(), json!(1.5)),
+            (true.into(), json!(true)),
+            (b"\x00\x01".into(), json!({ "$binary": "AAE=" })),
+            (Value::timestamp(1000), json!({ "$timestamp": 1000 })),
+            (vec![Value::from(1), "a".into()].into(), json!([1, "a"])),
+            (map.into(), json!({ "name": "tyr" })),
+        ]
+    }
+
+    #[test]
+    fn value_json_conversion_should_work() {
+        for (value, json) in value_json_pairs() {
+            assert_eq!(value_to_json(&value), json);
+            assert_eq!(json_to_value(json).unwrap(), value);
+        }
+        assert!(json_to_value(json!({ "$binary": 1 })).is_err());
+    }
+
+    #[test]
+    fn percent_decode_should_work() {
+        assert_eq!(percent_decode("a%20b%2Fc"), "a b/c");
+        assert_eq!(percent_decode("100%"), "100%");
+    }
+
+    // 在 duplex stream 上启动网关，返回客户端一端
+    fn start(admin: bool) -> DuplexStream {
+        let svc: Service = ServiceInner::new(MemTable::new())
+            .acl(Acl::new(["admin"]))
+            .into();
+        let (client, server) = duplex(64 * 1024);
+        let identity = admin.then(|| "admin".to_string());
+        tokio::spawn(serve_http(server, svc, None, identity));
+        client
+    }
+
+    async fn request(stream: &mut DuplexStream, method: &str, path: &str, body: &str) -> String {
+        let req = format!(
+            "{} {} HTTP/1.1\r\nhost: localhost\r\ncontent-length: {}\r\n\r\n{}",
+            method,
+            path,
+            body.len(),
+            body
+        );
+        stream.write_all(req.as_bytes()).await.unwrap();
+        let mut buf = vec![0; 4096];
+        let n = stream.read(&mut buf).await.unwrap();
+        String::from_utf8_lossy(&buf[..n]).into_owned()
+    }
+
+    #[tokio::test]
+    async fn rest_routes_should_work() {
+        let mut stream = start(false);
+        let res = request(&mut stream, "PUT", "/tables/t1/keys/k1", r#"{"a": [1, 2]}"#).await;
+        assert!(res.starts_with("HTTP/1.1 200"));
+        assert!(res.ends_with("null"));
+
+        let res = request(&mut stream, "GET", "/tables/t1/keys/k1", "").await;
+        assert!(res.ends_with(r#"{"a":[1,2]}"#));
+        let res = request(&mut stream, "GET", "/tables/t1/keys", "").await;
+        assert!(res.ends_with(r#"{"k1":{"a":[1,2]}}"#));
+
+        let res = request(&mut stream, "DELETE", "/tables/t1/keys/k1", "").await;
+        assert!(res.starts_with("HTTP/1.1 200"));
+        let res = request(&mut stream, "GET", "/tables/t1/keys/k1", "").await;
+        assert!(res.starts_with("HTTP/1.1 404"));
+
+        let res = request(&mut stream, "PUT", "/tables/t1/keys/k1", "{").await;
+        assert!(res.starts_with("HTTP/1.1 400"));
+        let res = request(&mut stream, "GET", "/nowhere", "").await;
+        assert!(res.starts_with("HTTP/1.1 404"));
+    }
+
+    #[tokio::test]
+    async fn admin_routes_should_follow_acl() {
+        let mut stream = start(false);
+        let res = request(&mut stream, "GET", "/tables", "").await;
+        assert!(res.starts_with("HTTP/1.1 403"));
+
+        let mut stream = start(true);
+        let res = request(&mut stream, "GET", "/tables", "").await;
+        assert!(res.starts_with("HTTP/1.1 200"));
+    }
+
+    #[tokio::test]
+    async fn topics_should_be_server_sent_events() {
+        let svc: Service = ServiceInner::new(MemTable::new()).into();
+        let (mut client, server) = duplex(64 * 1024);
+        tokio::spawn(serve_http(server, svc.clone(), None, None));
+
+        let req = "GET /topics/lobby HTTP/1.1\r\nhost: localhost\r\n\r\n";
+        client.write_all(req.as_bytes()).await.unwrap();
+        let mut buf = vec![0; 4096];
+        let mut received = String::new();
+        while !received.contains("event: subscribed") {
+            let n = client.read(&mut buf).await.unwrap();
+            received.push_str(&String::from_utf8_lossy(&buf[..n]));
+        }
+        assert!(received.contains("text/event-stream"));
+
+        let (mut publisher, server) = duplex(64 * 1024);
+        tokio::spawn(serve_http(server, svc, None, None));
+        let res = request(&mut publisher, "POST", "/topics/lobby", r#"["hello"]"#).await;
+        assert!(res.starts_with("HTTP/1.1 200"));
+
+        while !received.contains("event: message") {
+            let n = client.read(&mut buf).await.unwrap();
+            received.push_str(&String::from_utf8_lossy(&buf[..n]));
+        }
+        assert!(received.contains("data: \"hello\""));
+    }
+}
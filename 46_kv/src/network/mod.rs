@@ -1,30 +1,62 @@
 mod frame;
+mod handshake;
+mod http;
 mod multiplex;
+mod quic;
+mod resp;
 mod stream;
 mod tls;
 mod stream_result;
-
-pub use frame::{read_frame, FrameCoder};
+mod ws;
+
+pub use frame::{
+    negotiate_codec, CompressionCodec, FrameCoder, FrameDecoder, COMPRESSION_LIMIT,
+    DEFAULT_MAX_FRAME, LEN_LEN, MAX_FRAME,
+};
+pub use handshake::*;
+pub use http::*;
 pub use multiplex::*;
+pub use quic::*;
+pub use resp::*;
 pub use stream::*;
 pub use tls::*;
 pub use stream_result::*;
+pub use ws::*;
+
 
+use crate::{
+    command_request::RequestData, ClientInfo, CommandRequest, CommandResponse, CompressionConfig,
+    KvError, Service, Storage,
+};
 
-use crate::{CommandRequest, CommandResponse, KvError, Service, Storage};
+/// 客户端请求默认的 deadline
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+// 客户端比服务器多等待一段时间，让服务器有机会返回 504
+const TIMEOUT_GRACE: Duration = Duration::from_secs(1);
 use futures::{SinkExt, StreamExt};
-use tokio::io::{AsyncRead, AsyncWrite};
-use tracing::info;
+use std::{borrow::Cow, slice, sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    time::{self, Instant},
+};
+use tracing::{info, warn};
 
 /// 处理服务器端的某个accept下来的socket的读写
 pub struct ProstServerStream<S, Store> {
     inner: ProstStream<S, CommandRequest, CommandResponse>,
     service: Service<Store>,
+    client: Option<ClientInfo>,
 }
 
 /// 处理客户端socket的读写
 pub struct ProstClientStream<S> {
     inner: ProstStream<S, CommandResponse, CommandRequest>,
+    // 握手之后协商的能力
+    capabilities: Option<Capabilities>,
+    // 没有设置 deadline 的请求使用的 deadline
+    timeout: Option<Duration>,
+    // 超时之后响应可能还在路上，这个 stream 不能再使用
+    timed_out: bool,
 }
 
 impl<S, Store> ProstServerStream<S, Store>
@@ -33,19 +65,90 @@ where
     Store: Storage,
 {
     pub fn new(stream: S, service: Service<Store>) -> Self {
+        // 协商之前使用 gzip，兼容不发送 Hello 的客户端
+        let mut inner = ProstStream::new(stream);
+        inner.set_compression(CompressionCodec::Gzip, service.compression().limit);
+        inner.set_max_frame(service.max_frame());
         Self {
-            inner: ProstStream::new(stream),
+            inner,
             service,
+            client: None,
         }
     }
 
+    /// 以某个客户端的身份处理命令
+    pub fn with_client(mut self, client: ClientInfo) -> Self {
+        self.client = Some(client);
+        self
+    }
+
     pub async fn process(mut self) -> Result<(), KvError> {
+        // stream 结束或者 future 被 drop（连接断开）时，释放这个 stream 上获取的锁
+        let locks = self.service.lock_session();
         let stream = &mut self.inner;
-        while let Some(Ok(cmd)) = stream.next().await {
+        let idle = self.service.timeout().stream_idle();
+        loop {
+            // 超过 stream_idle_ms 没有收到命令时结束这个 stream
+            let cmd = match idle {
+                Some(idle) => match time::timeout(idle, stream.next()).await {
+                    Ok(cmd) => cmd,
+                    Err(_) => {
+                        info!("Stream is idle for {:?}, closing", idle);
+                        break;
+                    }
+                },
+                None => stream.next().await,
+            };
+            let cmd = match cmd {
+                None => break,
+                Some(Ok(cmd)) => cmd,
+                Some(Err(e)) => {
+                    // 返回错误给客户端，而不是直接断开。只有 protobuf 解析出错时 stream 还能继续，
+                    // 其它错误（frame 太大，连接中断等）之后无法再找到下一个 frame 的开始
+                    warn!("Failed to read command: {:?}", e);
+                    let fatal = !matches!(e, KvError::DecodeError(_));
+                    let _ = stream.send(&e.into()).await;
+                    match fatal {
+                        true => break,
+                        false => continue,
+                    }
+                }
+            };
             info!("Got a new command: {:?}", cmd);
-            let mut res = self.service.execute(cmd);
+            // Hello 由 stream 处理：回复协商的结果，之后的 frame 都使用协商的 codec
+            if let Some(RequestData::Hello(hello)) = &cmd.request_data {
+                let config = self.service.compression();
+                match Capabilities::negotiate(hello, config, stream.max_frame()) {
+                    Ok(caps) => {
+                        stream.send(&(&caps).into()).await?;
+                        stream.set_compression(caps.codec, config.limit);
+                        stream.set_send_limit(caps.max_frame);
+                        continue;
+                    }
+                    // 版本不兼容，返回错误后结束这个 stream
+                    Err(e) => {
+                        stream.send(&e.into()).await?;
+                        break;
+                    }
+                }
+            }
+            let deadline = cmd.deadline().map(Instant::from_std);
+            let mut res = self.service.execute_in(cmd, self.client.as_ref(), Some(&locks));
+            // deadline 只限制第一个响应，订阅之后的消息不受限制
+            let first = match deadline {
+                Some(deadline) => time::timeout_at(deadline, res.next())
+                    .await
+                    .unwrap_or_else(|_| Some(Arc::new(KvError::Timeout.into()))),
+                None => res.next().await,
+            };
+            if let Some(data) = first {
+                send_response(stream, &data).await?;
+                if data.status == 504 {
+                    continue;
+                }
+            }
             while let Some(data) = res.next().await {
-                stream.send(&data).await.unwrap();
+                send_response(stream, &data).await?;
             }
         }
 
@@ -53,6 +156,23 @@ where
     }
 }
 
+// 响应超过了对端能接收的最大 frame 时返回 413
+async fn send_response<S>(
+    stream: &mut ProstStream<S, CommandRequest, CommandResponse>,
+    res: &CommandResponse,
+) -> Result<(), KvError>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    match stream.send(res).await {
+        Err(KvError::FrameError) => {
+            warn!("Response is larger than the max frame of the client");
+            stream.send(&KvError::FrameError.into()).await
+        }
+        v => v,
+    }
+}
+
 impl<S> ProstClientStream<S>
 where
     S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
@@ -60,26 +180,118 @@ where
     pub fn new(stream: S) -> Self {
         Self {
             inner: ProstStream::new(stream),
+            capabilities: None,
+            timeout: Some(DEFAULT_REQUEST_TIMEOUT),
+            timed_out: false,
         }
     }
 
+    /// 设置请求默认的 deadline，None 表示不超时
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// 设置能接收的最大 frame，握手时会发给服务器
+    pub fn with_max_frame(mut self, max_frame: usize) -> Self {
+        self.inner.set_max_frame(max_frame);
+        self
+    }
+
+    /// 在 stream 开始时握手，返回协商的能力
+    ///
+    /// 优先使用配置的 codec；服务器不支持握手时继续使用 gzip，版本不兼容时返回错误
+    pub async fn hello(&mut self, config: &CompressionConfig) -> Result<Capabilities, KvError> {
+        let codecs = std::iter::once(config.codec)
+            .chain(CompressionCodec::ALL.into_iter().filter(|c| *c != config.codec))
+            .map(|c| c.as_str().to_string())
+            .collect();
+        let cmd = CommandRequest::new_hello(codecs, self.inner.max_frame());
+        let res = self.execute_unary(&cmd).await?;
+        let caps = match res.status {
+            200 => Capabilities::try_from(res)?,
+            426 => return Err(KvError::IncompatibleProtocol(res.message)),
+            _ => Capabilities::legacy(),
+        };
+        caps.check_version()?;
+        self.inner.set_compression(caps.codec, config.limit);
+        self.capabilities = Some(caps.clone());
+        Ok(caps)
+    }
+
+    /// 握手之后协商的能力，没有握手时返回 None
+    pub fn capabilities(&self) -> Option<&Capabilities> {
+        self.capabilities.as_ref()
+    }
+
     pub async fn execute(&mut self, cmd: CommandRequest) -> Result<CommandResponse, KvError> {
-        let stream = &mut self.inner;
-        stream.send(&cmd).await?;
+        self.execute_unary(&cmd).await
+    }
+
+    /// 发送一个请求并等待响应
+    ///
+    /// 请求没有设置 deadline 时使用 stream 默认的 deadline，服务器在 deadline 之后返回 504，
+    /// 客户端等不到响应时返回 `KvError::Timeout`，之后这个 stream 不能再使用
+    pub async fn execute_unary(&mut self, cmd: &CommandRequest) -> Result<CommandResponse, KvError> {
+        let mut res = self.execute_pipeline(slice::from_ref(cmd)).await?;
+        Ok(res.remove(0))
+    }
 
-        match stream.next().await {
-            Some(v) => v,
-            None => Err(KvError::Internal("Didn't get any response".into())),
+    /// 先发送所有的请求，再按顺序读取它们的响应（pipelining）
+    ///
+    /// 每个请求只能有一个响应，不能用于订阅。超时的规则和 execute_unary 相同，
+    /// 客户端等待的时间是这些请求中最长的 deadline
+    pub async fn execute_pipeline(
+        &mut self,
+        cmds: &[CommandRequest],
+    ) -> Result<Vec<CommandResponse>, KvError> {
+        if self.timed_out {
+            return Err(KvError::Timeout);
+        }
+        let cmds: Vec<_> = cmds
+            .iter()
+            .map(|cmd| match (cmd.timeout(), self.timeout) {
+                (None, Some(t)) => Cow::Owned(cmd.clone().with_timeout(t)),
+                _ => Cow::Borrowed(cmd),
+            })
+            .collect();
+        // 有一个请求没有 deadline 时一直等待
+        let timeout = cmds
+            .iter()
+            .map(|cmd| cmd.timeout())
+            .collect::<Option<Vec<_>>>()
+            .and_then(|timeouts| timeouts.into_iter().max());
+        let fut = Self::send_and_recv(&mut self.inner, &cmds);
+        let timeout = match timeout {
+            Some(timeout) => timeout + TIMEOUT_GRACE,
+            None => return fut.await,
+        };
+        match time::timeout(timeout, fut).await {
+            Ok(res) => res,
+            Err(_) => {
+                self.timed_out = true;
+                Err(KvError::Timeout)
+            }
         }
     }
-    pub async fn execute_unary(&mut self, cmd: &CommandRequest) -> Result<CommandResponse, KvError> {
-        let stream = &mut self.inner;
-        stream.send(cmd).await?;
 
-        match stream.next().await {
-            Some(v) => v,
-            None => Err(KvError::Internal("Didn't get any response".into())),
+    async fn send_and_recv(
+        stream: &mut ProstStream<S, CommandResponse, CommandRequest>,
+        cmds: &[Cow<'_, CommandRequest>],
+    ) -> Result<Vec<CommandResponse>, KvError> {
+        for cmd in cmds {
+            stream.feed(cmd.as_ref()).await?;
         }
+        stream.flush().await?;
+
+        let mut res = Vec::with_capacity(cmds.len());
+        for _ in cmds {
+            match stream.next().await {
+                Some(v) => res.push(v?),
+                None => return Err(KvError::Internal("Didn't get any response".into())),
+            }
+        }
+        Ok(res)
     }
 
     pub async fn execute_streaming(self, cmd: &CommandRequest) -> Result<StreamResult, KvError> {
@@ -99,7 +311,7 @@ mod tests {
     use std::net::SocketAddr;
     use tokio::net::{TcpListener, TcpStream};
 
-    use crate::{assert_res_ok, MemTable, ServiceInner, Value};
+    use crate::{assert_res_ok, MemTable, ServiceInner, TimeoutConfig, Value};
 
     use super::*;
 
@@ -149,6 +361,159 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn client_server_hello_should_negotiate_codec() -> anyhow::Result<()> {
+        let addr = start_server().await?;
+
+        let stream = TcpStream::connect(addr).await?;
+        let mut client = ProstClientStream::new(stream);
+
+        // 服务器默认使用 gzip，客户端只支持 lz4 时使用 lz4
+        let hello = CommandRequest::new_hello(vec!["lz4".into()], MAX_FRAME);
+        let res = client.execute_unary(&hello).await?;
+        let caps = Capabilities::try_from(res)?;
+        assert_eq!(caps.codec, CompressionCodec::Lz4);
+        client.inner.set_compression(CompressionCodec::Lz4, COMPRESSION_LIMIT);
+
+        let v: Value = Bytes::from(vec![0u8; 16384]).into();
+        let cmd = CommandRequest::new_hset("t2", "k2", v.clone());
+        assert_res_ok(&client.execute(cmd).await?, &[Value::default()], &[]);
+        let cmd = CommandRequest::new_hget("t2", "k2");
+        assert_res_ok(&client.execute(cmd).await?, &[v], &[]);
+
+        // hello 返回协商的 codec
+        let stream = TcpStream::connect(addr).await?;
+        let mut client = ProstClientStream::new(stream);
+        let config = CompressionConfig {
+            codec: CompressionCodec::None,
+            limit: 0,
+        };
+        let caps = client.hello(&config).await?;
+        assert_eq!(caps.codec, CompressionCodec::Gzip);
+        assert_eq!(caps.version, PROTOCOL_VERSION);
+        assert_eq!(client.capabilities(), Some(&caps));
+        // limit 为 0 时所有 frame 都压缩
+        let cmd = CommandRequest::new_hset("t1", "k1", "v1".into());
+        assert_res_ok(&client.execute(cmd).await?, &[Value::default()], &[]);
+        let cmd = CommandRequest::new_hget("t1", "k1");
+        assert_res_ok(&client.execute(cmd).await?, &["v1".into()], &[]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn incompatible_client_should_be_rejected() -> anyhow::Result<()> {
+        let addr = start_server().await?;
+
+        let stream = TcpStream::connect(addr).await?;
+        let mut client = ProstClientStream::new(stream);
+        let mut hello = CommandRequest::new_hello(vec![], MAX_FRAME);
+        if let Some(RequestData::Hello(hello)) = &mut hello.request_data {
+            hello.version = MIN_PROTOCOL_VERSION - 1;
+        }
+        let res = client.execute_unary(&hello).await?;
+        assert_eq!(res.status, 426);
+        // 服务器结束了这个 stream
+        let cmd = CommandRequest::new_hget("t1", "k1");
+        assert!(client.execute(cmd).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn oversized_frame_should_get_error_response() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let service: Service = ServiceInner::new(MemTable::new()).max_frame(1024).into();
+            ProstServerStream::new(stream, service).process().await
+        });
+
+        let stream = TcpStream::connect(addr).await?;
+        let mut client = ProstClientStream::new(stream);
+        let caps = client.hello(&CompressionConfig::default()).await?;
+        assert_eq!(caps.max_frame, 1024);
+
+        // 服务器返回 413 之后关闭 stream
+        let v: Value = Bytes::from((0..4096).map(|i| i as u8).collect::<Vec<_>>()).into();
+        let cmd = CommandRequest::new_hset("t1", "k1", v);
+        let res = client.execute(cmd).await?;
+        assert_eq!(res.status, 413);
+        let cmd = CommandRequest::new_hget("t1", "k1");
+        assert!(client.execute(cmd).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn oversized_response_should_get_error_response() -> anyhow::Result<()> {
+        let addr = start_server().await?;
+
+        let stream = TcpStream::connect(addr).await?;
+        let mut client = ProstClientStream::new(stream).with_max_frame(1024);
+        let caps = client.hello(&CompressionConfig::default()).await?;
+        assert_eq!(caps.max_frame, 1024);
+
+        let v: Value = Bytes::from(vec![0u8; 4096]).into();
+        let cmd = CommandRequest::new_hset("t1", "k1", v);
+        assert_res_ok(&client.execute(cmd).await?, &[Value::default()], &[]);
+        // 响应超过了客户端的 max_frame，服务器返回 413，stream 可以继续使用
+        let res = client.execute(CommandRequest::new_hget("t1", "k1")).await?;
+        assert_eq!(res.status, 413);
+        let cmd = CommandRequest::new_hset("t1", "k2", "v2".into());
+        assert_res_ok(&client.execute(cmd).await?, &[Value::default()], &[]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn idle_stream_should_be_closed() -> anyhow::Result<()> {
+        let (client, server) = tokio::io::duplex(4096);
+        let timeout = TimeoutConfig {
+            stream_idle_ms: 50,
+            ..Default::default()
+        };
+        let service: Service = ServiceInner::new(MemTable::new()).timeout(timeout).into();
+        let handle = tokio::spawn(ProstServerStream::new(server, service).process());
+
+        let mut client = ProstClientStream::new(client);
+        let cmd = CommandRequest::new_hset("t1", "k1", "v1".into());
+        assert_res_ok(&client.execute(cmd).await?, &[Value::default()], &[]);
+        // 没有新的命令，服务器结束这个 stream
+        time::timeout(Duration::from_millis(500), handle).await???;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn client_should_time_out_without_response() -> anyhow::Result<()> {
+        // 服务器不回应
+        let (client, _server) = tokio::io::duplex(4096);
+        let mut client = ProstClientStream::new(client).with_timeout(Some(Duration::from_millis(10)));
+        let cmd = CommandRequest::new_hget("t1", "k1");
+        let start = Instant::now();
+        assert!(matches!(client.execute_unary(&cmd).await, Err(KvError::Timeout)));
+        assert!(start.elapsed() < TIMEOUT_GRACE * 2);
+        // 超时之后 stream 不能再使用
+        assert!(matches!(client.execute_unary(&cmd).await, Err(KvError::Timeout)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pipeline_should_return_responses_in_order() -> anyhow::Result<()> {
+        let addr = start_server().await?;
+        let stream = TcpStream::connect(addr).await?;
+        let mut client = ProstClientStream::new(stream);
+
+        let cmds: Vec<_> = (0..10i64)
+            .map(|i| CommandRequest::new_hset("t1", format!("k{}", i), i.into()))
+            .chain((0..10i64).map(|i| CommandRequest::new_hget("t1", format!("k{}", i))))
+            .collect();
+        let res = client.execute_pipeline(&cmds).await?;
+        assert_eq!(res.len(), 20);
+        for (i, res) in res[10..].iter().enumerate() {
+            assert_res_ok(res, &[(i as i64).into()], &[]);
+        }
+        Ok(())
+    }
+
     async fn start_server() -> Result<SocketAddr> {
         let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
         let addr = listener.local_addr().unwrap();
@@ -182,8 +547,9 @@ pub mod utils {
             cx: &mut std::task::Context<'_>,
             buf: &mut tokio::io::ReadBuf<'_>,
         ) -> Poll<std::io::Result<()>> {
-            let len = buf.capacity();
-            let data = self.get_mut().buf.split_to(len);
+            let this = self.get_mut();
+            let len = buf.remaining().min(this.buf.len());
+            let data = this.buf.split_to(len);
             buf.put_slice(&data);
             Poll::Ready(Ok(()))
         }
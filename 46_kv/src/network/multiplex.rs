@@ -1,15 +1,27 @@
 use futures::{future, Future, TryStreamExt};
-use std::marker::PhantomData;
-use tokio::io::{AsyncRead, AsyncWrite};
+use std::{
+    marker::PhantomData,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    task::JoinHandle,
+    time,
+};
 use tokio_util::compat::{Compat, FuturesAsyncReadCompatExt, TokioAsyncReadCompatExt};
 use yamux::{Config, Connection, ConnectionError, Control, Mode, WindowUpdateMode};
 use tracing::instrument;
-use crate::ProstClientStream;
+use crate::{metrics, CommandRequest, ProstClientStream, DEFAULT_MAX_FRAME};
 
 /// Yamux 控制结构
 pub struct YamuxCtrl<S> {
     /// yamux control, 用于创建新的stream
     ctrl: Control,
+    /// 驱动yamux连接的task
+    conn: JoinHandle<Result<(), ConnectionError>>,
+    /// 客户端的 keepalive task
+    keepalive: Option<JoinHandle<()>>,
     _conn: PhantomData<S>,
 }
 
@@ -34,7 +46,7 @@ where
 
     #[instrument(name = "yamux_ctrl_new", skip_all)]
     // 创建YamuxCtrl
-    fn new<F, Fut>(stream: S, config: Option<Config>, is_client: bool, f: F) -> Self
+    fn new<F, Fut>(stream: S, config: Option<Config>, is_client: bool, mut f: F) -> Self
     where
         F: FnMut(yamux::Stream) -> Fut,
         F: Send + 'static,
@@ -56,15 +68,64 @@ where
         // 创建yamux ctrl
         let ctrl = conn.control();
 
-        // pull 所有 stream下的数据
-        tokio::spawn(yamux::into_stream(conn).try_for_each_concurrent(None, f));
+        // pull 所有 stream下的数据，服务端同时统计活跃的连接和 stream
+        let gauge = move |g| (!is_client).then(|| metrics::GaugeGuard::new(g));
+        let conn = tokio::spawn(async move {
+            let _conn = gauge(&metrics::ACTIVE_CONNECTIONS);
+            yamux::into_stream(conn)
+                .try_for_each_concurrent(None, |stream| {
+                    let guard = gauge(&metrics::ACTIVE_STREAMS);
+                    let fut = f(stream);
+                    async move {
+                        let _guard = guard;
+                        fut.await
+                    }
+                })
+                .await
+        });
 
         Self {
             ctrl,
+            conn,
+            keepalive: None,
             _conn: PhantomData::default(),
         }
     }
 
+    /// 每隔 interval 打开一个 stream 发送 Hello，让服务器知道连接仍然活跃
+    ///
+    /// yamux 0.9 不能主动发送 ping，而空的 stream 直到写入数据才会发出 SYN，
+    /// 所以用最轻量的 Hello 请求代替；连接断开时打开 stream 失败，keepalive 结束
+    pub fn with_keepalive(mut self, interval: Duration) -> Self {
+        let mut ctrl = self.ctrl.clone();
+        let keepalive = tokio::spawn(async move {
+            loop {
+                time::sleep(interval).await;
+                let stream = match ctrl.open_stream().await {
+                    Ok(stream) => stream,
+                    Err(_) => break,
+                };
+                let mut stream = ProstClientStream::new(stream.compat()).with_timeout(Some(interval));
+                let hello = CommandRequest::new_hello(vec![], DEFAULT_MAX_FRAME);
+                let _ = stream.execute_unary(&hello).await;
+            }
+        });
+        if let Some(old) = self.keepalive.replace(keepalive) {
+            old.abort();
+        }
+        self
+    }
+
+    /// 等待底层的连接结束
+    pub async fn closed(&mut self) {
+        let _ = (&mut self.conn).await;
+    }
+
+    /// 强制关闭底层的连接
+    pub fn abort(&self) {
+        self.conn.abort();
+    }
+
     // 打开一个新的stream
     #[instrument(skip_all)]
     pub async fn open_stream(&mut self) -> Result<ProstClientStream<Compat<yamux::Stream>>, ConnectionError> {
@@ -73,6 +134,65 @@ where
     }
 }
 
+impl<S> Drop for YamuxCtrl<S> {
+    fn drop(&mut self) {
+        if let Some(keepalive) = self.keepalive.take() {
+            keepalive.abort();
+        }
+    }
+}
+
+/// 记录连接上活跃的 stream，用于服务器关闭空闲的连接
+#[derive(Debug, Clone)]
+pub struct IdleTracker {
+    // 活跃的 stream 数量，和最后一个 stream 结束的时间
+    inner: Arc<Mutex<(usize, Instant)>>,
+}
+
+/// 活跃的 stream，drop 时 stream 结束
+pub struct IdleGuard(IdleTracker);
+
+impl Default for IdleTracker {
+    fn default() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new((0, Instant::now()))),
+        }
+    }
+}
+
+impl IdleTracker {
+    /// 开始一个 stream
+    pub fn track(&self) -> IdleGuard {
+        self.inner.lock().unwrap().0 += 1;
+        IdleGuard(self.clone())
+    }
+
+    /// 连接上没有 stream 的时间超过 timeout 时返回
+    pub async fn idle(&self, timeout: Duration) {
+        loop {
+            let wait = {
+                let (active, last) = *self.inner.lock().unwrap();
+                match active {
+                    0 => timeout.checked_sub(last.elapsed()),
+                    _ => Some(timeout),
+                }
+            };
+            match wait {
+                Some(wait) if !wait.is_zero() => time::sleep(wait).await,
+                _ => return,
+            }
+        }
+    }
+}
+
+impl Drop for IdleGuard {
+    fn drop(&mut self) {
+        let mut inner = self.0.inner.lock().unwrap();
+        inner.0 -= 1;
+        inner.1 = Instant::now();
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -154,6 +274,33 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn idle_tracker_should_wait_for_streams() {
+        let tracker = IdleTracker::default();
+        let guard = tracker.track();
+        let idle = Duration::from_millis(50);
+        // 有活跃的 stream 时不会空闲
+        assert!(time::timeout(idle * 3, tracker.idle(idle)).await.is_err());
+        drop(guard);
+        assert!(time::timeout(idle * 3, tracker.idle(idle)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn keepalive_should_open_streams() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let (client, server) = tokio::io::duplex(4096);
+        let count = Arc::new(AtomicUsize::new(0));
+        let count1 = count.clone();
+        let _server = YamuxCtrl::new_server(server, None, move |_stream| {
+            count1.fetch_add(1, Ordering::SeqCst);
+            future::ready(Ok(()))
+        });
+        let _client = YamuxCtrl::new_client(client, None).with_keepalive(Duration::from_millis(20));
+        time::sleep(Duration::from_millis(150)).await;
+        assert!(count.load(Ordering::SeqCst) >= 3);
+    }
+
     #[tokio::test]
     async fn yamux_ctrl_client_server_should_work() -> Result<()> {
         // 创建使用了TLS的yamux server
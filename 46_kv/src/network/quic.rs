@@ -0,0 +1,316 @@
+//! QUIC 传输
+//!
+//! 每个 QUIC 双向 stream 和一个 yamux stream 一样，承载一个 `ProstStream`。QUIC 的
+//! stream 之间没有 TCP 上的队头阻塞，适合丢包比较多的网络。证书、客户端认证和 ALPN
+//! 和 TLS 端口一致，但 quinn 使用 rustls 0.20，所以证书在这里单独加载。
+
+use crate::{
+    network::tls::{cert_identity, ALPN_KV},
+    KvError, ProstClientStream, ProstServerStream, Service, Storage,
+};
+use futures::StreamExt;
+use quinn::{
+    ClientConfig, Connecting, Connection, Endpoint, NewConnection, RecvStream, SendStream,
+    ServerConfig,
+};
+use rustls::{server::AllowAnyAuthenticatedClient, Certificate, PrivateKey, RootCertStore};
+use std::{
+    io::{self, Cursor},
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tracing::{info, warn};
+
+/// 一个 QUIC 双向 stream
+pub struct QuicStream {
+    send: SendStream,
+    recv: RecvStream,
+}
+
+impl QuicStream {
+    pub fn new(send: SendStream, recv: RecvStream) -> Self {
+        Self { send, recv }
+    }
+}
+
+impl AsyncRead for QuicStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.recv).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for QuicStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.send).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.send).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.send).poll_shutdown(cx)
+    }
+}
+
+/// 加载 server cert / client CA，生成 QUIC 的 ServerConfig
+///
+/// client_cas 为空时不验证客户端证书，否则信任其中任意一个 CA 签发的客户端证书
+pub fn quic_server_config(
+    cert: &str,
+    key: &str,
+    client_cas: &[&str],
+) -> Result<ServerConfig, KvError> {
+    let certs = load_certs(cert, "server")?;
+    let key = load_key(key)?;
+    let builder = rustls::ServerConfig::builder().with_safe_defaults();
+    let builder = match client_cas.is_empty() {
+        true => builder.with_no_client_auth(),
+        false => {
+            let roots = load_roots(client_cas.iter().copied())?;
+            builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots))
+        }
+    };
+    let mut config = builder
+        .with_single_cert(certs, key)
+        .map_err(|_| KvError::CertificateParseError("server", "cert"))?;
+    config.alpn_protocols = vec![ALPN_KV.as_bytes().to_vec()];
+    Ok(ServerConfig::with_crypto(Arc::new(config)))
+}
+
+/// 加载 client cert / server CA，生成 QUIC 的 ClientConfig
+///
+/// 没有指定 server CA 时使用系统的根证书，这时不支持客户端证书
+pub fn quic_client_config(
+    identity: Option<(&str, &str)>,
+    server_ca: Option<&str>,
+) -> Result<ClientConfig, KvError> {
+    let roots = match (server_ca, identity) {
+        (Some(ca), _) => load_roots([ca])?,
+        (None, None) => return Ok(ClientConfig::with_native_roots()),
+        (None, Some(_)) => {
+            return Err(KvError::InvalidConfig(
+                "QUIC client cert requires server CA".into(),
+            ))
+        }
+    };
+    let builder = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots);
+    let mut config = match identity {
+        Some((cert, key)) => builder
+            .with_single_cert(load_certs(cert, "client")?, load_key(key)?)
+            .map_err(|_| KvError::CertificateParseError("client", "cert"))?,
+        None => builder.with_no_client_auth(),
+    };
+    config.alpn_protocols = vec![ALPN_KV.as_bytes().to_vec()];
+    Ok(ClientConfig::new(Arc::new(config)))
+}
+
+fn load_certs(cert: &str, name: &'static str) -> Result<Vec<Certificate>, KvError> {
+    let certs = rustls_pemfile::certs(&mut Cursor::new(cert))
+        .map_err(|_| KvError::CertificateParseError(name, "cert"))?;
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn load_roots<'a>(cas: impl IntoIterator<Item = &'a str>) -> Result<RootCertStore, KvError> {
+    let mut roots = RootCertStore::empty();
+    for ca in cas {
+        for cert in load_certs(ca, "CA")? {
+            roots
+                .add(&cert)
+                .map_err(|_| KvError::CertificateParseError("CA", "cert"))?;
+        }
+    }
+    Ok(roots)
+}
+
+fn load_key(key: &str) -> Result<PrivateKey, KvError> {
+    let mut cursor = Cursor::new(key);
+    // 依次尝试 PKCS8 / RSA / EC 格式的私钥
+    while let Ok(Some(item)) = rustls_pemfile::read_one(&mut cursor) {
+        match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => return Ok(PrivateKey(key)),
+            _ => {}
+        }
+    }
+    Err(KvError::CertificateParseError("private", "key"))
+}
+
+/// 处理一个 QUIC 连接，每个双向 stream 交给一个 `ProstServerStream`
+async fn serve_quic<Store: Storage>(
+    connecting: Connecting,
+    svc: Service<Store>,
+) -> Result<(), KvError> {
+    let NewConnection {
+        connection,
+        mut bi_streams,
+        ..
+    } = connecting.await?;
+    let peer = connection.remote_address();
+    let identity = connection
+        .peer_identity()
+        .and_then(|id| id.downcast::<Vec<Certificate>>().ok())
+        .and_then(|certs| cert_identity(&certs.first()?.0));
+    let client = svc.register_client(Some(peer), identity);
+    let info = client.info().clone();
+    info!("QUIC client {:?} connected", peer);
+
+    let accept = async {
+        // 对端关闭连接时 bi_streams 返回错误
+        while let Some(Ok((send, recv))) = bi_streams.next().await {
+            let stream = ProstServerStream::new(QuicStream::new(send, recv), svc.clone())
+                .with_client(info.clone());
+            tokio::spawn(async move {
+                if let Err(e) = stream.process().await {
+                    warn!("QUIC stream failed: {:?}", e);
+                }
+            });
+        }
+    };
+
+    // 连接正常结束，或者被管理命令 kill
+    tokio::select! {
+        _ = accept => {},
+        _ = client.killed() => {
+            info!("QUIC client {:?} is killed", peer);
+            connection.close(0u32.into(), b"killed");
+        }
+    }
+    info!("QUIC client {:?} disconnected", peer);
+    Ok(())
+}
+
+/// 启动 QUIC 监听端口，addr 是 UDP 地址
+pub async fn start_quic_server<Store: Storage>(
+    addr: &str,
+    svc: Service<Store>,
+    config: ServerConfig,
+) -> Result<(), KvError> {
+    let addr: SocketAddr = addr
+        .parse()
+        .map_err(|_| KvError::InvalidConfig(format!("invalid QUIC address: {}", addr)))?;
+    let (_endpoint, mut incoming) = Endpoint::server(config, addr)?;
+    info!("QUIC listening on {}", addr);
+    while let Some(connecting) = incoming.next().await {
+        let svc = svc.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_quic(connecting, svc).await {
+                warn!("QUIC connection failed: {:?}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+/// QUIC 客户端的连接，和 `YamuxCtrl` 一样可以打开多个 stream
+pub struct QuicCtrl {
+    // endpoint drop 之后连接也会关闭
+    _endpoint: Endpoint,
+    connection: Connection,
+}
+
+impl QuicCtrl {
+    /// 连接 QUIC 服务器，domain 是服务器证书中的域名
+    pub async fn connect(addr: &str, domain: &str, config: ClientConfig) -> Result<Self, KvError> {
+        let addr: SocketAddr = addr
+            .parse()
+            .map_err(|_| KvError::InvalidConfig(format!("invalid QUIC address: {}", addr)))?;
+        let local: SocketAddr = match addr {
+            SocketAddr::V4(_) => "0.0.0.0:0",
+            SocketAddr::V6(_) => "[::]:0",
+        }
+        .parse()
+        .unwrap();
+        let mut endpoint = Endpoint::client(local)?;
+        endpoint.set_default_client_config(config);
+        let connecting = endpoint
+            .connect(addr, domain)
+            .map_err(|e| KvError::Internal(e.to_string()))?;
+        let NewConnection { connection, .. } = connecting.await?;
+        Ok(Self {
+            _endpoint: endpoint,
+            connection,
+        })
+    }
+
+    /// 打开一个新的 stream
+    pub async fn open_stream(&self) -> Result<ProstClientStream<QuicStream>, KvError> {
+        let (send, recv) = self.connection.open_bi().await?;
+        Ok(ProstClientStream::new(QuicStream::new(send, recv)))
+    }
+
+    /// 关闭连接
+    pub fn close(&self) {
+        self.connection.close(0u32.into(), b"");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assert_res_ok, CommandRequest, MemTable, ServiceInner, Value};
+
+    const CA_CERT: &str = include_str!("../../fixtures/ca.cert");
+    const SERVER_CERT: &str = include_str!("../../fixtures/server.cert");
+    const SERVER_KEY: &str = include_str!("../../fixtures/server.key");
+
+    async fn start_server(client_cas: &[&str]) -> String {
+        let config = quic_server_config(SERVER_CERT, SERVER_KEY, client_cas).unwrap();
+        let (endpoint, mut incoming) =
+            Endpoint::server(config, "127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = endpoint.local_addr().unwrap().to_string();
+        let svc: Service = ServiceInner::new(MemTable::new()).into();
+        tokio::spawn(async move {
+            let _endpoint = endpoint;
+            while let Some(connecting) = incoming.next().await {
+                tokio::spawn(serve_quic(connecting, svc.clone()));
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn quic_streams_should_work() -> anyhow::Result<()> {
+        let addr = start_server(&[]).await;
+        let config = quic_client_config(None, Some(CA_CERT))?;
+        let ctrl = QuicCtrl::connect(&addr, "kvserver.acme.inc", config).await?;
+
+        // 同一个连接上的多个 stream
+        let mut s1 = ctrl.open_stream().await?;
+        let mut s2 = ctrl.open_stream().await?;
+        let cmd = CommandRequest::new_hset("t1", "k1", "v1".into());
+        assert_res_ok(&s1.execute_unary(&cmd).await?, &[Value::default()], &[]);
+        let cmd = CommandRequest::new_hget("t1", "k1");
+        assert_res_ok(&s2.execute_unary(&cmd).await?, &["v1".into()], &[]);
+        ctrl.close();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn quic_client_without_cert_should_be_rejected() -> anyhow::Result<()> {
+        let addr = start_server(&[CA_CERT]).await;
+        let config = quic_client_config(None, Some(CA_CERT))?;
+        let result = async {
+            let ctrl = QuicCtrl::connect(&addr, "kvserver.acme.inc", config).await?;
+            let mut stream = ctrl.open_stream().await?;
+            stream.execute_unary(&CommandRequest::new_hget("t1", "k1")).await
+        }
+        .await;
+        assert!(result.is_err());
+        Ok(())
+    }
+}
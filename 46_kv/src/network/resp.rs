@@ -0,0 +1,687 @@
+//! RESP（Redis 协议）兼容的监听端口
+//!
+//! 支持 RESP2 和 RESP3，连接默认使用 RESP2，`HELLO 3` 之后切换到 RESP3。
+//! redis 的 hash 命令中的 key 对应 table，field 对应 key：
+//!
+//! ```text
+//! HGET t k / HSET t k v [k v ...] / HDEL t k [k ...] / HGETALL t / HMGET t k [k ...] / HEXISTS t k
+//! PUBLISH topic message / SUBSCRIBE topic [topic ...] / UNSUBSCRIBE [topic ...]
+//! PING / ECHO / HELLO / QUIT / COMMAND / CLIENT
+//! ```
+//!
+//! RESP 连接没有客户端证书，以匿名客户端的身份执行命令。
+
+use crate::{
+    value, ClientInfo, CommandRequest, CommandResponse, KvError, Kvpair, Service, Storage, Value,
+};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures::StreamExt;
+use std::{collections::HashMap, convert::TryFrom, net::SocketAddr};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpListener,
+    sync::mpsc,
+    task::JoinHandle,
+};
+use tracing::{info, warn};
+
+/// bulk string 的最大长度，和 redis 的 proto-max-bulk-len 一致
+const MAX_BULK_SIZE: usize = 512 * 1024 * 1024;
+/// 一行（inline 命令或者类型和长度）的最大长度，和 redis 的 PROTO_INLINE_MAX_SIZE 一致
+const MAX_LINE_SIZE: usize = 64 * 1024;
+/// 数组、map 等嵌套的最大层数，防止恶意的请求耗尽栈空间
+const MAX_DEPTH: usize = 8;
+/// 订阅消息的缓冲区大小
+const PUSH_CAPACITY: usize = 128;
+
+/// RESP 的数据类型，RESP3 特有的类型在 RESP2 连接上会被转换成 RESP2 的类型
+#[derive(Debug, Clone, PartialEq)]
+pub enum RespValue {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Bytes),
+    Null,
+    Array(Vec<RespValue>),
+    Map(Vec<(RespValue, RespValue)>),
+    Double(f64),
+    Boolean(bool),
+    Push(Vec<RespValue>),
+}
+
+fn protocol_error(msg: impl Into<String>) -> KvError {
+    KvError::InvalidCommand(format!("Protocol error: {}", msg.into()))
+}
+
+// 在 buf[pos..] 中找到一行，返回这一行的内容和下一行的开始位置；
+// 超过 MAX_LINE_SIZE 还没有结束的行是错误
+fn read_line(buf: &[u8], pos: usize) -> Result<Option<(&[u8], usize)>, KvError> {
+    match buf[pos..].windows(2).position(|w| w == b"\r\n") {
+        Some(end) if end <= MAX_LINE_SIZE => Ok(Some((&buf[pos..pos + end], pos + end + 2))),
+        Some(_) => Err(protocol_error("too big line")),
+        None if buf.len() - pos > MAX_LINE_SIZE => Err(protocol_error("too big line")),
+        None => Ok(None),
+    }
+}
+
+fn parse_int(line: &[u8]) -> Result<i64, KvError> {
+    std::str::from_utf8(line)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| protocol_error("invalid integer"))
+}
+
+impl RespValue {
+    /// 从 buf 中解析一个完整的值，数据不完整时返回 None，buf 不会被修改
+    pub fn parse(buf: &mut BytesMut) -> Result<Option<Self>, KvError> {
+        match Self::parse_at(buf, 0, 0)? {
+            Some((v, len)) => {
+                buf.advance(len);
+                Ok(Some(v))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn parse_at(buf: &[u8], pos: usize, depth: usize) -> Result<Option<(Self, usize)>, KvError> {
+        let kind = match buf.get(pos) {
+            Some(b) => *b,
+            None => return Ok(None),
+        };
+        let (line, next) = match read_line(buf, pos + 1)? {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+        let text = || String::from_utf8_lossy(line).into_owned();
+        let v = match kind {
+            b'+' => Self::Simple(text()),
+            b'-' => Self::Error(text()),
+            b':' => Self::Integer(parse_int(line)?),
+            b'(' => Self::Bulk(Bytes::copy_from_slice(line)),
+            b'_' => Self::Null,
+            b'#' => match line {
+                b"t" => Self::Boolean(true),
+                b"f" => Self::Boolean(false),
+                _ => return Err(protocol_error("invalid boolean")),
+            },
+            b',' => Self::Double(
+                text()
+                    .parse()
+                    .map_err(|_| protocol_error("invalid double"))?,
+            ),
+            b'$' | b'=' | b'!' => {
+                let len = parse_int(line)?;
+                if len < 0 {
+                    return Ok(Some((Self::Null, next)));
+                }
+                let len = len as usize;
+                if len > MAX_BULK_SIZE {
+                    return Err(protocol_error("invalid bulk length"));
+                }
+                if buf.len() < next + len + 2 {
+                    return Ok(None);
+                }
+                if &buf[next + len..next + len + 2] != b"\r\n" {
+                    return Err(protocol_error("bulk string is not terminated by CRLF"));
+                }
+                let data = &buf[next..next + len];
+                let v = match kind {
+                    b'$' => Self::Bulk(Bytes::copy_from_slice(data)),
+                    // verbatim string 的前 4 个字节是格式，例如 `txt:`
+                    b'=' => Self::Bulk(Bytes::copy_from_slice(data.get(4..).unwrap_or_default())),
+                    _ => Self::Error(String::from_utf8_lossy(data).into_owned()),
+                };
+                return Ok(Some((v, next + len + 2)));
+            }
+            b'*' | b'~' | b'>' | b'%' => {
+                let len = parse_int(line)?;
+                if len < 0 {
+                    return Ok(Some((Self::Null, next)));
+                }
+                if depth >= MAX_DEPTH {
+                    return Err(protocol_error("too deeply nested"));
+                }
+                // map 中每一项有 key 和 value 两个值
+                let count = match kind {
+                    b'%' => len.checked_mul(2),
+                    _ => Some(len),
+                }
+                .ok_or_else(|| protocol_error("invalid multibulk length"))?;
+                let mut items = Vec::new();
+                let mut pos = next;
+                for _ in 0..count {
+                    match Self::parse_at(buf, pos, depth + 1)? {
+                        Some((v, next)) => {
+                            items.push(v);
+                            pos = next;
+                        }
+                        None => return Ok(None),
+                    }
+                }
+                let v = match kind {
+                    b'>' => Self::Push(items),
+                    b'%' => {
+                        let mut iter = items.into_iter();
+                        let mut pairs = Vec::new();
+                        while let (Some(k), Some(v)) = (iter.next(), iter.next()) {
+                            pairs.push((k, v));
+                        }
+                        Self::Map(pairs)
+                    }
+                    _ => Self::Array(items),
+                };
+                return Ok(Some((v, pos)));
+            }
+            _ => return Err(protocol_error(format!("unknown type `{}`", kind as char))),
+        };
+        Ok(Some((v, next)))
+    }
+
+    /// 编码到 buf 中，resp3 为 false 时把 RESP3 特有的类型转换成 RESP2 的类型
+    pub fn encode(&self, resp3: bool, buf: &mut BytesMut) {
+        match self {
+            Self::Simple(s) => put_line(buf, b'+', s.as_bytes()),
+            Self::Error(s) => put_line(buf, b'-', s.as_bytes()),
+            Self::Integer(i) => put_line(buf, b':', i.to_string().as_bytes()),
+            Self::Bulk(data) => {
+                put_line(buf, b'$', data.len().to_string().as_bytes());
+                buf.put_slice(data);
+                buf.put_slice(b"\r\n");
+            }
+            Self::Null if resp3 => buf.put_slice(b"_\r\n"),
+            Self::Null => buf.put_slice(b"$-1\r\n"),
+            Self::Array(items) => put_items(buf, b'*', items, resp3),
+            Self::Push(items) => put_items(buf, if resp3 { b'>' } else { b'*' }, items, resp3),
+            Self::Map(pairs) if resp3 => {
+                put_line(buf, b'%', pairs.len().to_string().as_bytes());
+                for (k, v) in pairs {
+                    k.encode(resp3, buf);
+                    v.encode(resp3, buf);
+                }
+            }
+            Self::Map(pairs) => {
+                put_line(buf, b'*', (pairs.len() * 2).to_string().as_bytes());
+                for (k, v) in pairs {
+                    k.encode(resp3, buf);
+                    v.encode(resp3, buf);
+                }
+            }
+            Self::Double(f) if resp3 => put_line(buf, b',', format_double(*f).as_bytes()),
+            Self::Double(f) => Self::Bulk(format_double(*f).into()).encode(resp3, buf),
+            Self::Boolean(b) if resp3 => put_line(buf, b'#', if *b { b"t" } else { b"f" }),
+            Self::Boolean(b) => Self::Integer(*b as i64).encode(resp3, buf),
+        }
+    }
+}
+
+fn put_line(buf: &mut BytesMut, kind: u8, line: &[u8]) {
+    buf.put_u8(kind);
+    buf.put_slice(line);
+    buf.put_slice(b"\r\n");
+}
+
+fn put_items(buf: &mut BytesMut, kind: u8, items: &[RespValue], resp3: bool) {
+    put_line(buf, kind, items.len().to_string().as_bytes());
+    for item in items {
+        item.encode(resp3, buf);
+    }
+}
+
+fn format_double(f: f64) -> String {
+    match f {
+        f if f == f64::INFINITY => "inf".into(),
+        f if f == f64::NEG_INFINITY => "-inf".into(),
+        f => f.to_string(),
+    }
+}
+
+/// 把 Value 转换成 RESP 的值
+pub fn value_to_resp(v: &Value) -> RespValue {
+    match &v.value {
+        None | Some(value::Value::Null(_)) => RespValue::Null,
+        Some(value::Value::String(s)) => RespValue::Bulk(s.clone().into()),
+        Some(value::Value::Binary(b)) => RespValue::Bulk(b.clone()),
+        Some(value::Value::Integer(i)) => RespValue::Integer(*i),
+        Some(value::Value::Timestamp(ms)) => RespValue::Integer(*ms),
+        Some(value::Value::Float(f)) => RespValue::Double(*f),
+        Some(value::Value::Bool(b)) => RespValue::Boolean(*b),
+        Some(value::Value::List(list)) => {
+            RespValue::Array(list.values.iter().map(value_to_resp).collect())
+        }
+        Some(value::Value::Map(map)) => RespValue::Map(
+            map.fields
+                .iter()
+                .map(|(k, v)| (RespValue::Bulk(k.clone().into()), value_to_resp(v)))
+                .collect(),
+        ),
+    }
+}
+
+// 命令的参数是 utf8 时保存为 string，否则保存为 binary
+fn arg_to_value(arg: &Bytes) -> Value {
+    match std::str::from_utf8(arg) {
+        Ok(s) => s.into(),
+        Err(_) => arg.clone().into(),
+    }
+}
+
+fn arg_to_string(arg: &Bytes) -> String {
+    String::from_utf8_lossy(arg).into_owned()
+}
+
+fn simple(s: &str) -> RespValue {
+    RespValue::Simple(s.into())
+}
+
+fn bulk(s: impl Into<String>) -> RespValue {
+    RespValue::Bulk(s.into().into())
+}
+
+fn resp_error(e: &CommandResponse) -> RespValue {
+    RespValue::Error(format!("ERR {}", e.message))
+}
+
+// 订阅的 id 和转发消息的 task
+struct Subscription {
+    id: u32,
+    task: JoinHandle<()>,
+}
+
+/// 一个 RESP 连接的状态
+struct RespConnection<Store> {
+    svc: Service<Store>,
+    client: ClientInfo,
+    resp3: bool,
+    quit: bool,
+    subscriptions: HashMap<String, Subscription>,
+    push: mpsc::Sender<RespValue>,
+}
+
+impl<Store: Storage> RespConnection<Store> {
+    async fn execute(&self, cmd: CommandRequest) -> CommandResponse {
+        let mut stream = self.svc.execute_as(cmd, Some(&self.client));
+        match stream.next().await {
+            Some(res) => (*res).clone(),
+            None => KvError::Internal("no response".into()).into(),
+        }
+    }
+
+    /// 解析一个命令（bulk string 组成的数组）并执行，返回需要回复给客户端的值
+    async fn dispatch(&mut self, frame: RespValue) -> Vec<RespValue> {
+        let args = match frame {
+            RespValue::Array(items) => items
+                .into_iter()
+                .map(|v| match v {
+                    RespValue::Bulk(b) => Some(b),
+                    RespValue::Simple(s) => Some(s.into()),
+                    RespValue::Integer(i) => Some(i.to_string().into()),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>(),
+            _ => None,
+        };
+        let args = match args {
+            Some(args) if !args.is_empty() => args,
+            _ => return vec![RespValue::Error("ERR Protocol error: expect array of bulk strings".into())],
+        };
+        let name = arg_to_string(&args[0]).to_ascii_uppercase();
+        let args = &args[1..];
+        let wrong_args = || {
+            vec![RespValue::Error(format!(
+                "ERR wrong number of arguments for '{}' command",
+                name.to_ascii_lowercase()
+            ))]
+        };
+
+        let reply = match (name.as_str(), args) {
+            ("PING", []) => simple("PONG"),
+            ("PING", [msg]) | ("ECHO", [msg]) => RespValue::Bulk(msg.clone()),
+            ("QUIT", _) => {
+                self.quit = true;
+                simple("OK")
+            }
+            ("COMMAND", _) => RespValue::Array(vec![]),
+            ("CLIENT", _) => simple("OK"),
+            ("HELLO", args) => self.hello(args),
+            ("HGET", [t, k]) => {
+                let cmd = CommandRequest::new_hget(arg_to_string(t), arg_to_string(k));
+                let res = self.execute(cmd).await;
+                match res.status {
+                    200 => res.values.first().map(value_to_resp).unwrap_or(RespValue::Null),
+                    404 => RespValue::Null,
+                    _ => resp_error(&res),
+                }
+            }
+            ("HSET", [t, pairs @ ..]) if !pairs.is_empty() && pairs.len() % 2 == 0 => {
+                let pairs = pairs
+                    .chunks(2)
+                    .map(|kv| Kvpair::new(arg_to_string(&kv[0]), arg_to_value(&kv[1])))
+                    .collect();
+                let res = self.execute(CommandRequest::new_hmset(arg_to_string(t), pairs)).await;
+                // 返回新增的 field 数量
+                match res.status {
+                    200 => RespValue::Integer(res.values.iter().filter(|v| v.value.is_none()).count() as i64),
+                    _ => resp_error(&res),
+                }
+            }
+            ("HDEL", [t, keys @ ..]) if !keys.is_empty() => {
+                let keys = keys.iter().map(arg_to_string).collect();
+                let res = self.execute(CommandRequest::new_hmdel(arg_to_string(t), keys)).await;
+                match res.status {
+                    200 => RespValue::Integer(res.values.iter().filter(|v| v.value.is_some()).count() as i64),
+                    _ => resp_error(&res),
+                }
+            }
+            ("HGETALL", [t]) => {
+                let res = self.execute(CommandRequest::new_hgetall(arg_to_string(t))).await;
+                match res.status {
+                    200 => RespValue::Map(
+                        res.pairs
+                            .iter()
+                            .map(|p| {
+                                let v = p.value.as_ref().map(value_to_resp).unwrap_or(RespValue::Null);
+                                (bulk(p.key.clone()), v)
+                            })
+                            .collect(),
+                    ),
+                    _ => resp_error(&res),
+                }
+            }
+            ("HMGET", [t, keys @ ..]) if !keys.is_empty() => {
+                let keys = keys.iter().map(arg_to_string).collect();
+                let res = self.execute(CommandRequest::new_hmget(arg_to_string(t), keys)).await;
+                match res.status {
+                    200 => RespValue::Array(res.values.iter().map(value_to_resp).collect()),
+                    _ => resp_error(&res),
+                }
+            }
+            ("HEXISTS", [t, k]) => {
+                let cmd = CommandRequest::new_hexist(arg_to_string(t), arg_to_string(k));
+                let res = self.execute(cmd).await;
+                match (res.status, res.values.first().cloned().map(bool::try_from)) {
+                    (200, Some(Ok(b))) => RespValue::Integer(b as i64),
+                    _ => resp_error(&res),
+                }
+            }
+            ("PUBLISH", [topic, msg]) => {
+                let cmd = CommandRequest::new_publish(arg_to_string(topic), vec![arg_to_value(msg)]);
+                let res = self.execute(cmd).await;
+                // 返回发布时的订阅者数量
+                match (res.status, res.values.first().map(i64::try_from)) {
+                    (200, Some(Ok(count))) => RespValue::Integer(count),
+                    _ => resp_error(&res),
+                }
+            }
+            ("SUBSCRIBE", topics) if !topics.is_empty() => {
+                let mut replies = Vec::new();
+                for topic in topics {
+                    replies.push(self.subscribe(arg_to_string(topic)).await);
+                }
+                return replies;
+            }
+            ("UNSUBSCRIBE", topics) => {
+                let topics: Vec<_> = match topics {
+                    [] => self.subscriptions.keys().cloned().collect(),
+                    topics => topics.iter().map(arg_to_string).collect(),
+                };
+                let mut replies = Vec::new();
+                for topic in topics {
+                    replies.push(self.unsubscribe(topic).await);
+                }
+                return replies;
+            }
+            ("PING" | "ECHO" | "HGET" | "HSET" | "HDEL" | "HGETALL" | "HMGET" | "HEXISTS", _)
+            | ("PUBLISH" | "SUBSCRIBE", _) => return wrong_args(),
+            _ => RespValue::Error(format!("ERR unknown command '{}'", name.to_ascii_lowercase())),
+        };
+        vec![reply]
+    }
+
+    fn hello(&mut self, args: &[Bytes]) -> RespValue {
+        match args.first().map(arg_to_string).as_deref() {
+            None | Some("2") => self.resp3 = false,
+            Some("3") => self.resp3 = true,
+            Some(_) => {
+                return RespValue::Error("NOPROTO unsupported protocol version".into());
+            }
+        }
+        let proto = if self.resp3 { 3 } else { 2 };
+        RespValue::Map(vec![
+            (bulk("server"), bulk("kv")),
+            (bulk("version"), bulk(env!("CARGO_PKG_VERSION"))),
+            (bulk("proto"), RespValue::Integer(proto)),
+            (bulk("id"), RespValue::Integer(self.client.id as i64)),
+            (bulk("mode"), bulk("standalone")),
+            (bulk("role"), bulk("master")),
+            (bulk("modules"), RespValue::Array(vec![])),
+        ])
+    }
+
+    async fn subscribe(&mut self, topic: String) -> RespValue {
+        if !self.subscriptions.contains_key(&topic) {
+            let cmd = CommandRequest::new_subscribe(topic.clone());
+            let mut stream = self.svc.execute_as(cmd, Some(&self.client));
+            // 第一个响应是订阅的 id
+            let id = match stream.next().await.as_deref().map(i64::try_from) {
+                Some(Ok(id)) => id as u32,
+                _ => return RespValue::Error("ERR failed to subscribe".into()),
+            };
+            let (push, name) = (self.push.clone(), topic.clone());
+            let task = tokio::spawn(async move {
+                while let Some(res) = stream.next().await {
+                    let msg = match res.values.as_slice() {
+                        [v] => value_to_resp(v),
+                        values => RespValue::Array(values.iter().map(value_to_resp).collect()),
+                    };
+                    let push_msg = RespValue::Push(vec![bulk("message"), bulk(name.clone()), msg]);
+                    if push.send(push_msg).await.is_err() {
+                        break;
+                    }
+                }
+            });
+            self.subscriptions.insert(topic.clone(), Subscription { id, task });
+        }
+        let count = self.subscriptions.len() as i64;
+        RespValue::Push(vec![bulk("subscribe"), bulk(topic), RespValue::Integer(count)])
+    }
+
+    async fn unsubscribe(&mut self, topic: String) -> RespValue {
+        if let Some(sub) = self.subscriptions.remove(&topic) {
+            sub.task.abort();
+            self.execute(CommandRequest::new_unsubscribe(topic.clone(), sub.id))
+                .await;
+        }
+        let count = self.subscriptions.len() as i64;
+        RespValue::Push(vec![bulk("unsubscribe"), bulk(topic), RespValue::Integer(count)])
+    }
+}
+
+// 不是以 `*` 开始的命令是 inline 命令，例如 telnet 中输入的 `PING`
+fn parse_inline(buf: &mut BytesMut) -> Result<Option<RespValue>, KvError> {
+    let (line, next) = match read_line(buf, 0)? {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+    let items = line
+        .split(|b| b.is_ascii_whitespace())
+        .filter(|s| !s.is_empty())
+        .map(|s| RespValue::Bulk(Bytes::copy_from_slice(s)))
+        .collect();
+    buf.advance(next);
+    Ok(Some(RespValue::Array(items)))
+}
+
+/// 在一个连接上处理 RESP 命令
+pub async fn serve_resp<S, Store>(
+    stream: S,
+    svc: Service<Store>,
+    peer: Option<SocketAddr>,
+) -> Result<(), KvError>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    Store: Storage,
+{
+    let handle = svc.register_client(peer, None);
+    // 一个连接上缓冲的未处理数据不能超过原生端口的 max frame
+    let max_buffer = svc.max_frame();
+    let (tx, mut rx) = mpsc::channel(PUSH_CAPACITY);
+    let mut conn = RespConnection {
+        svc,
+        client: handle.info().clone(),
+        resp3: false,
+        quit: false,
+        subscriptions: HashMap::new(),
+        push: tx,
+    };
+    let (mut reader, mut writer) = tokio::io::split(stream);
+    let mut rbuf = BytesMut::with_capacity(4096);
+    let mut wbuf = BytesMut::with_capacity(4096);
+
+    let result = loop {
+        tokio::select! {
+            n = reader.read_buf(&mut rbuf) => {
+                match n {
+                    Ok(0) => break Ok(()),
+                    Ok(_) => {}
+                    Err(e) => break Err(e.into()),
+                }
+                loop {
+                    let frame = match rbuf.first() {
+                        None => Ok(None),
+                        Some(b'*') => RespValue::parse(&mut rbuf),
+                        Some(_) => parse_inline(&mut rbuf),
+                    };
+                    let frame = match frame {
+                        Ok(Some(frame)) => frame,
+                        Ok(None) if rbuf.len() <= max_buffer => break,
+                        // 还没有收到完整的命令，缓冲的数据已经超过了上限
+                        Ok(None) => {
+                            let e = protocol_error("request is larger than max frame");
+                            RespValue::Error(format!("ERR {}", e)).encode(conn.resp3, &mut wbuf);
+                            conn.quit = true;
+                            break;
+                        }
+                        Err(e) => {
+                            RespValue::Error(format!("ERR {}", e)).encode(conn.resp3, &mut wbuf);
+                            conn.quit = true;
+                            break;
+                        }
+                    };
+                    for reply in conn.dispatch(frame).await {
+                        reply.encode(conn.resp3, &mut wbuf);
+                    }
+                    if conn.quit {
+                        break;
+                    }
+                }
+            }
+            Some(msg) = rx.recv() => msg.encode(conn.resp3, &mut wbuf),
+            _ = handle.killed() => break Ok(()),
+        }
+        if let Err(e) = writer.write_all(&wbuf).await {
+            break Err(e.into());
+        }
+        wbuf.clear();
+        if conn.quit {
+            break Ok(());
+        }
+    };
+
+    for (topic, sub) in conn.subscriptions.drain() {
+        sub.task.abort();
+        // unsubscribe 在 execute 时就已经完成，不需要等待响应
+        let cmd = CommandRequest::new_unsubscribe(topic, sub.id);
+        let _ = conn.svc.execute_as(cmd, Some(&conn.client));
+    }
+    result
+}
+
+/// 启动 RESP 监听端口
+pub async fn start_resp_server<Store: Storage>(addr: &str, svc: Service<Store>) -> Result<(), KvError> {
+    let listener = TcpListener::bind(addr).await?;
+    info!("RESP listening on {}", addr);
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let svc = svc.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_resp(stream, svc, Some(peer)).await {
+                warn!("RESP connection {} failed: {:?}", peer, e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(data: &[u8]) -> Option<RespValue> {
+        let mut buf = BytesMut::from(data);
+        RespValue::parse(&mut buf).unwrap()
+    }
+
+    #[test]
+    fn parse_should_work() {
+        let cmd = parse(b"*2\r\n$4\r\nHGET\r\n$-1\r\n").unwrap();
+        assert_eq!(
+            cmd,
+            RespValue::Array(vec![RespValue::Bulk("HGET".into()), RespValue::Null])
+        );
+        assert_eq!(
+            parse(b"%1\r\n+a\r\n#t\r\n").unwrap(),
+            RespValue::Map(vec![(simple("a"), RespValue::Boolean(true))])
+        );
+        assert_eq!(parse(b",1.5\r\n").unwrap(), RespValue::Double(1.5));
+        assert_eq!(parse(b"=7\r\ntxt:abc\r\n").unwrap(), bulk("abc"));
+    }
+
+    #[test]
+    fn incomplete_frame_should_not_be_consumed() {
+        let data = b"*2\r\n$4\r\nHGET\r\n$2\r\nk";
+        for i in 0..data.len() {
+            let mut buf = BytesMut::from(&data[..i]);
+            assert_eq!(RespValue::parse(&mut buf).unwrap(), None);
+            assert_eq!(buf.len(), i);
+        }
+        // bulk string 之后必须是 CRLF
+        let mut buf = BytesMut::from(&b"$1\r\nab\r\n:x\r\n"[..]);
+        assert!(RespValue::parse(&mut buf).is_err());
+        let mut buf = BytesMut::from(&b"$1\r\na\r\n:x\r\n"[..]);
+        assert_eq!(RespValue::parse(&mut buf).unwrap(), Some(bulk("a")));
+        assert!(RespValue::parse(&mut buf).is_err());
+    }
+
+    #[test]
+    fn malicious_frame_should_be_rejected() {
+        // 嵌套太深
+        let mut buf = BytesMut::from(&b"*1\r\n".repeat(20_000)[..]);
+        assert!(RespValue::parse(&mut buf).is_err());
+        let mut buf = BytesMut::from(&b"*1\r\n".repeat(MAX_DEPTH)[..]);
+        assert_eq!(RespValue::parse(&mut buf).unwrap(), None);
+        // map 的长度溢出
+        let mut buf = BytesMut::from(&b"%9223372036854775807\r\n"[..]);
+        assert!(RespValue::parse(&mut buf).is_err());
+        // 太长的行
+        let line = vec![b'a'; MAX_LINE_SIZE + 1];
+        assert!(parse_inline(&mut BytesMut::from(&line[..])).is_err());
+        let mut buf = BytesMut::from(&b"*"[..]);
+        buf.extend_from_slice(&line);
+        assert!(RespValue::parse(&mut buf).is_err());
+    }
+
+    #[test]
+    fn encode_should_depend_on_protocol() {
+        let v = RespValue::Map(vec![(bulk("a"), RespValue::Null)]);
+        let mut buf = BytesMut::new();
+        v.encode(true, &mut buf);
+        assert_eq!(&buf[..], b"%1\r\n$1\r\na\r\n_\r\n");
+
+        buf.clear();
+        v.encode(false, &mut buf);
+        assert_eq!(&buf[..], b"*2\r\n$1\r\na\r\n$-1\r\n");
+
+        buf.clear();
+        RespValue::Boolean(true).encode(false, &mut buf);
+        assert_eq!(&buf[..], b":1\r\n");
+    }
+}
@@ -1,12 +1,17 @@
-use crate::{read_frame, FrameCoder, KvError};
+use crate::{
+    metrics, CompressionCodec, FrameCoder, FrameDecoder, KvError, COMPRESSION_LIMIT, LEN_LEN,
+    MAX_FRAME,
+};
 use bytes::BytesMut;
-use futures::{ready, FutureExt, Sink, Stream};
+use futures::{ready, Sink, Stream};
 use std::{
+    io::{self, ErrorKind::UnexpectedEof},
     marker::PhantomData,
     pin::Pin,
     task::{Context, Poll},
 };
 use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::{codec::Decoder, io::poll_read_buf};
 
 /// 处理KV server prost frame的stream
 pub struct ProstStream<S, In, Out> {
@@ -18,6 +23,13 @@ pub struct ProstStream<S, In, Out> {
     written: usize,
     // 读缓存
     rbuf: BytesMut,
+    // 从读缓存中解出 frame
+    decoder: FrameDecoder,
+    // 发送时使用的压缩算法和压缩的阈值，读取时根据 frame header 解压缩
+    codec: CompressionCodec,
+    compression_limit: usize,
+    // 对端能接收的最大 frame
+    send_limit: usize,
 
     // 类型占位符
     _in: PhantomData<In>,
@@ -35,10 +47,35 @@ where
             written: 0,
             wbuf: BytesMut::new(),
             rbuf: BytesMut::new(),
+            decoder: FrameDecoder::default(),
+            codec: CompressionCodec::Gzip,
+            compression_limit: COMPRESSION_LIMIT,
+            send_limit: MAX_FRAME,
             _in: PhantomData::default(),
             _out: PhantomData::default(),
         }
     }
+
+    /// 设置能接收的最大 frame
+    pub fn set_max_frame(&mut self, max_frame: usize) {
+        self.decoder.set_max_frame(max_frame);
+    }
+
+    pub fn max_frame(&self) -> usize {
+        self.decoder.max_frame()
+    }
+
+    /// 设置对端能接收的最大 frame，更大的 frame 发送时返回 `KvError::FrameError`，
+    /// 它不会被写入，stream 可以继续使用
+    pub fn set_send_limit(&mut self, limit: usize) {
+        self.send_limit = limit;
+    }
+
+    /// 设置发送时使用的压缩算法和压缩的阈值
+    pub fn set_compression(&mut self, codec: CompressionCodec, limit: usize) {
+        self.codec = codec;
+        self.compression_limit = limit;
+    }
 }
 
 // 一般来说，如果我们的stream是Unpin，最好实现一下
@@ -53,22 +90,25 @@ where
     /// 当调用next()时，得到Result<In, KvError>
     type Item = Result<In, KvError>;
 
-    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        // 上一次调用结束后，rbuf应该为空
-        assert!(self.rbuf.len() == 0);
-
-        // 从rbuf中分离出rest(摆脱对self的引用)
-        let mut rest = self.rbuf.split_off(0);
-
-        // 使用read_frame来获取数据
-        let fut = read_frame(&mut self.stream, &mut rest);
-        ready!(Box::pin(fut).poll_unpin(cx))?;
-
-        // 拿到一个frame的数据，把buffer合并回去
-        self.rbuf.unsplit(rest);
-
-        //调用decode_frame获取解析后的数据
-        Poll::Ready(Some(In::decode_frame(&mut self.rbuf)))
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            // 读缓存中有完整的 frame，直接 decode
+            if let Some(payload) = this.decoder.decode(&mut this.rbuf)? {
+                metrics::BYTES_IN.inc_by((LEN_LEN + payload.len()) as _);
+                return Poll::Ready(Some(In::decode(payload).map_err(Into::into)));
+            }
+
+            // 否则从 stream 中读取更多的数据
+            let n = ready!(poll_read_buf(Pin::new(&mut this.stream), cx, &mut this.rbuf))?;
+            if n == 0 {
+                // 在两个 frame 之间关闭是正常结束，否则 frame 是不完整的
+                return match this.rbuf.is_empty() && this.decoder.is_idle() {
+                    true => Poll::Ready(None),
+                    false => Poll::Ready(Some(Err(io::Error::from(UnexpectedEof).into()))),
+                };
+            }
+        }
     }
 }
 
@@ -88,7 +128,16 @@ where
 
     fn start_send(self: Pin<&mut Self>, item: &Out) -> Result<(), Self::Error> {
         let this = self.get_mut();
-        item.encode_frame(&mut this.wbuf)?;
+        // 对端同时限制解压缩之前和之后的长度
+        if item.encoded_len() > this.send_limit {
+            return Err(KvError::FrameError);
+        }
+        let start = this.wbuf.len();
+        item.encode_frame_with(&mut this.wbuf, this.codec, this.compression_limit)?;
+        if this.wbuf.len() - start - LEN_LEN > this.send_limit {
+            this.wbuf.truncate(start);
+            return Err(KvError::FrameError);
+        }
         Ok(())
     }
 
@@ -99,6 +148,7 @@ where
         while this.written != this.wbuf.len() {
             let n = ready!(Pin::new(&mut this.stream).poll_write(cx, &this.wbuf[this.written..]))?;
             this.written += n;
+            metrics::BYTES_OUT.inc_by(n as _);
         }
 
         // 清除wbuf
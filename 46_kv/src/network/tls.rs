@@ -1,25 +1,38 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
 use std::io::Cursor;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
 
 use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::{task::JoinHandle, time};
+use tokio_rustls::rustls::sign::{self, CertifiedKey};
 use tokio_rustls::rustls::{internal::pemfile, Certificate, ClientConfig, ServerConfig};
-use tokio_rustls::rustls::{AllowAnyAuthenticatedClient, NoClientAuth, PrivateKey, RootCertStore};
-use tokio_rustls::webpki::DNSNameRef;
+use tokio_rustls::rustls::{
+    AllowAnyAuthenticatedClient, ClientHello, DistinguishedNames, NoClientAuth, PrivateKey,
+    ResolvesServerCert, RootCertStore, Session, TLSError,
+};
+use tokio_rustls::rustls::{ClientCertVerified, ClientCertVerifier};
+use tokio_rustls::webpki::{DNSName, DNSNameRef};
 use tokio_rustls::TlsConnector;
 use tokio_rustls::{
     client::TlsStream as ClientTlsStream, server::TlsStream as ServerTlsStream, TlsAcceptor,
 };
-use tracing::instrument;
+use tracing::{info, instrument, warn};
+use x509_parser::pem::Pem;
 
-use crate::KvError;
+use crate::{KvError, ServerTlsConfig};
 
 /// KV Server自己的ALPN（Application-Layer Protocol Negotiation）
-const ALPN_KV: &str = "kv";
+pub(crate) const ALPN_KV: &str = "kv";
 
 /// 存放TLS ServerConfig并提供方法accept，把底层协议转换成TLS
 #[derive(Clone)]
 pub struct TlsServerAcceptor {
-    inner: Arc<ServerConfig>,
+    /// 当前使用的 ServerConfig，重新加载证书时整体替换
+    inner: Arc<RwLock<Arc<ServerConfig>>>,
+    /// 协商的 ALPN 协议
+    protocols: Arc<Vec<Vec<u8>>>,
 }
 
 /// 存放TLS Client并提供方法connect把底层的协议转换成TLS
@@ -92,32 +105,64 @@ impl TlsServerAcceptor {
     /// 加载server cert / CA cert，生成ServerConfig
     #[instrument(name = "tls_acceptor_new", skip_all)]
     pub fn new(cert: &str, key: &str, client_ca: Option<&str>) -> Result<Self, KvError> {
-        let certs = load_certs(cert)?;
-        let key = load_key(key)?;
-
-        let mut config = match client_ca {
-            None => ServerConfig::new(NoClientAuth::new()),
-            Some(cert) => {
-                // 如果客户端证书是某个CA证书签发的，则吧这个CA证书加载到信任链中
-                let mut cert = Cursor::new(cert);
-                let mut client_root_cert_store = RootCertStore::empty();
-                client_root_cert_store
-                    .add_pem_file(&mut cert)
-                    .map_err(|_| KvError::CertificateParseError("CA", "cert"))?;
-
-                let client_auth = AllowAnyAuthenticatedClient::new(client_root_cert_store);
-                ServerConfig::new(client_auth)
-            }
-        };
-
-        // 加载服务证书
-        config
-            .set_single_cert(certs, key)
-            .map_err(|_| KvError::CertificateParseError("server", "cert"))?;
-        config.set_protocols(&[Vec::from(&ALPN_KV[..])]);
+        Self::from_config(&ServerTlsConfig {
+            cert: cert.into(),
+            key: key.into(),
+            ca: client_ca.map(|s| s.into()),
+            ..Default::default()
+        })
+    }
 
+    /// 根据配置加载默认证书、SNI 证书、客户端 CA 和 CRL，生成ServerConfig
+    #[instrument(name = "tls_acceptor_from_config", skip_all)]
+    pub fn from_config(config: &ServerTlsConfig) -> Result<Self, KvError> {
+        let protocols = vec![Vec::from(ALPN_KV)];
+        let inner = build_server_config(&config.resolve()?, &protocols)?;
         Ok(Self {
-            inner: Arc::new(config),
+            inner: Arc::new(RwLock::new(Arc::new(inner))),
+            protocols: Arc::new(protocols),
+        })
+    }
+
+    /// 使用同样的证书和客户端认证，但是协商另外的 ALPN 协议
+    ///
+    /// 和原来的 acceptor 共享证书，重新加载证书后同时生效
+    pub fn with_protocols(&self, protocols: &[&str]) -> Self {
+        let protocols: Vec<_> = protocols.iter().map(|p| p.as_bytes().to_vec()).collect();
+        Self {
+            inner: self.inner.clone(),
+            protocols: Arc::new(protocols),
+        }
+    }
+
+    /// 重新加载证书，之后的连接使用新的证书；加载失败时保留原来的证书
+    #[instrument(name = "tls_acceptor_reload", skip_all)]
+    pub fn reload(&self, config: &ServerTlsConfig) -> Result<(), KvError> {
+        let inner = build_server_config(&config.resolve()?, &self.protocols)?;
+        *self.inner.write().unwrap() = Arc::new(inner);
+        Ok(())
+    }
+
+    /// 每隔 interval 检查证书文件的修改时间，有变化时重新加载
+    pub fn watch(&self, config: ServerTlsConfig, interval: Duration) -> JoinHandle<()> {
+        let acceptor = self.clone();
+        tokio::spawn(async move {
+            let mut modified = files_modified(&config);
+            loop {
+                time::sleep(interval).await;
+                let current = files_modified(&config);
+                if current == modified {
+                    continue;
+                }
+                // 文件可能还在写入，加载失败时下一次检查会再次尝试
+                match acceptor.reload(&config) {
+                    Ok(()) => {
+                        info!("TLS certificates reloaded");
+                        modified = current;
+                    }
+                    Err(e) => warn!("Failed to reload TLS certificates: {:?}", e),
+                }
+            }
         })
     }
 
@@ -127,9 +172,204 @@ impl TlsServerAcceptor {
     where
         S: AsyncRead + AsyncWrite + Unpin + Send,
     {
-        let acceptor = TlsAcceptor::from(self.inner.clone());
+        let acceptor = TlsAcceptor::from(self.config());
         Ok(acceptor.accept(stream).await?)
     }
+
+    // 当前的 ServerConfig，ALPN 协议不同时复制一份
+    fn config(&self) -> Arc<ServerConfig> {
+        let config = self.inner.read().unwrap().clone();
+        if config.alpn_protocols == *self.protocols {
+            return config;
+        }
+        let mut config = (*config).clone();
+        config.set_protocols(&self.protocols);
+        Arc::new(config)
+    }
+}
+
+// 生成 ServerConfig，config 中的证书都已经是 PEM 字符串
+fn build_server_config(
+    config: &ServerTlsConfig,
+    protocols: &[Vec<u8>],
+) -> Result<ServerConfig, KvError> {
+    let mut cas = config.client_cas().peekable();
+    let verifier = if cas.peek().is_none() {
+        NoClientAuth::new()
+    } else {
+        // 如果客户端证书是某个CA证书签发的，则吧这个CA证书加载到信任链中
+        let mut client_root_cert_store = RootCertStore::empty();
+        for ca in cas {
+            let mut cert = Cursor::new(ca);
+            client_root_cert_store
+                .add_pem_file(&mut cert)
+                .map_err(|_| KvError::CertificateParseError("CA", "cert"))?;
+        }
+
+        let client_auth = AllowAnyAuthenticatedClient::new(client_root_cert_store);
+        if config.crls.is_empty() {
+            client_auth
+        } else {
+            let revocations = Revocations::load(&config.crls)?;
+            Arc::new(RevocationVerifier::new(client_auth, revocations))
+        }
+    };
+
+    let mut resolver = SniResolver::new(certified_key(&config.cert, &config.key, "server")?);
+    for sni in &config.sni {
+        resolver.add(&sni.name, certified_key(&sni.cert, &sni.key, "sni")?)?;
+    }
+
+    let mut server_config = ServerConfig::new(verifier);
+    server_config.cert_resolver = Arc::new(resolver);
+    server_config.set_protocols(protocols);
+    Ok(server_config)
+}
+
+fn certified_key(cert: &str, key: &str, name: &'static str) -> Result<CertifiedKey, KvError> {
+    let certs = load_certs(cert)?;
+    if certs.is_empty() {
+        return Err(KvError::CertificateParseError(name, "cert"));
+    }
+    let key = load_key(key)?;
+    let key = sign::any_supported_type(&key).map_err(|_| KvError::CertificateParseError(name, "key"))?;
+    Ok(CertifiedKey::new(certs, Arc::new(key)))
+}
+
+// 证书文件的修改时间，文件不存在或者读取失败时为 None
+fn files_modified(config: &ServerTlsConfig) -> Vec<Option<SystemTime>> {
+    config
+        .files()
+        .into_iter()
+        .map(|f| fs::metadata(f).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+/// 根据客户端发送的 SNI 选择服务器证书，没有匹配时使用默认证书
+struct SniResolver {
+    default: CertifiedKey,
+    certs: HashMap<String, CertifiedKey>,
+}
+
+impl SniResolver {
+    fn new(default: CertifiedKey) -> Self {
+        Self {
+            default,
+            certs: HashMap::new(),
+        }
+    }
+
+    // 添加 name 对应的证书，证书必须对 name 有效
+    fn add(&mut self, name: &str, cert: CertifiedKey) -> Result<(), KvError> {
+        let dns = DNSNameRef::try_from_ascii_str(name)
+            .map_err(|_| KvError::InvalidConfig(format!("invalid sni name: {}", name)))?;
+        cert.cross_check_end_entity_cert(Some(dns))
+            .map_err(|_| KvError::CertificateParseError("sni", "cert"))?;
+        self.certs.insert(name.to_ascii_lowercase(), cert);
+        Ok(())
+    }
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<CertifiedKey> {
+        let cert = client_hello
+            .server_name()
+            .and_then(|name| {
+                let name: &str = name.into();
+                self.certs.get(&name.to_ascii_lowercase())
+            })
+            .unwrap_or(&self.default);
+        Some(cert.clone())
+    }
+}
+
+/// CRL 中吊销的证书，用签发者和序列号标识
+///
+/// CRL 来自本地的配置，这里只解析不验证它的签名
+#[derive(Debug, Default)]
+struct Revocations(HashSet<(Vec<u8>, Vec<u8>)>);
+
+impl Revocations {
+    // 从 PEM 格式的 CRL 中加载，一个 PEM 字符串中可以有多个 CRL
+    fn load(crls: &[String]) -> Result<Self, KvError> {
+        let mut revoked = HashSet::new();
+        for crl in crls {
+            for pem in Pem::iter_from_buffer(crl.as_bytes()) {
+                let pem = pem.map_err(|_| KvError::CertificateParseError("CRL", "file"))?;
+                let (_, crl) = x509_parser::parse_x509_crl(&pem.contents)
+                    .map_err(|_| KvError::CertificateParseError("CRL", "file"))?;
+                let issuer = crl.issuer().as_raw();
+                for cert in crl.iter_revoked_certificates() {
+                    revoked.insert((issuer.to_vec(), cert.raw_serial().to_vec()));
+                }
+            }
+        }
+        Ok(Self(revoked))
+    }
+
+    // DER 格式的证书是否被吊销
+    fn is_revoked(&self, der: &[u8]) -> bool {
+        match x509_parser::parse_x509_certificate(der) {
+            Ok((_, cert)) => {
+                let key = (cert.issuer().as_raw().to_vec(), cert.raw_serial().to_vec());
+                self.0.contains(&key)
+            }
+            Err(_) => true,
+        }
+    }
+}
+
+/// 先用 CA 验证客户端证书，再检查它是否被吊销
+struct RevocationVerifier {
+    inner: Arc<dyn ClientCertVerifier>,
+    revocations: Revocations,
+}
+
+impl RevocationVerifier {
+    fn new(inner: Arc<dyn ClientCertVerifier>, revocations: Revocations) -> Self {
+        Self { inner, revocations }
+    }
+}
+
+impl ClientCertVerifier for RevocationVerifier {
+    fn offer_client_auth(&self) -> bool {
+        self.inner.offer_client_auth()
+    }
+
+    fn client_auth_mandatory(&self, sni: Option<&DNSName>) -> Option<bool> {
+        self.inner.client_auth_mandatory(sni)
+    }
+
+    fn client_auth_root_subjects(&self, sni: Option<&DNSName>) -> Option<DistinguishedNames> {
+        self.inner.client_auth_root_subjects(sni)
+    }
+
+    fn verify_client_cert(
+        &self,
+        presented_certs: &[Certificate],
+        sni: Option<&DNSName>,
+    ) -> Result<ClientCertVerified, TLSError> {
+        let verified = self.inner.verify_client_cert(presented_certs, sni)?;
+        match presented_certs.first() {
+            Some(cert) if self.revocations.is_revoked(&cert.0) => {
+                Err(TLSError::General("client certificate revoked".into()))
+            }
+            _ => Ok(verified),
+        }
+    }
+}
+
+/// 从客户端证书的 CN 中获取客户端的身份，没有客户端证书时返回 None
+pub fn peer_identity<S>(stream: &ServerTlsStream<S>) -> Option<String> {
+    let certs = stream.get_ref().1.get_peer_certificates()?;
+    cert_identity(&certs.first()?.0)
+}
+
+/// 从 DER 格式的证书中获取 CN
+pub(crate) fn cert_identity(der: &[u8]) -> Option<String> {
+    let (_, cert) = x509_parser::parse_x509_certificate(der).ok()?;
+    let cn = cert.subject().iter_common_name().next()?;
+    cn.as_str().ok().map(|s| s.to_string())
 }
 
 fn load_certs(cert: &str) -> Result<Vec<Certificate>, KvError> {
@@ -192,6 +432,7 @@ mod tests {
     use crate::tls_utils::{tls_acceptor, tls_connector};
 
     use super::*;
+    use crate::SniCertConfig;
     use anyhow::Result;
     use std::net::SocketAddr;
     use tokio::{
@@ -241,6 +482,138 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn cert_identity_should_be_client_cert_cn() {
+        let certs = load_certs(include_str!("../../fixtures/client.cert")).unwrap();
+        assert_eq!(cert_identity(&certs[0].0), Some("awesome-device-id".to_string()));
+    }
+
+    #[tokio::test]
+    async fn tls_should_select_cert_by_sni() -> Result<()> {
+        // 默认证书是 kvadmin.acme.inc，kvserver.acme.inc 通过 SNI 选择
+        let config = ServerTlsConfig {
+            cert: include_str!("../../fixtures/admin.cert").into(),
+            key: include_str!("../../fixtures/admin.key").into(),
+            sni: vec![SniCertConfig {
+                name: "kvserver.acme.inc".into(),
+                cert: include_str!("../../fixtures/server.cert").into(),
+                key: include_str!("../../fixtures/server.key").into(),
+            }],
+            ..Default::default()
+        };
+        let addr = start_echo_server(TlsServerAcceptor::from_config(&config)?).await?;
+
+        for domain in ["kvserver.acme.inc", "kvadmin.acme.inc"] {
+            let mut connector = tls_connector(false)?;
+            connector.domain = Arc::new(domain.into());
+            echo(&connector, addr).await?;
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn sni_cert_should_match_name() {
+        let config = ServerTlsConfig {
+            cert: include_str!("../../fixtures/server.cert").into(),
+            key: include_str!("../../fixtures/server.key").into(),
+            sni: vec![SniCertConfig {
+                name: "kvserver.acme.inc".into(),
+                cert: include_str!("../../fixtures/admin.cert").into(),
+                key: include_str!("../../fixtures/admin.key").into(),
+            }],
+            ..Default::default()
+        };
+        assert!(TlsServerAcceptor::from_config(&config).is_err());
+    }
+
+    #[tokio::test]
+    async fn tls_should_use_reloaded_cert() -> Result<()> {
+        let mut config = ServerTlsConfig {
+            cert: include_str!("../../fixtures/admin.cert").into(),
+            key: include_str!("../../fixtures/admin.key").into(),
+            ..Default::default()
+        };
+        let acceptor = TlsServerAcceptor::from_config(&config)?;
+        let addr = start_echo_server(acceptor.clone()).await?;
+
+        let connector = tls_connector(false)?;
+        assert!(echo(&connector, addr).await.is_err());
+
+        config.cert = include_str!("../../fixtures/server.cert").into();
+        config.key = include_str!("../../fixtures/server.key").into();
+        acceptor.reload(&config)?;
+        echo(&connector, addr).await?;
+
+        // 加载失败时保留原来的证书
+        config.cert = "fixtures/missing.cert".into();
+        assert!(acceptor.reload(&config).is_err());
+        echo(&connector, addr).await?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn revoked_client_cert_should_be_detected() {
+        let crl = include_str!("../../fixtures/client.crl").to_string();
+        let revocations = Revocations::load(&[crl]).unwrap();
+        let client = load_certs(include_str!("../../fixtures/client.cert")).unwrap();
+        let server = load_certs(include_str!("../../fixtures/server.cert")).unwrap();
+        assert!(revocations.is_revoked(&client[0].0));
+        assert!(!revocations.is_revoked(&server[0].0));
+    }
+
+    #[test]
+    fn acceptor_should_load_multiple_client_cas_and_crls() {
+        let config = ServerTlsConfig {
+            cert: include_str!("../../fixtures/server.cert").into(),
+            key: include_str!("../../fixtures/server.key").into(),
+            ca: Some(include_str!("../../fixtures/ca.cert").into()),
+            client_cas: vec![include_str!("../../fixtures/ca.cert").into()],
+            crls: vec![include_str!("../../fixtures/client.crl").into()],
+            ..Default::default()
+        };
+        assert!(TlsServerAcceptor::from_config(&config).is_ok());
+
+        let config = ServerTlsConfig {
+            crls: vec!["-----BEGIN X509 CRL-----\nbad\n-----END X509 CRL-----".into()],
+            ..config
+        };
+        assert!(TlsServerAcceptor::from_config(&config).is_err());
+    }
+
+    // 连接 echo server 并检查返回的数据
+    async fn echo(connector: &TlsClientConnector, addr: SocketAddr) -> Result<()> {
+        let stream = TcpStream::connect(addr).await?;
+        let mut stream = connector.connect(stream).await?;
+        stream.write_all(b"hello world!").await?;
+        let mut buf = [0; 12];
+        stream.read_exact(&mut buf).await?;
+        assert_eq!(&buf, b"hello world!");
+        Ok(())
+    }
+
+    // 可以接受多个连接的 echo server
+    async fn start_echo_server(acceptor: TlsServerAcceptor) -> Result<SocketAddr> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    if let Ok(mut stream) = acceptor.accept(stream).await {
+                        let mut buf = [0; 12];
+                        stream.read_exact(&mut buf).await.unwrap();
+                        stream.write_all(&buf).await.unwrap();
+                    }
+                });
+            }
+        });
+        Ok(addr)
+    }
+
     async fn start_server(client_cert: bool) -> Result<SocketAddr> {
         let acceptor = tls_acceptor(client_cert)?;
 
@@ -0,0 +1,218 @@
+//! WebSocket 监听端口
+//!
+//! 每个 binary message 是一个 `FrameCoder` 格式的 frame，和 TCP 上的格式一致。
+//! `WsStream` 把 WebSocket 适配成 `AsyncRead` / `AsyncWrite`，这样可以直接用
+//! `ProstServerStream` 处理。和 yamux 的 stream 一样，一个 WebSocket 连接上的命令是
+//! 依次处理的，订阅之后连接上会一直推送订阅的消息，直到连接关闭。
+
+use crate::{peer_identity, KvError, ProstServerStream, Service, Storage, TlsServerAcceptor};
+use bytes::{Buf, Bytes, BytesMut};
+use futures::{ready, SinkExt, StreamExt};
+use std::{
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpListener,
+};
+use tokio_tungstenite::{accept_async, tungstenite::Message, WebSocketStream};
+use tracing::{info, warn};
+
+use super::http::ALPN_HTTP;
+
+/// 把 WebSocket 的 binary message 适配成字节流
+pub struct WsStream<S> {
+    inner: WebSocketStream<S>,
+    // 当前 message 中还没有读取的数据
+    rbuf: Bytes,
+    // 下一次 flush 时作为一个 message 发送的数据
+    wbuf: BytesMut,
+}
+
+impl<S> WsStream<S> {
+    pub fn new(inner: WebSocketStream<S>) -> Self {
+        Self {
+            inner,
+            rbuf: Bytes::new(),
+            wbuf: BytesMut::new(),
+        }
+    }
+}
+
+fn ws_error(e: tokio_tungstenite::tungstenite::Error) -> io::Error {
+    io::Error::other(e)
+}
+
+impl<S> AsyncRead for WsStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        loop {
+            if !self.rbuf.is_empty() {
+                let n = self.rbuf.len().min(buf.remaining());
+                buf.put_slice(&self.rbuf[..n]);
+                self.rbuf.advance(n);
+                return Poll::Ready(Ok(()));
+            }
+            match ready!(self.inner.poll_next_unpin(cx)) {
+                Some(Ok(Message::Binary(data))) => self.rbuf = data.into(),
+                // ping / pong 由 tungstenite 处理
+                Some(Ok(Message::Ping(_) | Message::Pong(_))) => {}
+                // 连接关闭，返回 EOF
+                Some(Ok(Message::Close(_))) | None => return Poll::Ready(Ok(())),
+                Some(Ok(msg)) => {
+                    let msg = format!("expect binary message, got {:?}", msg);
+                    return Poll::Ready(Err(io::Error::new(io::ErrorKind::InvalidData, msg)));
+                }
+                Some(Err(e)) => return Poll::Ready(Err(ws_error(e))),
+            }
+        }
+    }
+}
+
+impl<S> AsyncWrite for WsStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.wbuf.extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if !self.wbuf.is_empty() {
+            ready!(self.inner.poll_ready_unpin(cx)).map_err(ws_error)?;
+            let data = self.wbuf.split().to_vec();
+            self.inner
+                .start_send_unpin(Message::Binary(data))
+                .map_err(ws_error)?;
+        }
+        self.inner.poll_flush_unpin(cx).map_err(ws_error)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.as_mut().poll_flush(cx))?;
+        self.inner.poll_close_unpin(cx).map_err(ws_error)
+    }
+}
+
+/// 在一个连接上完成 WebSocket 握手并处理命令
+pub async fn serve_ws<S, Store>(
+    stream: S,
+    svc: Service<Store>,
+    peer: Option<SocketAddr>,
+    identity: Option<String>,
+) -> Result<(), KvError>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    Store: Storage,
+{
+    let ws = accept_async(stream)
+        .await
+        .map_err(|e| KvError::Internal(format!("WebSocket handshake failed: {}", e)))?;
+    let client = svc.register_client(peer, identity);
+    let stream = ProstServerStream::new(WsStream::new(ws), svc).with_client(client.info().clone());
+
+    // 连接正常结束，或者被管理命令 kill
+    tokio::select! {
+        result = stream.process() => result,
+        _ = client.killed() => Ok(()),
+    }
+}
+
+/// 启动 WebSocket 监听端口，和原生的监听端口使用同样的 TLS 证书和客户端认证
+pub async fn start_ws_server<Store: Storage>(
+    addr: &str,
+    svc: Service<Store>,
+    acceptor: TlsServerAcceptor,
+) -> Result<(), KvError> {
+    let acceptor = acceptor.with_protocols(&[ALPN_HTTP]);
+    let listener = TcpListener::bind(addr).await?;
+    info!("WebSocket listening on {}", addr);
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let svc = svc.clone();
+        let acceptor = acceptor.clone();
+        tokio::spawn(async move {
+            let stream = match acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(e) => return warn!("Failed to process TLS: {:?}", e),
+            };
+            let identity = peer_identity(&stream);
+            if let Err(e) = serve_ws(stream, svc, Some(peer), identity).await {
+                warn!("WebSocket connection {} failed: {:?}", peer, e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        assert_res_ok, CommandRequest, CommandResponse, FrameCoder, MemTable, ServiceInner, Value,
+    };
+    use tokio::io::{duplex, DuplexStream};
+    use tokio_tungstenite::client_async;
+
+    async fn connect(svc: &Service) -> WebSocketStream<DuplexStream> {
+        let (server, client) = duplex(64 * 1024);
+        tokio::spawn(serve_ws(server, svc.clone(), None, None));
+        let (ws, _) = client_async("ws://localhost/", client).await.unwrap();
+        ws
+    }
+
+    async fn send(ws: &mut WebSocketStream<DuplexStream>, cmd: CommandRequest) {
+        let mut buf = BytesMut::new();
+        cmd.encode_frame(&mut buf).unwrap();
+        ws.send(Message::Binary(buf.to_vec())).await.unwrap();
+    }
+
+    async fn recv(ws: &mut WebSocketStream<DuplexStream>) -> CommandResponse {
+        match ws.next().await {
+            Some(Ok(Message::Binary(data))) => {
+                CommandResponse::decode_frame(&mut BytesMut::from(&data[..])).unwrap()
+            }
+            msg => panic!("unexpected message: {:?}", msg),
+        }
+    }
+
+    #[tokio::test]
+    async fn ws_command_should_work() {
+        let svc: Service = ServiceInner::new(MemTable::new()).into();
+        let mut ws = connect(&svc).await;
+        send(&mut ws, CommandRequest::new_hset("t1", "k1", "v1".into())).await;
+        assert_res_ok(&recv(&mut ws).await, &[Value::default()], &[]);
+        send(&mut ws, CommandRequest::new_hget("t1", "k1")).await;
+        assert_res_ok(&recv(&mut ws).await, &["v1".into()], &[]);
+    }
+
+    #[tokio::test]
+    async fn ws_subscription_should_stream_messages() {
+        let svc: Service = ServiceInner::new(MemTable::new()).into();
+        let mut ws = connect(&svc).await;
+        send(&mut ws, CommandRequest::new_subscribe("lobby")).await;
+        // 第一个响应是订阅的 id
+        assert_eq!(recv(&mut ws).await.status, 200);
+
+        // 在另一个连接上发布消息
+        let mut publisher = connect(&svc).await;
+        let data = vec!["hello".into(), 42.into()];
+        send(&mut publisher, CommandRequest::new_publish("lobby", data.clone())).await;
+        assert_res_ok(&recv(&mut publisher).await, &[1.into()], &[]);
+
+        assert_res_ok(&recv(&mut ws).await, &data, &[]);
+    }
+}
@@ -1,7 +1,11 @@
 #[derive(PartialOrd)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct CommandRequest {
-    #[prost(oneof="command_request::RequestData", tags="1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12")]
+    /// 请求的 deadline：服务器收到请求之后 timeout_ms 毫秒，0 表示没有 deadline。
+    /// 超过 deadline 时服务器停止执行，返回 504
+    #[prost(uint64, tag="100")]
+    pub timeout_ms: u64,
+    #[prost(oneof="command_request::RequestData", tags="1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31, 32, 33, 34, 35, 36, 37, 38, 39, 40, 41, 42, 43, 44")]
     pub request_data: ::core::option::Option<command_request::RequestData>,
 }
 /// Nested message and enum types in `CommandRequest`.
@@ -33,8 +37,179 @@ pub mod command_request {
         Unsubscribe(super::Unsubscribe),
         #[prost(message, tag="12")]
         Publish(super::Publish),
+        #[prost(message, tag="13")]
+        ListTables(super::ListTables),
+        #[prost(message, tag="14")]
+        Dbsize(super::Dbsize),
+        #[prost(message, tag="15")]
+        Info(super::Info),
+        #[prost(message, tag="16")]
+        ClientList(super::ClientList),
+        #[prost(message, tag="17")]
+        ClientKill(super::ClientKill),
+        #[prost(message, tag="18")]
+        CreateTable(super::CreateTable),
+        #[prost(message, tag="19")]
+        DropTable(super::DropTable),
+        #[prost(message, tag="20")]
+        RenameTable(super::RenameTable),
+        #[prost(message, tag="21")]
+        Backup(super::Backup),
+        #[prost(message, tag="22")]
+        Restore(super::Restore),
+        #[prost(message, tag="23")]
+        Hfind(super::Hfind),
+        #[prost(message, tag="24")]
+        Lpush(super::Lpush),
+        #[prost(message, tag="25")]
+        Rpush(super::Rpush),
+        #[prost(message, tag="26")]
+        Lpop(super::Lpop),
+        #[prost(message, tag="27")]
+        Rpop(super::Rpop),
+        #[prost(message, tag="28")]
+        Lrange(super::Lrange),
+        #[prost(message, tag="29")]
+        Fget(super::Fget),
+        #[prost(message, tag="30")]
+        Fset(super::Fset),
+        #[prost(message, tag="31")]
+        Fdel(super::Fdel),
+        #[prost(message, tag="32")]
+        Zadd(super::Zadd),
+        #[prost(message, tag="33")]
+        Zrem(super::Zrem),
+        #[prost(message, tag="34")]
+        Zscore(super::Zscore),
+        #[prost(message, tag="35")]
+        Zrank(super::Zrank),
+        #[prost(message, tag="36")]
+        Zrange(super::Zrange),
+        #[prost(message, tag="37")]
+        Zrangebyscore(super::Zrangebyscore),
+        #[prost(message, tag="38")]
+        Eval(super::Eval),
+        #[prost(message, tag="39")]
+        Hsetnx(super::Hsetnx),
+        #[prost(message, tag="40")]
+        Hcas(super::Hcas),
+        #[prost(message, tag="41")]
+        Lock(super::Lock),
+        #[prost(message, tag="42")]
+        Unlock(super::Unlock),
+        #[prost(message, tag="43")]
+        RenewLock(super::RenewLock),
+        #[prost(message, tag="44")]
+        Hello(super::Hello),
     }
 }
+// 以下是管理命令，需要admin权限
+
+/// 列出所有的table
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ListTables {
+}
+/// 返回table中key的数量，如果table为空，返回每个table的key数量
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Dbsize {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+}
+/// 返回服务器信息：运行时间，版本，存储后端，数据大小等
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Info {
+}
+/// 列出所有连接的客户端
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ClientList {
+}
+/// 断开某个客户端的连接
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ClientKill {
+    #[prost(uint64, tag="1")]
+    pub id: u64,
+}
+/// table的选项，0表示不限制
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct TableOptions {
+    /// 新写入的key默认的过期时间（秒）
+    #[prost(uint64, tag="1")]
+    pub default_ttl: u64,
+    /// table中最多可以存放的key的数量
+    #[prost(uint64, tag="2")]
+    pub max_keys: u64,
+    /// table中value的类型：string/binary/integer/float/bool/list/map/timestamp，空表示不限制
+    #[prost(string, tag="3")]
+    pub value_type: ::prost::alloc::string::String,
+    /// table上的二级索引
+    #[prost(message, repeated, tag="4")]
+    pub indexes: ::prost::alloc::vec::Vec<IndexSpec>,
+}
+/// 二级索引，索引value或者value中的某个字段
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct IndexSpec {
+    /// 索引的名字，Hfind时使用
+    #[prost(string, tag="1")]
+    pub name: ::prost::alloc::string::String,
+    /// 被索引的字段，空表示整个value，嵌套的字段用a.b.c表示
+    #[prost(string, tag="2")]
+    pub field: ::prost::alloc::string::String,
+}
+/// 创建table，如果table已经存在则报错
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct CreateTable {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(message, optional, tag="2")]
+    pub options: ::core::option::Option<TableOptions>,
+}
+/// 删除table和其中所有的数据
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DropTable {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+}
+/// 重命名table
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RenameTable {
+    #[prost(string, tag="1")]
+    pub from: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub to: ::prost::alloc::string::String,
+}
+/// 把所有的数据备份到服务器上的文件中，返回备份的table和key的数量
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Backup {
+    #[prost(string, tag="1")]
+    pub path: ::prost::alloc::string::String,
+}
+/// 从服务器上的备份文件恢复数据，已经存在的key会被覆盖
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Restore {
+    #[prost(string, tag="1")]
+    pub path: ::prost::alloc::string::String,
+}
+/// 备份文件中table的记录，后面跟着这个table的所有Kvpair
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct BackupTable {
+    #[prost(string, tag="1")]
+    pub name: ::prost::alloc::string::String,
+    #[prost(message, optional, tag="2")]
+    pub options: ::core::option::Option<TableOptions>,
+}
 /// subscribe到某个主题，任何发布到这个主题的数据都会被收到
 /// 成功后，第一个返回的CommandResponse，我们返回一个唯一的subscription id
 #[derive(PartialOrd)]
@@ -98,7 +273,7 @@ pub struct Hmget {
 #[derive(PartialOrd)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Value {
-    #[prost(oneof="value::Value", tags="1, 2, 3, 4, 5")]
+    #[prost(oneof="value::Value", tags="1, 2, 3, 4, 5, 6, 7, 8, 9")]
     pub value: ::core::option::Option<value::Value>,
 }
 /// Nested message and enum types in `Value`.
@@ -116,8 +291,36 @@ pub mod value {
         Float(f64),
         #[prost(bool, tag="5")]
         Bool(bool),
+        #[prost(message, tag="6")]
+        List(super::ValueList),
+        #[prost(message, tag="7")]
+        Map(super::ValueMap),
+        /// unix 毫秒
+        #[prost(int64, tag="8")]
+        Timestamp(i64),
+        #[prost(message, tag="9")]
+        Null(super::Null),
     }
 }
+/// 列表，元素可以是任意的Value
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ValueList {
+    #[prost(message, repeated, tag="1")]
+    pub values: ::prost::alloc::vec::Vec<Value>,
+}
+/// 字段名到Value的map，可以嵌套，构成文档
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ValueMap {
+    #[prost(btree_map="string, message", tag="1")]
+    pub fields: ::prost::alloc::collections::BTreeMap<::prost::alloc::string::String, Value>,
+}
+/// 显式的空值，和没有设置的Value不同
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Null {
+}
 #[derive(PartialOrd)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Kvpair {
@@ -134,6 +337,29 @@ pub struct Hset {
     #[prost(message, optional, tag="2")]
     pub pair: ::core::option::Option<Kvpair>,
 }
+/// key不存在时才写入，返回[是否写入, 当前的值]
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hsetnx {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(message, optional, tag="2")]
+    pub pair: ::core::option::Option<Kvpair>,
+}
+/// 当前的值等于expected时才写入，expected为空表示key不存在时才写入；
+/// value为空表示删除这个key。返回[是否写入, 当前的值]，key不存在时当前的值为null
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hcas {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(message, optional, tag="3")]
+    pub expected: ::core::option::Option<Value>,
+    #[prost(message, optional, tag="4")]
+    pub value: ::core::option::Option<Value>,
+}
 #[derive(PartialOrd)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Hmset {
@@ -174,3 +400,252 @@ pub struct Hmexist {
     #[prost(string, repeated, tag="2")]
     pub keys: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
 }
+/// 通过二级索引查找，返回索引值在[start, end]之间的kv pair，没有end时精确匹配start
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hfind {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub index: ::prost::alloc::string::String,
+    #[prost(message, optional, tag="3")]
+    pub start: ::core::option::Option<Value>,
+    #[prost(message, optional, tag="4")]
+    pub end: ::core::option::Option<Value>,
+}
+/// 在list头部加入values，key不存在时创建list，返回list的长度
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Lpush {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(message, repeated, tag="3")]
+    pub values: ::prost::alloc::vec::Vec<Value>,
+}
+/// 在list尾部加入values，key不存在时创建list，返回list的长度
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Rpush {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(message, repeated, tag="3")]
+    pub values: ::prost::alloc::vec::Vec<Value>,
+}
+/// 移除并返回list的第一个元素，list为空时删除key
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Lpop {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub key: ::prost::alloc::string::String,
+}
+/// 移除并返回list的最后一个元素，list为空时删除key
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Rpop {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub key: ::prost::alloc::string::String,
+}
+/// 返回list中[start, stop]之间的元素，负数表示从尾部开始计算
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Lrange {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(int64, tag="3")]
+    pub start: i64,
+    #[prost(int64, tag="4")]
+    pub stop: i64,
+}
+/// 获取map中的字段，嵌套的字段用a.b.c表示
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Fget {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(string, tag="3")]
+    pub field: ::prost::alloc::string::String,
+}
+/// 设置map中的字段，key或者中间的map不存在时会创建，返回旧的值
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Fset {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(string, tag="3")]
+    pub field: ::prost::alloc::string::String,
+    #[prost(message, optional, tag="4")]
+    pub value: ::core::option::Option<Value>,
+}
+/// 删除map中的字段，返回旧的值
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Fdel {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(string, tag="3")]
+    pub field: ::prost::alloc::string::String,
+}
+/// sorted set中的成员和分数
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ScoredMember {
+    #[prost(string, tag="1")]
+    pub member: ::prost::alloc::string::String,
+    #[prost(double, tag="2")]
+    pub score: f64,
+}
+/// 向sorted set加入成员，已有的成员更新分数，返回新加入的成员数量
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Zadd {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(message, repeated, tag="3")]
+    pub members: ::prost::alloc::vec::Vec<ScoredMember>,
+}
+/// 从sorted set删除成员，返回删除的成员数量
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Zrem {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(string, repeated, tag="3")]
+    pub members: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// 返回成员的分数
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Zscore {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(string, tag="3")]
+    pub member: ::prost::alloc::string::String,
+}
+/// 返回成员按分数从小到大的排名，从0开始
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Zrank {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(string, tag="3")]
+    pub member: ::prost::alloc::string::String,
+}
+/// 返回排名在[start, stop]之间的成员和分数，负数表示从尾部开始计算
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Zrange {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(int64, tag="3")]
+    pub start: i64,
+    #[prost(int64, tag="4")]
+    pub stop: i64,
+}
+/// 返回分数在[min, max]之间的成员和分数，跳过offset个后最多返回limit个，limit为0表示不限制
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Zrangebyscore {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(double, tag="3")]
+    pub min: f64,
+    #[prost(double, tag="4")]
+    pub max: f64,
+    #[prost(uint64, tag="5")]
+    pub offset: u64,
+    #[prost(uint64, tag="6")]
+    pub limit: u64,
+}
+/// 在服务器上原子地执行脚本，脚本中可以用(arg i)取得args中的参数
+/// max_steps限制脚本最多执行的步数，为0时使用默认值
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Eval {
+    #[prost(string, tag="1")]
+    pub script: ::prost::alloc::string::String,
+    #[prost(message, repeated, tag="2")]
+    pub args: ::prost::alloc::vec::Vec<Value>,
+    #[prost(uint64, tag="3")]
+    pub max_steps: u64,
+}
+/// 获取锁，owner标识锁的持有者，lease_ms是租约的时长
+/// 成功时返回单调递增的fencing token，锁被其他owner持有时返回409
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Lock {
+    #[prost(string, tag="1")]
+    pub name: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub owner: ::prost::alloc::string::String,
+    #[prost(uint64, tag="3")]
+    pub lease_ms: u64,
+}
+/// 释放锁，owner和token必须和获取锁时一致
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Unlock {
+    #[prost(string, tag="1")]
+    pub name: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub owner: ::prost::alloc::string::String,
+    #[prost(uint64, tag="3")]
+    pub token: u64,
+}
+/// 延长锁的租约，返回fencing token
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RenewLock {
+    #[prost(string, tag="1")]
+    pub name: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub owner: ::prost::alloc::string::String,
+    #[prost(uint64, tag="3")]
+    pub token: u64,
+    #[prost(uint64, tag="4")]
+    pub lease_ms: u64,
+}
+/// stream 开始时的握手，由 stream 处理，不会交给 Service
+/// codecs 是客户端支持的压缩算法，按优先级排列；version 是客户端的协议版本；
+/// commands 是客户端会使用的命令；max_frame 是客户端能接收的最大 frame。
+/// 服务器在 pairs 中返回协商的 codec / version / commands / max_frame，
+/// 版本不兼容时返回 426
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hello {
+    #[prost(string, repeated, tag="1")]
+    pub codecs: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    #[prost(uint32, tag="2")]
+    pub version: u32,
+    #[prost(string, repeated, tag="3")]
+    pub commands: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    #[prost(uint64, tag="4")]
+    pub max_frame: u64,
+}
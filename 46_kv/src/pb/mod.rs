@@ -1,12 +1,70 @@
 pub mod abi;
 
-use crate::KvError;
+use crate::{KvError, COMMANDS, PROTOCOL_VERSION};
 use abi::{command_request::RequestData, *};
 use bytes::Bytes;
 use http::StatusCode;
 use prost::Message;
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// 请求的 deadline 最长一天，客户端传入更大的 timeout_ms 时按一天计算
+pub const MAX_REQUEST_TIMEOUT: Duration = Duration::from_secs(24 * 60 * 60);
 
 impl CommandRequest {
+    /// 设置请求的 deadline
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout_ms = timeout.as_millis() as _;
+        self
+    }
+
+    /// 请求的超时时间，没有设置时返回 None，最长为 MAX_REQUEST_TIMEOUT
+    pub fn timeout(&self) -> Option<Duration> {
+        (self.timeout_ms > 0)
+            .then(|| Duration::from_millis(self.timeout_ms).min(MAX_REQUEST_TIMEOUT))
+    }
+
+    /// 数据命令访问的 table，其它命令返回 None
+    pub fn table(&self) -> Option<&str> {
+        let table = match self.request_data.as_ref()? {
+            RequestData::Hget(v) => &v.table,
+            RequestData::Hgetall(v) => &v.table,
+            RequestData::Hmget(v) => &v.table,
+            RequestData::Hset(v) => &v.table,
+            RequestData::Hmset(v) => &v.table,
+            RequestData::Hdel(v) => &v.table,
+            RequestData::Hmdel(v) => &v.table,
+            RequestData::Hexist(v) => &v.table,
+            RequestData::Hmexist(v) => &v.table,
+            RequestData::Hfind(v) => &v.table,
+            RequestData::Hsetnx(v) => &v.table,
+            RequestData::Hcas(v) => &v.table,
+            RequestData::Lpush(v) => &v.table,
+            RequestData::Rpush(v) => &v.table,
+            RequestData::Lpop(v) => &v.table,
+            RequestData::Rpop(v) => &v.table,
+            RequestData::Lrange(v) => &v.table,
+            RequestData::Fget(v) => &v.table,
+            RequestData::Fset(v) => &v.table,
+            RequestData::Fdel(v) => &v.table,
+            RequestData::Zadd(v) => &v.table,
+            RequestData::Zrem(v) => &v.table,
+            RequestData::Zscore(v) => &v.table,
+            RequestData::Zrank(v) => &v.table,
+            RequestData::Zrange(v) => &v.table,
+            RequestData::Zrangebyscore(v) => &v.table,
+            _ => return None,
+        };
+        Some(table)
+    }
+
+    /// 从现在开始计算的 deadline，没有设置超时或者溢出时返回 None
+    pub fn deadline(&self) -> Option<Instant> {
+        self.timeout().and_then(|t| Instant::now().checked_add(t))
+    }
+
     // 创建HSET命令
     pub fn new_hset(table: impl Into<String>, key: impl Into<String>, value: Value) -> Self {
         Self {
@@ -14,14 +72,43 @@ impl CommandRequest {
                 table: table.into(),
                 pair: Some(Kvpair::new(key, value)),
             })),
+            ..Default::default()
+        }
+    }
+    pub fn new_hsetnx(table: impl Into<String>, key: impl Into<String>, value: Value) -> Self {
+        Self {
+            request_data: Some(RequestData::Hsetnx(Hsetnx {
+                table: table.into(),
+                pair: Some(Kvpair::new(key, value)),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_hcas(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        expected: Option<Value>,
+        value: Option<Value>,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Hcas(Hcas {
+                table: table.into(),
+                key: key.into(),
+                expected,
+                value,
+            })),
+            ..Default::default()
         }
     }
+
     pub fn new_hmset(table: impl Into<String>, pairs: Vec<Kvpair>) -> Self {
         Self {
             request_data: Some(RequestData::Hmset(Hmset {
                 table: table.into(),
                 pairs: pairs,
             })),
+            ..Default::default()
         }
     }
 
@@ -32,6 +119,7 @@ impl CommandRequest {
                 table: table.into(),
                 key: key.into(),
             })),
+            ..Default::default()
         }
     }
 
@@ -40,6 +128,7 @@ impl CommandRequest {
             request_data: Some(RequestData::Hgetall(Hgetall {
                 table: table.into(),
             })),
+            ..Default::default()
         }
     }
 
@@ -49,6 +138,7 @@ impl CommandRequest {
                 table: table.into(),
                 keys: keys,
             })),
+            ..Default::default()
         }
     }
 
@@ -58,6 +148,7 @@ impl CommandRequest {
                 table: table.into(),
                 key: key.into(),
             })),
+            ..Default::default()
         }
     }
 
@@ -67,6 +158,7 @@ impl CommandRequest {
                 table: table.into(),
                 keys: keys,
             })),
+            ..Default::default()
         }
     }
     pub fn new_hexist(table: impl Into<String>, key: impl Into<String>) -> Self {
@@ -75,6 +167,7 @@ impl CommandRequest {
                 table: table.into(),
                 key: key.into(),
             })),
+            ..Default::default()
         }
     }
     pub fn new_hmexist(table: impl Into<String>, keys: Vec<String>) -> Self {
@@ -83,15 +176,16 @@ impl CommandRequest {
                 table: table.into(),
                 keys: keys,
             })),
+            ..Default::default()
         }
     }
 
     pub fn new_subscribe(name: impl Into<String>) -> Self {
-        Self { request_data: Some(RequestData::Subscribe(Subscribe{topic: name.into()})) }
+        Self { request_data: Some(RequestData::Subscribe(Subscribe{topic: name.into()})), ..Default::default() }
     }
 
     pub fn new_unsubscribe(name: impl Into<String>, id: u32) -> Self {
-        Self { request_data: Some(RequestData::Unsubscribe(Unsubscribe{topic: name.into(), id})) }
+        Self { request_data: Some(RequestData::Unsubscribe(Unsubscribe{topic: name.into(), id})), ..Default::default() }
     }
 
     pub fn new_publish(name: impl Into<String>, data: Vec<Value>) -> Self {
@@ -99,13 +193,415 @@ impl CommandRequest {
             request_data: Some(RequestData::Publish(Publish{
                 topic: name.into(),
                 data,
-            })) 
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_list_tables() -> Self {
+        Self { request_data: Some(RequestData::ListTables(ListTables {})), ..Default::default() }
+    }
+
+    pub fn new_dbsize(table: impl Into<String>) -> Self {
+        Self { request_data: Some(RequestData::Dbsize(Dbsize { table: table.into() })), ..Default::default() }
+    }
+
+    pub fn new_info() -> Self {
+        Self { request_data: Some(RequestData::Info(Info {})), ..Default::default() }
+    }
+
+    pub fn new_client_list() -> Self {
+        Self { request_data: Some(RequestData::ClientList(ClientList {})), ..Default::default() }
+    }
+
+    pub fn new_client_kill(id: u64) -> Self {
+        Self { request_data: Some(RequestData::ClientKill(ClientKill { id })), ..Default::default() }
+    }
+
+    pub fn new_create_table(table: impl Into<String>, options: TableOptions) -> Self {
+        Self {
+            request_data: Some(RequestData::CreateTable(CreateTable {
+                table: table.into(),
+                options: Some(options),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_drop_table(table: impl Into<String>) -> Self {
+        Self { request_data: Some(RequestData::DropTable(DropTable { table: table.into() })), ..Default::default() }
+    }
+
+    pub fn new_rename_table(from: impl Into<String>, to: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::RenameTable(RenameTable {
+                from: from.into(),
+                to: to.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_hfind(table: impl Into<String>, index: impl Into<String>, value: Value) -> Self {
+        Self {
+            request_data: Some(RequestData::Hfind(Hfind {
+                table: table.into(),
+                index: index.into(),
+                start: Some(value),
+                end: None,
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_hfind_range(
+        table: impl Into<String>,
+        index: impl Into<String>,
+        start: Value,
+        end: Value,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Hfind(Hfind {
+                table: table.into(),
+                index: index.into(),
+                start: Some(start),
+                end: Some(end),
+            })),
+            ..Default::default()
         }
     }
 
+    pub fn new_lpush(table: impl Into<String>, key: impl Into<String>, values: Vec<Value>) -> Self {
+        Self {
+            request_data: Some(RequestData::Lpush(Lpush {
+                table: table.into(),
+                key: key.into(),
+                values,
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_rpush(table: impl Into<String>, key: impl Into<String>, values: Vec<Value>) -> Self {
+        Self {
+            request_data: Some(RequestData::Rpush(Rpush {
+                table: table.into(),
+                key: key.into(),
+                values,
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_lpop(table: impl Into<String>, key: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Lpop(Lpop {
+                table: table.into(),
+                key: key.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_rpop(table: impl Into<String>, key: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Rpop(Rpop {
+                table: table.into(),
+                key: key.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_lrange(table: impl Into<String>, key: impl Into<String>, start: i64, stop: i64) -> Self {
+        Self {
+            request_data: Some(RequestData::Lrange(Lrange {
+                table: table.into(),
+                key: key.into(),
+                start,
+                stop,
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_fget(table: impl Into<String>, key: impl Into<String>, field: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Fget(Fget {
+                table: table.into(),
+                key: key.into(),
+                field: field.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_fset(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        field: impl Into<String>,
+        value: Value,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Fset(Fset {
+                table: table.into(),
+                key: key.into(),
+                field: field.into(),
+                value: Some(value),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_fdel(table: impl Into<String>, key: impl Into<String>, field: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Fdel(Fdel {
+                table: table.into(),
+                key: key.into(),
+                field: field.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_zadd(table: impl Into<String>, key: impl Into<String>, members: Vec<(String, f64)>) -> Self {
+        let members = members
+            .into_iter()
+            .map(|(member, score)| ScoredMember { member, score })
+            .collect();
+        Self {
+            request_data: Some(RequestData::Zadd(Zadd {
+                table: table.into(),
+                key: key.into(),
+                members,
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_zrem(table: impl Into<String>, key: impl Into<String>, members: Vec<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Zrem(Zrem {
+                table: table.into(),
+                key: key.into(),
+                members,
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_zscore(table: impl Into<String>, key: impl Into<String>, member: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Zscore(Zscore {
+                table: table.into(),
+                key: key.into(),
+                member: member.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_zrank(table: impl Into<String>, key: impl Into<String>, member: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Zrank(Zrank {
+                table: table.into(),
+                key: key.into(),
+                member: member.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_zrange(table: impl Into<String>, key: impl Into<String>, start: i64, stop: i64) -> Self {
+        Self {
+            request_data: Some(RequestData::Zrange(Zrange {
+                table: table.into(),
+                key: key.into(),
+                start,
+                stop,
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_zrangebyscore(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        min: f64,
+        max: f64,
+        offset: u64,
+        limit: u64,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Zrangebyscore(Zrangebyscore {
+                table: table.into(),
+                key: key.into(),
+                min,
+                max,
+                offset,
+                limit,
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_eval(script: impl Into<String>, args: Vec<Value>, max_steps: u64) -> Self {
+        Self {
+            request_data: Some(RequestData::Eval(Eval {
+                script: script.into(),
+                args,
+                max_steps,
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_lock(name: impl Into<String>, owner: impl Into<String>, lease_ms: u64) -> Self {
+        Self {
+            request_data: Some(RequestData::Lock(Lock {
+                name: name.into(),
+                owner: owner.into(),
+                lease_ms,
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_unlock(name: impl Into<String>, owner: impl Into<String>, token: u64) -> Self {
+        Self {
+            request_data: Some(RequestData::Unlock(Unlock {
+                name: name.into(),
+                owner: owner.into(),
+                token,
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_renew_lock(
+        name: impl Into<String>,
+        owner: impl Into<String>,
+        token: u64,
+        lease_ms: u64,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::RenewLock(RenewLock {
+                name: name.into(),
+                owner: owner.into(),
+                token,
+                lease_ms,
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_hello(codecs: Vec<String>, max_frame: usize) -> Self {
+        Self {
+            request_data: Some(RequestData::Hello(Hello {
+                codecs,
+                version: PROTOCOL_VERSION,
+                commands: COMMANDS.iter().map(|c| c.to_string()).collect(),
+                max_frame: max_frame as _,
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_backup(path: impl Into<String>) -> Self {
+        Self { request_data: Some(RequestData::Backup(Backup { path: path.into() })), ..Default::default() }
+    }
+
+    pub fn new_restore(path: impl Into<String>) -> Self {
+        Self { request_data: Some(RequestData::Restore(Restore { path: path.into() })), ..Default::default() }
+    }
+
     pub fn format(&self) -> String {
         format!("{:?}", self)
     }
+
+    /// 是否是锁命令，锁命令需要记录在连接的 session 中
+    pub fn is_lock(&self) -> bool {
+        matches!(
+            self.request_data,
+            Some(RequestData::Lock(_)) | Some(RequestData::Unlock(_)) | Some(RequestData::RenewLock(_))
+        )
+    }
+
+    /// 需要读写整个 store、执行时间很长的命令
+    pub fn is_blocking(&self) -> bool {
+        matches!(
+            self.request_data,
+            Some(RequestData::Backup(_)) | Some(RequestData::Restore(_))
+        )
+    }
+
+    /// 是否是需要admin权限的管理命令
+    pub fn is_admin(&self) -> bool {
+        matches!(
+            self.request_data,
+            Some(RequestData::ListTables(_))
+                | Some(RequestData::Dbsize(_))
+                | Some(RequestData::Info(_))
+                | Some(RequestData::ClientList(_))
+                | Some(RequestData::ClientKill(_))
+                | Some(RequestData::CreateTable(_))
+                | Some(RequestData::DropTable(_))
+                | Some(RequestData::RenameTable(_))
+                | Some(RequestData::Backup(_))
+                | Some(RequestData::Restore(_))
+        )
+    }
+
+    /// 命令的名字，用于 metrics 等场景
+    pub fn command_name(&self) -> &'static str {
+        match &self.request_data {
+            Some(RequestData::Hget(_)) => "hget",
+            Some(RequestData::Hgetall(_)) => "hgetall",
+            Some(RequestData::Hmget(_)) => "hmget",
+            Some(RequestData::Hset(_)) => "hset",
+            Some(RequestData::Hmset(_)) => "hmset",
+            Some(RequestData::Hsetnx(_)) => "hsetnx",
+            Some(RequestData::Hcas(_)) => "hcas",
+            Some(RequestData::Lock(_)) => "lock",
+            Some(RequestData::Unlock(_)) => "unlock",
+            Some(RequestData::RenewLock(_)) => "renew_lock",
+            Some(RequestData::Hello(_)) => "hello",
+            Some(RequestData::Hdel(_)) => "hdel",
+            Some(RequestData::Hmdel(_)) => "hmdel",
+            Some(RequestData::Hexist(_)) => "hexist",
+            Some(RequestData::Hmexist(_)) => "hmexist",
+            Some(RequestData::Hfind(_)) => "hfind",
+            Some(RequestData::Lpush(_)) => "lpush",
+            Some(RequestData::Rpush(_)) => "rpush",
+            Some(RequestData::Lpop(_)) => "lpop",
+            Some(RequestData::Rpop(_)) => "rpop",
+            Some(RequestData::Lrange(_)) => "lrange",
+            Some(RequestData::Fget(_)) => "fget",
+            Some(RequestData::Fset(_)) => "fset",
+            Some(RequestData::Fdel(_)) => "fdel",
+            Some(RequestData::Zadd(_)) => "zadd",
+            Some(RequestData::Zrem(_)) => "zrem",
+            Some(RequestData::Zscore(_)) => "zscore",
+            Some(RequestData::Zrank(_)) => "zrank",
+            Some(RequestData::Zrange(_)) => "zrange",
+            Some(RequestData::Zrangebyscore(_)) => "zrangebyscore",
+            Some(RequestData::Eval(_)) => "eval",
+            Some(RequestData::Subscribe(_)) => "subscribe",
+            Some(RequestData::Unsubscribe(_)) => "unsubscribe",
+            Some(RequestData::Publish(_)) => "publish",
+            Some(RequestData::ListTables(_)) => "list_tables",
+            Some(RequestData::Dbsize(_)) => "dbsize",
+            Some(RequestData::Info(_)) => "info",
+            Some(RequestData::ClientList(_)) => "client_list",
+            Some(RequestData::ClientKill(_)) => "client_kill",
+            Some(RequestData::CreateTable(_)) => "create_table",
+            Some(RequestData::DropTable(_)) => "drop_table",
+            Some(RequestData::RenameTable(_)) => "rename_table",
+            Some(RequestData::Backup(_)) => "backup",
+            Some(RequestData::Restore(_)) => "restore",
+            None => "none",
+        }
+    }
 }
 
 impl CommandResponse {
@@ -118,12 +614,110 @@ impl CommandResponse {
     pub fn format(&self) -> String {
         format!("{:?}", self)
     }
+
+    /// 成功时返回自己，失败时把状态码转换成对应的 KvError（和 From<KvError> 相反）
+    pub fn into_result(self) -> Result<Self, KvError> {
+        let status = StatusCode::from_u16(self.status as _);
+        if matches!(status, Ok(s) if s.is_success()) {
+            return Ok(self);
+        }
+        // 去掉 KvError 的 Display 加上的前缀，保留服务器给出的原因
+        let reason = |prefix: &str| {
+            let msg = self.message.as_str();
+            msg.strip_prefix(prefix).unwrap_or(msg).to_string()
+        };
+        Err(match status {
+            Ok(StatusCode::NOT_FOUND) => KvError::NotFound(reason("Not found: ")),
+            Ok(StatusCode::BAD_REQUEST) => match self.message.strip_prefix("Command is invalid: `") {
+                Some(msg) => KvError::InvalidCommand(msg.trim_end_matches('`').to_string()),
+                None => KvError::InvalidCommand(self.message),
+            },
+            Ok(StatusCode::FORBIDDEN) => KvError::PermissionDenied(reason("Permission denied: ")),
+            Ok(StatusCode::CONFLICT) => KvError::AlreadyExists(reason("Already exists: ")),
+            Ok(StatusCode::UNPROCESSABLE_ENTITY) => {
+                KvError::ConstraintViolation(reason("Constraint violation: "))
+            }
+            Ok(StatusCode::GATEWAY_TIMEOUT) => KvError::Timeout,
+            Ok(StatusCode::PAYLOAD_TOO_LARGE) => KvError::FrameError,
+            Ok(StatusCode::UPGRADE_REQUIRED) => KvError::IncompatibleProtocol(self.message),
+            _ => KvError::Internal(reason("Internal error: ")),
+        })
+    }
 }
 
 impl Value {
     pub fn format(&self) -> String {
         format!("{:?}", self)
     }
+
+    /// 显式的空值
+    pub fn null() -> Self {
+        Self {
+            value: Some(value::Value::Null(Null {})),
+        }
+    }
+
+    /// unix 毫秒表示的时间
+    pub fn timestamp(ms: i64) -> Self {
+        Self {
+            value: Some(value::Value::Timestamp(ms)),
+        }
+    }
+
+    /// 按 a.b.c 的路径取出嵌套 map 中的字段
+    pub fn get_field(&self, path: &str) -> Option<&Value> {
+        path.split('.').try_fold(self, |v, name| match &v.value {
+            Some(value::Value::Map(m)) => m.fields.get(name),
+            _ => None,
+        })
+    }
+
+    fn get_field_mut(&mut self, path: &str) -> Option<&mut Value> {
+        path.split('.').try_fold(self, |v, name| match &mut v.value {
+            Some(value::Value::Map(m)) => m.fields.get_mut(name),
+            _ => None,
+        })
+    }
+
+    /// 按路径设置字段，不存在的 map 会被创建，返回旧的值
+    pub fn set_field(&mut self, path: &str, value: Value) -> Result<Option<Value>, KvError> {
+        let (parent, name) = match path.rsplit_once('.') {
+            Some((parent, name)) => {
+                let mut map = self.as_map_mut()?;
+                for name in parent.split('.') {
+                    map = map.entry(name.into()).or_default().as_map_mut()?;
+                }
+                (map, name)
+            }
+            None => (self.as_map_mut()?, path),
+        };
+        Ok(parent.insert(name.into(), value))
+    }
+
+    /// 按路径删除字段，返回旧的值
+    pub fn remove_field(&mut self, path: &str) -> Result<Option<Value>, KvError> {
+        let (parent, name) = match path.rsplit_once('.') {
+            Some((parent, name)) => match self.get_field_mut(parent) {
+                Some(v) => (v, name),
+                None => return Ok(None),
+            },
+            None => (self, path),
+        };
+        Ok(parent.as_map_mut()?.remove(name))
+    }
+
+    // 没有设置的 Value 被当作空的 map
+    fn as_map_mut(&mut self) -> Result<&mut BTreeMap<String, Value>, KvError> {
+        match self.value {
+            None => self.value = Some(value::Value::Map(ValueMap::default())),
+            Some(value::Value::Map(_)) => {}
+            _ => return Err(KvError::InvalidCommand(format!("{} is not a map", self.format()))),
+        }
+        match &mut self.value {
+            Some(value::Value::Map(m)) => Ok(&mut m.fields),
+            _ => unreachable!(),
+        }
+    }
 }
 
 impl Kvpair {
@@ -189,6 +783,32 @@ impl From<Bytes> for Value {
     }
 }
 
+impl From<Vec<Value>> for Value {
+    fn from(values: Vec<Value>) -> Self {
+        Self {
+            value: Some(value::Value::List(ValueList { values })),
+        }
+    }
+}
+
+impl From<BTreeMap<String, Value>> for Value {
+    fn from(fields: BTreeMap<String, Value>) -> Self {
+        Self {
+            value: Some(value::Value::Map(ValueMap { fields })),
+        }
+    }
+}
+
+impl From<SystemTime> for Value {
+    fn from(t: SystemTime) -> Self {
+        let ms = match t.duration_since(UNIX_EPOCH) {
+            Ok(d) => d.as_millis() as i64,
+            Err(e) => -(e.duration().as_millis() as i64),
+        };
+        Value::timestamp(ms)
+    }
+}
+
 impl From<Vec<Value>> for CommandResponse {
     fn from(v: Vec<Value>) -> Self {
         Self {
@@ -220,7 +840,19 @@ impl From<KvError> for CommandResponse {
 
         match e {
             KvError::NotFound(_) => result.status = StatusCode::NOT_FOUND.as_u16() as _,
-            KvError::InvalidCommand(_) => result.status = StatusCode::BAD_REQUEST.as_u16() as _,
+            KvError::InvalidCommand(_) | KvError::ScriptError(_) => {
+                result.status = StatusCode::BAD_REQUEST.as_u16() as _
+            }
+            KvError::PermissionDenied(_) => result.status = StatusCode::FORBIDDEN.as_u16() as _,
+            KvError::AlreadyExists(_) => result.status = StatusCode::CONFLICT.as_u16() as _,
+            KvError::ConstraintViolation(_) => {
+                result.status = StatusCode::UNPROCESSABLE_ENTITY.as_u16() as _
+            }
+            KvError::Timeout => result.status = StatusCode::GATEWAY_TIMEOUT.as_u16() as _,
+            KvError::FrameError => result.status = StatusCode::PAYLOAD_TOO_LARGE.as_u16() as _,
+            KvError::IncompatibleProtocol(_) => {
+                result.status = StatusCode::UPGRADE_REQUIRED.as_u16() as _
+            }
             _ => {}
         }
         result
@@ -237,6 +869,41 @@ impl From<Vec<Kvpair>> for CommandResponse {
     }
 }
 
+impl TryFrom<Value> for Vec<Value> {
+    type Error = KvError;
+    fn try_from(v: Value) -> Result<Self, Self::Error> {
+        match v.value {
+            Some(value::Value::List(l)) => Ok(l.values),
+            _ => Err(KvError::ConvertError(v.format(), "List")),
+        }
+    }
+}
+
+impl TryFrom<Value> for BTreeMap<String, Value> {
+    type Error = KvError;
+    fn try_from(v: Value) -> Result<Self, Self::Error> {
+        match v.value {
+            Some(value::Value::Map(m)) => Ok(m.fields),
+            _ => Err(KvError::ConvertError(v.format(), "Map")),
+        }
+    }
+}
+
+impl TryFrom<Value> for SystemTime {
+    type Error = KvError;
+    fn try_from(v: Value) -> Result<Self, Self::Error> {
+        match v.value {
+            Some(value::Value::Timestamp(ms)) if ms >= 0 => {
+                Ok(UNIX_EPOCH + Duration::from_millis(ms as u64))
+            }
+            Some(value::Value::Timestamp(ms)) => {
+                Ok(UNIX_EPOCH - Duration::from_millis(ms.unsigned_abs()))
+            }
+            _ => Err(KvError::ConvertError(v.format(), "Timestamp")),
+        }
+    }
+}
+
 impl TryFrom<&[u8]> for Value {
     type Error = KvError;
     fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
@@ -274,6 +941,16 @@ impl TryFrom<Value> for Bytes {
     }
 }
 
+impl TryFrom<Value> for String {
+    type Error = KvError;
+    fn try_from(v: Value) -> Result<Self, Self::Error> {
+        match v.value {
+            Some(value::Value::String(s)) => Ok(s),
+            _ => Err(KvError::ConvertError(v.format(), "String")),
+        }
+    }
+}
+
 impl TryFrom<Value> for f64 {
     type Error = KvError;
     fn try_from(v: Value) -> Result<Self, Self::Error> {
@@ -310,7 +987,7 @@ impl TryFrom<&CommandResponse> for i64 {
             return Err(KvError::ConvertError(value.format(), "CommandResponse"));
         }
 
-        match value.values.get(0) {
+        match value.values.first() {
             Some(v) => v.try_into(),
             None => Err(KvError::ConvertError(value.format(), "CommandResponse")),
         }
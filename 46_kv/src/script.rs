@@ -0,0 +1,584 @@
+//! Eval 命令使用的脚本语言
+//!
+//! 脚本由若干个 s 表达式组成，最后一个表达式的值是脚本的结果，例如：
+//!
+//! ```text
+//! ; 当前值小于参数时才更新
+//! (let cur (hget "scores" "alice"))
+//! (if (< cur (arg 0))
+//!     (do (hset "scores" "alice" (arg 0)) true)
+//!     false)
+//! ```
+//!
+//! 语言中没有循环和函数定义，每求值一个表达式计一步，超过步数限制时脚本被终止；
+//! 拼接字符串和创建 list 时按生成的字节数计数，超过字节数限制时脚本也被终止。
+//! 脚本的写入先放在缓冲区中，执行结束后用 compare_and_swap_batch 原子地提交：
+//! 脚本读到的 key 都没有被修改时写入所有数据，否则不写入并重新执行脚本。
+//! 脚本执行出错时不会写入任何数据。
+
+use crate::{storage::check_reserved_table, value, CasOp, KvError, Storage, Value};
+use prost::Message;
+use std::{
+    cell::Cell,
+    collections::{BTreeMap, HashMap},
+    time::Instant,
+};
+
+/// 默认的步数限制
+pub const DEFAULT_MAX_STEPS: u64 = 10_000;
+/// 步数限制的上限
+pub const MAX_STEPS_LIMIT: u64 = 1_000_000;
+/// 一次执行中拼接字符串和创建 list 最多生成的字节数
+pub const MAX_SCRIPT_BYTES: usize = 16 * 1024 * 1024;
+/// 每执行这么多步检查一次 deadline
+const DEADLINE_CHECK_STEPS: u64 = 1024;
+
+thread_local! {
+    // 当前请求的 deadline，命令在一个线程中同步执行，所以放在 thread local 中
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+/// 在请求的 deadline 内执行 f，其中执行的脚本、备份和恢复超过 deadline 时停止并返回 `KvError::Timeout`
+///
+/// 其它命令执行的时间很短，不检查 deadline，执行完之后由调用者决定是否返回 504
+pub fn with_deadline<T>(deadline: Option<Instant>, f: impl FnOnce() -> T) -> T {
+    let prev = DEADLINE.with(|d| d.replace(deadline));
+    let result = f();
+    DEADLINE.with(|d| d.set(prev));
+    result
+}
+
+/// 当前请求已经超过 deadline 时返回 `KvError::Timeout`
+pub(crate) fn check_deadline() -> Result<(), KvError> {
+    match DEADLINE.with(|d| d.get()) {
+        Some(deadline) if Instant::now() >= deadline => Err(KvError::Timeout),
+        _ => Ok(()),
+    }
+}
+/// 表达式最多嵌套的层数
+const MAX_DEPTH: usize = 64;
+/// 提交冲突时最多重新执行的次数
+const MAX_RETRIES: usize = 10;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Literal(Value),
+    Symbol(String),
+    List(Vec<Expr>),
+}
+
+/// 解析好的脚本
+#[derive(Debug, Clone, PartialEq)]
+pub struct Script {
+    exprs: Vec<Expr>,
+}
+
+fn script_error(msg: impl Into<String>) -> KvError {
+    KvError::ScriptError(msg.into())
+}
+
+impl Script {
+    pub fn parse(source: &str) -> Result<Self, KvError> {
+        let mut parser = Parser {
+            chars: source.chars().peekable(),
+        };
+        let mut exprs = Vec::new();
+        while let Some(expr) = parser.next_expr(0)? {
+            exprs.push(expr);
+        }
+        Ok(Self { exprs })
+    }
+
+    /// 在 store 上执行脚本，返回脚本的结果：list 返回其中的元素，其它的值返回它本身
+    pub fn run(
+        &self,
+        store: &impl Storage,
+        args: &[Value],
+        max_steps: u64,
+    ) -> Result<Vec<Value>, KvError> {
+        let max_steps = match max_steps {
+            0 => DEFAULT_MAX_STEPS,
+            n => n.min(MAX_STEPS_LIMIT),
+        };
+
+        for _ in 0..MAX_RETRIES {
+            let mut interpreter = Interpreter {
+                txn: Txn::new(store),
+                vars: HashMap::new(),
+                args,
+                steps: 0,
+                max_steps,
+                bytes: 0,
+                deadline: DEADLINE.with(|d| d.get()),
+            };
+            let mut result = Value::null();
+            for expr in &self.exprs {
+                result = interpreter.eval(expr)?;
+            }
+            if interpreter.txn.commit()? {
+                return Ok(match result.value {
+                    Some(value::Value::List(list)) => list.values,
+                    _ => vec![result],
+                });
+            }
+        }
+        Err(script_error("too many conflicts with concurrent writes"))
+    }
+}
+
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+impl<'a> Parser<'a> {
+    // 跳过空白和 ; 开始的注释
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.chars.peek() {
+            match c {
+                ';' => {
+                    while !matches!(self.chars.next(), Some('\n') | None) {}
+                }
+                c if c.is_whitespace() => {
+                    self.chars.next();
+                }
+                _ => break,
+            }
+        }
+    }
+
+    fn next_expr(&mut self, depth: usize) -> Result<Option<Expr>, KvError> {
+        if depth > MAX_DEPTH {
+            return Err(script_error("expression is nested too deeply"));
+        }
+        self.skip_whitespace();
+        let c = match self.chars.peek() {
+            Some(c) => *c,
+            None => return Ok(None),
+        };
+        let expr = match c {
+            '(' => {
+                self.chars.next();
+                let mut items = Vec::new();
+                loop {
+                    self.skip_whitespace();
+                    match self.chars.peek() {
+                        Some(')') => {
+                            self.chars.next();
+                            break;
+                        }
+                        Some(_) => items.extend(self.next_expr(depth + 1)?),
+                        None => return Err(script_error("missing `)`")),
+                    }
+                }
+                Expr::List(items)
+            }
+            ')' => return Err(script_error("unexpected `)`")),
+            '"' => {
+                self.chars.next();
+                Expr::Literal(self.string()?.into())
+            }
+            _ => self.atom(),
+        };
+        Ok(Some(expr))
+    }
+
+    fn string(&mut self) -> Result<String, KvError> {
+        let mut s = String::new();
+        loop {
+            match self.chars.next() {
+                Some('"') => return Ok(s),
+                Some('\\') => match self.chars.next() {
+                    Some('n') => s.push('\n'),
+                    Some('t') => s.push('\t'),
+                    Some(c) => s.push(c),
+                    None => break,
+                },
+                Some(c) => s.push(c),
+                None => break,
+            }
+        }
+        Err(script_error("unterminated string"))
+    }
+
+    fn atom(&mut self) -> Expr {
+        let mut s = String::new();
+        while let Some(c) = self.chars.peek() {
+            if c.is_whitespace() || *c == '(' || *c == ')' || *c == '"' || *c == ';' {
+                break;
+            }
+            s.push(*c);
+            self.chars.next();
+        }
+        match s.as_str() {
+            "nil" => Expr::Literal(Value::null()),
+            "true" => Expr::Literal(true.into()),
+            "false" => Expr::Literal(false.into()),
+            _ => match (s.parse::<i64>(), s.parse::<f64>()) {
+                (Ok(i), _) => Expr::Literal(i.into()),
+                (_, Ok(f)) => Expr::Literal(f.into()),
+                _ => Expr::Symbol(s),
+            },
+        }
+    }
+}
+
+// 脚本对 store 的读写，写入在提交前只存在于缓冲区中
+struct Txn<'a, S> {
+    store: &'a S,
+    // 脚本第一次读到的值
+    reads: HashMap<(String, String), Option<Value>>,
+    // 脚本写入的值，None 表示删除
+    writes: BTreeMap<(String, String), Option<Value>>,
+}
+
+impl<'a, S: Storage> Txn<'a, S> {
+    fn new(store: &'a S) -> Self {
+        Self {
+            store,
+            reads: HashMap::new(),
+            writes: BTreeMap::new(),
+        }
+    }
+
+    fn get(&mut self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        check_reserved_table(table)?;
+        let k = (table.to_string(), key.to_string());
+        if let Some(v) = self.writes.get(&k) {
+            return Ok(v.clone());
+        }
+        if let Some(v) = self.reads.get(&k) {
+            return Ok(v.clone());
+        }
+        let v = self.store.get(table, key)?;
+        self.reads.insert(k, v.clone());
+        Ok(v)
+    }
+
+    fn put(&mut self, table: &str, key: &str, value: Option<Value>) -> Result<Option<Value>, KvError> {
+        let old = self.get(table, key)?;
+        self.writes.insert((table.into(), key.into()), value);
+        Ok(old)
+    }
+
+    // 原子地检查读到的 key 没有被修改并提交所有的写入，有冲突时返回 false
+    fn commit(self) -> Result<bool, KvError> {
+        // 写入前一定读过这个 key，所以 reads 包含了所有写入的 key
+        let mut writes = self.writes;
+        let ops = self
+            .reads
+            .into_iter()
+            .map(|(k, expected)| CasOp {
+                new: writes.remove(&k),
+                table: k.0,
+                key: k.1,
+                expected,
+            })
+            .collect();
+        self.store.compare_and_swap_batch(ops)
+    }
+}
+
+struct Interpreter<'a, S> {
+    txn: Txn<'a, S>,
+    vars: HashMap<String, Value>,
+    args: &'a [Value],
+    steps: u64,
+    max_steps: u64,
+    // 已经生成的字节数
+    bytes: usize,
+    deadline: Option<Instant>,
+}
+
+fn truthy(v: &Value) -> bool {
+    match &v.value {
+        None | Some(value::Value::Null(_)) => false,
+        Some(value::Value::Bool(b)) => *b,
+        _ => true,
+    }
+}
+
+fn as_str(v: &Value) -> Result<&str, KvError> {
+    match &v.value {
+        Some(value::Value::String(s)) => Ok(s),
+        _ => Err(script_error(format!("expect string, got {}", v.format()))),
+    }
+}
+
+fn arith(op: &str, a: &Value, b: &Value) -> Result<Value, KvError> {
+    use value::Value::*;
+    let overflow = || script_error("integer overflow");
+    match (&a.value, &b.value) {
+        (Some(Integer(a)), Some(Integer(b))) => {
+            let v = match op {
+                "+" => a.checked_add(*b),
+                "-" => a.checked_sub(*b),
+                "*" => a.checked_mul(*b),
+                _ if *b == 0 => return Err(script_error("division by zero")),
+                _ => a.checked_div(*b),
+            };
+            v.map(Value::from).ok_or_else(overflow)
+        }
+        (Some(Integer(_) | Float(_)), Some(Integer(_) | Float(_))) => {
+            let (a, b) = (to_f64(a), to_f64(b));
+            Ok(match op {
+                "+" => a + b,
+                "-" => a - b,
+                "*" => a * b,
+                _ => a / b,
+            }
+            .into())
+        }
+        (Some(String(a)), Some(String(b))) if op == "+" => Ok(format!("{}{}", a, b).into()),
+        _ => Err(script_error(format!(
+            "cannot apply {} to {} and {}",
+            op,
+            a.format(),
+            b.format()
+        ))),
+    }
+}
+
+fn to_f64(v: &Value) -> f64 {
+    match v.value {
+        Some(value::Value::Integer(i)) => i as f64,
+        Some(value::Value::Float(f)) => f,
+        _ => f64::NAN,
+    }
+}
+
+fn compare(a: &Value, b: &Value) -> Result<std::cmp::Ordering, KvError> {
+    use value::Value::*;
+    let result = match (&a.value, &b.value) {
+        (Some(Integer(a)), Some(Integer(b))) => Some(a.cmp(b)),
+        (Some(Integer(_) | Float(_)), Some(Integer(_) | Float(_))) => {
+            to_f64(a).partial_cmp(&to_f64(b))
+        }
+        (Some(String(a)), Some(String(b))) => Some(a.cmp(b)),
+        (Some(Timestamp(a)), Some(Timestamp(b))) => Some(a.cmp(b)),
+        _ => None,
+    };
+    result.ok_or_else(|| script_error(format!("cannot compare {} and {}", a.format(), b.format())))
+}
+
+impl<'a, S: Storage> Interpreter<'a, S> {
+    fn eval(&mut self, expr: &Expr) -> Result<Value, KvError> {
+        self.steps += 1;
+        if self.steps > self.max_steps {
+            return Err(script_error(format!("exceeded {} steps", self.max_steps)));
+        }
+        if self.steps.is_multiple_of(DEADLINE_CHECK_STEPS)
+            && matches!(self.deadline, Some(d) if Instant::now() >= d)
+        {
+            return Err(KvError::Timeout);
+        }
+        match expr {
+            Expr::Literal(v) => Ok(v.clone()),
+            Expr::Symbol(name) => self
+                .vars
+                .get(name)
+                .cloned()
+                .ok_or_else(|| script_error(format!("undefined variable: {}", name))),
+            Expr::List(items) => match items.split_first() {
+                Some((Expr::Symbol(name), rest)) => self.call(name, rest),
+                Some(_) => Err(script_error("expect function name")),
+                None => Ok(Value::null()),
+            },
+        }
+    }
+
+    // 把新生成的 value 的大小计入字节数限制
+    fn charge(&mut self, v: Value) -> Result<Value, KvError> {
+        self.bytes = self.bytes.saturating_add(v.encoded_len());
+        if self.bytes > MAX_SCRIPT_BYTES {
+            return Err(script_error(format!("exceeded {} bytes", MAX_SCRIPT_BYTES)));
+        }
+        Ok(v)
+    }
+
+    fn call(&mut self, name: &str, rest: &[Expr]) -> Result<Value, KvError> {
+        // 先处理不需要对所有参数求值的特殊形式
+        match (name, rest) {
+            ("let", [Expr::Symbol(var), expr]) => {
+                let v = self.eval(expr)?;
+                self.vars.insert(var.clone(), v.clone());
+                return Ok(v);
+            }
+            ("let", _) => return Err(script_error("usage: (let name expr)")),
+            ("if", [cond, then, other @ ..]) if other.len() <= 1 => {
+                return match (truthy(&self.eval(cond)?), other) {
+                    (true, _) => self.eval(then),
+                    (false, [other]) => self.eval(other),
+                    (false, _) => Ok(Value::null()),
+                }
+            }
+            ("if", _) => return Err(script_error("usage: (if cond then [else])")),
+            ("and", _) => {
+                let mut v = Value::from(true);
+                for expr in rest {
+                    v = self.eval(expr)?;
+                    if !truthy(&v) {
+                        break;
+                    }
+                }
+                return Ok(v);
+            }
+            ("or", _) => {
+                let mut v = Value::from(false);
+                for expr in rest {
+                    v = self.eval(expr)?;
+                    if truthy(&v) {
+                        break;
+                    }
+                }
+                return Ok(v);
+            }
+            _ => {}
+        }
+
+        let args = rest
+            .iter()
+            .map(|e| self.eval(e))
+            .collect::<Result<Vec<_>, _>>()?;
+        let null = || Value::null();
+        match (name, args.as_slice()) {
+            ("do", args) => Ok(args.last().cloned().unwrap_or_else(null)),
+            ("hget", [t, k]) => Ok(self.txn.get(as_str(t)?, as_str(k)?)?.unwrap_or_else(null)),
+            ("hexist", [t, k]) => Ok(self.txn.get(as_str(t)?, as_str(k)?)?.is_some().into()),
+            ("hset", [t, k, v]) => {
+                let old = self.txn.put(as_str(t)?, as_str(k)?, Some(v.clone()))?;
+                Ok(old.unwrap_or_else(null))
+            }
+            ("hdel", [t, k]) => Ok(self.txn.put(as_str(t)?, as_str(k)?, None)?.unwrap_or_else(null)),
+            ("arg", [i]) => {
+                let i: i64 = i.try_into()?;
+                Ok(self.args.get(i as usize).cloned().unwrap_or_else(null))
+            }
+            ("list", args) => self.charge(args.to_vec().into()),
+            ("not", [v]) => Ok((!truthy(v)).into()),
+            ("=", [a, b]) => Ok((a == b).into()),
+            ("!=", [a, b]) => Ok((a != b).into()),
+            ("<", [a, b]) => Ok(compare(a, b)?.is_lt().into()),
+            ("<=", [a, b]) => Ok(compare(a, b)?.is_le().into()),
+            (">", [a, b]) => Ok(compare(a, b)?.is_gt().into()),
+            (">=", [a, b]) => Ok(compare(a, b)?.is_ge().into()),
+            ("+" | "-" | "*" | "/", [a, b]) => {
+                let v = arith(name, a, b)?;
+                match &v.value {
+                    Some(value::Value::String(_)) => self.charge(v),
+                    _ => Ok(v),
+                }
+            }
+            ("error", [msg]) => Err(script_error(as_str(msg)?.to_string())),
+            _ => Err(script_error(format!(
+                "unknown function or wrong number of arguments: ({} ...{} args)",
+                name,
+                args.len()
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemTable;
+
+    fn run(store: &MemTable, script: &str, args: &[Value]) -> Result<Vec<Value>, KvError> {
+        Script::parse(script)?.run(store, args, 0)
+    }
+
+    #[test]
+    fn parse_should_work() {
+        let script = Script::parse("; comment\n(hset \"t1\" \"k\\\"1\" -1.5) nil").unwrap();
+        assert_eq!(
+            script.exprs,
+            vec![
+                Expr::List(vec![
+                    Expr::Symbol("hset".into()),
+                    Expr::Literal("t1".into()),
+                    Expr::Literal("k\"1".into()),
+                    Expr::Literal((-1.5).into()),
+                ]),
+                Expr::Literal(Value::null()),
+            ]
+        );
+        assert!(Script::parse("(hget \"t1\"").is_err());
+        assert!(Script::parse(")").is_err());
+        assert!(Script::parse(&"(".repeat(100)).is_err());
+    }
+
+    #[test]
+    fn set_if_less_should_work() {
+        let store = MemTable::new();
+        store.set("t1", "k".into(), 10.into()).unwrap();
+        let script = r#"
+            (let cur (hget "t1" "k"))
+            (if (< cur (arg 0)) (do (hset "t1" "k" (arg 0)) true) false)
+        "#;
+        assert_eq!(run(&store, script, &[5.into()]).unwrap(), vec![false.into()]);
+        assert_eq!(run(&store, script, &[20.into()]).unwrap(), vec![true.into()]);
+        assert_eq!(store.get("t1", "k").unwrap(), Some(20.into()));
+    }
+
+    #[test]
+    fn move_key_should_work() {
+        let store = MemTable::new();
+        store.set("src", "k".into(), "v".into()).unwrap();
+        let script = r#"
+            (let v (hget "src" "k"))
+            (if (= v nil) (error "not found"))
+            (hdel "src" "k")
+            (hset "dst" "k" v)
+            (list v (hexist "src" "k"))
+        "#;
+        let result = run(&store, script, &[]).unwrap();
+        assert_eq!(result, vec!["v".into(), false.into()]);
+        assert_eq!(store.get("dst", "k").unwrap(), Some("v".into()));
+
+        // 出错时不会写入任何数据
+        let err = run(&store, script, &[]).unwrap_err();
+        assert_eq!(err.to_string(), "Script error: not found");
+        assert!(store.get("src", "k").unwrap().is_none());
+    }
+
+    #[test]
+    fn script_should_be_bounded() {
+        let store = MemTable::new();
+        let script = Script::parse("(+ 1 (+ 2 (+ 3 4)))").unwrap();
+        assert_eq!(script.run(&store, &[], 0).unwrap(), vec![10.into()]);
+        assert!(script.run(&store, &[], 3).is_err());
+
+        // 字符串和 list 每次加倍，很快超过字节数限制
+        let source = format!("(let s \"{}\") {}", "x".repeat(1024), "(let s (+ s s)) ".repeat(20));
+        let err = run(&store, &source, &[]).unwrap_err();
+        assert!(err.to_string().contains("bytes"));
+        let source = format!("(let l (list s)) {}", "(let l (list l l)) ".repeat(20));
+        let source = format!("(let s \"{}\") {}", "x".repeat(1024), source);
+        let err = run(&store, &source, &[]).unwrap_err();
+        assert!(err.to_string().contains("bytes"));
+    }
+
+    #[test]
+    fn script_should_stop_at_deadline() {
+        let store = MemTable::new();
+        let source = "(hset \"t\" \"k\" 1) ".repeat(10_000);
+        let script = Script::parse(&source).unwrap();
+        let result = with_deadline(Some(Instant::now()), || script.run(&store, &[], MAX_STEPS_LIMIT));
+        assert!(matches!(result, Err(KvError::Timeout)));
+        // 没有提交任何写入
+        assert_eq!(run(&store, "(hget \"t\" \"k\")", &[]).unwrap(), vec![Value::null()]);
+
+        // 没有 deadline 时正常执行
+        assert!(script.run(&store, &[], MAX_STEPS_LIMIT).is_ok());
+    }
+
+    #[test]
+    fn runtime_errors_should_be_reported() {
+        let store = MemTable::new();
+        assert!(run(&store, "(/ 1 0)", &[]).is_err());
+        assert!(run(&store, "(+ 1 \"a\")", &[]).is_err());
+        assert!(run(&store, "(unknown 1)", &[]).is_err());
+        assert!(run(&store, "x", &[]).is_err());
+        assert_eq!(run(&store, "(+ 1 2.5)", &[]).unwrap(), vec![3.5.into()]);
+    }
+}
@@ -0,0 +1,410 @@
+//! 类型化的异步客户端：自动构造命令、检查状态码、把 Value 转换成需要的类型。
+//!
+//! ```ignore
+//! let mut client = KvClient::connect(&config).await?;
+//! client.hset("users", "alice", Json(user)).await?;
+//! let user: Option<Json<User>> = client.hget("users", "alice").await?;
+//! ```
+
+use crate::{
+    start_client_with_config, start_quic_client_with_config, ClientConfig, CommandRequest,
+    CommandResponse, KvError, Kvpair, ProstClientStream, QuicCtrl, QuicStream, StreamResult,
+    TransportConfig, Value, YamuxCtrl,
+};
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    pin::Pin,
+    task::{Context, Poll},
+    time::SystemTime,
+};
+use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+use tokio_util::compat::Compat;
+
+/// 可以写入的值，所有能转换成 Value 的类型，以及 Json<T>
+pub trait IntoValue {
+    fn into_value(self) -> Result<Value, KvError>;
+}
+
+/// 可以从 Value 中读取的类型
+pub trait FromValue: Sized {
+    fn from_value(v: Value) -> Result<Self, KvError>;
+}
+
+/// 用 serde 把 T 序列化成 JSON，存成 Value::Binary
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Json<T>(pub T);
+
+/// 高层的 KV 客户端，命令在同一个 stream 上按顺序执行，订阅使用单独的 stream
+pub struct KvClient {
+    config: ClientConfig,
+    conn: Connection,
+    stream: ClientStream,
+}
+
+/// 一个订阅，按顺序产生发布到主题的每个值；连接断开或者取消订阅之后结束
+pub struct Subscription {
+    id: u32,
+    inner: Option<StreamResult>,
+    pending: VecDeque<Value>,
+}
+
+enum Connection {
+    Tcp(YamuxCtrl<TlsStream<TcpStream>>),
+    Quic(QuicCtrl),
+}
+
+enum ClientStream {
+    Tcp(ProstClientStream<Compat<yamux::Stream>>),
+    Quic(ProstClientStream<QuicStream>),
+}
+
+impl<T: Into<Value>> IntoValue for T {
+    fn into_value(self) -> Result<Value, KvError> {
+        Ok(self.into())
+    }
+}
+
+impl<T: Serialize> IntoValue for Json<T> {
+    fn into_value(self) -> Result<Value, KvError> {
+        let data =
+            serde_json::to_vec(&self.0).map_err(|e| KvError::SerializationError(e.to_string()))?;
+        Ok(Bytes::from(data).into())
+    }
+}
+
+impl<T: DeserializeOwned> FromValue for Json<T> {
+    fn from_value(v: Value) -> Result<Self, KvError> {
+        let data = Bytes::try_from(v)?;
+        serde_json::from_slice(&data)
+            .map(Json)
+            .map_err(|e| KvError::SerializationError(e.to_string()))
+    }
+}
+
+impl FromValue for Value {
+    fn from_value(v: Value) -> Result<Self, KvError> {
+        Ok(v)
+    }
+}
+
+macro_rules! impl_from_value {
+    ($($t:ty),*) => {
+        $(impl FromValue for $t {
+            fn from_value(v: Value) -> Result<Self, KvError> {
+                v.try_into()
+            }
+        })*
+    };
+}
+
+impl_from_value!(
+    String,
+    i64,
+    f64,
+    bool,
+    Bytes,
+    SystemTime,
+    Vec<Value>,
+    BTreeMap<String, Value>
+);
+
+impl KvClient {
+    /// 按配置连接服务器（TLS + yamux 或者 QUIC）
+    pub async fn connect(config: &ClientConfig) -> Result<Self, KvError> {
+        let mut conn = match config.general.transport {
+            TransportConfig::Tcp => Connection::Tcp(
+                start_client_with_config(config)
+                    .await
+                    .map_err(into_kv_error)?,
+            ),
+            TransportConfig::Quic => Connection::Quic(
+                start_quic_client_with_config(config)
+                    .await
+                    .map_err(into_kv_error)?,
+            ),
+        };
+        let stream = conn.open(config).await?;
+        Ok(Self {
+            config: config.clone(),
+            conn,
+            stream,
+        })
+    }
+
+    /// 执行任意的命令，状态码不是 2xx 时返回对应的错误
+    pub async fn execute(&mut self, cmd: &CommandRequest) -> Result<CommandResponse, KvError> {
+        let res = match &mut self.stream {
+            ClientStream::Tcp(stream) => stream.execute_unary(cmd).await,
+            ClientStream::Quic(stream) => stream.execute_unary(cmd).await,
+        };
+        // 超时之后 stream 不能再使用，换一个新的
+        if matches!(res, Err(KvError::Timeout)) {
+            self.stream = self.conn.open(&self.config).await?;
+        }
+        res?.into_result()
+    }
+
+    /// 读取一个 key，不存在时返回 None
+    pub async fn hget<T: FromValue>(
+        &mut self,
+        table: impl Into<String>,
+        key: impl Into<String>,
+    ) -> Result<Option<T>, KvError> {
+        match self.execute(&CommandRequest::new_hget(table, key)).await {
+            Ok(res) => first(res).map(Some),
+            Err(KvError::NotFound(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// 读取多个 key，不存在的 key 对应 None
+    pub async fn hmget<T: FromValue>(
+        &mut self,
+        table: impl Into<String>,
+        keys: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Result<Vec<Option<T>>, KvError> {
+        let keys = keys.into_iter().map(Into::into).collect();
+        let res = self
+            .execute(&CommandRequest::new_hmget(table, keys))
+            .await?;
+        res.values.into_iter().map(optional).collect()
+    }
+
+    /// 读取表中所有的 key 和值
+    pub async fn hgetall<T: FromValue>(
+        &mut self,
+        table: impl Into<String>,
+    ) -> Result<Vec<(String, T)>, KvError> {
+        let res = self.execute(&CommandRequest::new_hgetall(table)).await?;
+        res.pairs
+            .into_iter()
+            .map(|pair| Ok((pair.key, T::from_value(pair.value.unwrap_or_default())?)))
+            .collect()
+    }
+
+    /// 写入一个 key
+    pub async fn hset(
+        &mut self,
+        table: impl Into<String>,
+        key: impl Into<String>,
+        value: impl IntoValue,
+    ) -> Result<(), KvError> {
+        let cmd = CommandRequest::new_hset(table, key, value.into_value()?);
+        self.execute(&cmd).await.map(|_| ())
+    }
+
+    /// 写入多个 key
+    pub async fn hmset<K: Into<String>, V: IntoValue>(
+        &mut self,
+        table: impl Into<String>,
+        pairs: impl IntoIterator<Item = (K, V)>,
+    ) -> Result<(), KvError> {
+        let pairs = pairs
+            .into_iter()
+            .map(|(k, v)| Ok(Kvpair::new(k, v.into_value()?)))
+            .collect::<Result<_, KvError>>()?;
+        self.execute(&CommandRequest::new_hmset(table, pairs))
+            .await
+            .map(|_| ())
+    }
+
+    /// 删除一个 key，返回它之前是否存在
+    pub async fn hdel(
+        &mut self,
+        table: impl Into<String>,
+        key: impl Into<String>,
+    ) -> Result<bool, KvError> {
+        let res = self.execute(&CommandRequest::new_hdel(table, key)).await?;
+        Ok(optional::<Value>(res.values.into_iter().next().unwrap_or_default())?.is_some())
+    }
+
+    /// key 是否存在
+    pub async fn hexist(
+        &mut self,
+        table: impl Into<String>,
+        key: impl Into<String>,
+    ) -> Result<bool, KvError> {
+        first(
+            self.execute(&CommandRequest::new_hexist(table, key))
+                .await?,
+        )
+    }
+
+    /// 往主题里发布一组值
+    pub async fn publish<V: IntoValue>(
+        &mut self,
+        topic: impl Into<String>,
+        values: impl IntoIterator<Item = V>,
+    ) -> Result<(), KvError> {
+        let values = values
+            .into_iter()
+            .map(IntoValue::into_value)
+            .collect::<Result<_, _>>()?;
+        self.execute(&CommandRequest::new_publish(topic, values))
+            .await
+            .map(|_| ())
+    }
+
+    /// 订阅一个主题，在新的 stream 上接收数据
+    pub async fn subscribe(&mut self, topic: impl Into<String>) -> Result<Subscription, KvError> {
+        let cmd = CommandRequest::new_subscribe(topic);
+        let stream = match self.conn.open(&self.config).await? {
+            ClientStream::Tcp(stream) => stream.execute_streaming(&cmd).await?,
+            ClientStream::Quic(stream) => stream.execute_streaming(&cmd).await?,
+        };
+        Ok(Subscription {
+            id: stream.id,
+            inner: Some(stream),
+            pending: VecDeque::new(),
+        })
+    }
+
+    /// 取消订阅，对应的 Subscription 随之结束
+    pub async fn unsubscribe(&mut self, topic: impl Into<String>, id: u32) -> Result<(), KvError> {
+        self.execute(&CommandRequest::new_unsubscribe(topic, id))
+            .await
+            .map(|_| ())
+    }
+}
+
+impl Connection {
+    // 打开一个新的 stream 并完成 hello
+    async fn open(&mut self, config: &ClientConfig) -> Result<ClientStream, KvError> {
+        Ok(match self {
+            Self::Tcp(ctrl) => ClientStream::Tcp(hello(ctrl.open_stream().await?, config).await?),
+            Self::Quic(ctrl) => ClientStream::Quic(hello(ctrl.open_stream().await?, config).await?),
+        })
+    }
+}
+
+async fn hello<S>(
+    stream: ProstClientStream<S>,
+    config: &ClientConfig,
+) -> Result<ProstClientStream<S>, KvError>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+{
+    let mut stream = stream
+        .with_max_frame(config.general.max_frame)
+        .with_timeout(config.timeout.request());
+    stream.hello(&config.compression).await?;
+    Ok(stream)
+}
+
+impl Subscription {
+    /// 订阅的 id，用于取消订阅
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+impl Stream for Subscription {
+    type Item = Value;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            if let Some(v) = self.pending.pop_front() {
+                return Poll::Ready(Some(v));
+            }
+            let inner = match self.inner.as_mut() {
+                Some(inner) => inner,
+                None => return Poll::Ready(None),
+            };
+            match inner.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok(res))) if res.status == 200 => self.pending.extend(res.values),
+                // 出错或者 stream 结束之后不再产生数据
+                Poll::Ready(_) => self.inner = None,
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+// 响应中的第一个值
+fn first<T: FromValue>(res: CommandResponse) -> Result<T, KvError> {
+    match res.values.into_iter().next() {
+        Some(v) => T::from_value(v),
+        None => Err(KvError::Internal("response has no value".into())),
+    }
+}
+
+// 服务器用空的 Value 表示不存在
+fn optional<T: FromValue>(v: Value) -> Result<Option<T>, KvError> {
+    match v.value {
+        None => Ok(None),
+        Some(_) => T::from_value(v).map(Some),
+    }
+}
+
+// 连接的错误是 anyhow::Error，尽量还原成原来的 KvError
+fn into_kv_error(e: anyhow::Error) -> KvError {
+    match e.downcast::<KvError>() {
+        Ok(e) => e,
+        Err(e) => match e.downcast::<std::io::Error>() {
+            Ok(e) => e.into(),
+            Err(e) => KvError::Internal(e.to_string()),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct User {
+        name: String,
+        age: u8,
+    }
+
+    #[test]
+    fn json_should_be_stored_as_binary() {
+        let user = User {
+            name: "alice".into(),
+            age: 30,
+        };
+        let v = Json(&user).into_value().unwrap();
+        let data: Bytes = v.clone().try_into().unwrap();
+        assert_eq!(&data[..], br#"{"name":"alice","age":30}"#);
+        let Json(decoded) = Json::<User>::from_value(v).unwrap();
+        assert_eq!(decoded, user);
+
+        let err = Json::<User>::from_value("alice".into()).unwrap_err();
+        assert!(matches!(err, KvError::ConvertError(..)));
+        let err = Json::<User>::from_value(Bytes::from("{}").into()).unwrap_err();
+        assert!(matches!(err, KvError::SerializationError(_)));
+    }
+
+    #[test]
+    fn optional_should_treat_empty_value_as_none() {
+        assert_eq!(optional::<i64>(Value::default()).unwrap(), None);
+        assert_eq!(optional::<i64>(42.into()).unwrap(), Some(42));
+        assert!(optional::<i64>("42".into()).is_err());
+    }
+
+    #[test]
+    fn status_code_should_map_to_error() {
+        let errors = [
+            KvError::NotFound("table: t1, key: k1".into()),
+            KvError::InvalidCommand("missing key".into()),
+            KvError::PermissionDenied("admin only".into()),
+            KvError::AlreadyExists("table t1".into()),
+            KvError::ConstraintViolation("unique index".into()),
+            KvError::Timeout,
+            KvError::FrameError,
+            KvError::IncompatibleProtocol("version 2".into()),
+            KvError::Internal("oops".into()),
+        ];
+        for e in errors {
+            let expected = e.to_string();
+            let err = CommandResponse::from(e).into_result().unwrap_err();
+            assert_eq!(err.to_string(), expected);
+        }
+        assert!(CommandResponse::ok().into_result().is_ok());
+    }
+}
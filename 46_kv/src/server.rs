@@ -1,7 +1,15 @@
 use std::env;
 use anyhow::Result;
-use kv6::{start_server_with_config, RotationConfig, ServerConfig};
-use tokio::fs;
+use clap::{Parser, Subcommand};
+use kv6::{
+    backup_file, restore_file, start_server_with_config, verify, AclConfig, BackupStats, CertPem,
+    CertSubject, ClientConfig, ClientTlsConfig, GeneralConfig, KvError, RotationConfig,
+    ServerConfig, ServerTlsConfig, SledDb, StorageConfig,
+};
+use std::{
+    fs::{self, File},
+    path::{Path, PathBuf},
+};
 use tracing::span;
 use tracing_subscriber::{
     fmt::{self, format},
@@ -10,15 +18,336 @@ use tracing_subscriber::{
     EnvFilter,
 };
 
+/// 命令行参数，优先级高于配置文件和环境变量
+#[derive(Parser, Debug)]
+#[clap(name = "kvs", version, about = "KV server")]
+struct Args {
+    /// 配置文件路径，也可以通过 KV_SERVER_CONFIG 指定
+    #[clap(short, long)]
+    config: Option<String>,
+    /// 监听地址
+    #[clap(long)]
+    addr: Option<String>,
+    /// 存储：memtable 或者 sled:<path>
+    #[clap(long)]
+    storage: Option<StorageConfig>,
+    /// 服务器证书（PEM 文件路径）
+    #[clap(long)]
+    cert: Option<String>,
+    /// 服务器私钥（PEM 文件路径）
+    #[clap(long)]
+    key: Option<String>,
+    /// 签发客户端证书的 CA（PEM 文件路径）
+    #[clap(long)]
+    ca: Option<String>,
+    /// 日志目录
+    #[clap(long)]
+    log_path: Option<String>,
+    /// 开启 metrics，并在这个地址上提供 Prometheus 格式的数据
+    #[clap(long)]
+    metrics_addr: Option<String>,
+    /// 打印最终生效的配置（隐藏私钥）后退出
+    #[clap(long)]
+    check_config: bool,
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+/// 离线的数据工具，直接操作配置中的 sled 数据目录，运行时服务器不能同时打开它。
+/// 服务器运行时请使用 Backup / Restore 管理命令
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// 把所有数据备份到文件
+    Backup {
+        #[clap(short, long)]
+        output: String,
+    },
+    /// 从备份文件恢复数据，已有的 key 会被覆盖
+    Restore {
+        #[clap(short, long)]
+        input: String,
+    },
+    /// 检查备份文件是否完整
+    Verify {
+        #[clap(short, long)]
+        input: String,
+    },
+    /// 生成 CA、证书和使用它们的配置
+    Cert {
+        #[clap(subcommand)]
+        command: CertCommand,
+    },
+}
+
+/// 证书和配置默认写入的目录
+const CERT_DIR: &str = "certs";
+
+/// 配置中使用证书文件路径时，检查文件变化的间隔
+const CERT_RELOAD_MS: u64 = 60_000;
+
+/// 证书写到 dir 下的 <name>.cert 和 <name>.key，签发证书时使用 dir 下的 ca.cert / ca.key
+#[derive(Subcommand, Debug)]
+enum CertCommand {
+    /// 创建自签名的 CA
+    Ca {
+        #[clap(flatten)]
+        subject: SubjectArgs,
+        #[clap(long, default_value = "Acme CA")]
+        cn: String,
+        /// CA 证书中的域名
+        #[clap(long = "domain")]
+        domains: Vec<String>,
+        #[clap(long, default_value_t = 3650)]
+        days: i64,
+    },
+    /// 签发服务器证书
+    Server {
+        #[clap(flatten)]
+        subject: SubjectArgs,
+        #[clap(long, default_value = "server")]
+        name: String,
+        #[clap(long, default_value = "Acme KV server")]
+        cn: String,
+        /// 客户端连接时使用的域名，可以指定多个
+        #[clap(long = "san", required = true)]
+        sans: Vec<String>,
+        #[clap(long, default_value_t = 1825)]
+        days: i64,
+    },
+    /// 签发客户端证书，CN 是 ACL 中使用的客户端身份
+    Client {
+        #[clap(flatten)]
+        subject: SubjectArgs,
+        #[clap(long, default_value = "client")]
+        name: String,
+        #[clap(long)]
+        cn: String,
+        #[clap(long, default_value_t = 365)]
+        days: i64,
+    },
+    /// 用 dir 下的证书生成 server.conf 和 client.conf
+    Config {
+        #[clap(long, default_value = CERT_DIR)]
+        dir: String,
+        /// 覆盖已经存在的配置文件
+        #[clap(long)]
+        force: bool,
+        /// 服务器证书的名字
+        #[clap(long, default_value = "server")]
+        server: String,
+        /// 客户端证书的名字，指定后服务器要求客户端证书
+        #[clap(long)]
+        client: Option<String>,
+        #[clap(long, default_value = "127.0.0.1:9527")]
+        addr: String,
+        /// 客户端连接时使用的域名，必须在服务器证书的 SAN 中
+        #[clap(long, default_value = "kvserver.acme.inc")]
+        domain: String,
+        /// 把客户端证书的 CN 加入 ACL 的 admins
+        #[clap(long)]
+        admin: bool,
+        /// 把 PEM 内容写入配置，而不是文件路径
+        #[clap(long)]
+        embed: bool,
+    },
+}
+
+#[derive(clap::Args, Debug)]
+struct SubjectArgs {
+    /// 证书写入的目录
+    #[clap(long, default_value = CERT_DIR)]
+    dir: String,
+    /// 覆盖已经存在的证书和私钥
+    #[clap(long)]
+    force: bool,
+    #[clap(long, default_value = "CN")]
+    country: String,
+    #[clap(long, default_value = "Acme Inc.")]
+    org: String,
+}
+
+impl SubjectArgs {
+    fn subject(&self, cn: String, domains: Vec<String>, days: i64) -> CertSubject {
+        CertSubject {
+            country: self.country.clone(),
+            org: self.org.clone(),
+            cn,
+            domains,
+            days,
+        }
+    }
+
+    fn write(&self, pem: &CertPem, name: &str) -> Result<()> {
+        let (cert, key) = pem.write(&self.dir, name, self.force).map_err(|e| match e {
+            KvError::AlreadyExists(path) => {
+                anyhow::anyhow!("{} already exists, use --force to overwrite", path)
+            }
+            e => e.into(),
+        })?;
+        println!("written: {}, {}", cert.display(), key.display());
+        Ok(())
+    }
+}
+
+impl CertCommand {
+    fn run(self) -> Result<()> {
+        match self {
+            CertCommand::Ca { subject, cn, domains, days } => {
+                let ca = CertPem::new_ca(&subject.subject(cn, domains, days))?;
+                subject.write(&ca, "ca")
+            }
+            CertCommand::Server { subject, name, cn, sans, days } => {
+                let ca = CertPem::read(&subject.dir, "ca")?;
+                let cert = CertPem::new_server(&ca, &subject.subject(cn, sans, days))?;
+                subject.write(&cert, &name)
+            }
+            CertCommand::Client { subject, name, cn, days } => {
+                let ca = CertPem::read(&subject.dir, "ca")?;
+                let cert = CertPem::new_client(&ca, &subject.subject(cn, vec![], days))?;
+                subject.write(&cert, &name)
+            }
+            CertCommand::Config { dir, force, server, client, addr, domain, admin, embed } => {
+                let dir = Path::new(&dir);
+                // 配置中使用文件路径，证书更新后服务器可以重新加载
+                let pem = |path: PathBuf| -> Result<String> {
+                    Ok(match embed {
+                        true => fs::read_to_string(path)?,
+                        false => path.display().to_string(),
+                    })
+                };
+                let ca = pem(CertPem::paths(dir, "ca").0)?;
+                let (cert, key) = CertPem::paths(dir, &server);
+                let general = GeneralConfig {
+                    addr,
+                    ..Default::default()
+                };
+
+                let mut admins = vec![];
+                let identity = match &client {
+                    Some(name) => {
+                        if admin {
+                            let identity = CertPem::read(dir, name)?.identity();
+                            admins.extend(identity);
+                        }
+                        let (cert, key) = CertPem::paths(dir, name);
+                        Some((pem(cert)?, pem(key)?))
+                    }
+                    None => None,
+                };
+
+                let server_config = ServerConfig {
+                    general: general.clone(),
+                    tls: ServerTlsConfig {
+                        cert: pem(cert)?,
+                        key: pem(key)?,
+                        ca: client.as_ref().map(|_| ca.clone()),
+                        reload_ms: if embed { 0 } else { CERT_RELOAD_MS },
+                        ..Default::default()
+                    },
+                    acl: AclConfig { admins },
+                    ..Default::default()
+                };
+                let client_config = ClientConfig {
+                    general,
+                    tls: ClientTlsConfig {
+                        domain,
+                        identity,
+                        ca: Some(ca),
+                    },
+                    ..Default::default()
+                };
+
+                for (name, content) in [
+                    ("server.conf", toml::to_string_pretty(&server_config)?),
+                    ("client.conf", toml::to_string_pretty(&client_config)?),
+                ] {
+                    let path = dir.join(name);
+                    if path.exists() && !force {
+                        anyhow::bail!("{} already exists, use --force to overwrite", path.display());
+                    }
+                    fs::write(&path, content)?;
+                    println!("written: {}", path.display());
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Command {
+    fn run(self, config: &ServerConfig) -> Result<()> {
+        let progress = |s: &BackupStats| {
+            eprintln!("{} tables, {} pairs, {} zsets", s.tables, s.pairs, s.zsets)
+        };
+        let stats = match self {
+            Command::Cert { command } => return command.run(),
+            Command::Verify { input } => verify(File::open(input)?)?,
+            Command::Backup { output } => backup_file(&open_sled(config)?, output, progress)?,
+            Command::Restore { input } => restore_file(&open_sled(config)?, input, progress)?,
+        };
+        println!(
+            "done: {} tables, {} pairs, {} zsets",
+            stats.tables, stats.pairs, stats.zsets
+        );
+        Ok(())
+    }
+}
+
+fn open_sled(config: &ServerConfig) -> Result<SledDb> {
+    match &config.storage {
+        StorageConfig::SledDb(path) => Ok(SledDb::new(path)),
+        StorageConfig::MemTable => Err(anyhow::anyhow!(
+            "memtable has no data on disk, use the Backup / Restore admin commands instead"
+        )),
+    }
+}
+
+impl Args {
+    fn apply(self, config: &mut ServerConfig) {
+        if let Some(v) = self.addr {
+            config.general.addr = v;
+        }
+        if let Some(v) = self.storage {
+            config.storage = v;
+        }
+        if let Some(v) = self.cert {
+            config.tls.cert = v;
+        }
+        if let Some(v) = self.key {
+            config.tls.key = v;
+        }
+        if let Some(v) = self.ca {
+            config.tls.ca = Some(v);
+        }
+        if let Some(v) = self.log_path {
+            config.log.path = v;
+        }
+        if let Some(v) = self.metrics_addr {
+            config.metrics.enabled = true;
+            config.metrics.addr = v;
+        }
+    }
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // 如果有环境变量，使用环境变量中的config
-    let config = match env::var("KV_SERVER_CONFIG") {
-        Ok(path) => fs::read_to_string(&path).await?,
-        Err(_) => include_str!("../fixtures/server.conf").to_string(),
-    };
-    let config: ServerConfig = toml::from_str(&config)?;
+    let mut args = Args::parse();
+
+    // 默认值 -> 配置文件 -> 环境变量 -> 命令行参数
+    let path = args.config.clone().or_else(|| env::var("KV_SERVER_CONFIG").ok());
+    let mut config = ServerConfig::load_layered(path.as_deref())?;
+    let check_config = args.check_config;
+    let command = args.command.take();
+    args.apply(&mut config);
+
+    if check_config {
+        print!("{}", toml::to_string_pretty(&config.redacted())?);
+        return config.validate().map_err(Into::into);
+    }
+    if let Some(command) = command {
+        return command.run(&config);
+    }
+    config.validate()?;
 
     let tracer = opentelemetry_jaeger::new_pipeline()
         .with_service_name("kv-server")
@@ -0,0 +1,371 @@
+use crate::{
+    backup_file, command_request::RequestData, restore_file, storage::check_reserved_table,
+    Backup, BackupStats, ClientInfo, ClientKill, ClientList, CommandRequest, CommandResponse,
+    CreateTable, Dbsize, DropTable, Info, KvError, Kvpair, ListTables, RenameTable, Restore, Role,
+    Service, Storage, Value, RESERVED_TABLE_PREFIX,
+};
+use tracing::info;
+
+// 所有的 table，不包括服务器内部使用的 table
+fn user_tables(store: &impl Storage) -> Result<Vec<String>, KvError> {
+    let mut tables = store.list_tables()?;
+    tables.retain(|t| !t.starts_with(RESERVED_TABLE_PREFIX));
+    Ok(tables)
+}
+
+/// 管理命令的处理，需要访问整个 Service
+pub trait AdminService {
+    fn execute<Store: Storage>(self, svc: &Service<Store>) -> CommandResponse;
+}
+
+impl AdminService for ListTables {
+    fn execute<Store: Storage>(self, svc: &Service<Store>) -> CommandResponse {
+        match user_tables(&svc.inner.store) {
+            Ok(mut tables) => {
+                tables.sort();
+                tables.into_iter().map(Value::from).collect::<Vec<_>>().into()
+            }
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl AdminService for Dbsize {
+    fn execute<Store: Storage>(self, svc: &Service<Store>) -> CommandResponse {
+        let store = &svc.inner.store;
+        if !self.table.is_empty() {
+            if let Err(e) = check_reserved_table(&self.table) {
+                return e.into();
+            }
+            return match store.count(&self.table) {
+                Ok(n) => Value::from(n as i64).into(),
+                Err(e) => e.into(),
+            };
+        }
+
+        // 没有指定 table，返回所有 table 的 key 数量
+        let tables = match user_tables(store) {
+            Ok(v) => v,
+            Err(e) => return e.into(),
+        };
+        let pairs: Result<Vec<_>, KvError> = tables
+            .into_iter()
+            .map(|t| store.count(&t).map(|n| Kvpair::new(t, (n as i64).into())))
+            .collect();
+        match pairs {
+            Ok(mut pairs) => {
+                pairs.sort_by(|a, b| a.key.cmp(&b.key));
+                pairs.into()
+            }
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl AdminService for Info {
+    fn execute<Store: Storage>(self, svc: &Service<Store>) -> CommandResponse {
+        let store = &svc.inner.store;
+        let size = match store.estimated_size() {
+            Ok(v) => v,
+            Err(e) => return e.into(),
+        };
+        let tables = match user_tables(store) {
+            Ok(v) => v.len(),
+            Err(e) => return e.into(),
+        };
+
+        vec![
+            Kvpair::new("version", env!("CARGO_PKG_VERSION").into()),
+            Kvpair::new("uptime", (svc.inner.started_at.elapsed().as_secs() as i64).into()),
+            Kvpair::new("storage", store.backend().into()),
+            Kvpair::new("estimated_size", (size as i64).into()),
+            Kvpair::new("tables", (tables as i64).into()),
+            Kvpair::new("clients", (svc.clients.len() as i64).into()),
+        ]
+        .into()
+    }
+}
+
+impl AdminService for ClientList {
+    fn execute<Store: Storage>(self, svc: &Service<Store>) -> CommandResponse {
+        svc.clients
+            .list()
+            .into_iter()
+            .map(|c| Kvpair::new(c.id.to_string(), c.to_string().into()))
+            .collect::<Vec<_>>()
+            .into()
+    }
+}
+
+impl AdminService for ClientKill {
+    fn execute<Store: Storage>(self, svc: &Service<Store>) -> CommandResponse {
+        match svc.clients.kill(self.id) {
+            true => CommandResponse::ok(),
+            false => KvError::NotFound(format!("client {}", self.id)).into(),
+        }
+    }
+}
+
+impl AdminService for CreateTable {
+    fn execute<Store: Storage>(self, svc: &Service<Store>) -> CommandResponse {
+        let options = self.options.unwrap_or_default();
+        match svc.inner.store.create_table(&self.table, options) {
+            Ok(()) => CommandResponse::ok(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl AdminService for DropTable {
+    fn execute<Store: Storage>(self, svc: &Service<Store>) -> CommandResponse {
+        if let Err(e) = check_reserved_table(&self.table) {
+            return e.into();
+        }
+        match svc.inner.store.drop_table(&self.table) {
+            Ok(true) => CommandResponse::ok(),
+            Ok(false) => KvError::NotFound(format!("table {}", self.table)).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl AdminService for RenameTable {
+    fn execute<Store: Storage>(self, svc: &Service<Store>) -> CommandResponse {
+        if let Err(e) = check_reserved_table(&self.from) {
+            return e.into();
+        }
+        match svc.inner.store.rename_table(&self.from, &self.to) {
+            Ok(()) => CommandResponse::ok(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl From<BackupStats> for CommandResponse {
+    fn from(stats: BackupStats) -> Self {
+        vec![
+            Kvpair::new("tables", (stats.tables as i64).into()),
+            Kvpair::new("pairs", (stats.pairs as i64).into()),
+            Kvpair::new("zsets", (stats.zsets as i64).into()),
+        ]
+        .into()
+    }
+}
+
+impl AdminService for Backup {
+    fn execute<Store: Storage>(self, svc: &Service<Store>) -> CommandResponse {
+        let progress = |s: &BackupStats| info!("backup {}: {} pairs", self.path, s.pairs);
+        match backup_file(&svc.inner.store, &self.path, progress) {
+            Ok(stats) => stats.into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl AdminService for Restore {
+    fn execute<Store: Storage>(self, svc: &Service<Store>) -> CommandResponse {
+        let progress = |s: &BackupStats| info!("restore {}: {} pairs", self.path, s.pairs);
+        match restore_file(&svc.inner.store, &self.path, progress) {
+            Ok(stats) => stats.into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+/// 处理管理命令，只有 admin 角色的客户端可以执行
+pub fn dispatch_admin<Store: Storage>(
+    cmd: CommandRequest,
+    svc: &Service<Store>,
+    client: Option<&ClientInfo>,
+) -> CommandResponse {
+    if svc.inner.acl.role(client) != Role::Admin {
+        return KvError::PermissionDenied(format!("{} requires admin role", cmd.command_name()))
+            .into();
+    }
+
+    match cmd.request_data {
+        Some(RequestData::ListTables(param)) => param.execute(svc),
+        Some(RequestData::Dbsize(param)) => param.execute(svc),
+        Some(RequestData::Info(param)) => param.execute(svc),
+        Some(RequestData::ClientList(param)) => param.execute(svc),
+        Some(RequestData::ClientKill(param)) => param.execute(svc),
+        Some(RequestData::CreateTable(param)) => param.execute(svc),
+        Some(RequestData::DropTable(param)) => param.execute(svc),
+        Some(RequestData::RenameTable(param)) => param.execute(svc),
+        Some(RequestData::Backup(param)) => param.execute(svc),
+        Some(RequestData::Restore(param)) => param.execute(svc),
+        _ => KvError::InvalidCommand(format!("{} is not an admin command", cmd.format())).into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        acquire_lock, assert_res_error, assert_res_ok, dispatch, Acl, ClientHandle, MemTable,
+        ServiceInner, TableOptions, LOCK_TABLE,
+    };
+
+    fn admin_service() -> (Service, ClientHandle) {
+        let service: Service = ServiceInner::new(MemTable::new())
+            .acl(Acl::new(["admin"]))
+            .into();
+        let handle = service.register_client(None, Some("admin".into()));
+        (service, handle)
+    }
+
+    #[test]
+    fn admin_command_without_admin_role_should_be_denied() {
+        let (service, _) = admin_service();
+        let res = dispatch_admin(CommandRequest::new_info(), &service, None);
+        assert_res_error(&res, 403, "Permission denied");
+    }
+
+    #[test]
+    fn list_tables_and_dbsize_should_work() {
+        let (service, handle) = admin_service();
+        let admin = handle.info();
+        let store = &service.inner.store;
+        store.set("t2", "k1".into(), "v1".into()).unwrap();
+        store.set("t1", "k1".into(), "v1".into()).unwrap();
+        store.set("t1", "k2".into(), "v2".into()).unwrap();
+
+        let res = dispatch_admin(CommandRequest::new_list_tables(), &service, Some(admin));
+        assert_res_ok(&res, &["t1".into(), "t2".into()], &[]);
+
+        let res = dispatch_admin(CommandRequest::new_dbsize("t1"), &service, Some(admin));
+        assert_res_ok(&res, &[2.into()], &[]);
+
+        let res = dispatch_admin(CommandRequest::new_dbsize(""), &service, Some(admin));
+        let pairs = &[Kvpair::new("t1", 2.into()), Kvpair::new("t2", 1.into())];
+        assert_res_ok(&res, &[], pairs);
+    }
+
+    #[test]
+    fn reserved_table_should_be_protected() {
+        let (service, handle) = admin_service();
+        let admin = Some(handle.info());
+        let store = &service.inner.store;
+        acquire_lock(store, "job", "w1", 10_000).unwrap();
+        store.set("t1", "k1".into(), "v1".into()).unwrap();
+
+        let res = dispatch_admin(CommandRequest::new_list_tables(), &service, admin);
+        assert_res_ok(&res, &["t1".into()], &[]);
+        let res = dispatch_admin(CommandRequest::new_dbsize(""), &service, admin);
+        assert_res_ok(&res, &[], &[Kvpair::new("t1", 1.into())]);
+        let res = dispatch_admin(CommandRequest::new_dbsize(LOCK_TABLE), &service, admin);
+        assert_res_error(&res, 403, "Permission denied");
+
+        let res = dispatch_admin(CommandRequest::new_drop_table(LOCK_TABLE), &service, admin);
+        assert_res_error(&res, 403, "Permission denied");
+        let res = dispatch_admin(
+            CommandRequest::new_rename_table(LOCK_TABLE, "t2"),
+            &service,
+            admin,
+        );
+        assert_res_error(&res, 403, "Permission denied");
+        // 锁仍然被持有
+        assert!(acquire_lock(store, "job", "w2", 10_000).is_err());
+    }
+
+    #[test]
+    fn table_management_should_work() {
+        let (service, handle) = admin_service();
+        let admin = Some(handle.info());
+        let options = TableOptions {
+            value_type: "integer".into(),
+            ..Default::default()
+        };
+
+        let res = dispatch_admin(
+            CommandRequest::new_create_table("t1", options.clone()),
+            &service,
+            admin,
+        );
+        assert_res_ok(&res, &[], &[]);
+        let res = dispatch_admin(
+            CommandRequest::new_create_table("t1", options),
+            &service,
+            admin,
+        );
+        assert_res_error(&res, 409, "Already exists");
+
+        // table的选项对普通命令生效
+        let res = dispatch(
+            CommandRequest::new_hset("t1", "k1", "v1".into()),
+            &service.inner.store,
+        );
+        assert_res_error(&res, 422, "Constraint violation");
+
+        let res = dispatch_admin(
+            CommandRequest::new_rename_table("t1", "t2"),
+            &service,
+            admin,
+        );
+        assert_res_ok(&res, &[], &[]);
+        let res = dispatch_admin(CommandRequest::new_drop_table("t2"), &service, admin);
+        assert_res_ok(&res, &[], &[]);
+        let res = dispatch_admin(CommandRequest::new_drop_table("t2"), &service, admin);
+        assert_res_error(&res, 404, "Not found");
+    }
+
+    #[test]
+    fn backup_and_restore_should_work() {
+        let (service, handle) = admin_service();
+        let admin = Some(handle.info());
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("kv.backup");
+        let path = path.to_str().unwrap();
+        service
+            .inner
+            .store
+            .set("t1", "k1".into(), "v1".into())
+            .unwrap();
+
+        let res = dispatch_admin(CommandRequest::new_backup(path), &service, admin);
+        let pairs = &[
+            Kvpair::new("tables", 1.into()),
+            Kvpair::new("pairs", 1.into()),
+            Kvpair::new("zsets", 0.into()),
+        ];
+        assert_res_ok(&res, &[], pairs);
+
+        let (target, handle) = admin_service();
+        let res = dispatch_admin(CommandRequest::new_restore(path), &target, Some(handle.info()));
+        assert_res_ok(&res, &[], pairs);
+        assert_eq!(
+            target.inner.store.get("t1", "k1").unwrap(),
+            Some("v1".into())
+        );
+
+        let res = dispatch_admin(CommandRequest::new_restore("/nonexist"), &target, admin);
+        assert_eq!(res.status, 500);
+    }
+
+    #[test]
+    fn info_should_work() {
+        let (service, handle) = admin_service();
+        let admin = handle.info();
+        let res = dispatch_admin(CommandRequest::new_info(), &service, Some(admin));
+        assert_eq!(res.status, 200);
+        assert!(res
+            .pairs
+            .contains(&Kvpair::new("storage", "memtable".into())));
+        assert!(res.pairs.contains(&Kvpair::new("clients", 1.into())));
+    }
+
+    #[test]
+    fn client_list_and_kill_should_work() {
+        let (service, handle) = admin_service();
+        let admin = handle.info();
+        let res = dispatch_admin(CommandRequest::new_client_list(), &service, Some(admin));
+        assert_eq!(res.pairs.len(), 1);
+        assert_eq!(res.pairs[0].key, admin.id.to_string());
+
+        let res = dispatch_admin(CommandRequest::new_client_kill(admin.id), &service, Some(admin));
+        assert_res_ok(&res, &[], &[]);
+
+        let res = dispatch_admin(CommandRequest::new_client_kill(0), &service, Some(admin));
+        assert_res_error(&res, 404, "Not found");
+    }
+}
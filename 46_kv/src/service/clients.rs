@@ -0,0 +1,191 @@
+use dashmap::DashMap;
+use std::{
+    collections::HashSet,
+    fmt,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+use tokio::sync::Notify;
+use tracing::debug;
+
+/// 下一个client id
+static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// 客户端的角色
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    /// 只能执行普通命令
+    User,
+    /// 可以执行管理命令
+    Admin,
+}
+
+/// 访问控制列表，根据客户端证书里的身份（CN）决定角色
+#[derive(Debug, Clone, Default)]
+pub struct Acl {
+    admins: HashSet<String>,
+}
+
+impl Acl {
+    pub fn new(admins: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            admins: admins.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    /// 没有身份的客户端只能是普通用户
+    pub fn role(&self, client: Option<&ClientInfo>) -> Role {
+        match client.and_then(|c| c.identity.as_ref()) {
+            Some(id) if self.admins.contains(id) => Role::Admin,
+            _ => Role::User,
+        }
+    }
+}
+
+/// 连接到服务器的客户端
+#[derive(Debug, Clone)]
+pub struct ClientInfo {
+    pub id: u64,
+    pub peer: Option<SocketAddr>,
+    pub identity: Option<String>,
+    pub connected_at: Instant,
+}
+
+impl fmt::Display for ClientInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let peer = self.peer.map(|p| p.to_string());
+        write!(
+            f,
+            "id={} addr={} identity={} age={}",
+            self.id,
+            peer.as_deref().unwrap_or("-"),
+            self.identity.as_deref().unwrap_or("-"),
+            self.connected_at.elapsed().as_secs()
+        )
+    }
+}
+
+/// 所有连接中的客户端
+#[derive(Debug, Default)]
+pub struct ClientRegistry {
+    clients: DashMap<u64, (ClientInfo, Arc<Notify>)>,
+}
+
+/// 注册客户端后得到的句柄，drop 时把客户端从列表中删除
+pub struct ClientHandle {
+    info: ClientInfo,
+    killed: Arc<Notify>,
+    registry: Arc<ClientRegistry>,
+}
+
+impl ClientRegistry {
+    /// 注册一个新的客户端
+    pub fn register(
+        self: &Arc<Self>,
+        peer: Option<SocketAddr>,
+        identity: Option<String>,
+    ) -> ClientHandle {
+        let info = ClientInfo {
+            id: NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed),
+            peer,
+            identity,
+            connected_at: Instant::now(),
+        };
+        let killed = Arc::new(Notify::new());
+        self.clients.insert(info.id, (info.clone(), killed.clone()));
+        debug!("Client {} is registered", info);
+
+        ClientHandle {
+            info,
+            killed,
+            registry: self.clone(),
+        }
+    }
+
+    /// 所有客户端，按 id 排序
+    pub fn list(&self) -> Vec<ClientInfo> {
+        let mut clients: Vec<_> = self.clients.iter().map(|c| c.value().0.clone()).collect();
+        clients.sort_by_key(|c| c.id);
+        clients
+    }
+
+    /// 通知客户端所在的连接断开，如果客户端不存在返回 false
+    pub fn kill(&self, id: u64) -> bool {
+        match self.clients.get(&id) {
+            Some(entry) => {
+                entry.value().1.notify_one();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.clients.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty()
+    }
+}
+
+impl ClientHandle {
+    pub fn info(&self) -> &ClientInfo {
+        &self.info
+    }
+
+    /// 等待客户端被 kill
+    pub async fn killed(&self) {
+        self.killed.notified().await
+    }
+}
+
+impl Drop for ClientHandle {
+    fn drop(&mut self) {
+        self.registry.clients.remove(&self.info.id);
+        debug!("Client {} is removed", self.info.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::time;
+
+    #[tokio::test]
+    async fn client_registry_should_work() {
+        let registry = Arc::new(ClientRegistry::default());
+        let peer = "127.0.0.1:5000".parse().ok();
+        let handle = registry.register(peer, Some("admin".into()));
+        assert_eq!(registry.list().len(), 1);
+
+        assert!(registry.kill(handle.info().id));
+        time::timeout(Duration::from_millis(10), handle.killed())
+            .await
+            .unwrap();
+
+        drop(handle);
+        assert!(registry.is_empty());
+        assert!(!registry.kill(9527));
+    }
+
+    #[test]
+    fn acl_role_should_work() {
+        let acl = Acl::new(["admin"]);
+        let mut client = ClientInfo {
+            id: 1,
+            peer: None,
+            identity: Some("admin".into()),
+            connected_at: Instant::now(),
+        };
+        assert_eq!(acl.role(Some(&client)), Role::Admin);
+        client.identity = Some("device".into());
+        assert_eq!(acl.role(Some(&client)), Role::User);
+        assert_eq!(acl.role(None), Role::User);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::*;
+use crate::{storage::normalize_range, *};
 
 impl CommandService for Hget {
     fn execute(self, store: &impl Storage) -> CommandResponse {
@@ -19,6 +19,19 @@ impl CommandService for Hgetall {
     }
 }
 
+impl CommandService for Hmget {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        self.keys
+            .iter()
+            .map(|key| match store.get(&self.table, key) {
+                Ok(Some(v)) => v,
+                _ => Value::default(),
+            })
+            .collect::<Vec<_>>()
+            .into()
+    }
+}
+
 impl CommandService for Hset {
     fn execute(self, store: &impl Storage) -> CommandResponse {
         match self.pair {
@@ -32,6 +45,44 @@ impl CommandService for Hset {
     }
 }
 
+// 条件写入的结果：[是否写入, 当前的值]
+fn cas_response(
+    result: Result<Result<(), Option<Value>>, KvError>,
+    new: Option<Value>,
+) -> CommandResponse {
+    let (written, current) = match result {
+        Ok(Ok(())) => (true, new),
+        Ok(Err(current)) => (false, current),
+        Err(e) => return e.into(),
+    };
+    vec![written.into(), current.unwrap_or_else(Value::null)].into()
+}
+
+impl CommandService for Hsetnx {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match self.pair {
+            Some(v) => {
+                let value = v.value.unwrap_or_default();
+                let result = store.compare_and_swap(&self.table, &v.key, None, Some(value.clone()));
+                cas_response(result, Some(value))
+            }
+            None => KvError::InvalidCommand("missing pair".into()).into(),
+        }
+    }
+}
+
+impl CommandService for Hcas {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let result = store.compare_and_swap(
+            &self.table,
+            &self.key,
+            self.expected.as_ref(),
+            self.value.clone(),
+        );
+        cas_response(result, self.value)
+    }
+}
+
 impl CommandService for Hmset {
     fn execute(self, store: &impl Storage) -> CommandResponse {
         let pairs = self.pairs;
@@ -95,6 +146,235 @@ impl CommandService for Hmexist {
     }
 }
 
+impl CommandService for Hfind {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let start = self.start.unwrap_or_default();
+        let end = self.end.unwrap_or_else(|| start.clone());
+        match store.find(&self.table, &self.index, &start, &end) {
+            Ok(v) => v.into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+// 把value当作list，不存在的key是空list
+fn as_list(v: Option<&Value>) -> Result<Vec<Value>, KvError> {
+    match v {
+        None | Some(Value { value: None }) => Ok(Vec::new()),
+        Some(v) => v
+            .clone()
+            .try_into()
+            .map_err(|_| KvError::InvalidCommand(format!("{} is not a list", v.format()))),
+    }
+}
+
+fn push(
+    store: &impl Storage,
+    table: &str,
+    key: &str,
+    f: impl Fn(&mut Vec<Value>),
+) -> CommandResponse {
+    let result = update(store, table, key, |v| {
+        let mut list = as_list(v)?;
+        f(&mut list);
+        let len = list.len() as i64;
+        Ok((Some(list.into()), len))
+    });
+    match result {
+        Ok(len) => Value::from(len).into(),
+        Err(e) => e.into(),
+    }
+}
+
+fn pop(
+    store: &impl Storage,
+    table: &str,
+    key: &str,
+    f: impl Fn(&mut Vec<Value>) -> Option<Value>,
+) -> CommandResponse {
+    let result = update(store, table, key, |v| {
+        let mut list = as_list(v)?;
+        let item = f(&mut list)
+            .ok_or_else(|| KvError::NotFound(format!("table: {}, key: {}", table, key)))?;
+        // list为空时删除key
+        let new = (!list.is_empty()).then(|| list.into());
+        Ok((new, item))
+    });
+    match result {
+        Ok(v) => v.into(),
+        Err(e) => e.into(),
+    }
+}
+
+impl CommandService for Lpush {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        push(store, &self.table, &self.key, |list| {
+            // 和redis一样，依次插入到头部，最后一个value在最前面
+            for v in self.values.iter().cloned() {
+                list.insert(0, v);
+            }
+        })
+    }
+}
+
+impl CommandService for Rpush {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        push(store, &self.table, &self.key, |list| {
+            list.extend(self.values.iter().cloned())
+        })
+    }
+}
+
+impl CommandService for Lpop {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        pop(store, &self.table, &self.key, |list| {
+            (!list.is_empty()).then(|| list.remove(0))
+        })
+    }
+}
+
+impl CommandService for Rpop {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        pop(store, &self.table, &self.key, |list| list.pop())
+    }
+}
+
+impl CommandService for Lrange {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let list = match store.get(&self.table, &self.key).and_then(|v| as_list(v.as_ref())) {
+            Ok(v) => v,
+            Err(e) => return e.into(),
+        };
+        match normalize_range(list.len(), self.start, self.stop) {
+            Some(range) => list[range].to_vec().into(),
+            None => Vec::<Value>::new().into(),
+        }
+    }
+}
+
+impl CommandService for Fget {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.get(&self.table, &self.key) {
+            Ok(Some(v)) => match v.get_field(&self.field) {
+                Some(v) => v.clone().into(),
+                None => KvError::NotFound(format!("field {}", self.field)).into(),
+            },
+            Ok(None) => KvError::NotFound(format!("table: {}, key: {}", self.table, self.key)).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Fset {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let value = self.value.unwrap_or_default();
+        let result = update(store, &self.table, &self.key, |v| {
+            let mut doc = v.cloned().unwrap_or_default();
+            let old = doc.set_field(&self.field, value.clone())?;
+            Ok((Some(doc), old))
+        });
+        match result {
+            Ok(v) => v.unwrap_or_default().into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Fdel {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let result = update(store, &self.table, &self.key, |v| {
+            let mut doc = match v {
+                Some(v) => v.clone(),
+                None => return Ok((None, None)),
+            };
+            let old = doc.remove_field(&self.field)?;
+            Ok((Some(doc), old))
+        });
+        match result {
+            Ok(v) => v.unwrap_or_default().into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Zadd {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let members = self
+            .members
+            .into_iter()
+            .map(|v| (v.member, v.score))
+            .collect();
+        match store.zadd(&self.table, &self.key, members) {
+            Ok(n) => Value::from(n as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Zrem {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.zrem(&self.table, &self.key, &self.members) {
+            Ok(n) => Value::from(n as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Zscore {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.zscore(&self.table, &self.key, &self.member) {
+            Ok(Some(v)) => Value::from(v).into(),
+            Ok(None) => KvError::NotFound(format!("member {}", self.member)).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Zrank {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.zrank(&self.table, &self.key, &self.member) {
+            Ok(Some(v)) => Value::from(v as i64).into(),
+            Ok(None) => KvError::NotFound(format!("member {}", self.member)).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Zrange {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.zrange(&self.table, &self.key, self.start, self.stop) {
+            Ok(v) => v.into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Zrangebyscore {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let result = store.zrange_by_score(
+            &self.table,
+            &self.key,
+            self.min,
+            self.max,
+            self.offset,
+            self.limit,
+        );
+        match result {
+            Ok(v) => v.into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Eval {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match Script::parse(&self.script).and_then(|s| s.run(store, &self.args, self.max_steps)) {
+            Ok(v) => v.into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::process::Command;
@@ -149,6 +429,15 @@ mod tests {
         assert_res_ok(&res, &[], pairs);
     }
 
+    #[test]
+    fn hmget_should_work() {
+        let store = MemTable::new();
+        set_key_pairs("t1", vec![("u1", "v1")], &store);
+        let cmd = CommandRequest::new_hmget("t1", vec!["u1".into(), "u2".into()]);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(&res, &["v1".into(), Value::default()], &[]);
+    }
+
     #[test]
     fn hmset_should_work() {
         let store = MemTable::new();
@@ -229,4 +518,142 @@ mod tests {
                 dispatch(cmd, store);
             });
     }
+
+    #[test]
+    fn hfind_should_work() {
+        let store = MemTable::new();
+        let options = TableOptions {
+            indexes: vec![IndexSpec {
+                name: "age".into(),
+                field: "".into(),
+            }],
+            ..Default::default()
+        };
+        store.create_table("users", options).unwrap();
+        let cmd = CommandRequest::new_hmset(
+            "users",
+            vec![
+                Kvpair::new("u1", 30.into()),
+                Kvpair::new("u2", 20.into()),
+                Kvpair::new("u3", 30.into()),
+            ],
+        );
+        dispatch(cmd, &store);
+
+        let res = dispatch(CommandRequest::new_hfind("users", "age", 30.into()), &store);
+        let pairs = &[Kvpair::new("u1", 30.into()), Kvpair::new("u3", 30.into())];
+        assert_res_ok(&res, &[], pairs);
+
+        let cmd = CommandRequest::new_hfind_range("users", "age", 0.into(), 25.into());
+        let res = dispatch(cmd, &store);
+        assert_res_ok(&res, &[], &[Kvpair::new("u2", 20.into())]);
+
+        let res = dispatch(CommandRequest::new_hfind("users", "name", 30.into()), &store);
+        assert_res_error(res, 404, "Not found");
+    }
+
+    #[test]
+    fn list_commands_should_work() {
+        let store = MemTable::new();
+        let cmd = CommandRequest::new_rpush("t1", "l", vec![1.into(), 2.into()]);
+        assert_res_ok(&dispatch(cmd, &store), &[2.into()], &[]);
+        let cmd = CommandRequest::new_lpush("t1", "l", vec![0.into(), (-1).into()]);
+        assert_res_ok(&dispatch(cmd, &store), &[4.into()], &[]);
+
+        let res = dispatch(CommandRequest::new_lrange("t1", "l", 0, -1), &store);
+        assert_res_ok(&res, &[(-1).into(), 0.into(), 1.into(), 2.into()], &[]);
+        let res = dispatch(CommandRequest::new_lrange("t1", "l", -2, 10), &store);
+        assert_res_ok(&res, &[1.into(), 2.into()], &[]);
+
+        let res = dispatch(CommandRequest::new_lpop("t1", "l"), &store);
+        assert_res_ok(&res, &[(-1).into()], &[]);
+        let res = dispatch(CommandRequest::new_rpop("t1", "l"), &store);
+        assert_res_ok(&res, &[2.into()], &[]);
+
+        // 不是list的value不能当作list操作
+        dispatch(CommandRequest::new_hset("t1", "s", "hello".into()), &store);
+        let res = dispatch(CommandRequest::new_lpop("t1", "s"), &store);
+        assert_res_error(res, 400, "Command is invalid");
+        let res = dispatch(CommandRequest::new_lpop("t1", "none"), &store);
+        assert_res_error(res, 404, "Not found");
+    }
+
+    #[test]
+    fn map_commands_should_work() {
+        let store = MemTable::new();
+        let cmd = CommandRequest::new_fset("t1", "u1", "name", "tyr".into());
+        assert_res_ok(&dispatch(cmd, &store), &[Value::default()], &[]);
+        let cmd = CommandRequest::new_fset("t1", "u1", "address.city", "Shanghai".into());
+        dispatch(cmd, &store);
+
+        let res = dispatch(CommandRequest::new_fget("t1", "u1", "address.city"), &store);
+        assert_res_ok(&res, &["Shanghai".into()], &[]);
+
+        let res = dispatch(CommandRequest::new_fdel("t1", "u1", "name"), &store);
+        assert_res_ok(&res, &["tyr".into()], &[]);
+        let res = dispatch(CommandRequest::new_fget("t1", "u1", "name"), &store);
+        assert_res_error(res, 404, "Not found");
+    }
+
+    #[test]
+    fn zset_commands_should_work() {
+        let store = MemTable::new();
+        let members = vec![("alice".into(), 30.0), ("bob".into(), 10.0)];
+        let res = dispatch(CommandRequest::new_zadd("t1", "board", members), &store);
+        assert_res_ok(&res, &[2.into()], &[]);
+
+        let res = dispatch(CommandRequest::new_zscore("t1", "board", "alice"), &store);
+        assert_res_ok(&res, &[30.0.into()], &[]);
+        let res = dispatch(CommandRequest::new_zrank("t1", "board", "alice"), &store);
+        assert_res_ok(&res, &[1.into()], &[]);
+        let res = dispatch(CommandRequest::new_zrank("t1", "board", "carol"), &store);
+        assert_res_error(res, 404, "Not found");
+
+        let res = dispatch(CommandRequest::new_zrange("t1", "board", 0, 0), &store);
+        assert_res_ok(&res, &[], &[Kvpair::new("bob", 10.0.into())]);
+        let cmd = CommandRequest::new_zrangebyscore("t1", "board", 20.0, 100.0, 0, 10);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(&res, &[], &[Kvpair::new("alice", 30.0.into())]);
+
+        let res = dispatch(
+            CommandRequest::new_zrem("t1", "board", vec!["bob".into()]),
+            &store,
+        );
+        assert_res_ok(&res, &[1.into()], &[]);
+    }
+
+    #[test]
+    fn eval_should_work() {
+        let store = MemTable::new();
+        let script = r#"(hset "t1" "k" (arg 0)) (list (hget "t1" "k") (+ (arg 0) 1))"#;
+        let res = dispatch(CommandRequest::new_eval(script, vec![1.into()], 0), &store);
+        assert_res_ok(&res, &[1.into(), 2.into()], &[]);
+
+        let res = dispatch(CommandRequest::new_eval("(hget", vec![], 0), &store);
+        assert_res_error(res, 400, "Script error");
+    }
+
+    #[test]
+    fn conditional_writes_should_work() {
+        let store = MemTable::new();
+        let res = dispatch(CommandRequest::new_hsetnx("t1", "job1", "w1".into()), &store);
+        assert_res_ok(&res, &[true.into(), "w1".into()], &[]);
+        let res = dispatch(CommandRequest::new_hsetnx("t1", "job1", "w2".into()), &store);
+        assert_res_ok(&res, &[false.into(), "w1".into()], &[]);
+
+        let cmd = CommandRequest::new_hcas("t1", "job1", Some("w2".into()), Some("w3".into()));
+        let res = dispatch(cmd, &store);
+        assert_res_ok(&res, &[false.into(), "w1".into()], &[]);
+        let cmd = CommandRequest::new_hcas("t1", "job1", Some("w1".into()), Some("w3".into()));
+        let res = dispatch(cmd, &store);
+        assert_res_ok(&res, &[true.into(), "w3".into()], &[]);
+
+        // value为空时删除key
+        let cmd = CommandRequest::new_hcas("t1", "job1", Some("w3".into()), None);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(&res, &[true.into(), Value::null()], &[]);
+        let cmd = CommandRequest::new_hcas("t1", "job1", None, Some("w4".into()));
+        let res = dispatch(cmd, &store);
+        assert_res_ok(&res, &[true.into(), "w4".into()], &[]);
+    }
 }
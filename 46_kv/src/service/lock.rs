@@ -0,0 +1,443 @@
+//! 分布式锁
+//!
+//! 锁保存在 `__locks__` table 中，每个锁一条记录：`{owner, token, expires_at}`。
+//! 记录在释放后也不会删除，只是清空 owner，这样下一次获取锁时 fencing token 可以在
+//! 上一次的基础上递增（SledDb 重启后也是如此）。所有的修改都通过 compare_and_swap 完成。
+//!
+//! 每个 yamux stream 有一个 `LockSession`，记录这个 stream 上获取的锁，
+//! stream 或者连接断开时 session 被 drop，其中还没有释放的锁会被释放。
+//! 同一个 owner 可以在多个 stream 上重复获取同一个锁（token 相同），
+//! 这时只有最后一个持有它的 session drop 时才会释放。
+
+use crate::{
+    command_request::RequestData, storage::now_ms, update, value, CommandRequest, CommandResponse,
+    KvError, Lock, RenewLock, Service, Storage, Unlock, Value,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::TryFrom,
+    sync::Mutex,
+};
+use tracing::debug;
+
+/// 保存锁的 table，`__` 开头的 table 不能被数据命令访问
+pub const LOCK_TABLE: &str = "__locks__";
+
+/// 锁的租约最长一天
+pub const MAX_LEASE_MS: u64 = 24 * 60 * 60 * 1000;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct LockRecord {
+    owner: String,
+    token: u64,
+    expires_at: u64,
+}
+
+impl LockRecord {
+    // 锁被 owner 持有并且没有过期
+    fn held_by(&self, owner: &str, now: u64) -> bool {
+        !self.owner.is_empty() && self.owner == owner && self.expires_at > now
+    }
+
+    fn is_free(&self, now: u64) -> bool {
+        self.owner.is_empty() || self.expires_at <= now
+    }
+}
+
+impl TryFrom<Option<&Value>> for LockRecord {
+    type Error = KvError;
+
+    fn try_from(v: Option<&Value>) -> Result<Self, Self::Error> {
+        let v = match v {
+            Some(v) => v,
+            None => return Ok(Self::default()),
+        };
+        let field = |name: &str| {
+            v.get_field(name)
+                .ok_or_else(|| KvError::Internal(format!("invalid lock record: {}", v.format())))
+        };
+        match (&field("owner")?.value, &field("expires_at")?.value) {
+            (Some(value::Value::String(owner)), Some(value::Value::Timestamp(ms))) => Ok(Self {
+                owner: owner.clone(),
+                token: i64::try_from(field("token")?)? as u64,
+                expires_at: *ms as u64,
+            }),
+            _ => Err(KvError::Internal(format!(
+                "invalid lock record: {}",
+                v.format()
+            ))),
+        }
+    }
+}
+
+impl From<LockRecord> for Value {
+    fn from(r: LockRecord) -> Self {
+        let mut map = BTreeMap::new();
+        map.insert("owner".to_string(), r.owner.into());
+        map.insert("token".to_string(), (r.token as i64).into());
+        map.insert(
+            "expires_at".to_string(),
+            Value::timestamp(r.expires_at as i64),
+        );
+        map.into()
+    }
+}
+
+fn check_lease(lease_ms: u64) -> Result<(), KvError> {
+    match lease_ms {
+        0 => Err(KvError::InvalidCommand(
+            "lease must be greater than 0".into(),
+        )),
+        ms if ms > MAX_LEASE_MS => Err(KvError::InvalidCommand(format!(
+            "lease must not be greater than {} ms",
+            MAX_LEASE_MS
+        ))),
+        _ => Ok(()),
+    }
+}
+
+fn check_owner(owner: &str) -> Result<(), KvError> {
+    match owner {
+        "" => Err(KvError::InvalidCommand("owner cannot be empty".into())),
+        _ => Ok(()),
+    }
+}
+
+/// 获取锁，返回 fencing token
+///
+/// 同一个 owner 重复获取没有过期的锁时只延长租约，token 不变
+pub fn acquire_lock(
+    store: &impl Storage,
+    name: &str,
+    owner: &str,
+    lease_ms: u64,
+) -> Result<u64, KvError> {
+    check_owner(owner)?;
+    check_lease(lease_ms)?;
+    update(store, LOCK_TABLE, name, |v| {
+        let mut record = LockRecord::try_from(v)?;
+        let now = now_ms();
+        if !record.held_by(owner, now) {
+            if !record.is_free(now) {
+                return Err(KvError::AlreadyExists(format!(
+                    "lock {} is held by {}",
+                    name, record.owner
+                )));
+            }
+            record.owner = owner.into();
+            record.token += 1;
+        }
+        record.expires_at = now.saturating_add(lease_ms);
+        let token = record.token;
+        Ok((Some(record.into()), token))
+    })
+}
+
+/// 延长锁的租约，锁必须仍然被 owner 以 token 持有
+pub fn renew_lock(
+    store: &impl Storage,
+    name: &str,
+    owner: &str,
+    token: u64,
+    lease_ms: u64,
+) -> Result<u64, KvError> {
+    check_lease(lease_ms)?;
+    update(store, LOCK_TABLE, name, |v| {
+        let mut record = LockRecord::try_from(v)?;
+        let now = now_ms();
+        if !record.held_by(owner, now) || record.token != token {
+            return Err(not_held(name, owner));
+        }
+        record.expires_at = now.saturating_add(lease_ms);
+        Ok((Some(record.into()), token))
+    })
+}
+
+/// 释放锁，锁必须仍然被 owner 以 token 持有
+pub fn release_lock(
+    store: &impl Storage,
+    name: &str,
+    owner: &str,
+    token: u64,
+) -> Result<(), KvError> {
+    update(store, LOCK_TABLE, name, |v| {
+        let mut record = LockRecord::try_from(v)?;
+        if !record.held_by(owner, now_ms()) || record.token != token {
+            return Err(not_held(name, owner));
+        }
+        record.owner.clear();
+        record.expires_at = 0;
+        Ok((Some(record.into()), ()))
+    })
+}
+
+fn not_held(name: &str, owner: &str) -> KvError {
+    KvError::NotFound(format!("lock {} held by {}", name, owner))
+}
+
+/// 每次获取的锁（锁名、owner、token）被多少个 session 持有，所有的 session 共享
+#[derive(Debug, Default)]
+pub(crate) struct LockHolders {
+    counts: Mutex<HashMap<(String, String, u64), usize>>,
+}
+
+impl LockHolders {
+    fn hold(&self, name: &str, owner: &str, token: u64) {
+        let mut counts = self.counts.lock().unwrap();
+        *counts.entry((name.into(), owner.into(), token)).or_default() += 1;
+    }
+
+    // 一个 session 不再持有锁，返回它是不是最后一个持有者
+    fn release(&self, name: &str, owner: &str, token: u64) -> bool {
+        let mut counts = self.counts.lock().unwrap();
+        let key = (name.to_string(), owner.to_string(), token);
+        match counts.get_mut(&key) {
+            Some(n) if *n > 1 => {
+                *n -= 1;
+                false
+            }
+            _ => {
+                counts.remove(&key);
+                true
+            }
+        }
+    }
+}
+
+/// 一个 yamux stream 上获取的锁，drop 时释放所有还没有释放、也没有被其它 session 持有的锁
+pub struct LockSession<Store: Storage> {
+    svc: Service<Store>,
+    // 锁名 -> (owner, token)
+    held: Mutex<HashMap<String, (String, u64)>>,
+}
+
+impl<Store: Storage> LockSession<Store> {
+    pub fn new(svc: Service<Store>) -> Self {
+        Self {
+            svc,
+            held: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn track(&self, name: &str, owner: &str, token: u64) {
+        let holders = &self.svc.lock_holders;
+        let mut held = self.held.lock().unwrap();
+        match held.insert(name.into(), (owner.into(), token)) {
+            // 在这个 session 上重复获取，已经计数过了
+            Some(old) if old.0 == owner && old.1 == token => return,
+            // 之前获取的锁已经过期，被重新获取了
+            Some((owner, token)) => {
+                holders.release(name, &owner, token);
+            }
+            None => {}
+        }
+        holders.hold(name, owner, token);
+    }
+
+    fn untrack(&self, name: &str) {
+        if let Some((owner, token)) = self.held.lock().unwrap().remove(name) {
+            self.svc.lock_holders.release(name, &owner, token);
+        }
+    }
+
+    /// session 中还没有释放的锁的数量
+    pub fn len(&self) -> usize {
+        self.held.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<Store: Storage> Drop for LockSession<Store> {
+    fn drop(&mut self) {
+        let held = std::mem::take(self.held.get_mut().unwrap());
+        for (name, (owner, token)) in held {
+            // 其它 session 还持有这个锁
+            if !self.svc.lock_holders.release(&name, &owner, token) {
+                continue;
+            }
+            // 锁已经过期或者被别人获取时，释放会失败，忽略即可
+            let result = release_lock(&self.svc.inner.store, &name, &owner, token);
+            debug!(
+                "Release lock {} held by {} on disconnect: {:?}",
+                name, owner, result
+            );
+        }
+    }
+}
+
+/// 锁命令的处理，获取到的锁记录在 session 中
+pub trait LockService {
+    fn execute<Store: Storage>(
+        self,
+        store: &Store,
+        session: Option<&LockSession<Store>>,
+    ) -> CommandResponse;
+}
+
+impl LockService for Lock {
+    fn execute<Store: Storage>(
+        self,
+        store: &Store,
+        session: Option<&LockSession<Store>>,
+    ) -> CommandResponse {
+        match acquire_lock(store, &self.name, &self.owner, self.lease_ms) {
+            Ok(token) => {
+                if let Some(session) = session {
+                    session.track(&self.name, &self.owner, token);
+                }
+                Value::from(token as i64).into()
+            }
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl LockService for RenewLock {
+    fn execute<Store: Storage>(
+        self,
+        store: &Store,
+        _session: Option<&LockSession<Store>>,
+    ) -> CommandResponse {
+        match renew_lock(store, &self.name, &self.owner, self.token, self.lease_ms) {
+            Ok(token) => Value::from(token as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl LockService for Unlock {
+    fn execute<Store: Storage>(
+        self,
+        store: &Store,
+        session: Option<&LockSession<Store>>,
+    ) -> CommandResponse {
+        match release_lock(store, &self.name, &self.owner, self.token) {
+            Ok(()) => {
+                if let Some(session) = session {
+                    session.untrack(&self.name);
+                }
+                CommandResponse::ok()
+            }
+            Err(e) => e.into(),
+        }
+    }
+}
+
+pub fn dispatch_lock<Store: Storage>(
+    cmd: CommandRequest,
+    store: &Store,
+    session: Option<&LockSession<Store>>,
+) -> CommandResponse {
+    match cmd.request_data {
+        Some(RequestData::Lock(param)) => param.execute(store, session),
+        Some(RequestData::RenewLock(param)) => param.execute(store, session),
+        Some(RequestData::Unlock(param)) => param.execute(store, session),
+        _ => KvError::InvalidCommand(format!("{} is not a lock command", cmd.format())).into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assert_res_error, assert_res_ok, MemTable, ServiceInner};
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn lock_should_work() {
+        let store = MemTable::new();
+        let t1 = acquire_lock(&store, "job", "w1", 1000).unwrap();
+        // 重复获取时 token 不变
+        assert_eq!(acquire_lock(&store, "job", "w1", 1000).unwrap(), t1);
+        assert!(matches!(
+            acquire_lock(&store, "job", "w2", 1000),
+            Err(KvError::AlreadyExists(_))
+        ));
+
+        assert_eq!(renew_lock(&store, "job", "w1", t1, 1000).unwrap(), t1);
+        assert!(renew_lock(&store, "job", "w1", t1 + 1, 1000).is_err());
+        assert!(release_lock(&store, "job", "w2", t1).is_err());
+        release_lock(&store, "job", "w1", t1).unwrap();
+
+        // fencing token 单调递增
+        let t2 = acquire_lock(&store, "job", "w2", 1000).unwrap();
+        assert!(t2 > t1);
+
+        // 租约有上限，避免 expires_at 溢出
+        assert!(acquire_lock(&store, "job2", "w1", u64::MAX).is_err());
+        assert!(renew_lock(&store, "job", "w2", t2, MAX_LEASE_MS + 1).is_err());
+    }
+
+    #[test]
+    fn lock_table_should_be_reserved() {
+        let store = MemTable::new();
+        acquire_lock(&store, "job", "w1", 1000).unwrap();
+        let res = crate::dispatch(CommandRequest::new_hgetall(LOCK_TABLE), &store);
+        assert_eq!(res.status, 403);
+        let res = crate::dispatch(CommandRequest::new_hdel(LOCK_TABLE, "job"), &store);
+        assert_eq!(res.status, 403);
+        assert!(store.contains(LOCK_TABLE, "job").unwrap());
+        assert!(store.create_table("__t", Default::default()).is_err());
+    }
+
+    #[test]
+    fn expired_lock_can_be_taken_over() {
+        let store = MemTable::new();
+        let t1 = acquire_lock(&store, "job", "w1", 10).unwrap();
+        thread::sleep(Duration::from_millis(20));
+        let t2 = acquire_lock(&store, "job", "w2", 1000).unwrap();
+        assert!(t2 > t1);
+        // 过期的持有者不能再续约或者释放
+        assert!(renew_lock(&store, "job", "w1", t1, 1000).is_err());
+        assert!(release_lock(&store, "job", "w1", t1).is_err());
+    }
+
+    #[test]
+    fn lock_session_should_release_on_drop() {
+        let svc: Service = ServiceInner::new(MemTable::new()).into();
+        let session = LockSession::new(svc.clone());
+        let res = dispatch_lock(
+            CommandRequest::new_lock("job", "w1", 10_000),
+            &svc.inner.store,
+            Some(&session),
+        );
+        assert_res_ok(&res, &[1.into()], &[]);
+        let res = dispatch_lock(
+            CommandRequest::new_lock("job", "w2", 10_000),
+            &svc.inner.store,
+            None,
+        );
+        assert_res_error(&res, 409, "Already exists");
+        assert_eq!(session.len(), 1);
+
+        drop(session);
+        let res = dispatch_lock(
+            CommandRequest::new_lock("job", "w2", 10_000),
+            &svc.inner.store,
+            None,
+        );
+        assert_res_ok(&res, &[2.into()], &[]);
+    }
+
+    #[test]
+    fn reentrant_lock_should_be_released_by_last_session() {
+        let svc: Service = ServiceInner::new(MemTable::new()).into();
+        let store = &svc.inner.store;
+        let s1 = LockSession::new(svc.clone());
+        let s2 = LockSession::new(svc.clone());
+        let lock = || CommandRequest::new_lock("job", "w1", 10_000);
+        assert_res_ok(&dispatch_lock(lock(), store, Some(&s1)), &[1.into()], &[]);
+        // 同一个 owner 在另一个 stream 上重复获取，token 不变
+        assert_res_ok(&dispatch_lock(lock(), store, Some(&s2)), &[1.into()], &[]);
+        // 在同一个 stream 上重复获取不会重复计数
+        assert_res_ok(&dispatch_lock(lock(), store, Some(&s1)), &[1.into()], &[]);
+
+        // 第一个 stream 关闭时锁仍然被第二个 stream 持有
+        drop(s1);
+        assert!(acquire_lock(store, "job", "w2", 10_000).is_err());
+        drop(s2);
+        assert_eq!(acquire_lock(store, "job", "w2", 10_000).unwrap(), 2);
+    }
+}
@@ -1,14 +1,22 @@
 use crate::{
-    command_request::RequestData, CommandRequest, CommandResponse, KvError, MemTable, Storage,
+    command_request::RequestData, metrics, storage::check_reserved_table, CommandRequest,
+    CommandResponse, CompressionConfig, with_deadline, KvError, MemTable, Storage, TimeoutConfig,
+    DEFAULT_MAX_FRAME,
 };
-use std::sync::Arc;
+use std::{net::SocketAddr, sync::Arc, time::Instant};
 use tracing::{debug, instrument};
 use futures::{stream};
 
+mod admin_service;
+mod clients;
 mod command_service;
+mod lock;
 mod topic;
 mod topic_service;
 
+pub use admin_service::*;
+pub use clients::*;
+pub use lock::*;
 pub use topic::*;
 pub use topic_service::*;
 
@@ -21,6 +29,8 @@ pub trait CommandService {
 pub struct Service<Store = MemTable> {
     inner: Arc<ServiceInner<Store>>,
     broadcaster: Arc<Broadcaster>,
+    clients: Arc<ClientRegistry>,
+    lock_holders: Arc<LockHolders>,
 }
 
 impl<Store> Clone for Service<Store> {
@@ -28,6 +38,8 @@ impl<Store> Clone for Service<Store> {
         Self {
             inner: Arc::clone(&self.inner),
             broadcaster: Arc::clone(&self.broadcaster),
+            clients: Arc::clone(&self.clients),
+            lock_holders: Arc::clone(&self.lock_holders),
         }
     }
 }
@@ -37,25 +49,106 @@ impl<Store: Storage> Service<Store> {
     //         inner: Arc::new(ServiceInner { store }),
     //     }
     // }
-    #[instrument(name = "service_execute", skip_all)]
+    /// 以匿名客户端的身份执行命令
     pub fn execute(&self, cmd: CommandRequest) -> StreamingResponse {
+        self.execute_as(cmd, None)
+    }
+
+    /// 以某个客户端的身份执行命令，管理命令需要客户端有admin角色
+    pub fn execute_as(&self, cmd: CommandRequest, client: Option<&ClientInfo>) -> StreamingResponse {
+        self.execute_in(cmd, client, None)
+    }
+
+    /// 在某个连接的 session 中执行命令，获取的锁在 session drop 时释放
+    #[instrument(name = "service_execute", skip_all)]
+    pub fn execute_in(
+        &self,
+        cmd: CommandRequest,
+        client: Option<&ClientInfo>,
+        locks: Option<&LockSession<Store>>,
+    ) -> StreamingResponse {
         debug!("Got request: {:?}", cmd);
         self.inner.on_received.notify(&cmd);
-        let mut res = dispatch(cmd.clone(), &self.inner.store);
+        let name = cmd.command_name();
+        let timer = metrics::COMMAND_DURATION
+            .with_label_values(&[name])
+            .start_timer();
+        // 超过 deadline 时脚本、备份和恢复停止执行，返回 504
+        let deadline = cmd.deadline();
+        // 备份和恢复需要读写整个 store，放到 blocking 线程池中执行，不阻塞处理连接的线程
+        if cmd.is_blocking() {
+            if let Ok(handle) = tokio::runtime::Handle::try_current() {
+                let (svc, client) = (self.clone(), client.cloned());
+                let task = handle.spawn_blocking(move || {
+                    with_deadline(deadline, || dispatch_admin(cmd, &svc, client.as_ref()))
+                });
+                let svc = self.clone();
+                return Box::pin(stream::once(async move {
+                    let res = task
+                        .await
+                        .unwrap_or_else(|e| KvError::Internal(e.to_string()).into());
+                    timer.observe_duration();
+                    Arc::new(svc.finish(name, res))
+                }));
+            }
+        }
+        let res = with_deadline(deadline, || {
+            if cmd.is_admin() {
+                dispatch_admin(cmd.clone(), self, client)
+            } else if cmd.is_lock() {
+                dispatch_lock(cmd.clone(), &self.inner.store, locks)
+            } else {
+                dispatch(cmd.clone(), &self.inner.store)
+            }
+        });
 
         if res == CommandResponse::default() {
-            dispatch_stream(cmd, Arc::clone(&self.broadcaster))
+            let stream = dispatch_stream(cmd, Arc::clone(&self.broadcaster));
+            timer.observe_duration();
+            metrics::COMMANDS.with_label_values(&[name, "stream"]).inc();
+            stream
         } else {
-            debug!("Execited response: {:?}", res);
-            self.inner.on_executed.notify(&res);
-            self.inner.on_before_send.notify(&mut res);
-            if !self.inner.on_before_send.is_empty() {
-                debug!("Modified response: {:?}", res);
-            }
-
+            timer.observe_duration();
+            let res = self.finish(name, res);
             Box::pin(stream::once(async {Arc::new(res)}))
         }
     }
+
+    // 统计命令的结果，调用执行之后和发送之前的回调
+    fn finish(&self, name: &str, mut res: CommandResponse) -> CommandResponse {
+        metrics::COMMANDS
+            .with_label_values(&[name, &res.status.to_string()])
+            .inc();
+        debug!("Execited response: {:?}", res);
+        self.inner.on_executed.notify(&res);
+        self.inner.on_before_send.notify(&mut res);
+        if !self.inner.on_before_send.is_empty() {
+            debug!("Modified response: {:?}", res);
+        }
+        res
+    }
+
+    /// 为一个连接创建锁的 session
+    pub fn lock_session(&self) -> LockSession<Store> {
+        LockSession::new(self.clone())
+    }
+
+    pub fn compression(&self) -> &CompressionConfig {
+        &self.inner.compression
+    }
+
+    pub fn max_frame(&self) -> usize {
+        self.inner.max_frame
+    }
+
+    pub fn timeout(&self) -> &TimeoutConfig {
+        &self.inner.timeout
+    }
+
+    /// 注册一个新连接的客户端，返回的句柄drop时客户端被删除
+    pub fn register_client(&self, peer: Option<SocketAddr>, identity: Option<String>) -> ClientHandle {
+        self.clients.register(peer, identity)
+    }
 }
 
 impl<Store: Storage> From<ServiceInner<Store>> for Service<Store> {
@@ -63,12 +156,19 @@ impl<Store: Storage> From<ServiceInner<Store>> for Service<Store> {
         Self {
             inner: Arc::new(inner),
             broadcaster: Default::default(),
+            clients: Default::default(),
+            lock_holders: Default::default(),
         }
     }
 }
 
 pub struct ServiceInner<Store> {
     store: Store,
+    acl: Acl,
+    compression: CompressionConfig,
+    max_frame: usize,
+    timeout: TimeoutConfig,
+    started_at: Instant,
     on_received: Vec<fn(&CommandRequest)>,
     on_executed: Vec<fn(&CommandResponse)>,
     on_before_send: Vec<fn(&mut CommandResponse)>,
@@ -79,12 +179,36 @@ impl<Store: Storage> ServiceInner<Store> {
     pub fn new(store: Store) -> Self {
         Self {
             store,
+            acl: Acl::default(),
+            compression: CompressionConfig::default(),
+            max_frame: DEFAULT_MAX_FRAME,
+            timeout: TimeoutConfig::default(),
+            started_at: Instant::now(),
             on_received: Vec::new(),
             on_executed: Vec::new(),
             on_before_send: Vec::new(),
             on_after_send: Vec::new(),
         }
     }
+    pub fn acl(mut self, acl: Acl) -> Self {
+        self.acl = acl;
+        self
+    }
+    /// stream 上能接收的最大 frame
+    pub fn max_frame(mut self, max_frame: usize) -> Self {
+        self.max_frame = max_frame;
+        self
+    }
+    /// stream 的空闲超时
+    pub fn timeout(mut self, timeout: TimeoutConfig) -> Self {
+        self.timeout = timeout;
+        self
+    }
+    /// stream 上 frame 的压缩配置
+    pub fn compression(mut self, compression: CompressionConfig) -> Self {
+        self.compression = compression;
+        self
+    }
     pub fn fn_received(mut self, f: fn(&CommandRequest)) -> Self {
         self.on_received.push(f);
         self
@@ -131,15 +255,40 @@ impl<Arg> NotifyMut<Arg> for Vec<fn(&mut Arg)> {
 }
 
 pub fn dispatch(cmd: CommandRequest, store: &impl Storage) -> CommandResponse {
+    if let Err(e) = cmd.table().map_or(Ok(()), check_reserved_table) {
+        return e.into();
+    }
     match cmd.request_data {
         Some(RequestData::Hget(param)) => param.execute(store),
         Some(RequestData::Hgetall(param)) => param.execute(store),
+        Some(RequestData::Hmget(param)) => param.execute(store),
         Some(RequestData::Hset(param)) => param.execute(store),
         Some(RequestData::Hmset(param)) => param.execute(store),
         Some(RequestData::Hdel(param)) => param.execute(store),
         Some(RequestData::Hmdel(param)) => param.execute(store),
         Some(RequestData::Hexist(param)) => param.execute(store),
         Some(RequestData::Hmexist(param)) => param.execute(store),
+        Some(RequestData::Hfind(param)) => param.execute(store),
+        Some(RequestData::Lpush(param)) => param.execute(store),
+        Some(RequestData::Rpush(param)) => param.execute(store),
+        Some(RequestData::Lpop(param)) => param.execute(store),
+        Some(RequestData::Rpop(param)) => param.execute(store),
+        Some(RequestData::Lrange(param)) => param.execute(store),
+        Some(RequestData::Fget(param)) => param.execute(store),
+        Some(RequestData::Fset(param)) => param.execute(store),
+        Some(RequestData::Fdel(param)) => param.execute(store),
+        Some(RequestData::Zadd(param)) => param.execute(store),
+        Some(RequestData::Zrem(param)) => param.execute(store),
+        Some(RequestData::Zscore(param)) => param.execute(store),
+        Some(RequestData::Zrank(param)) => param.execute(store),
+        Some(RequestData::Zrange(param)) => param.execute(store),
+        Some(RequestData::Zrangebyscore(param)) => param.execute(store),
+        Some(RequestData::Eval(param)) => param.execute(store),
+        Some(RequestData::Hsetnx(param)) => param.execute(store),
+        Some(RequestData::Hcas(param)) => param.execute(store),
+        Some(RequestData::Hello(_)) => {
+            KvError::InvalidCommand("hello must be sent at the start of a stream".into()).into()
+        }
         None => KvError::InvalidCommand("Request as no data".into()).into(),
         _ =>  CommandResponse::default(),
     }
@@ -158,7 +307,7 @@ pub fn dispatch_stream(cmd: CommandRequest, topic: impl Topic) -> StreamingRespo
 
 #[cfg(test)]
 mod tests {
-    use std::thread;
+    use std::time::Duration;
 
     use futures::StreamExt;
     use http::StatusCode;
@@ -216,10 +365,56 @@ mod tests {
 
         let mut res = service.execute(CommandRequest::new_hset("t1", "k1", "v1".into()));
         let data = res.next().await.unwrap();
-        assert_eq!(data.status, StatusCode::CREATED.as_u16() as _);
+        assert_eq!(data.status, StatusCode::CREATED.as_u16() as u32);
         assert_eq!(data.message, "");
         assert_eq!(data.values, vec![Value::default()]);
     }
+
+    #[tokio::test]
+    async fn request_past_deadline_should_return_504() {
+        let service: Service = ServiceInner::new(MemTable::default()).into();
+        let script = "(hset \"t1\" \"k1\" 1) ".repeat(100_000);
+        let cmd = CommandRequest::new_eval(script, vec![], crate::MAX_STEPS_LIMIT)
+            .with_timeout(Duration::from_millis(1));
+        let data = service.execute(cmd).next().await.unwrap();
+        assert_eq!(data.status, StatusCode::GATEWAY_TIMEOUT.as_u16() as u32);
+
+        // 脚本没有执行完，没有写入
+        let mut res = service.execute(CommandRequest::new_hget("t1", "k1"));
+        assert_eq!(res.next().await.unwrap().status, 404);
+    }
+
+    #[tokio::test]
+    async fn backup_past_deadline_should_stop() {
+        let service: Service = ServiceInner::new(MemTable::default())
+            .acl(crate::Acl::new(["admin"]))
+            .into();
+        let handle = service.register_client(None, Some("admin".into()));
+        for i in 0..100_000 {
+            service.inner.store.set("t1", format!("k{}", i), i.into()).unwrap();
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("kv.backup");
+        let cmd = CommandRequest::new_backup(path.to_str().unwrap())
+            .with_timeout(Duration::from_millis(1));
+        let data = service.execute_as(cmd, Some(handle.info())).next().await.unwrap();
+        assert_eq!(data.status, StatusCode::GATEWAY_TIMEOUT.as_u16() as u32);
+
+        // 备份已经停止，写了一半的文件被删除，之后的写入不受影响
+        assert!(!path.exists());
+        let mut res = service.execute(CommandRequest::new_hset("t1", "k1", "v1".into()));
+        assert_eq!(res.next().await.unwrap().status, 200);
+    }
+
+    #[tokio::test]
+    async fn huge_timeout_should_not_overflow() {
+        let service: Service = ServiceInner::new(MemTable::default()).into();
+        let mut cmd = CommandRequest::new_hset("t1", "k1", 1.into());
+        cmd.timeout_ms = u64::MAX;
+        assert_eq!(cmd.timeout(), Some(crate::MAX_REQUEST_TIMEOUT));
+        let data = service.execute(cmd).next().await.unwrap();
+        assert_eq!(data.status, 200);
+    }
 }
 
 #[cfg(test)]
@@ -1,5 +1,5 @@
-use crate::{CommandResponse, KvError, Value};
-use dashmap::{DashMap, DashSet};
+use crate::{metrics, CommandResponse, KvError, Value};
+use dashmap::{mapref::entry::Entry, DashMap, DashSet};
 use std::sync::{
     atomic::{AtomicU32, Ordering},
     Arc,
@@ -23,8 +23,8 @@ pub trait Topic: Send + Sync + 'static {
     fn subscribe(self, name: String) -> mpsc::Receiver<Arc<CommandResponse>>;
     /// 取消对主题的订阅
     fn unsubscribe(self, name: String, id: u32) -> Result<u32, KvError>;
-    /// 往主题里发布一个数据
-    fn publish(self, name: String, value: Arc<CommandResponse>);
+    /// 往主题里发布一个数据，返回发布时主题的订阅者数量
+    fn publish(self, name: String, value: Arc<CommandResponse>) -> usize;
 }
 
 /// 用于主题发布和订阅的数据结构
@@ -40,7 +40,13 @@ impl Topic for Arc<Broadcaster> {
     #[instrument(name = "topic_subscribe", skip_all)]
     fn subscribe(self, name: String) -> mpsc::Receiver<Arc<CommandResponse>> {
         let id = {
-            let entry = self.topics.entry(name).or_default();
+            let entry = match self.topics.entry(name) {
+                Entry::Occupied(entry) => entry.into_ref(),
+                Entry::Vacant(entry) => {
+                    metrics::TOPICS.inc();
+                    entry.insert(DashSet::new())
+                }
+            };
             let id = get_next_subscription_id();
             entry.value().insert(id);
             id
@@ -61,6 +67,7 @@ impl Topic for Arc<Broadcaster> {
 
         // 把tx存入subscription table
         self.subscriptions.insert(id, tx);
+        metrics::SUBSCRIPTIONS.inc();
         debug!("Subscription {} is added", id);
 
         // 返回rx给网络处理的上下文
@@ -76,7 +83,8 @@ impl Topic for Arc<Broadcaster> {
     }
 
     #[instrument(name = "topic_publish", skip_all)]
-    fn publish(self, name: String, value: Arc<CommandResponse>) {
+    fn publish(self, name: String, value: Arc<CommandResponse>) -> usize {
+        let count = self.topics.get(&name).map_or(0, |topic| topic.len());
         tokio::spawn(async move {
             let mut ids = vec![];
             match self.topics.get(&name) {
@@ -108,6 +116,7 @@ impl Topic for Arc<Broadcaster> {
                 self.remove_subscription(name.clone(), id);
             }
         });
+        count
     }
 }
 
@@ -117,17 +126,29 @@ impl Broadcaster {
             // 在topics表里找到topic的subscription id， 删除
             v.remove(&id);
 
-            // 如果这个topic为空，则也删除topic
-            if v.is_empty() {
+            // 如果这个topic为空，则也删除topic，期间可能有新的订阅，所以删除时再检查一次
+            drop(v);
+            if self.topics.remove_if(&name, |_, v| v.is_empty()).is_some() {
                 info!("Topic: {:?} is deleted", &name);
-                drop(v);
-                self.topics.remove(&name);
+                metrics::TOPICS.dec();
             }
         }
 
         debug!("Subscription {} is removed", id);
         // 在subscription表中同样删除
-        self.subscriptions.remove(&id).map(|(id, _)| id)
+        let result = self.subscriptions.remove(&id).map(|(id, _)| id);
+        if result.is_some() {
+            metrics::SUBSCRIPTIONS.dec();
+        }
+        result
+    }
+}
+
+// 进程中可能有多个Broadcaster，gauge只增减自己的部分，drop时减去剩下的
+impl Drop for Broadcaster {
+    fn drop(&mut self) {
+        metrics::TOPICS.sub(self.topics.len() as _);
+        metrics::SUBSCRIPTIONS.sub(self.subscriptions.len() as _);
     }
 }
 
@@ -164,7 +185,7 @@ mod tests {
 
         // 如果subscriber取消订阅则收不到新数据
         let result = b.clone().unsubscribe(lobby.clone(), id1 as _).unwrap();
-        assert_eq!(result, id1 as _);
+        assert_eq!(result, id1 as u32);
 
         // publish
         let v: Value = "world".into();
@@ -1,7 +1,7 @@
 use futures::{stream, Stream};
 use std::{pin::Pin, sync::Arc, ops::Sub};
 use tokio_stream::wrappers::ReceiverStream;
-use crate::{CommandResponse, Publish, Subscribe, Topic, Unsubscribe};
+use crate::{CommandResponse, Publish, Subscribe, Topic, Unsubscribe, Value};
 
 pub type StreamingResponse = Pin<Box<dyn Stream<Item = Arc<CommandResponse>> + Send>>;
 
@@ -29,8 +29,10 @@ impl TopicService for Unsubscribe {
 
 impl TopicService for Publish {
     fn execute(self, topic: impl Topic) -> StreamingResponse {
-        topic.publish(self.topic, Arc::new(self.data.into()));
-        Box::pin(stream::once(async { Arc::new(CommandResponse::ok()) }))
+        // 返回发布时的订阅者数量，消息是异步投递的
+        let count = topic.publish(self.topic, Arc::new(self.data.into()));
+        let res: CommandResponse = Value::from(count as i64).into();
+        Box::pin(stream::once(async { Arc::new(res) }))
     }
 }
 
@@ -48,7 +50,7 @@ mod tests {
         let cmd = CommandRequest::new_publish("lobby", vec!["hello".into()]);
         let mut res = dispatch_stream(cmd, topic);
         let data = res.next().await.unwrap();
-        assert_res_ok(&data, &[], &[]);
+        assert_res_ok(&data, &[0.into()], &[]);
     }
 
     #[tokio::test]
@@ -0,0 +1,211 @@
+//! 二级索引的编码
+//!
+//! 每个被索引的 value 对应一个索引项：`索引名 | 0x00 | 编码后的索引值 | 主 key`。
+//! 索引值的编码保持顺序：编码后的字节序和 value 的大小顺序一致，因此可以直接做范围查询。
+//! 不同类型的 value 按类型排序，范围查询时 start 和 end 应该是同一种类型。
+
+use crate::{value, KvError, TableOptions, Value};
+use std::ops::Range;
+
+const TAG_BOOL: u8 = 1;
+const TAG_INTEGER: u8 = 2;
+const TAG_FLOAT: u8 = 3;
+const TAG_STRING: u8 = 4;
+const TAG_BINARY: u8 = 5;
+const TAG_TIMESTAMP: u8 = 6;
+
+/// 取出 value 中被索引的部分，field 为空表示整个 value
+pub(crate) fn indexed_value<'a>(value: &'a Value, field: &str) -> Option<&'a Value> {
+    match field {
+        "" => Some(value),
+        _ => value.get_field(field),
+    }
+}
+
+// 变长的数据：0x00 转义成 0x00 0xff，最后以 0x00 0x01 结束，这样短的数据总是排在前面
+pub(crate) fn encode_bytes(data: &[u8], buf: &mut Vec<u8>) {
+    for b in data {
+        buf.push(*b);
+        if *b == 0 {
+            buf.push(0xff);
+        }
+    }
+    buf.extend_from_slice(&[0, 1]);
+}
+
+/// 按顺序编码索引值，无法索引的 value（list/map/null）返回 None
+fn encode_value(v: &Value, buf: &mut Vec<u8>) -> Option<()> {
+    match v.value.as_ref()? {
+        value::Value::Bool(b) => buf.extend_from_slice(&[TAG_BOOL, *b as u8]),
+        value::Value::Integer(i) => {
+            buf.push(TAG_INTEGER);
+            encode_i64(*i, buf);
+        }
+        value::Value::Timestamp(ms) => {
+            buf.push(TAG_TIMESTAMP);
+            encode_i64(*ms, buf);
+        }
+        value::Value::Float(f) => {
+            buf.push(TAG_FLOAT);
+            encode_f64(*f, buf);
+        }
+        value::Value::String(s) => {
+            buf.push(TAG_STRING);
+            encode_bytes(s.as_bytes(), buf);
+        }
+        value::Value::Binary(b) => {
+            buf.push(TAG_BINARY);
+            encode_bytes(b, buf);
+        }
+        value::Value::List(_) | value::Value::Map(_) | value::Value::Null(_) => return None,
+    }
+    Some(())
+}
+
+// 翻转符号位，负数排在正数前面
+fn encode_i64(i: i64, buf: &mut Vec<u8>) {
+    buf.extend_from_slice(&((i as u64) ^ (1 << 63)).to_be_bytes());
+}
+
+// 正数翻转符号位，负数翻转所有位，编码后的字节序和 f64::total_cmp 一致
+pub(crate) fn encode_f64(f: f64, buf: &mut Vec<u8>) {
+    let bits = f.to_bits();
+    let bits = match bits >> 63 {
+        0 => bits | (1 << 63),
+        _ => !bits,
+    };
+    buf.extend_from_slice(&bits.to_be_bytes());
+}
+
+fn index_prefix(index: &str) -> Vec<u8> {
+    let mut buf = index.as_bytes().to_vec();
+    buf.push(0);
+    buf
+}
+
+/// 一个 kv pair 在 table 所有索引中的索引项
+pub(crate) fn index_entries(options: &TableOptions, key: &str, value: &Value) -> Vec<Vec<u8>> {
+    options
+        .indexes
+        .iter()
+        .filter_map(|spec| {
+            let v = indexed_value(value, &spec.field)?;
+            let mut buf = index_prefix(&spec.name);
+            encode_value(v, &mut buf)?;
+            buf.extend_from_slice(key.as_bytes());
+            Some(buf)
+        })
+        .collect()
+}
+
+/// 索引值在 [start, end] 之间的索引项的范围
+pub(crate) fn index_range(
+    options: &TableOptions,
+    index: &str,
+    start: &Value,
+    end: &Value,
+) -> Result<Range<Vec<u8>>, KvError> {
+    if !options.indexes.iter().any(|spec| spec.name == index) {
+        return Err(KvError::NotFound(format!("index {}", index)));
+    }
+    let encode = |v: &Value| {
+        let mut buf = index_prefix(index);
+        encode_value(v, &mut buf)
+            .ok_or_else(|| KvError::InvalidCommand(format!("cannot search by {}", v.format())))?;
+        Ok::<_, KvError>(buf)
+    };
+
+    let start = encode(start)?;
+    // 主 key 是 utf8，不会出现 0xff，所以 end 之后加上 0xff 就包含了所有等于 end 的索引项
+    let mut end = encode(end)?;
+    end.push(0xff);
+    Ok(start..end)
+}
+
+impl TableOptions {
+    /// 检查索引的定义是否合法
+    pub(crate) fn validate_indexes(&self) -> Result<(), KvError> {
+        for (i, spec) in self.indexes.iter().enumerate() {
+            if spec.name.is_empty() || spec.name.contains('\0') {
+                return Err(KvError::InvalidCommand(format!(
+                    "invalid index name: `{}`",
+                    spec.name
+                )));
+            }
+            if self.indexes[..i].iter().any(|v| v.name == spec.name) {
+                return Err(KvError::InvalidCommand(format!(
+                    "duplicated index: `{}`",
+                    spec.name
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// table 上是否有索引
+    pub(crate) fn has_indexes(&self) -> bool {
+        !self.indexes.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IndexSpec;
+
+    fn encode(v: Value) -> Vec<u8> {
+        let mut buf = Vec::new();
+        encode_value(&v, &mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn encoded_value_should_keep_order() {
+        assert!(encode((-10).into()) < encode((-1).into()));
+        assert!(encode((-1).into()) < encode(0.into()));
+        assert!(encode(1.into()) < encode(256.into()));
+        assert!(encode((-1.5).into()) < encode((-0.5).into()));
+        assert!(encode((-0.5).into()) < encode(0.5.into()));
+        assert!(encode(0.5.into()) < encode(10.0.into()));
+        assert!(encode("a".into()) < encode("a\0".into()));
+        assert!(encode("a\0".into()) < encode("ab".into()));
+        assert!(encode("ab".into()) < encode("b".into()));
+    }
+
+    #[test]
+    fn index_range_should_contain_matched_entries() {
+        let options = TableOptions {
+            indexes: vec![IndexSpec {
+                name: "email".into(),
+                field: "".into(),
+            }],
+            ..Default::default()
+        };
+        let entries = index_entries(&options, "u1", &"a@b.com".into());
+        assert_eq!(entries.len(), 1);
+
+        let range = index_range(&options, "email", &"a@b.com".into(), &"a@b.com".into()).unwrap();
+        assert!(range.contains(&entries[0]));
+        let range = index_range(&options, "email", &"a".into(), &"a@".into()).unwrap();
+        assert!(!range.contains(&entries[0]));
+
+        assert!(index_range(&options, "name", &"a".into(), &"b".into()).is_err());
+    }
+
+    #[test]
+    fn index_should_work_on_nested_field() {
+        let options = TableOptions {
+            indexes: vec![IndexSpec {
+                name: "city".into(),
+                field: "address.city".into(),
+            }],
+            ..Default::default()
+        };
+        let mut user = Value::default();
+        user.set_field("address.city", "Shanghai".into()).unwrap();
+        assert_eq!(index_entries(&options, "u1", &user).len(), 1);
+
+        // 没有这个字段的 value 不会被索引
+        assert!(index_entries(&options, "u2", &"Shanghai".into()).is_empty());
+    }
+}
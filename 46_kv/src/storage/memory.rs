@@ -1,10 +1,42 @@
-use crate::{KvError, Kvpair, Storage, StorageIter, Value};
-use dashmap::{mapref::one::Ref, DashMap};
+use crate::{
+    storage::{
+        apply_batch,
+        index::{index_entries, index_range},
+        same_value,
+        table::{now_ms, validate_table_name},
+        zset::{normalize_score, ZSet},
+    },
+    CasOp, KvError, Kvpair, Storage, StorageIter, TableOptions, TableSnapshot, Value, ZsetIter,
+};
+use dashmap::{
+    mapref::{entry::Entry, one::Ref},
+    DashMap,
+};
+use prost::Message;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex, RwLock},
+};
+
+// 索引项 -> 主 key
+type Index = BTreeMap<Vec<u8>, String>;
 
 // 使用DashMap构建MemTable， 实现了Storage trait
 #[derive(Clone, Debug, Default)]
 pub struct MemTable {
     tables: DashMap<String, DashMap<String, Value>>,
+    // 显式创建的table的选项
+    options: DashMap<String, TableOptions>,
+    // key的过期时间（unix 毫秒），按table存放
+    expires: DashMap<String, DashMap<String, u64>>,
+    // 有索引的table的所有索引项，修改数据和索引时需要持有这个锁
+    indexes: DashMap<String, Arc<Mutex<Index>>>,
+    // table -> key -> sorted set
+    zsets: DashMap<String, DashMap<String, ZSet>>,
+    // 向有 max_keys 限制的 table 写入时持有这个锁，检查数量和插入新 key 是原子的
+    capacity: Arc<Mutex<()>>,
+    // 普通的写入持有读锁，批量写入持有写锁，这样其它写入看不到只执行了一半的批量写入
+    batch: Arc<RwLock<()>>,
 }
 
 impl MemTable {
@@ -21,28 +53,203 @@ impl MemTable {
             }
         }
     }
+
+    fn get_options(&self, table: &str) -> TableOptions {
+        self.options
+            .get(table)
+            .map(|v| v.value().clone())
+            .unwrap_or_default()
+    }
+
+    fn is_expired(&self, table: &str, key: &str, now: u64) -> bool {
+        self.expires
+            .get(table)
+            .and_then(|t| t.get(key).map(|v| *v.value() <= now))
+            .unwrap_or(false)
+    }
+
+    fn get_or_create_zsets(&self, table: &str) -> Ref<'_, String, DashMap<String, ZSet>> {
+        match self.zsets.get(table) {
+            Some(zsets) => zsets,
+            None => self.zsets.entry(table.into()).or_default().downgrade(),
+        }
+    }
+
+    // 在sorted set上执行f，sorted set不存在时返回默认值
+    fn with_zset<T: Default>(&self, table: &str, key: &str, f: impl FnOnce(&ZSet) -> T) -> T {
+        self.zsets
+            .get(table)
+            .and_then(|t| t.get(key).map(|z| f(z.value())))
+            .unwrap_or_default()
+    }
+
+    fn index(&self, table: &str) -> Option<Arc<Mutex<Index>>> {
+        self.indexes.get(table).map(|v| Arc::clone(v.value()))
+    }
+
+    // 如果key已经过期，删除它
+    fn purge_expired(&self, table: &str, key: &str) {
+        if self.is_expired(table, key, now_ms()) {
+            self.remove(table, key);
+        }
+    }
+
+    // 删除key，同时删除它的过期时间和索引项
+    fn remove(&self, table: &str, key: &str) -> Option<Value> {
+        let lock = self.index(table);
+        let mut index = lock.as_ref().map(|v| v.lock().unwrap());
+        if let Some(t) = self.expires.get(table) {
+            t.remove(key);
+        }
+        let old = self
+            .tables
+            .get(table)
+            .and_then(|t| t.remove(key).map(|(_k, v)| v));
+        if let Some(index) = index.as_mut() {
+            update_index(index, &self.get_options(table), key, old.as_ref(), None);
+        }
+        old
+    }
+
+    // 不持有 batch 锁的 compare_and_swap
+    fn cas(
+        &self,
+        table: &str,
+        key: &str,
+        expected: Option<&Value>,
+        new: Option<Value>,
+    ) -> Result<Result<(), Option<Value>>, KvError> {
+        let options = self.get_options(table);
+        if let Some(v) = &new {
+            options.check_value(v)?;
+        }
+        self.purge_expired(table, key);
+
+        let _capacity = (options.max_keys > 0).then(|| self.capacity.lock().unwrap());
+        let lock = self.index(table);
+        let mut index = lock.as_ref().map(|v| v.lock().unwrap());
+        let data = match (&new, self.tables.get(table)) {
+            (_, Some(data)) => data,
+            (Some(_), None) => self.get_or_create_table(table),
+            // 删除不存在的table中的key
+            (None, None) => return Ok(expected.map_or(Ok(()), |_| Err(None))),
+        };
+        if expected.is_none() && new.is_some() {
+            options.check_capacity(|| Ok(data.len() as u64))?;
+        }
+
+        let indexed = index.as_ref().and(new.clone());
+        let has_new = new.is_some();
+        // 持有key所在shard的锁，比较和替换是原子的
+        let old = match data.entry(key.into()) {
+            Entry::Occupied(mut entry) => {
+                if !same_value(Some(entry.get()), expected) {
+                    return Ok(Err(Some(entry.get().clone())));
+                }
+                match new {
+                    Some(v) => Some(entry.insert(v)),
+                    None => Some(entry.remove()),
+                }
+            }
+            Entry::Vacant(entry) => {
+                if expected.is_some() {
+                    return Ok(Err(None));
+                }
+                if let Some(v) = new {
+                    entry.insert(v);
+                }
+                None
+            }
+        };
+        if let Some(index) = index.as_mut() {
+            update_index(index, &options, key, old.as_ref(), indexed.as_ref());
+        }
+
+        match options.expire_at().filter(|_| has_new) {
+            Some(at) => {
+                self.expires.entry(table.into()).or_default().insert(key.into(), at);
+            }
+            None => {
+                if let Some(t) = self.expires.get(table) {
+                    t.remove(key);
+                }
+            }
+        }
+        Ok(Ok(()))
+    }
+
+    // 一个table中所有未过期的数据
+    fn snapshot(&self, table: &str) -> DashMap<String, Value> {
+        let data = match self.tables.get(table) {
+            Some(t) => t.value().clone(),
+            None => return DashMap::new(),
+        };
+        if let Some(expires) = self.expires.get(table) {
+            let now = now_ms();
+            expires
+                .iter()
+                .filter(|v| *v.value() <= now)
+                .for_each(|v| {
+                    data.remove(v.key());
+                });
+        }
+        data
+    }
 }
 
 impl Storage for MemTable {
     fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
-        let table = self.get_or_create_table(table);
-        Ok(table.get(key).map(|v| v.value().clone()))
+        self.purge_expired(table, key);
+        Ok(self
+            .tables
+            .get(table)
+            .and_then(|t| t.get(key).map(|v| v.value().clone())))
     }
 
     fn set(&self, table: &str, key: String, value: Value) -> Result<Option<Value>, KvError> {
-        let table = self.get_or_create_table(table);
-        Ok(table.insert(key, value))
+        let options = self.get_options(table);
+        options.check_value(&value)?;
+        self.purge_expired(table, &key);
+
+        let _batch = self.batch.read().unwrap();
+        let _capacity = (options.max_keys > 0).then(|| self.capacity.lock().unwrap());
+        let lock = self.index(table);
+        let mut index = lock.as_ref().map(|v| v.lock().unwrap());
+        let new = index.as_ref().map(|_| value.clone());
+        let old = {
+            let table = self.get_or_create_table(table);
+            if !table.contains_key(&key) {
+                options.check_capacity(|| Ok(table.len() as u64))?;
+            }
+            table.insert(key.clone(), value)
+        };
+        if let Some(index) = index.as_mut() {
+            update_index(index, &options, &key, old.as_ref(), new.as_ref());
+        }
+
+        match options.expire_at() {
+            Some(at) => {
+                self.expires.entry(table.into()).or_default().insert(key, at);
+            }
+            None => {
+                if let Some(t) = self.expires.get(table) {
+                    t.remove(&key);
+                }
+            }
+        }
+        Ok(old)
     }
     fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
-        let table = self.get_or_create_table(table);
-        Ok(table.contains_key(key))
+        self.purge_expired(table, key);
+        Ok(self.tables.get(table).is_some_and(|t| t.contains_key(key)))
     }
     fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
-        let table = self.get_or_create_table(table);
-        Ok(table.remove(key).map(|(_k, v)| v))
+        let _batch = self.batch.read().unwrap();
+        self.purge_expired(table, key);
+        Ok(self.remove(table, key))
     }
     fn get_all(&self, table: &str) -> Result<Vec<crate::Kvpair>, KvError> {
-        let table = self.get_or_create_table(table);
+        let table = self.snapshot(table);
         Ok(table
             .iter()
             .map(|v| Kvpair::new(v.key(), v.value().clone()))
@@ -57,10 +264,271 @@ impl Storage for MemTable {
     //     Ok(Box::new(iter))
     // }
     fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError> {
-        let table = self.get_or_create_table(table).clone();
+        let table = self.snapshot(table);
         let iter = StorageIter::new(table.into_iter());
         Ok(Box::new(iter))
     }
+    fn list_tables(&self) -> Result<Vec<String>, KvError> {
+        let mut tables: Vec<_> = self.tables.iter().map(|t| t.key().clone()).collect();
+        // 只有sorted set的table
+        for zsets in self.zsets.iter() {
+            if !zsets.is_empty() && !self.tables.contains_key(zsets.key()) {
+                tables.push(zsets.key().clone());
+            }
+        }
+        Ok(tables)
+    }
+    fn count(&self, table: &str) -> Result<u64, KvError> {
+        let keys = self.tables.get(table).map_or(0, |t| t.len());
+        let zsets = self.zsets.get(table).map_or(0, |t| t.len());
+        Ok((keys + zsets) as u64)
+    }
+    fn backend(&self) -> &'static str {
+        "memtable"
+    }
+    fn estimated_size(&self) -> Result<u64, KvError> {
+        // 只统计 table 名、key 和 value 编码后的大小，不包括 DashMap 自身的开销
+        let size = self
+            .tables
+            .iter()
+            .map(|t| {
+                let data: usize = t
+                    .value()
+                    .iter()
+                    .map(|v| v.key().len() + v.value().encoded_len())
+                    .sum();
+                t.key().len() + data
+            })
+            .sum::<usize>();
+        Ok(size as u64)
+    }
+    fn create_table(&self, table: &str, options: TableOptions) -> Result<(), KvError> {
+        validate_table_name(table)?;
+        options.validate()?;
+        match self.tables.entry(table.into()) {
+            Entry::Occupied(_) => Err(KvError::AlreadyExists(format!("table {}", table))),
+            Entry::Vacant(entry) => {
+                if options.has_indexes() {
+                    self.indexes.insert(table.into(), Default::default());
+                }
+                self.options.insert(table.into(), options);
+                entry.insert(DashMap::new());
+                Ok(())
+            }
+        }
+    }
+    fn drop_table(&self, table: &str) -> Result<bool, KvError> {
+        let _batch = self.batch.read().unwrap();
+        self.options.remove(table);
+        self.expires.remove(table);
+        self.indexes.remove(table);
+        let zsets = self.zsets.remove(table).is_some();
+        Ok(self.tables.remove(table).is_some() || zsets)
+    }
+    fn rename_table(&self, from: &str, to: &str) -> Result<(), KvError> {
+        validate_table_name(to)?;
+        // 持有batch写锁，移动的过程中其它写入不会重新创建from
+        let _batch = self.batch.write().unwrap();
+        let has_zsets = self.zsets.get(to).is_some_and(|t| !t.is_empty());
+        if self.tables.contains_key(to) || self.options.contains_key(to) || has_zsets {
+            return Err(KvError::AlreadyExists(format!("table {}", to)));
+        }
+        let zsets = self.zsets.remove(from);
+        let data = self.tables.remove(from);
+        if data.is_none() && zsets.is_none() {
+            return Err(KvError::NotFound(format!("table {}", from)));
+        }
+        if let Some((_, data)) = data {
+            self.tables.insert(to.into(), data);
+        }
+        if let Some((_, zsets)) = zsets {
+            self.zsets.insert(to.into(), zsets);
+        }
+        if let Some((_, options)) = self.options.remove(from) {
+            self.options.insert(to.into(), options);
+        }
+        if let Some((_, expires)) = self.expires.remove(from) {
+            self.expires.insert(to.into(), expires);
+        }
+        // 索引项中不包含table名，可以直接移动
+        if let Some((_, index)) = self.indexes.remove(from) {
+            self.indexes.insert(to.into(), index);
+        }
+        Ok(())
+    }
+    fn table_options(&self, table: &str) -> Result<Option<TableOptions>, KvError> {
+        Ok(self.options.get(table).map(|v| v.value().clone()))
+    }
+    fn find(
+        &self,
+        table: &str,
+        index: &str,
+        start: &Value,
+        end: &Value,
+    ) -> Result<Vec<Kvpair>, KvError> {
+        let range = index_range(&self.get_options(table), index, start, end)?;
+        let lock = match self.index(table) {
+            Some(v) => v,
+            None => return Ok(Vec::new()),
+        };
+        // 持有索引的锁，读到的数据和索引是一致的
+        let index = lock.lock().unwrap();
+        let data = match self.tables.get(table) {
+            Some(v) => v,
+            None => return Ok(Vec::new()),
+        };
+        let now = now_ms();
+        Ok(index
+            .range(range)
+            .filter(|(_, key)| !self.is_expired(table, key, now))
+            .filter_map(|(_, key)| data.get(key).map(|v| Kvpair::new(key, v.value().clone())))
+            .collect())
+    }
+    fn compare_and_swap(
+        &self,
+        table: &str,
+        key: &str,
+        expected: Option<&Value>,
+        new: Option<Value>,
+    ) -> Result<Result<(), Option<Value>>, KvError> {
+        let _batch = self.batch.read().unwrap();
+        self.cas(table, key, expected, new)
+    }
+    fn compare_and_swap_batch(&self, ops: Vec<CasOp>) -> Result<bool, KvError> {
+        let _batch = self.batch.write().unwrap();
+        apply_batch(ops, |t, k| self.get(t, k), |t, k, e, n| self.cas(t, k, e, n))
+    }
+    fn export_table(&self, table: &str) -> Result<TableSnapshot, KvError> {
+        // 只在复制这个table的时候持有batch写锁，不会读到只执行了一半的批量写入
+        let _batch = self.batch.write().unwrap();
+        let now = now_ms();
+        let expires: HashMap<String, u64> = self
+            .expires
+            .get(table)
+            .map(|t| t.iter().map(|v| (v.key().clone(), *v.value())).collect())
+            .unwrap_or_default();
+        let pairs: Vec<_> = match self.tables.get(table) {
+            Some(data) => data
+                .iter()
+                .map(|v| (v.key().clone(), v.value().clone()))
+                .filter_map(|(key, value)| match expires.get(&key).copied() {
+                    Some(at) if at <= now => None,
+                    at => Some(Ok((Kvpair::new(key, value), at))),
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        Ok(TableSnapshot {
+            options: self.table_options(table)?,
+            pairs: Box::new(pairs.into_iter()),
+            zsets: self.zset_iter(table)?,
+        })
+    }
+    fn expire_at(&self, table: &str, key: &str) -> Result<Option<u64>, KvError> {
+        Ok(self
+            .expires
+            .get(table)
+            .and_then(|t| t.get(key).map(|v| *v.value())))
+    }
+    fn set_expire_at(&self, table: &str, key: &str, at: Option<u64>) -> Result<bool, KvError> {
+        let _batch = self.batch.read().unwrap();
+        // 持有key所在shard的锁，避免key在设置过期时间的同时被删除
+        let data = match self.tables.get(table) {
+            Some(data) => data,
+            None => return Ok(false),
+        };
+        let _entry = match data.get(key) {
+            Some(entry) => entry,
+            None => return Ok(false),
+        };
+        match at {
+            Some(at) => {
+                self.expires.entry(table.into()).or_default().insert(key.into(), at);
+            }
+            None => {
+                if let Some(t) = self.expires.get(table) {
+                    t.remove(key);
+                }
+            }
+        }
+        Ok(true)
+    }
+    fn zadd(&self, table: &str, key: &str, members: Vec<(String, f64)>) -> Result<u64, KvError> {
+        let members = members
+            .into_iter()
+            .map(|(m, s)| Ok((m, normalize_score(s)?)))
+            .collect::<Result<Vec<_>, KvError>>()?;
+        let _batch = self.batch.read().unwrap();
+        let zsets = self.get_or_create_zsets(table);
+        let mut zset = zsets.entry(key.into()).or_default();
+        Ok(members
+            .into_iter()
+            .filter(|(m, s)| zset.add(m.clone(), *s))
+            .count() as u64)
+    }
+    fn zrem(&self, table: &str, key: &str, members: &[String]) -> Result<u64, KvError> {
+        let _batch = self.batch.read().unwrap();
+        let zsets = match self.zsets.get(table) {
+            Some(v) => v,
+            None => return Ok(0),
+        };
+        let removed = match zsets.get_mut(key) {
+            Some(mut zset) => members.iter().filter(|m| zset.remove(m)).count() as u64,
+            None => 0,
+        };
+        zsets.remove_if(key, |_, zset| zset.is_empty());
+        Ok(removed)
+    }
+    fn zset_iter(&self, table: &str) -> Result<ZsetIter, KvError> {
+        let zsets: Vec<_> = match self.zsets.get(table) {
+            Some(zsets) => zsets
+                .iter()
+                .map(|z| (z.key().clone(), z.value().range(0, -1)))
+                .collect(),
+            None => Vec::new(),
+        };
+        Ok(Box::new(zsets.into_iter()))
+    }
+    fn zscore(&self, table: &str, key: &str, member: &str) -> Result<Option<f64>, KvError> {
+        Ok(self.with_zset(table, key, |z| z.score(member)))
+    }
+    fn zrank(&self, table: &str, key: &str, member: &str) -> Result<Option<u64>, KvError> {
+        Ok(self.with_zset(table, key, |z| z.rank(member)))
+    }
+    fn zrange(&self, table: &str, key: &str, start: i64, stop: i64) -> Result<Vec<Kvpair>, KvError> {
+        Ok(self.with_zset(table, key, |z| z.range(start, stop)))
+    }
+    fn zrange_by_score(
+        &self,
+        table: &str,
+        key: &str,
+        min: f64,
+        max: f64,
+        offset: u64,
+        limit: u64,
+    ) -> Result<Vec<Kvpair>, KvError> {
+        Ok(self.with_zset(table, key, |z| z.range_by_score(min, max, offset, limit)))
+    }
+}
+
+// 删除旧value的索引项，加入新value的索引项
+fn update_index(
+    index: &mut Index,
+    options: &TableOptions,
+    key: &str,
+    old: Option<&Value>,
+    new: Option<&Value>,
+) {
+    if let Some(old) = old {
+        for entry in index_entries(options, key, old) {
+            index.remove(&entry);
+        }
+    }
+    if let Some(new) = new {
+        for entry in index_entries(options, key, new) {
+            index.insert(entry, key.into());
+        }
+    }
 }
 
 impl From<(String, Value)> for Kvpair {
@@ -1,10 +1,31 @@
+mod index;
 mod memory;
+mod rank_tree;
 mod sleddb;
+mod table;
+mod zset;
 
 pub use memory::MemTable;
 pub use sleddb::SledDb;
+pub(crate) use table::{check_reserved_table, now_ms};
+pub use table::RESERVED_TABLE_PREFIX;
+pub(crate) use zset::normalize_range;
 
-use crate::{KvError, Kvpair, Value};
+use crate::{KvError, Kvpair, TableOptions, Value};
+use prost::Message;
+/// table中sorted set的迭代器，返回key和按分数排序的成员
+pub type ZsetIter = Box<dyn Iterator<Item = (String, Vec<Kvpair>)>>;
+
+/// 导出的kv pair和它的过期时间（unix 毫秒）
+pub type ExportIter = Box<dyn Iterator<Item = Result<(Kvpair, Option<u64>), KvError>>>;
+
+/// 导出的一个table，遍历时不持有存储的锁
+pub struct TableSnapshot {
+    pub options: Option<TableOptions>,
+    /// 没有过期的kv pair
+    pub pairs: ExportIter,
+    pub zsets: ZsetIter,
+}
 
 pub trait Storage: Send + Sync + 'static {
     // 从一个HashTable中里获取一个key的value
@@ -19,6 +40,151 @@ pub trait Storage: Send + Sync + 'static {
     fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError>;
     // 遍历HashTable，返回kv pair的Iterator
     fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError>;
+    // 列出所有的table
+    fn list_tables(&self) -> Result<Vec<String>, KvError>;
+    // 返回HashTable中key的数量，包括sorted set
+    fn count(&self, table: &str) -> Result<u64, KvError>;
+    // 存储后端的名字
+    fn backend(&self) -> &'static str;
+    // 估算数据占用的字节数
+    fn estimated_size(&self) -> Result<u64, KvError>;
+    // 显式创建一个table，table已经存在时报错
+    fn create_table(&self, table: &str, options: TableOptions) -> Result<(), KvError>;
+    // 删除table及其所有数据，返回table是否存在
+    fn drop_table(&self, table: &str) -> Result<bool, KvError>;
+    // 重命名table，包括数据和选项
+    fn rename_table(&self, from: &str, to: &str) -> Result<(), KvError>;
+    // 获取显式创建的table的选项
+    fn table_options(&self, table: &str) -> Result<Option<TableOptions>, KvError>;
+    // 通过二级索引查找索引值在[start, end]之间的kv pair，按索引值排序
+    fn find(&self, table: &str, index: &str, start: &Value, end: &Value)
+        -> Result<Vec<Kvpair>, KvError>;
+    // 当key的value和expected编码后的字节相同时（None表示key不存在）原子地替换成new
+    // （None表示删除key），否则返回Ok(Err(当前的value))
+    fn compare_and_swap(
+        &self,
+        table: &str,
+        key: &str,
+        expected: Option<&Value>,
+        new: Option<Value>,
+    ) -> Result<Result<(), Option<Value>>, KvError>;
+    // 原子地执行一组compare_and_swap：所有key的value都和expected相同时才写入，否则不写入
+    // 任何数据并返回Ok(false)。执行期间其它的写入会等待
+    fn compare_and_swap_batch(&self, ops: Vec<CasOp>) -> Result<bool, KvError>;
+    // 导出table的选项、kv pair及其过期时间和sorted set，用于备份，期间其它的写入不会被阻塞
+    fn export_table(&self, table: &str) -> Result<TableSnapshot, KvError>;
+    // key的过期时间（unix 毫秒），没有过期时间时返回None
+    fn expire_at(&self, table: &str, key: &str) -> Result<Option<u64>, KvError>;
+    // 设置key的过期时间，None表示不过期，key不存在时返回false
+    fn set_expire_at(&self, table: &str, key: &str, at: Option<u64>) -> Result<bool, KvError>;
+    // 向sorted set中加入成员，已有的成员更新分数，返回新加入的成员数量
+    fn zadd(&self, table: &str, key: &str, members: Vec<(String, f64)>) -> Result<u64, KvError>;
+    // 从sorted set中删除成员，返回删除的成员数量，sorted set为空时删除key
+    fn zrem(&self, table: &str, key: &str, members: &[String]) -> Result<u64, KvError>;
+    // 遍历table中所有的sorted set，返回key和按分数排序的成员
+    fn zset_iter(&self, table: &str) -> Result<ZsetIter, KvError>;
+    // 成员的分数
+    fn zscore(&self, table: &str, key: &str, member: &str) -> Result<Option<f64>, KvError>;
+    // 成员按分数从小到大的排名，从0开始
+    fn zrank(&self, table: &str, key: &str, member: &str) -> Result<Option<u64>, KvError>;
+    // 排名在[start, stop]之间的成员和分数，负数表示从尾部开始计算
+    fn zrange(&self, table: &str, key: &str, start: i64, stop: i64)
+        -> Result<Vec<Kvpair>, KvError>;
+    // 分数在[min, max]之间的成员和分数，跳过offset个成员后最多返回limit个，limit为0表示不限制
+    fn zrange_by_score(
+        &self,
+        table: &str,
+        key: &str,
+        min: f64,
+        max: f64,
+        offset: u64,
+        limit: u64,
+    ) -> Result<Vec<Kvpair>, KvError>;
+}
+
+/// compare_and_swap_batch 中的一项
+#[derive(Debug, Clone, PartialEq)]
+pub struct CasOp {
+    pub table: String,
+    pub key: String,
+    /// key当前的value，None表示key不存在
+    pub expected: Option<Value>,
+    /// 写入的value，None表示只检查不写入，Some(None)表示删除key
+    pub new: Option<Option<Value>>,
+}
+
+/// update 最多重试的次数，超过之后返回错误而不是一直重试
+pub const MAX_UPDATE_RETRIES: usize = 1000;
+
+/// 原子地读取-修改-写入一个key
+///
+/// f 根据当前的 value 返回新的 value（None 表示删除 key）和返回给调用者的结果。
+/// 如果期间 value 被其他人修改，f 会用最新的 value 重新执行，最多重试 MAX_UPDATE_RETRIES 次
+pub fn update<T>(
+    store: &impl Storage,
+    table: &str,
+    key: &str,
+    mut f: impl FnMut(Option<&Value>) -> Result<(Option<Value>, T), KvError>,
+) -> Result<T, KvError> {
+    let mut current = store.get(table, key)?;
+    for _ in 0..=MAX_UPDATE_RETRIES {
+        let (new, result) = f(current.as_ref())?;
+        match store.compare_and_swap(table, key, current.as_ref(), new)? {
+            Ok(()) => return Ok(result),
+            Err(v) => current = v,
+        }
+    }
+    Err(KvError::StorageError(
+        "update",
+        table.into(),
+        key.into(),
+        format!("value keeps changing after {} retries", MAX_UPDATE_RETRIES),
+    ))
+}
+
+// 在持有batch写锁时执行compare_and_swap_batch：先检查所有的key，再逐个写入。
+// 其它写入都在等待，所以写入只会因为table的约束失败，这时恢复已经写入的key
+pub(crate) fn apply_batch(
+    ops: Vec<CasOp>,
+    get: impl Fn(&str, &str) -> Result<Option<Value>, KvError>,
+    cas: impl Fn(&str, &str, Option<&Value>, Option<Value>) -> Result<Result<(), Option<Value>>, KvError>,
+) -> Result<bool, KvError> {
+    for op in &ops {
+        if !same_value(get(&op.table, &op.key)?.as_ref(), op.expected.as_ref()) {
+            return Ok(false);
+        }
+    }
+    let mut applied: Vec<&CasOp> = Vec::new();
+    for op in ops.iter().filter(|op| op.new.is_some()) {
+        let new = op.new.clone().flatten();
+        let result = cas(&op.table, &op.key, op.expected.as_ref(), new);
+        if let Err(e) = result.and_then(|r| r.map_err(|_| conflict(op))) {
+            for op in applied.into_iter().rev() {
+                let new = op.new.clone().flatten();
+                let _ = cas(&op.table, &op.key, new.as_ref(), op.expected.clone());
+            }
+            return Err(e);
+        }
+        applied.push(op);
+    }
+    Ok(true)
+}
+
+fn conflict(op: &CasOp) -> KvError {
+    KvError::StorageError(
+        "compare_and_swap_batch",
+        op.table.clone(),
+        op.key.clone(),
+        "value changed while holding the batch lock".into(),
+    )
+}
+
+// 按编码后的字节比较两个value，NaN 和自己相等，0.0 和 -0.0 不相等
+pub(crate) fn same_value(a: Option<&Value>, b: Option<&Value>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a.encode_to_vec() == b.encode_to_vec(),
+        (a, b) => a.is_none() && b.is_none(),
+    }
 }
 
 pub struct StorageIter<T> {
@@ -45,6 +211,7 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::IndexSpec;
     use tempfile::tempdir;
 
     #[test]
@@ -112,12 +279,398 @@ mod tests {
         )
     }
 
+    fn test_tables_and_count(store: impl Storage) {
+        store.set("t1", "k1".into(), "v1".into()).unwrap();
+        store.set("t2", "k1".into(), "v1".into()).unwrap();
+        store.set("t2", "k2".into(), "v2".into()).unwrap();
+
+        let mut tables = store.list_tables().unwrap();
+        tables.sort();
+        assert_eq!(tables, vec!["t1".to_string(), "t2".to_string()]);
+
+        assert_eq!(store.count("t1").unwrap(), 1);
+        assert_eq!(store.count("t2").unwrap(), 2);
+        assert_eq!(store.count("t3").unwrap(), 0);
+        assert!(store.estimated_size().unwrap() > 0);
+    }
+
+    fn test_rename_onto_existing_table(store: impl Storage) {
+        store.set("t1", "k1".into(), "v1".into()).unwrap();
+        // 只有sorted set的table
+        store.zadd("t2", "z1", vec![("a".into(), 1.0)]).unwrap();
+        // 显式创建但是还没有数据的table
+        let options = TableOptions {
+            value_type: "integer".into(),
+            ..Default::default()
+        };
+        store.create_table("t3", options.clone()).unwrap();
+
+        assert!(matches!(store.rename_table("t1", "t2"), Err(KvError::AlreadyExists(_))));
+        assert!(matches!(store.rename_table("t1", "t3"), Err(KvError::AlreadyExists(_))));
+        // 两边的数据都没有变化
+        assert_eq!(store.get("t1", "k1").unwrap(), Some("v1".into()));
+        assert_eq!(store.zscore("t2", "z1", "a").unwrap(), Some(1.0));
+        assert_eq!(store.table_options("t3").unwrap(), Some(options));
+
+        store.rename_table("t2", "t4").unwrap();
+        assert_eq!(store.zscore("t4", "z1", "a").unwrap(), Some(1.0));
+        assert_eq!(store.count("t2").unwrap(), 0);
+    }
+
+    fn test_table_management(store: impl Storage) {
+        // 读一个不存在的table不会创建它
+        assert!(store.get("t0", "k1").unwrap().is_none());
+        assert!(store.list_tables().unwrap().is_empty());
+
+        let options = TableOptions {
+            max_keys: 2,
+            value_type: "string".into(),
+            ..Default::default()
+        };
+        store.create_table("t1", options.clone()).unwrap();
+        assert!(store.create_table("t1", options.clone()).is_err());
+        assert_eq!(store.table_options("t1").unwrap(), Some(options));
+        assert_eq!(store.list_tables().unwrap(), vec!["t1".to_string()]);
+
+        // value类型和key的数量受到限制
+        assert!(store.set("t1", "k1".into(), 1.into()).is_err());
+        store.set("t1", "k1".into(), "v1".into()).unwrap();
+        store.set("t1", "k2".into(), "v2".into()).unwrap();
+        store.set("t1", "k2".into(), "v3".into()).unwrap();
+        assert!(store.set("t1", "k3".into(), "v3".into()).is_err());
+
+        store.rename_table("t1", "t2").unwrap();
+        assert!(store.get("t1", "k1").unwrap().is_none());
+        assert_eq!(store.get("t2", "k1").unwrap(), Some("v1".into()));
+        assert!(store.table_options("t2").unwrap().is_some());
+        assert!(store.rename_table("t1", "t3").is_err());
+
+        assert!(store.drop_table("t2").unwrap());
+        assert!(!store.drop_table("t2").unwrap());
+        assert!(store.get("t2", "k1").unwrap().is_none());
+        assert!(store.list_tables().unwrap().is_empty());
+
+        // 并发写入时 key 的数量也不会超过 max_keys
+        let options = TableOptions {
+            max_keys: 10,
+            ..Default::default()
+        };
+        store.create_table("t4", options).unwrap();
+        let store = std::sync::Arc::new(store);
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let store = store.clone();
+                std::thread::spawn(move || {
+                    for j in 0..10 {
+                        let _ = store.set("t4", format!("k{}-{}", i, j), j.into());
+                    }
+                })
+            })
+            .collect();
+        handles.into_iter().for_each(|h| h.join().unwrap());
+        assert_eq!(store.count("t4").unwrap(), 10);
+    }
+
+    fn test_default_ttl(store: impl Storage) {
+        let options = TableOptions {
+            default_ttl: 1,
+            ..Default::default()
+        };
+        store.create_table("t1", options).unwrap();
+        store.set("t1", "k1".into(), "v1".into()).unwrap();
+        assert!(store.contains("t1", "k1").unwrap());
+
+        std::thread::sleep(std::time::Duration::from_millis(1100));
+        assert!(store.get("t1", "k1").unwrap().is_none());
+        assert!(store.get_all("t1").unwrap().is_empty());
+    }
+
+    fn test_indexes(store: impl Storage) {
+        let options = TableOptions {
+            indexes: vec![IndexSpec {
+                name: "email".into(),
+                field: "".into(),
+            }],
+            ..Default::default()
+        };
+        store.create_table("users", options).unwrap();
+        store.set("users", "u1".into(), "a@x.com".into()).unwrap();
+        store.set("users", "u2".into(), "b@x.com".into()).unwrap();
+        store.set("users", "u3".into(), "a@x.com".into()).unwrap();
+
+        let a: Value = "a@x.com".into();
+        let found = store.find("users", "email", &a, &a).unwrap();
+        let expected = vec![Kvpair::new("u1", a.clone()), Kvpair::new("u3", a.clone())];
+        assert_eq!(found, expected);
+
+        // 修改和删除会更新索引
+        store.set("users", "u1".into(), "c@x.com".into()).unwrap();
+        store.del("users", "u3").unwrap();
+        assert!(store.find("users", "email", &a, &a).unwrap().is_empty());
+
+        let found = store
+            .find("users", "email", &"b".into(), &"d".into())
+            .unwrap();
+        let keys: Vec<_> = found.into_iter().map(|v| v.key).collect();
+        assert_eq!(keys, vec!["u2".to_string(), "u1".to_string()]);
+
+        // 索引随table一起重命名和删除
+        store.rename_table("users", "people").unwrap();
+        let c: Value = "c@x.com".into();
+        assert_eq!(store.find("people", "email", &c, &c).unwrap().len(), 1);
+        store.drop_table("people").unwrap();
+        assert!(store.find("people", "email", &c, &c).is_err());
+    }
+
+    fn test_compare_and_swap(store: impl Storage) {
+        let v1: Value = "v1".into();
+        assert_eq!(
+            store.compare_and_swap("t1", "k1", Some(&v1), None).unwrap(),
+            Err(None)
+        );
+        assert!(store
+            .compare_and_swap("t1", "k1", None, Some(v1.clone()))
+            .unwrap()
+            .is_ok());
+        assert_eq!(
+            store.compare_and_swap("t1", "k1", None, Some("v2".into())).unwrap(),
+            Err(Some(v1.clone()))
+        );
+        assert!(store
+            .compare_and_swap("t1", "k1", Some(&v1), None)
+            .unwrap()
+            .is_ok());
+        assert!(!store.contains("t1", "k1").unwrap());
+
+        // 按编码后的字节比较：0.0 和 -0.0 不相等，NaN 和自己相等
+        assert!(store
+            .compare_and_swap("t1", "k2", None, Some((-0.0).into()))
+            .unwrap()
+            .is_ok());
+        assert_eq!(
+            store
+                .compare_and_swap("t1", "k2", Some(&0.0.into()), Some(1.0.into()))
+                .unwrap(),
+            Err(Some((-0.0).into()))
+        );
+        assert!(store
+            .compare_and_swap("t1", "k2", Some(&(-0.0).into()), Some(f64::NAN.into()))
+            .unwrap()
+            .is_ok());
+        let v: f64 = update(&store, "t1", "k2", |v| {
+            let v: f64 = v.cloned().unwrap().try_into()?;
+            Ok((Some(1.0.into()), v))
+        })
+        .unwrap();
+        assert!(v.is_nan());
+        assert_eq!(store.get("t1", "k2").unwrap(), Some(1.0.into()));
+
+        // 批量写入：有一个key不符合预期时不写入任何数据
+        let op = |key: &str, expected: Option<Value>, new: Option<Option<Value>>| CasOp {
+            table: "t1".into(),
+            key: key.into(),
+            expected,
+            new,
+        };
+        let ops = vec![
+            op("k3", None, Some(Some(3.into()))),
+            op("k2", Some(0.into()), None),
+        ];
+        assert!(!store.compare_and_swap_batch(ops).unwrap());
+        assert!(!store.contains("t1", "k3").unwrap());
+        let ops = vec![
+            op("k3", None, Some(Some(3.into()))),
+            op("k2", Some(1.0.into()), Some(None)),
+        ];
+        assert!(store.compare_and_swap_batch(ops).unwrap());
+        assert_eq!(store.get("t1", "k3").unwrap(), Some(3.into()));
+        assert!(!store.contains("t1", "k2").unwrap());
+
+        // 写入违反table的约束时恢复已经写入的key
+        let options = TableOptions {
+            value_type: "integer".into(),
+            ..Default::default()
+        };
+        store.create_table("t5", options).unwrap();
+        let ops = vec![
+            op("k3", Some(3.into()), Some(Some(4.into()))),
+            CasOp {
+                table: "t5".into(),
+                ..op("k1", None, Some(Some("v".into())))
+            },
+        ];
+        assert!(store.compare_and_swap_batch(ops).is_err());
+        assert_eq!(store.get("t1", "k3").unwrap(), Some(3.into()));
+        store.del("t1", "k3").unwrap();
+
+        // 并发的update不会丢失修改
+        let store = std::sync::Arc::new(store);
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let store = store.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..50 {
+                        update(store.as_ref(), "t1", "counter", |v| {
+                            let n = v.map_or(Ok(0), i64::try_from)?;
+                            Ok((Some((n + 1).into()), ()))
+                        })
+                        .unwrap();
+                    }
+                })
+            })
+            .collect();
+        handles.into_iter().for_each(|h| h.join().unwrap());
+        assert_eq!(store.get("t1", "counter").unwrap(), Some(200.into()));
+    }
+
+    fn test_nested_values(store: impl Storage) {
+        let mut doc = Value::default();
+        doc.set_field("name", "tyr".into()).unwrap();
+        doc.set_field("address.city", "Shanghai".into()).unwrap();
+        doc.set_field("tags", vec!["a".into(), Value::null()].into())
+            .unwrap();
+        doc.set_field("created", Value::timestamp(1_600_000_000_000))
+            .unwrap();
+        store.set("t1", "k1".into(), doc.clone()).unwrap();
+        assert_eq!(store.get("t1", "k1").unwrap(), Some(doc));
+    }
+
+    fn test_zset(store: impl Storage) {
+        let members = vec![
+            ("alice".to_string(), 30.0),
+            ("bob".to_string(), 10.0),
+            ("carol".to_string(), 20.0),
+        ];
+        assert_eq!(store.zadd("t1", "board", members).unwrap(), 3);
+        assert_eq!(store.zadd("t1", "board", vec![("bob".into(), 40.0)]).unwrap(), 0);
+        assert_eq!(store.zadd("t1", "board1", vec![("dave".into(), -1.0)]).unwrap(), 1);
+        assert!(store.zadd("t1", "board", vec![("x".into(), f64::NAN)]).is_err());
+
+        assert_eq!(store.zscore("t1", "board", "bob").unwrap(), Some(40.0));
+        assert_eq!(store.zscore("t1", "board", "dave").unwrap(), None);
+        assert_eq!(store.zrank("t1", "board", "carol").unwrap(), Some(0));
+        assert_eq!(store.zrank("t1", "board", "bob").unwrap(), Some(2));
+
+        let pairs = store.zrange("t1", "board", 0, -1).unwrap();
+        let members: Vec<_> = pairs.iter().map(|v| v.key.as_str()).collect();
+        assert_eq!(members, vec!["carol", "alice", "bob"]);
+        let pairs = store.zrange("t1", "board", -1, -1).unwrap();
+        assert_eq!(pairs, vec![Kvpair::new("bob", 40.0.into())]);
+
+        let pairs = store.zrange_by_score("t1", "board", 20.0, 40.0, 1, 1).unwrap();
+        assert_eq!(pairs, vec![Kvpair::new("alice", 30.0.into())]);
+        let pairs = store.zrange_by_score("t1", "board", 25.0, 100.0, 0, 0).unwrap();
+        assert_eq!(pairs.len(), 2);
+
+        let members = ["alice".to_string(), "nobody".to_string()];
+        assert_eq!(store.zrem("t1", "board", &members).unwrap(), 1);
+        assert_eq!(store.zrange("t1", "board", 0, -1).unwrap().len(), 2);
+
+        // 只有sorted set的table也会被列出和统计
+        assert_eq!(store.list_tables().unwrap(), vec!["t1".to_string()]);
+        assert_eq!(store.count("t1").unwrap(), 2);
+        let mut zsets: Vec<_> = store.zset_iter("t1").unwrap().collect();
+        zsets.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(zsets[0].0, "board");
+        assert_eq!(zsets[0].1, store.zrange("t1", "board", 0, -1).unwrap());
+        assert_eq!(zsets[1], ("board1".into(), vec![Kvpair::new("dave", (-1.0).into())]));
+
+        // sorted set随table一起重命名和删除
+        store.rename_table("t1", "t2").unwrap();
+        assert_eq!(store.zscore("t2", "board", "bob").unwrap(), Some(40.0));
+        assert!(store.drop_table("t2").unwrap());
+        assert!(store.zrange("t2", "board", 0, -1).unwrap().is_empty());
+    }
+
+    #[test]
+    fn memtable_zset_should_work() {
+        test_zset(MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_zset_should_work() {
+        let dir = tempdir().unwrap();
+        test_zset(SledDb::new(dir));
+    }
+
+    #[test]
+    fn memtable_compare_and_swap_should_work() {
+        test_compare_and_swap(MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_compare_and_swap_should_work() {
+        let dir = tempdir().unwrap();
+        test_compare_and_swap(SledDb::new(dir));
+    }
+
+    #[test]
+    fn memtable_nested_values_should_work() {
+        test_nested_values(MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_nested_values_should_work() {
+        let dir = tempdir().unwrap();
+        test_nested_values(SledDb::new(dir));
+    }
+
+    #[test]
+    fn memtable_indexes_should_work() {
+        test_indexes(MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_indexes_should_work() {
+        let dir = tempdir().unwrap();
+        test_indexes(SledDb::new(dir));
+    }
+
+    #[test]
+    fn memtable_table_management_should_work() {
+        test_table_management(MemTable::new());
+    }
+
+    #[test]
+    fn memtable_rename_onto_existing_table_should_fail() {
+        test_rename_onto_existing_table(MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_rename_onto_existing_table_should_fail() {
+        let dir = tempdir().unwrap();
+        test_rename_onto_existing_table(SledDb::new(dir));
+    }
+
+    #[test]
+    fn memtable_default_ttl_should_work() {
+        test_default_ttl(MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_table_management_should_work() {
+        let dir = tempdir().unwrap();
+        test_table_management(SledDb::new(dir));
+    }
+
+    #[test]
+    fn sleddb_default_ttl_should_work() {
+        let dir = tempdir().unwrap();
+        test_default_ttl(SledDb::new(dir));
+    }
+
     #[test]
     fn memtable_iter_should_work() {
         let store = MemTable::new();
         test_get_iter(store);
     }
 
+    #[test]
+    fn memtable_tables_and_count_should_work() {
+        let store = MemTable::new();
+        test_tables_and_count(store);
+    }
+
     #[test]
     fn sleddb_basic_interface_should_work() {
         let dir = tempdir().unwrap();
@@ -138,4 +691,11 @@ mod tests {
         let store = SledDb::new(dir);
         test_get_iter(store);
     }
+
+    #[test]
+    fn sleddb_tables_and_count_should_work() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        test_tables_and_count(store);
+    }
 }
@@ -0,0 +1,208 @@
+//! 按排名查找的有序集合：每个节点记录子树大小的 treap
+//!
+//! 节点的优先级是随机的，树的期望高度是 O(log n)，插入、删除、求排名和定位到某个排名都是 O(log n)，
+//! 从某个排名开始遍历 k 个节点是 O(log n + k)。MemTable 的 sorted set 用它按分数排序成员。
+
+use std::cmp::Ordering;
+
+type Link<K> = Option<Box<Node<K>>>;
+
+#[derive(Debug, Clone)]
+struct Node<K> {
+    key: K,
+    // 父节点的优先级不小于子节点
+    priority: u32,
+    // 以这个节点为根的子树中节点的数量
+    size: usize,
+    left: Link<K>,
+    right: Link<K>,
+}
+
+impl<K> Node<K> {
+    fn update(&mut self) {
+        self.size = 1 + size(&self.left) + size(&self.right);
+    }
+}
+
+fn size<K>(link: &Link<K>) -> usize {
+    link.as_ref().map_or(0, |n| n.size)
+}
+
+// 把树分成前 n 个节点和剩下的节点
+fn split<K>(link: Link<K>, n: usize) -> (Link<K>, Link<K>) {
+    let mut node = match link {
+        Some(node) => node,
+        None => return (None, None),
+    };
+    let left = size(&node.left);
+    if n <= left {
+        let (l, r) = split(node.left.take(), n);
+        node.left = r;
+        node.update();
+        (l, Some(node))
+    } else {
+        let (l, r) = split(node.right.take(), n - left - 1);
+        node.right = l;
+        node.update();
+        (Some(node), r)
+    }
+}
+
+// 合并两棵树，a 中所有的节点都排在 b 之前
+fn merge<K>(a: Link<K>, b: Link<K>) -> Link<K> {
+    match (a, b) {
+        (None, b) => b,
+        (a, None) => a,
+        (Some(mut a), Some(mut b)) => {
+            if a.priority >= b.priority {
+                a.right = merge(a.right.take(), Some(b));
+                a.update();
+                Some(a)
+            } else {
+                b.left = merge(Some(a), b.left.take());
+                b.update();
+                Some(b)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct RankTree<K> {
+    root: Link<K>,
+}
+
+impl<K> Default for RankTree<K> {
+    fn default() -> Self {
+        Self { root: None }
+    }
+}
+
+impl<K: Ord> RankTree<K> {
+    pub fn len(&self) -> usize {
+        size(&self.root)
+    }
+
+    /// 比 key 小的节点的数量，key 在树中时就是它的排名（从 0 开始）
+    pub fn rank(&self, key: &K) -> usize {
+        let mut rank = 0;
+        let mut link = &self.root;
+        while let Some(node) = link {
+            match key.cmp(&node.key) {
+                Ordering::Greater => {
+                    rank += size(&node.left) + 1;
+                    link = &node.right;
+                }
+                _ => link = &node.left,
+            }
+        }
+        rank
+    }
+
+    /// 插入 key，调用者需要保证 key 不在树中
+    pub fn insert(&mut self, key: K) {
+        let rank = self.rank(&key);
+        let (l, r) = split(self.root.take(), rank);
+        let node = Box::new(Node {
+            key,
+            priority: rand::random(),
+            size: 1,
+            left: None,
+            right: None,
+        });
+        self.root = merge(merge(l, Some(node)), r);
+    }
+
+    /// 删除 key，返回 key 是否在树中
+    pub fn remove(&mut self, key: &K) -> bool {
+        let rank = self.rank(key);
+        let (l, r) = split(self.root.take(), rank);
+        // r 中的第一个节点是不小于 key 的最小节点
+        let (first, r) = split(r, 1);
+        let found = matches!(&first, Some(node) if node.key == *key);
+        let first = if found { None } else { first };
+        self.root = merge(merge(l, first), r);
+        found
+    }
+
+    /// 从排名为 rank 的节点开始按顺序遍历
+    pub fn iter_from(&self, rank: usize) -> Iter<'_, K> {
+        // 栈中是还没有访问的祖先节点，它们的左子树已经访问过或者被跳过
+        let mut stack = Vec::new();
+        let mut rank = rank;
+        let mut link = &self.root;
+        while let Some(node) = link {
+            let left = size(&node.left);
+            match rank.cmp(&left) {
+                Ordering::Less => {
+                    stack.push(&**node);
+                    link = &node.left;
+                }
+                Ordering::Equal => {
+                    stack.push(&**node);
+                    break;
+                }
+                Ordering::Greater => {
+                    rank -= left + 1;
+                    link = &node.right;
+                }
+            }
+        }
+        Iter { stack }
+    }
+}
+
+pub(crate) struct Iter<'a, K> {
+    stack: Vec<&'a Node<K>>,
+}
+
+impl<'a, K> Iterator for Iter<'a, K> {
+    type Item = &'a K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        let mut link = &node.right;
+        while let Some(n) = link {
+            self.stack.push(&**n);
+            link = &n.left;
+        }
+        Some(&node.key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn rank_tree_should_match_btree_set() {
+        let mut rng = rand::thread_rng();
+        let mut tree = RankTree::default();
+        let mut expected = BTreeSet::new();
+        for _ in 0..5000 {
+            let key: u32 = rng.gen_range(0..500);
+            match rng.gen_bool(0.6) {
+                true if !expected.contains(&key) => {
+                    tree.insert(key);
+                    expected.insert(key);
+                }
+                _ => assert_eq!(tree.remove(&key), expected.remove(&key)),
+            }
+        }
+        assert_eq!(tree.len(), expected.len());
+
+        let sorted: Vec<_> = expected.iter().copied().collect();
+        assert_eq!(tree.iter_from(0).copied().collect::<Vec<_>>(), sorted);
+        for (i, key) in sorted.iter().enumerate() {
+            assert_eq!(tree.rank(key), i);
+            assert_eq!(tree.iter_from(i).next(), Some(key));
+        }
+        // 不在树中的 key 的排名是插入它的位置
+        assert_eq!(tree.rank(&u32::MAX), sorted.len());
+        assert_eq!(tree.iter_from(sorted.len()).next(), None);
+        let tail: Vec<_> = tree.iter_from(sorted.len() / 2).copied().collect();
+        assert_eq!(tail, sorted[sorted.len() / 2..]);
+    }
+}
@@ -1,13 +1,69 @@
-use crate::{KvError, Kvpair, Storage, StorageIter, Value};
-use sled::{Db, IVec};
-use std::{convert::TryInto, path::Path, str};
+use crate::{
+    storage::{
+        apply_batch,
+        index::{index_entries, index_range},
+        table::{now_ms, validate_table_name},
+        zset::{
+            decode_score, decode_score_entry, decode_zset_entry, member_key, normalize_range, normalize_score,
+            score_end, score_key, score_start, take_limit, zset_prefix, zset_table_prefix,
+        },
+    },
+    CasOp, KvError, Kvpair, Storage, StorageIter, TableOptions, TableSnapshot, Value, ZsetIter,
+};
+use prost::Message;
+use sled::{
+    transaction::{ConflictableTransactionError, TransactionError},
+    Batch, Db, IVec, Transactional, Tree,
+};
+use std::{
+    convert::{TryFrom, TryInto},
+    path::Path,
+    str,
+    sync::{Mutex, RwLock},
+};
+
+/// 存放table选项的tree
+const TABLES_TREE: &str = "__tables__";
+/// 存放key过期时间的tree
+const EXPIRES_TREE: &str = "__expires__";
+/// 存放二级索引的tree
+const INDEXES_TREE: &str = "__indexes__";
+/// 存放sorted set的tree
+const ZSETS_TREE: &str = "__zsets__";
 
 #[derive(Debug)]
-pub struct SledDb(Db);
+pub struct SledDb {
+    db: Db,
+    // table名 -> TableOptions
+    tables: Tree,
+    // table:key -> 过期时间（unix 毫秒，大端）
+    expires: Tree,
+    // table | 0x00 | 索引项 -> 主 key
+    indexes: Tree,
+    // sorted set，编码见 storage/zset.rs
+    zsets: Tree,
+    // 向有 max_keys 限制的 table 写入时持有这个锁，检查数量和插入新 key 是原子的
+    capacity: Mutex<()>,
+    // 普通的写入持有读锁，批量写入持有写锁，这样其它写入看不到只执行了一半的批量写入
+    batch: RwLock<()>,
+}
 
 impl SledDb {
     pub fn new(path: impl AsRef<Path>) -> Self {
-        Self(sled::open(path).unwrap())
+        let db = sled::open(path).unwrap();
+        let tables = db.open_tree(TABLES_TREE).unwrap();
+        let expires = db.open_tree(EXPIRES_TREE).unwrap();
+        let indexes = db.open_tree(INDEXES_TREE).unwrap();
+        let zsets = db.open_tree(ZSETS_TREE).unwrap();
+        Self {
+            db,
+            tables,
+            expires,
+            indexes,
+            zsets,
+            capacity: Mutex::new(()),
+            batch: RwLock::new(()),
+        }
     }
 
     fn get_full_key(table: &str, key: &str) -> String {
@@ -17,6 +73,184 @@ impl SledDb {
     fn get_table_prefix(table: &str) -> String {
         format!("{}:", table)
     }
+
+    fn get_options(&self, table: &str) -> Result<TableOptions, KvError> {
+        Ok(self.table_options(table)?.unwrap_or_default())
+    }
+
+    // table中普通key的数量，不包括sorted set
+    fn count_keys(&self, table: &str) -> Result<u64, KvError> {
+        let prefix = SledDb::get_table_prefix(table);
+        Ok(self.db.scan_prefix(prefix).keys().count() as u64)
+    }
+
+    fn get_index_key(table: &str, entry: &[u8]) -> Vec<u8> {
+        [table.as_bytes(), &[0], entry].concat()
+    }
+
+    // 如果key已经过期，删除它
+    fn purge_expired(&self, table: &str, key: &str) -> Result<(), KvError> {
+        let name = SledDb::get_full_key(table, key);
+        if is_expired(&self.expires, name.as_bytes(), now_ms()) {
+            self.remove(table, key)?;
+        }
+        Ok(())
+    }
+
+    // 删除key，同时删除它的过期时间和索引项
+    fn remove(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        let name = SledDb::get_full_key(table, key);
+        self.expires.remove(&name)?;
+        let options = self.get_options(table)?;
+        if options.has_indexes() {
+            return self.write_indexed(table, &options, key, None);
+        }
+        flip(self.db.remove(name)?.map(|v| v.as_ref().try_into()))
+    }
+
+    // 在一个事务中修改数据和索引，value为None时删除key
+    fn write_indexed(
+        &self,
+        table: &str,
+        options: &TableOptions,
+        key: &str,
+        value: Option<Value>,
+    ) -> Result<Option<Value>, KvError> {
+        let name = SledDb::get_full_key(table, key);
+        let new = value
+            .as_ref()
+            .map(|v| index_entries(options, key, v))
+            .unwrap_or_default();
+        let data: Option<Vec<u8>> = value.map(|v| v.try_into()).transpose()?;
+
+        let result = (&*self.db, &self.indexes).transaction(|(db, indexes)| {
+            let old = match &data {
+                Some(data) => db.insert(name.as_bytes(), data.clone())?,
+                None => db.remove(name.as_bytes())?,
+            };
+            let old = old
+                .map(|v| Value::try_from(v.as_ref()))
+                .transpose()
+                .map_err(ConflictableTransactionError::Abort)?;
+            if let Some(old) = &old {
+                for entry in index_entries(options, key, old) {
+                    indexes.remove(SledDb::get_index_key(table, &entry))?;
+                }
+            }
+            for entry in &new {
+                indexes.insert(SledDb::get_index_key(table, entry), key.as_bytes())?;
+            }
+            Ok(old)
+        });
+        result.map_err(tx_error)
+    }
+
+    // 不持有 batch 锁的 compare_and_swap
+    fn cas(
+        &self,
+        table: &str,
+        key: &str,
+        expected: Option<&Value>,
+        new: Option<Value>,
+    ) -> Result<Result<(), Option<Value>>, KvError> {
+        let options = self.get_options(table)?;
+        if let Some(v) = &new {
+            options.check_value(v)?;
+        }
+        self.purge_expired(table, key)?;
+        let _capacity = (options.max_keys > 0).then(|| self.capacity.lock().unwrap());
+        if expected.is_none() && new.is_some() {
+            options.check_capacity(|| self.count_keys(table))?;
+        }
+
+        let name = SledDb::get_full_key(table, key);
+        let entries = new
+            .as_ref()
+            .map(|v| index_entries(&options, key, v))
+            .unwrap_or_default();
+        let has_new = new.is_some();
+        let data: Option<Vec<u8>> = new.map(|v| v.try_into()).transpose()?;
+
+        let result = match options.has_indexes() {
+            true => self.compare_and_swap_indexed(table, key, &options, expected, &data, &entries)?,
+            false => self.compare_and_swap_raw(&name, expected, &data)?,
+        };
+
+        if result.is_ok() {
+            match options.expire_at().filter(|_| has_new) {
+                Some(at) => self.expires.insert(&name, &at.to_be_bytes())?,
+                None => self.expires.remove(&name)?,
+            };
+        }
+        Ok(result)
+    }
+
+    // 有索引的table在事务中比较和替换，同时维护索引
+    fn compare_and_swap_indexed(
+        &self,
+        table: &str,
+        key: &str,
+        options: &TableOptions,
+        expected: Option<&Value>,
+        data: &Option<Vec<u8>>,
+        entries: &[Vec<u8>],
+    ) -> Result<Result<(), Option<Value>>, KvError> {
+        let name = SledDb::get_full_key(table, key);
+        let expected: Option<Vec<u8>> = expected.map(|v| v.clone().try_into()).transpose()?;
+        let result = (&*self.db, &self.indexes).transaction(|(db, indexes)| {
+            let raw = db.get(name.as_bytes())?;
+            let current = raw
+                .as_ref()
+                .map(|v| Value::try_from(v.as_ref()))
+                .transpose()
+                .map_err(ConflictableTransactionError::Abort)?;
+            if raw.as_deref() != expected.as_deref() {
+                return Ok(Err(current));
+            }
+            match data {
+                Some(data) => db.insert(name.as_bytes(), data.clone())?,
+                None => db.remove(name.as_bytes())?,
+            };
+            if let Some(old) = &current {
+                for entry in index_entries(options, key, old) {
+                    indexes.remove(SledDb::get_index_key(table, &entry))?;
+                }
+            }
+            for entry in entries {
+                indexes.insert(SledDb::get_index_key(table, entry), key.as_bytes())?;
+            }
+            Ok(Ok(()))
+        });
+        result.map_err(tx_error)
+    }
+
+    // 没有索引的table直接使用sled的compare_and_swap
+    fn compare_and_swap_raw(
+        &self,
+        name: &str,
+        expected: Option<&Value>,
+        data: &Option<Vec<u8>>,
+    ) -> Result<Result<(), Option<Value>>, KvError> {
+        let old: Option<Vec<u8>> = expected.map(|v| v.clone().try_into()).transpose()?;
+        match self.db.compare_and_swap(name, old.as_deref(), data.as_deref())? {
+            Ok(()) => Ok(Ok(())),
+            Err(e) => {
+                let current = e
+                    .current
+                    .as_ref()
+                    .map(|v| Value::try_from(v.as_ref()))
+                    .transpose()?;
+                Ok(Err(current))
+            }
+        }
+    }
+}
+
+fn tx_error(e: TransactionError<KvError>) -> KvError {
+    match e {
+        TransactionError::Abort(e) => e,
+        TransactionError::Storage(e) => e.into(),
+    }
 }
 
 // 把 Option<Result<T, E>> flip 成 Result<Option<T>, E>
@@ -24,39 +258,440 @@ fn flip<T, E>(x: Option<Result<T, E>>) -> Result<Option<T>, E> {
     x.map_or(Ok(None), |v| v.map(Some))
 }
 
+fn is_expired(expires: &Tree, name: &[u8], now: u64) -> bool {
+    match expires.get(name) {
+        Ok(Some(v)) => v
+            .as_ref()
+            .try_into()
+            .map(|v| u64::from_be_bytes(v) <= now)
+            .unwrap_or(false),
+        _ => false,
+    }
+}
+
 impl Storage for SledDb {
     fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        self.purge_expired(table, key)?;
         let name = SledDb::get_full_key(table, key);
-        let result = self.0.get(name.as_bytes())?.map(|v| v.as_ref().try_into());
+        let result = self.db.get(name.as_bytes())?.map(|v| v.as_ref().try_into());
         flip(result)
     }
 
     fn set(&self, table: &str, key: String, value: Value) -> Result<Option<Value>, KvError> {
+        let options = self.get_options(table)?;
+        options.check_value(&value)?;
+
+        self.purge_expired(table, &key)?;
         let name = SledDb::get_full_key(table, &key);
-        let data: Vec<u8> = value.try_into()?;
-        let result = self.0.insert(name, data)?.map(|v| v.as_ref().try_into());
-        flip(result)
+        let _batch = self.batch.read().unwrap();
+        let _capacity = (options.max_keys > 0).then(|| self.capacity.lock().unwrap());
+        if !self.db.contains_key(&name)? {
+            options.check_capacity(|| self.count_keys(table))?;
+        }
+
+        let old = match options.has_indexes() {
+            true => self.write_indexed(table, &options, &key, Some(value))?,
+            false => {
+                let data: Vec<u8> = value.try_into()?;
+                flip(self.db.insert(&name, data)?.map(|v| v.as_ref().try_into()))?
+            }
+        };
+        match options.expire_at() {
+            Some(at) => self.expires.insert(&name, &at.to_be_bytes())?,
+            None => self.expires.remove(&name)?,
+        };
+        Ok(old)
     }
 
     fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        self.purge_expired(table, key)?;
         let name = SledDb::get_full_key(table, key);
-        Ok(self.0.contains_key(name)?)
+        Ok(self.db.contains_key(name)?)
     }
 
     fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
-        let name = SledDb::get_full_key(table, &key);
-        let result = self.0.remove(name)?.map(|v| v.as_ref().try_into());
-        flip(result)
+        let _batch = self.batch.read().unwrap();
+        self.purge_expired(table, key)?;
+        self.remove(table, key)
     }
     fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
+        Ok(self.get_iter(table)?.collect())
+    }
+    fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError> {
         let prefix = SledDb::get_table_prefix(table);
-        let result = self.0.scan_prefix(prefix).map(|v| v.into()).collect();
+        let expires = self.expires.clone();
+        let now = now_ms();
+        let iter = self.db.scan_prefix(prefix).filter(move |v| match v {
+            Ok((k, _)) => !is_expired(&expires, k, now),
+            Err(_) => true,
+        });
+        Ok(Box::new(StorageIter::new(iter)))
+    }
+    fn list_tables(&self) -> Result<Vec<String>, KvError> {
+        // 所有 key 都是 table:key 的形式，找到一个 table 后，直接跳到下一个 table 的起点
+        let mut tables = Vec::new();
+        let mut start = Vec::new();
+        while let Some((k, _)) = self.db.range(start.clone()..).next().transpose()? {
+            let key = str::from_utf8(&k).map_err(|e| KvError::Internal(e.to_string()))?;
+            let table = key.split(':').next().unwrap_or_default().to_string();
+            start = SledDb::get_table_prefix(&table).into_bytes();
+            // ':' 的下一个字符是 ';'，table; 之后就是下一个 table
+            *start.last_mut().unwrap() = b';';
+            tables.push(table);
+        }
+
+        // 只有 sorted set 的 table，同样找到一个后跳到下一个 table
+        let mut start = Vec::new();
+        while let Some(k) = self.zsets.range(start.clone()..).keys().next().transpose()? {
+            let (table, _, _) = decode_zset_entry(&k)?;
+            start = zset_table_prefix(&table);
+            // table 编码后以 0x00 0x01 结尾，改成 0x00 0x02 就是下一个 table 的起点
+            *start.last_mut().unwrap() = 2;
+            if !tables.contains(&table) {
+                tables.push(table);
+            }
+        }
+
+        // 显式创建的 table 可能还没有数据
+        for name in self.tables.iter().keys() {
+            let name = String::from_utf8_lossy(&name?).to_string();
+            if !tables.contains(&name) {
+                tables.push(name);
+            }
+        }
+        Ok(tables)
+    }
+    fn count(&self, table: &str) -> Result<u64, KvError> {
+        // 和 list_tables 一样，找到一个 sorted set 后直接跳到下一个
+        let table_prefix = zset_table_prefix(table);
+        let mut zsets = 0;
+        let mut start = table_prefix.clone();
+        while let Some(k) = self.zsets.range(start.clone()..).keys().next().transpose()? {
+            if !k.starts_with(&table_prefix) {
+                break;
+            }
+            let (_, key, _) = decode_zset_entry(&k)?;
+            start = zset_prefix(table, &key);
+            *start.last_mut().unwrap() = 2;
+            zsets += 1;
+        }
+        Ok(self.count_keys(table)? + zsets)
+    }
+    fn backend(&self) -> &'static str {
+        "sleddb"
+    }
+    fn estimated_size(&self) -> Result<u64, KvError> {
+        // 和 MemTable 一样，只统计 key 和 value 的大小
+        let mut size = 0;
+        for item in self.db.iter() {
+            let (k, v) = item?;
+            size += (k.len() + v.len()) as u64;
+        }
+        Ok(size)
+    }
+    fn create_table(&self, table: &str, options: TableOptions) -> Result<(), KvError> {
+        validate_table_name(table)?;
+        options.validate()?;
+        let prefix = SledDb::get_table_prefix(table);
+        if self.db.scan_prefix(prefix).next().is_some() {
+            return Err(KvError::AlreadyExists(format!("table {}", table)));
+        }
+        self.tables
+            .compare_and_swap(table, None as Option<&[u8]>, Some(options.encode_to_vec()))?
+            .map_err(|_| KvError::AlreadyExists(format!("table {}", table)))
+    }
+    fn drop_table(&self, table: &str) -> Result<bool, KvError> {
+        let _batch = self.batch.read().unwrap();
+        let prefix = SledDb::get_table_prefix(table);
+        let mut batch = Batch::default();
+        let mut expires = Batch::default();
+        let mut found = self.tables.remove(table)?.is_some();
+        let mut indexes = Batch::default();
+        for key in self.indexes.scan_prefix(SledDb::get_index_key(table, &[])).keys() {
+            indexes.remove(key?);
+        }
+        self.indexes.apply_batch(indexes)?;
+        let mut zsets = Batch::default();
+        for key in self.zsets.scan_prefix(zset_table_prefix(table)).keys() {
+            zsets.remove(key?);
+            found = true;
+        }
+        self.zsets.apply_batch(zsets)?;
+        for key in self.db.scan_prefix(&prefix).keys() {
+            let key = key?;
+            batch.remove(key.clone());
+            expires.remove(key);
+            found = true;
+        }
+        self.db.apply_batch(batch)?;
+        self.expires.apply_batch(expires)?;
+        Ok(found)
+    }
+    fn rename_table(&self, from: &str, to: &str) -> Result<(), KvError> {
+        validate_table_name(to)?;
+        // 和 MemTable 一样持有batch写锁，移动的过程中其它写入不会重新创建from
+        let _batch = self.batch.write().unwrap();
+        if self.tables.contains_key(to)? || self.count(to)? > 0 {
+            return Err(KvError::AlreadyExists(format!("table {}", to)));
+        }
+
+        let options = self.tables.remove(from)?;
+        let mut found = options.is_some();
+        if let Some(options) = options {
+            self.tables.insert(to, options)?;
+        }
+
+        let index_prefix = SledDb::get_index_key(from, &[]);
+        let mut indexes = Batch::default();
+        for item in self.indexes.scan_prefix(&index_prefix) {
+            let (k, v) = item?;
+            indexes.remove(k.clone());
+            indexes.insert(SledDb::get_index_key(to, &k[index_prefix.len()..]), v);
+        }
+        self.indexes.apply_batch(indexes)?;
+
+        let zset_prefix = zset_table_prefix(from);
+        let mut zsets = Batch::default();
+        for item in self.zsets.scan_prefix(&zset_prefix) {
+            let (k, v) = item?;
+            zsets.remove(k.clone());
+            zsets.insert([zset_table_prefix(to), k[zset_prefix.len()..].to_vec()].concat(), v);
+            found = true;
+        }
+        self.zsets.apply_batch(zsets)?;
+
+        let prefix = SledDb::get_table_prefix(from);
+        let mut batch = Batch::default();
+        let mut expires = Batch::default();
+        for item in self.db.scan_prefix(&prefix) {
+            let (k, v) = item?;
+            let new_key = [SledDb::get_table_prefix(to).as_bytes(), &k[prefix.len()..]].concat();
+            if let Some(at) = self.expires.get(&k)? {
+                expires.remove(k.clone());
+                expires.insert(new_key.clone(), at);
+            }
+            batch.remove(k);
+            batch.insert(new_key, v);
+            found = true;
+        }
+        if !found {
+            return Err(KvError::NotFound(format!("table {}", from)));
+        }
+        self.db.apply_batch(batch)?;
+        self.expires.apply_batch(expires)?;
+        Ok(())
+    }
+    fn table_options(&self, table: &str) -> Result<Option<TableOptions>, KvError> {
+        let result = self
+            .tables
+            .get(table)?
+            .map(|v| TableOptions::decode(v.as_ref()).map_err(KvError::from));
+        flip(result)
+    }
+    fn find(
+        &self,
+        table: &str,
+        index: &str,
+        start: &Value,
+        end: &Value,
+    ) -> Result<Vec<Kvpair>, KvError> {
+        let options = self.get_options(table)?;
+        let range = index_range(&options, index, start, end)?;
+        let range =
+            SledDb::get_index_key(table, &range.start)..SledDb::get_index_key(table, &range.end);
+
+        let now = now_ms();
+        let mut result = Vec::new();
+        for item in self.indexes.range(range) {
+            let (entry, key) = item?;
+            let key = str::from_utf8(&key).map_err(|e| KvError::Internal(e.to_string()))?;
+            let name = SledDb::get_full_key(table, key);
+            if is_expired(&self.expires, name.as_bytes(), now) {
+                continue;
+            }
+            if let Some(v) = self.db.get(&name)? {
+                let v: Value = v.as_ref().try_into()?;
+                // 读索引和读数据之间value可能被修改，只返回和索引项一致的数据
+                let matched = index_entries(&options, key, &v)
+                    .iter()
+                    .any(|e| SledDb::get_index_key(table, e) == entry.as_ref());
+                if matched {
+                    result.push(Kvpair::new(key, v));
+                }
+            }
+        }
         Ok(result)
     }
-    fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError> {
+    fn compare_and_swap(
+        &self,
+        table: &str,
+        key: &str,
+        expected: Option<&Value>,
+        new: Option<Value>,
+    ) -> Result<Result<(), Option<Value>>, KvError> {
+        let _batch = self.batch.read().unwrap();
+        self.cas(table, key, expected, new)
+    }
+    fn compare_and_swap_batch(&self, ops: Vec<CasOp>) -> Result<bool, KvError> {
+        let _batch = self.batch.write().unwrap();
+        apply_batch(ops, |t, k| self.get(t, k), |t, k, e, n| self.cas(t, k, e, n))
+    }
+    fn export_table(&self, table: &str) -> Result<TableSnapshot, KvError> {
+        // sled 的迭代器不持有锁，遍历期间的写入不会被阻塞
         let prefix = SledDb::get_table_prefix(table);
-        let iter = StorageIter::new(self.0.scan_prefix(prefix));
-        Ok(Box::new(iter))
+        let expires = self.expires.clone();
+        let now = now_ms();
+        let pairs = self.db.scan_prefix(&prefix).map(move |item| {
+            let (k, v) = item?;
+            let at = expires.get(&k)?.and_then(|v| v.as_ref().try_into().ok().map(u64::from_be_bytes));
+            if matches!(at, Some(at) if at <= now) {
+                return Ok(None);
+            }
+            let key = String::from_utf8_lossy(&k[prefix.len()..]);
+            Ok(Some((Kvpair::new(key, v.as_ref().try_into()?), at)))
+        });
+        Ok(TableSnapshot {
+            options: self.table_options(table)?,
+            pairs: Box::new(pairs.filter_map(Result::transpose)),
+            zsets: self.zset_iter(table)?,
+        })
+    }
+    fn expire_at(&self, table: &str, key: &str) -> Result<Option<u64>, KvError> {
+        let name = SledDb::get_full_key(table, key);
+        Ok(self
+            .expires
+            .get(name)?
+            .and_then(|v| v.as_ref().try_into().ok().map(u64::from_be_bytes)))
+    }
+    fn set_expire_at(&self, table: &str, key: &str, at: Option<u64>) -> Result<bool, KvError> {
+        let _batch = self.batch.read().unwrap();
+        let name = SledDb::get_full_key(table, key);
+        if !self.db.contains_key(&name)? {
+            return Ok(false);
+        }
+        match at {
+            Some(at) => self.expires.insert(&name, &at.to_be_bytes())?,
+            None => self.expires.remove(&name)?,
+        };
+        Ok(true)
+    }
+    fn zadd(&self, table: &str, key: &str, members: Vec<(String, f64)>) -> Result<u64, KvError> {
+        let members = members
+            .into_iter()
+            .map(|(m, s)| Ok((m, normalize_score(s)?)))
+            .collect::<Result<Vec<_>, KvError>>()?;
+        let prefix = zset_prefix(table, key);
+        let _batch = self.batch.read().unwrap();
+        let result = self.zsets.transaction(|tx| {
+            let mut added = 0;
+            for (member, score) in &members {
+                let old = tx.insert(member_key(&prefix, member), &score.to_be_bytes())?;
+                match old {
+                    Some(old) => {
+                        let old = decode_score(&old).map_err(ConflictableTransactionError::Abort)?;
+                        tx.remove(score_key(&prefix, old, member))?;
+                    }
+                    None => added += 1,
+                }
+                tx.insert(score_key(&prefix, *score, member), &score.to_be_bytes())?;
+            }
+            Ok(added)
+        });
+        result.map_err(tx_error)
+    }
+    fn zrem(&self, table: &str, key: &str, members: &[String]) -> Result<u64, KvError> {
+        let prefix = zset_prefix(table, key);
+        let _batch = self.batch.read().unwrap();
+        let result = self.zsets.transaction(|tx| {
+            let mut removed = 0;
+            for member in members {
+                if let Some(old) = tx.remove(member_key(&prefix, member))? {
+                    let old = decode_score(&old).map_err(ConflictableTransactionError::Abort)?;
+                    tx.remove(score_key(&prefix, old, member))?;
+                    removed += 1;
+                }
+            }
+            Ok(removed)
+        });
+        result.map_err(tx_error)
+    }
+    fn zset_iter(&self, table: &str) -> Result<ZsetIter, KvError> {
+        // 按分数排序的记录中同一个 sorted set 的成员是连续的
+        let mut zsets: Vec<(String, Vec<Kvpair>)> = Vec::new();
+        for item in self.zsets.scan_prefix(zset_table_prefix(table)) {
+            let (k, v) = item?;
+            let (_, key, is_score) = decode_zset_entry(&k)?;
+            if !is_score {
+                continue;
+            }
+            let pair = decode_score_entry(&zset_prefix(table, &key), &k, &v)?;
+            match zsets.last_mut() {
+                Some((last, members)) if *last == key => members.push(pair),
+                _ => zsets.push((key, vec![pair])),
+            }
+        }
+        Ok(Box::new(zsets.into_iter()))
+    }
+    fn zscore(&self, table: &str, key: &str, member: &str) -> Result<Option<f64>, KvError> {
+        let prefix = zset_prefix(table, key);
+        flip(self.zsets.get(member_key(&prefix, member))?.map(|v| decode_score(&v)))
+    }
+    fn zrank(&self, table: &str, key: &str, member: &str) -> Result<Option<u64>, KvError> {
+        let prefix = zset_prefix(table, key);
+        let score = match self.zscore(table, key, member)? {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+        // sled 不能按排名定位，需要数出排在成员之前的记录，是 O(rank)
+        let range = score_start(&prefix)..score_key(&prefix, score, member);
+        Ok(Some(self.zsets.range(range).count() as u64))
+    }
+    fn zrange(&self, table: &str, key: &str, start: i64, stop: i64) -> Result<Vec<Kvpair>, KvError> {
+        let prefix = zset_prefix(table, key);
+        let scores = || self.zsets.range(score_start(&prefix)..score_end(&prefix));
+        // 需要从头遍历到 stop，是 O(stop)。有负数时先数出成员的数量，是 O(n)
+        let len = match start < 0 || stop < 0 {
+            true => scores().count(),
+            false => i64::MAX as usize,
+        };
+        let range = match normalize_range(len, start, stop) {
+            Some(v) => v,
+            None => return Ok(Vec::new()),
+        };
+        scores()
+            .skip(*range.start())
+            .take(range.end() - range.start() + 1)
+            .map(|item| {
+                let (k, v) = item?;
+                decode_score_entry(&prefix, &k, &v)
+            })
+            .collect()
+    }
+    fn zrange_by_score(
+        &self,
+        table: &str,
+        key: &str,
+        min: f64,
+        max: f64,
+        offset: u64,
+        limit: u64,
+    ) -> Result<Vec<Kvpair>, KvError> {
+        let prefix = zset_prefix(table, key);
+        let limit = take_limit(limit);
+        let mut result = Vec::new();
+        let mut skipped = 0;
+        for item in self.zsets.range(score_key(&prefix, min, "")..score_end(&prefix)) {
+            let (k, v) = item?;
+            if decode_score(&v)? > max || result.len() >= limit {
+                break;
+            }
+            if skipped < offset {
+                skipped += 1;
+                continue;
+            }
+            result.push(decode_score_entry(&prefix, &k, &v)?);
+        }
+        Ok(result)
     }
 }
 
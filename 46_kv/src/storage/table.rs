@@ -0,0 +1,128 @@
+use crate::{value, KvError, TableOptions, Value};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// 当前时间（unix 毫秒），用于 key 的过期时间
+pub(crate) fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// 以它开头的 table 由服务器内部使用（例如保存锁的 `__locks__`），客户端不能直接访问
+pub const RESERVED_TABLE_PREFIX: &str = "__";
+
+/// table 名不能为空，不能包含 SledDb 用作分隔符的 ':'，也不能使用保留的前缀
+pub(crate) fn validate_table_name(table: &str) -> Result<(), KvError> {
+    if table.is_empty() || table.contains(':') || table.starts_with(RESERVED_TABLE_PREFIX) {
+        return Err(KvError::InvalidCommand(format!(
+            "invalid table name: `{}`",
+            table
+        )));
+    }
+    Ok(())
+}
+
+/// 客户端的数据命令不能访问内部使用的 table
+pub(crate) fn check_reserved_table(table: &str) -> Result<(), KvError> {
+    match table.starts_with(RESERVED_TABLE_PREFIX) {
+        true => Err(KvError::PermissionDenied(format!(
+            "table {} is reserved",
+            table
+        ))),
+        false => Ok(()),
+    }
+}
+
+impl TableOptions {
+    /// 检查 value 的类型是否符合 table 的要求
+    pub fn check_value(&self, v: &Value) -> Result<(), KvError> {
+        let matched = matches!(
+            (self.value_type.as_str(), &v.value),
+            ("", _)
+                | ("any", _)
+                | ("string", Some(value::Value::String(_)))
+                | ("binary", Some(value::Value::Binary(_)))
+                | ("integer", Some(value::Value::Integer(_)))
+                | ("float", Some(value::Value::Float(_)))
+                | ("bool", Some(value::Value::Bool(_)))
+                | ("list", Some(value::Value::List(_)))
+                | ("map", Some(value::Value::Map(_)))
+                | ("timestamp", Some(value::Value::Timestamp(_)))
+        );
+        match matched {
+            true => Ok(()),
+            false => Err(KvError::ConstraintViolation(format!(
+                "value {} is not {}",
+                v.format(),
+                self.value_type
+            ))),
+        }
+    }
+
+    /// 检查 table 是否还能放入新的 key，只有设置了 max_keys 时才调用 len 获取 key 的数量
+    pub fn check_capacity(
+        &self,
+        len: impl FnOnce() -> Result<u64, KvError>,
+    ) -> Result<(), KvError> {
+        if self.max_keys > 0 && len()? >= self.max_keys {
+            return Err(KvError::ConstraintViolation(format!(
+                "table is full with {} keys",
+                self.max_keys
+            )));
+        }
+        Ok(())
+    }
+
+    /// 检查选项本身是否合法
+    pub fn validate(&self) -> Result<(), KvError> {
+        match self.value_type.as_str() {
+            "" | "any" | "string" | "binary" | "integer" | "float" | "bool" | "list" | "map"
+            | "timestamp" => {}
+            v => {
+                return Err(KvError::InvalidCommand(format!(
+                    "unknown value type: {}",
+                    v
+                )))
+            }
+        }
+        self.validate_indexes()
+    }
+
+    /// 按默认 TTL 计算新写入 key 的过期时间
+    pub fn expire_at(&self) -> Option<u64> {
+        match self.default_ttl {
+            0 => None,
+            ttl => Some(now_ms() + ttl * 1000),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn table_options_should_check_value_type() {
+        let options = TableOptions {
+            value_type: "integer".into(),
+            ..Default::default()
+        };
+        assert!(options.check_value(&10.into()).is_ok());
+        assert!(options.check_value(&"hello".into()).is_err());
+        assert!(TableOptions::default().check_value(&"hello".into()).is_ok());
+    }
+
+    #[test]
+    fn table_options_should_check_capacity() {
+        let options = TableOptions {
+            max_keys: 2,
+            ..Default::default()
+        };
+        assert!(options.check_capacity(|| Ok(1)).is_ok());
+        assert!(options.check_capacity(|| Ok(2)).is_err());
+        // 没有限制时不计算 key 的数量
+        let unlimited = TableOptions::default();
+        assert!(unlimited.check_capacity(|| unreachable!()).is_ok());
+    }
+}
@@ -0,0 +1,243 @@
+//! sorted set：按分数排序的成员集合
+//!
+//! sorted set 和普通的 key 在不同的命名空间中。MemTable 使用 HashMap + RankTree 存放，
+//! 按排名查找是 O(log n)。SledDb 中每个成员有两条记录：`前缀 | m | 成员` -> 分数，
+//! 和按分数排序的 `前缀 | s | 分数 | 成员`，sled 不能按排名定位，按排名查找需要从头遍历。
+
+use crate::{
+    storage::{
+        index::{encode_bytes, encode_f64},
+        rank_tree::RankTree,
+    },
+    KvError, Kvpair,
+};
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    convert::TryInto,
+    ops::RangeInclusive,
+};
+
+const MEMBER_TAG: u8 = b'm';
+const SCORE_TAG: u8 = b's';
+
+/// 检查分数是否合法，-0.0 被当作 0.0
+pub(crate) fn normalize_score(score: f64) -> Result<f64, KvError> {
+    match score.is_nan() {
+        true => Err(KvError::InvalidCommand("score cannot be NaN".into())),
+        false => Ok(score + 0.0),
+    }
+}
+
+/// 和 redis 一样，[start, stop] 是闭区间，负数表示从尾部开始计算
+pub(crate) fn normalize_range(len: usize, start: i64, stop: i64) -> Option<RangeInclusive<usize>> {
+    let len = len as i64;
+    let start = if start < 0 { (len + start).max(0) } else { start };
+    let stop = if stop < 0 { len + stop } else { stop.min(len - 1) };
+    match start > stop {
+        true => None,
+        false => Some(start as usize..=stop as usize),
+    }
+}
+
+/// limit 为 0 表示不限制
+pub(crate) fn take_limit(limit: u64) -> usize {
+    match limit {
+        0 => usize::MAX,
+        n => n as usize,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Score(f64);
+
+impl Eq for Score {}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// MemTable 中的 sorted set
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ZSet {
+    scores: HashMap<String, f64>,
+    // 按 (分数, 成员) 排序
+    ordered: RankTree<(Score, String)>,
+}
+
+impl ZSet {
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    /// 加入成员或者更新成员的分数，返回是否是新成员
+    pub fn add(&mut self, member: String, score: f64) -> bool {
+        let old = self.scores.insert(member.clone(), score);
+        if let Some(old) = old {
+            self.ordered.remove(&(Score(old), member.clone()));
+        }
+        self.ordered.insert((Score(score), member));
+        old.is_none()
+    }
+
+    /// 删除成员，返回成员是否存在
+    pub fn remove(&mut self, member: &str) -> bool {
+        match self.scores.remove(member) {
+            Some(score) => self.ordered.remove(&(Score(score), member.to_string())),
+            None => false,
+        }
+    }
+
+    pub fn score(&self, member: &str) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    pub fn rank(&self, member: &str) -> Option<u64> {
+        let score = self.scores.get(member)?;
+        Some(self.ordered.rank(&(Score(*score), member.to_string())) as u64)
+    }
+
+    pub fn range(&self, start: i64, stop: i64) -> Vec<Kvpair> {
+        match normalize_range(self.ordered.len(), start, stop) {
+            Some(range) => self
+                .ordered
+                .iter_from(*range.start())
+                .take(range.end() - range.start() + 1)
+                .map(|(score, member)| Kvpair::new(member, score.0.into()))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn range_by_score(&self, min: f64, max: f64, offset: u64, limit: u64) -> Vec<Kvpair> {
+        // 分数不小于 min 的第一个成员的排名，直接跳过 offset 个成员
+        let start = self.ordered.rank(&(Score(min), String::new()));
+        self.ordered
+            .iter_from(start.saturating_add(offset as usize))
+            .take_while(|(score, _)| score.0 <= max)
+            .take(take_limit(limit))
+            .map(|(score, member)| Kvpair::new(member, score.0.into()))
+            .collect()
+    }
+}
+
+/// SledDb 中一个 table 所有 sorted set 的前缀
+pub(crate) fn zset_table_prefix(table: &str) -> Vec<u8> {
+    let mut buf = Vec::new();
+    encode_bytes(table.as_bytes(), &mut buf);
+    buf
+}
+
+/// SledDb 中一个 sorted set 所有记录的前缀，table 和 key 都经过转义，不会互相混淆
+pub(crate) fn zset_prefix(table: &str, key: &str) -> Vec<u8> {
+    let mut buf = zset_table_prefix(table);
+    encode_bytes(key.as_bytes(), &mut buf);
+    buf
+}
+
+/// 成员 -> 分数 的记录
+pub(crate) fn member_key(prefix: &[u8], member: &str) -> Vec<u8> {
+    [prefix, &[MEMBER_TAG], member.as_bytes()].concat()
+}
+
+/// 按分数排序的记录，value 是分数
+pub(crate) fn score_key(prefix: &[u8], score: f64, member: &str) -> Vec<u8> {
+    let mut buf = [prefix, &[SCORE_TAG]].concat();
+    encode_f64(score, &mut buf);
+    buf.extend_from_slice(member.as_bytes());
+    buf
+}
+
+/// 所有按分数排序的记录都在 [score_start, score_end) 中
+pub(crate) fn score_start(prefix: &[u8]) -> Vec<u8> {
+    [prefix, &[SCORE_TAG]].concat()
+}
+
+pub(crate) fn score_end(prefix: &[u8]) -> Vec<u8> {
+    [prefix, &[SCORE_TAG + 1]].concat()
+}
+
+/// 从按分数排序的记录中取出成员和分数
+pub(crate) fn decode_score_entry(prefix: &[u8], key: &[u8], value: &[u8]) -> Result<Kvpair, KvError> {
+    // 前缀 | s | 8 字节的分数 | 成员
+    let member = key
+        .get(prefix.len() + 9..)
+        .ok_or_else(|| KvError::Internal("invalid sorted set entry".into()))?;
+    let member = String::from_utf8_lossy(member);
+    Ok(Kvpair::new(member, decode_score(value)?.into()))
+}
+
+/// 从 SledDb 的记录中取出 table、sorted set 的 key，以及这是不是按分数排序的记录
+pub(crate) fn decode_zset_entry(entry: &[u8]) -> Result<(String, String, bool), KvError> {
+    let (table, rest) = decode_bytes(entry)?;
+    let (key, rest) = decode_bytes(rest)?;
+    Ok((table, key, rest.first() == Some(&SCORE_TAG)))
+}
+
+// encode_bytes 的逆操作，返回解码后的数据和剩下的字节
+fn decode_bytes(data: &[u8]) -> Result<(String, &[u8]), KvError> {
+    let mut buf = Vec::new();
+    let mut i = 0;
+    loop {
+        match (data.get(i), data.get(i + 1)) {
+            (Some(0), Some(0xff)) => buf.push(0),
+            (Some(0), Some(1)) => break,
+            (Some(b), _) if *b != 0 => {
+                buf.push(*b);
+                i += 1;
+                continue;
+            }
+            _ => return Err(KvError::Internal("invalid sorted set entry".into())),
+        }
+        i += 2;
+    }
+    Ok((String::from_utf8_lossy(&buf).into_owned(), &data[i + 2..]))
+}
+
+pub(crate) fn decode_score(value: &[u8]) -> Result<f64, KvError> {
+    let bytes = value
+        .try_into()
+        .map_err(|_| KvError::Internal("invalid sorted set score".into()))?;
+    Ok(f64::from_be_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_range_should_work() {
+        assert_eq!(normalize_range(5, 0, -1), Some(0..=4));
+        assert_eq!(normalize_range(5, -2, 10), Some(3..=4));
+        assert_eq!(normalize_range(5, 3, 1), None);
+        assert_eq!(normalize_range(0, 0, -1), None);
+    }
+
+    #[test]
+    fn score_key_should_keep_order() {
+        let prefix = zset_prefix("t1", "k1");
+        assert!(score_key(&prefix, -1.0, "b") < score_key(&prefix, 0.0, "a"));
+        assert!(score_key(&prefix, 1.0, "a") < score_key(&prefix, 1.0, "b"));
+        assert!(score_key(&prefix, f64::INFINITY, "a") < score_end(&prefix));
+        // 一个 key 是另一个 key 的前缀时，两个 sorted set 不会混在一起
+        assert!(!zset_prefix("t1", "k10").starts_with(&prefix));
+    }
+
+    #[test]
+    fn zset_entry_should_be_decoded() {
+        let prefix = zset_prefix("t\0:1", "k\0x");
+        let entry = decode_zset_entry(&score_key(&prefix, 1.0, "a")).unwrap();
+        assert_eq!(entry, ("t\0:1".into(), "k\0x".into(), true));
+        let entry = decode_zset_entry(&member_key(&prefix, "a")).unwrap();
+        assert_eq!(entry, ("t\0:1".into(), "k\0x".into(), false));
+        assert!(decode_zset_entry(b"t1").is_err());
+    }
+}
@@ -0,0 +1,128 @@
+use anyhow::Result;
+use kv6::{serve_resp, MemTable, Service, ServiceInner};
+use std::time::Duration;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    time,
+};
+
+/// 一个最简单的 RESP 客户端，只把回复按行读出来，不做解析
+struct RespClient {
+    stream: BufReader<TcpStream>,
+}
+
+impl RespClient {
+    async fn connect(addr: &str) -> Result<Self> {
+        let stream = TcpStream::connect(addr).await?;
+        Ok(Self {
+            stream: BufReader::new(stream),
+        })
+    }
+
+    async fn send(&mut self, args: &[&str]) -> Result<()> {
+        let mut buf = format!("*{}\r\n", args.len());
+        for arg in args {
+            buf.push_str(&format!("${}\r\n{}\r\n", arg.len(), arg));
+        }
+        self.stream.get_mut().write_all(buf.as_bytes()).await?;
+        Ok(())
+    }
+
+    // 读取一个完整的回复，返回其中所有的行
+    async fn read_reply(&mut self) -> Result<Vec<String>> {
+        let mut lines = Vec::new();
+        let mut pending = 1;
+        while pending > 0 {
+            pending -= 1;
+            let line = self.read_line().await?;
+            let (kind, rest) = line.split_at(1);
+            match kind {
+                "*" | ">" => pending += rest.parse::<usize>().unwrap_or(0),
+                "%" => pending += rest.parse::<usize>().unwrap_or(0) * 2,
+                "$" => {
+                    if let Ok(len) = rest.parse::<usize>() {
+                        let mut data = vec![0; len + 2];
+                        self.stream.read_exact(&mut data).await?;
+                        lines.push(line);
+                        lines.push(String::from_utf8_lossy(&data[..len]).into_owned());
+                        continue;
+                    }
+                }
+                _ => {}
+            }
+            lines.push(line);
+        }
+        Ok(lines)
+    }
+
+    async fn read_line(&mut self) -> Result<String> {
+        let mut line = String::new();
+        self.stream.read_line(&mut line).await?;
+        Ok(line.trim_end_matches("\r\n").to_string())
+    }
+
+    async fn call(&mut self, args: &[&str]) -> Result<Vec<String>> {
+        self.send(args).await?;
+        self.read_reply().await
+    }
+}
+
+#[tokio::test]
+async fn resp_server_should_work() -> Result<()> {
+    // 监听随机的端口，accept 之前的连接会在 backlog 中等待
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = &listener.local_addr()?.to_string();
+    let svc: Service = ServiceInner::new(MemTable::new()).into();
+    tokio::spawn(async move {
+        loop {
+            let (stream, peer) = listener.accept().await.unwrap();
+            tokio::spawn(serve_resp(stream, svc.clone(), Some(peer)));
+        }
+    });
+
+    let mut client = RespClient::connect(addr).await?;
+    assert_eq!(client.call(&["PING"]).await?, ["+PONG"]);
+    assert_eq!(client.call(&["HSET", "t1", "k1", "v1", "k2", "v2"]).await?, [":2"]);
+    assert_eq!(client.call(&["HSET", "t1", "k1", "v3"]).await?, [":0"]);
+    assert_eq!(client.call(&["HGET", "t1", "k1"]).await?, ["$2", "v3"]);
+    assert_eq!(client.call(&["HGET", "t1", "k9"]).await?, ["$-1"]);
+    assert_eq!(
+        client.call(&["HMGET", "t1", "k2", "k9"]).await?,
+        ["*2", "$2", "v2", "$-1"]
+    );
+    assert_eq!(client.call(&["HEXISTS", "t1", "k2"]).await?, [":1"]);
+    assert_eq!(client.call(&["HGETALL", "t1"]).await?.len(), 9);
+    assert_eq!(client.call(&["HDEL", "t1", "k2", "k9"]).await?, [":1"]);
+    assert_eq!(client.call(&["HEXISTS", "t1", "k2"]).await?, [":0"]);
+    assert_eq!(
+        client.call(&["HGETALL", "t1"]).await?,
+        ["*2", "$2", "k1", "$2", "v3"]
+    );
+    assert_eq!(
+        client.call(&["HGET", "t1"]).await?,
+        ["-ERR wrong number of arguments for 'hget' command"]
+    );
+
+    // 切换到 RESP3 之后 null 和 map 使用 RESP3 的类型
+    let reply = client.call(&["HELLO", "3"]).await?;
+    assert_eq!(reply[0], "%7");
+    assert_eq!(client.call(&["HGET", "t1", "k9"]).await?, ["_"]);
+    assert_eq!(
+        client.call(&["HGETALL", "t1"]).await?,
+        ["%1", "$2", "k1", "$2", "v3"]
+    );
+
+    // 订阅之后在另一个连接上发布消息
+    let mut subscriber = RespClient::connect(addr).await?;
+    assert_eq!(
+        subscriber.call(&["SUBSCRIBE", "news"]).await?,
+        ["*3", "$9", "subscribe", "$4", "news", ":1"]
+    );
+    assert_eq!(client.call(&["PUBLISH", "news", "hello"]).await?, [":1"]);
+    let msg = time::timeout(Duration::from_secs(1), subscriber.read_reply()).await??;
+    assert_eq!(msg, ["*3", "$7", "message", "$4", "news", "$5", "hello"]);
+
+    assert_eq!(client.call(&["QUIT"]).await?, ["+OK"]);
+    Ok(())
+}
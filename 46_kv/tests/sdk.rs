@@ -0,0 +1,104 @@
+use anyhow::Result;
+use futures::StreamExt;
+use kv6::{
+    start_server_with_config, ClientConfig, Json, KvClient, KvError, ServerConfig, StorageConfig,
+    TransportConfig, Value,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    net::{TcpListener, UdpSocket},
+    time::Duration,
+};
+use tokio::time;
+
+/// 等待服务器启动的次数和间隔，QUIC 连不上时要等到超时才失败
+const READY_RETRIES: usize = 50;
+const READY_INTERVAL: Duration = Duration::from_millis(20);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct User {
+    name: String,
+    age: u8,
+}
+
+// 在系统分配的空闲端口上启动服务器，连接成功后返回客户端
+async fn start_client(transport: TransportConfig) -> Result<KvClient> {
+    let addr = match transport {
+        TransportConfig::Tcp => TcpListener::bind("127.0.0.1:0")?.local_addr()?,
+        TransportConfig::Quic => UdpSocket::bind("127.0.0.1:0")?.local_addr()?,
+    };
+    let mut config: ServerConfig = toml::from_str(include_str!("../fixtures/server.conf"))?;
+    config.general.addr = addr.to_string();
+    config.general.transport = transport;
+    config.storage = StorageConfig::MemTable;
+    tokio::spawn(async move {
+        start_server_with_config(&config).await.unwrap();
+    });
+
+    let mut config: ClientConfig = toml::from_str(include_str!("../fixtures/client.conf"))?;
+    config.general.addr = addr.to_string();
+    config.general.transport = transport;
+    for _ in 0..READY_RETRIES {
+        if let Ok(Ok(client)) = time::timeout(CONNECT_TIMEOUT, KvClient::connect(&config)).await {
+            return Ok(client);
+        }
+        time::sleep(READY_INTERVAL).await;
+    }
+    anyhow::bail!("server didn't start on {}", addr)
+}
+
+#[tokio::test]
+async fn sdk_typed_commands_should_work() -> Result<()> {
+    let mut client = start_client(TransportConfig::Tcp).await?;
+
+    client.hset("t1", "name", "alice").await?;
+    client.hset("t1", "age", 30).await?;
+    assert_eq!(
+        client.hget::<String>("t1", "name").await?.as_deref(),
+        Some("alice")
+    );
+    assert_eq!(client.hget::<i64>("t1", "age").await?, Some(30));
+    assert_eq!(client.hget::<i64>("t1", "missing").await?, None);
+    // 类型不匹配时返回转换错误
+    let err = client.hget::<i64>("t1", "name").await.unwrap_err();
+    assert!(matches!(err, KvError::ConvertError(..)));
+
+    let user = User {
+        name: "bob".into(),
+        age: 42,
+    };
+    client.hset("users", "bob", Json(user.clone())).await?;
+    let found: Option<Json<User>> = client.hget("users", "bob").await?;
+    assert_eq!(found, Some(Json(user)));
+
+    client.hmset("t2", [("k1", 1), ("k2", 2)]).await?;
+    let values = client.hmget::<i64>("t2", ["k1", "missing", "k2"]).await?;
+    assert_eq!(values, [Some(1), None, Some(2)]);
+    let mut pairs = client.hgetall::<i64>("t2").await?;
+    pairs.sort();
+    assert_eq!(pairs, [("k1".into(), 1), ("k2".into(), 2)]);
+
+    assert!(client.hexist("t2", "k1").await?);
+    assert!(client.hdel("t2", "k1").await?);
+    assert!(!client.hdel("t2", "k1").await?);
+    assert!(!client.hexist("t2", "k1").await?);
+    Ok(())
+}
+
+#[tokio::test]
+async fn sdk_subscribe_should_receive_values() -> Result<()> {
+    let mut client = start_client(TransportConfig::Quic).await?;
+
+    let mut sub = client.subscribe("lobby").await?;
+    client.publish("lobby", ["hello", "world"]).await?;
+    client.publish("lobby", [42]).await?;
+    let values: Vec<Value> = (&mut sub).take(3).collect().await;
+    assert_eq!(values, ["hello".into(), "world".into(), 42.into()]);
+
+    // 取消订阅之后 stream 结束
+    client.unsubscribe("lobby", sub.id()).await?;
+    let next = time::timeout(Duration::from_secs(1), sub.next()).await?;
+    assert_eq!(next, None);
+    Ok(())
+}
@@ -1,7 +1,8 @@
 use anyhow::Result;
 use kv6::{
-    start_client_with_config, ClientConfig, CommandRequest,
-    ProstClientStream, ServerConfig, StorageConfig, start_server_with_config,
+    start_client_with_config, start_quic_client_with_config, ClientConfig, CommandRequest,
+    CompressionCodec, ServerConfig, StorageConfig, TransportConfig,
+    start_server_with_config,
 };
 use std::time::Duration;
 use tokio::time;
@@ -22,9 +23,14 @@ async fn yamux_server_client_full_tests() -> Result<()> {
     time::sleep(Duration::from_millis(10)).await;
     let mut config: ClientConfig = toml::from_str(include_str!("../fixtures/client.conf"))?;
     config.general.addr = addr.into();
+    config.compression.codec = CompressionCodec::Zstd;
 
     let mut ctrl = start_client_with_config(&config).await.unwrap();
     let mut stream = ctrl.open_stream().await?;
+    // 服务器默认使用 gzip，客户端支持 gzip 时服务器的选择优先
+    let caps = stream.hello(&config.compression).await?;
+    assert_eq!(caps.codec, CompressionCodec::Gzip);
+    assert!(caps.supports("hget"));
 
     // 生成一个HSET命令
     let cmd = CommandRequest::new_hset("table1", "hello", "world".to_string().into());
@@ -38,4 +44,39 @@ async fn yamux_server_client_full_tests() -> Result<()> {
     assert_eq!(data.values, &["world".into()]);
 
     Ok(())
-}
\ No newline at end of file
+}
+#[tokio::test]
+async fn quic_server_client_full_tests() -> Result<()> {
+    let addr = "127.0.0.1:10088";
+
+    let mut config: ServerConfig = toml::from_str(include_str!("../fixtures/server.conf"))?;
+    config.general.addr = addr.into();
+    config.general.transport = TransportConfig::Quic;
+    config.storage = StorageConfig::MemTable;
+
+    // 启动服务
+    tokio::spawn(async move {
+        start_server_with_config(&config).await.unwrap();
+    });
+
+    time::sleep(Duration::from_millis(10)).await;
+    let mut config: ClientConfig = toml::from_str(include_str!("../fixtures/client.conf"))?;
+    config.general.addr = addr.into();
+    config.general.transport = TransportConfig::Quic;
+
+    let ctrl = start_quic_client_with_config(&config).await?;
+    let mut stream = ctrl.open_stream().await?;
+
+    let cmd = CommandRequest::new_hset("table1", "hello", "world".to_string().into());
+    stream.execute_unary(&cmd).await?;
+
+    // 另一个 stream 上读取
+    let mut stream = ctrl.open_stream().await?;
+    let cmd = CommandRequest::new_hget("table1", "hello");
+    let data = stream.execute_unary(&cmd).await?;
+
+    assert_eq!(data.status, 200);
+    assert_eq!(data.values, &["world".into()]);
+
+    Ok(())
+}
@@ -0,0 +1,202 @@
+//! 确定性的模拟环境：单线程 runtime、暂停的时钟、内存中的连接。
+//!
+//! 所有的随机性都来自 seed，同一个 seed 每次运行的调度顺序、延迟和丢弃的 frame 都相同。
+//! 失败时打印 seed，用 SIM_SEED=<seed> 可以只重放这一次运行。
+
+use anyhow::Result;
+use futures::{future, Future};
+use kv6::{ProstServerStream, Service, Storage, YamuxCtrl, LEN_LEN, MAX_FRAME};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{
+    env,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+use tokio::{
+    io::{self, AsyncReadExt, AsyncWriteExt, DuplexStream, ReadHalf, WriteHalf},
+    time,
+};
+use tokio_util::compat::FuturesAsyncReadCompatExt;
+
+/// 内存中连接的缓冲区大小
+const BUF_SIZE: usize = 64 * 1024;
+
+/// 注入到连接上的故障
+#[derive(Debug, Clone, Default)]
+pub struct Faults {
+    /// 丢弃 frame 的概率，只对 prost frame 有效
+    pub drop: f64,
+    /// 每个 frame 随机延迟 [0, delay)
+    pub delay: Duration,
+    /// 两个方向一共转发这么多 frame 之后断开连接
+    pub disconnect_after: Option<usize>,
+}
+
+/// 模拟环境，提供 seed 确定的随机数和带故障的连接
+pub struct Sim {
+    rng: StdRng,
+}
+
+/// 连接上转发的单位：完整的 prost frame，或者任意的字节块（yamux）
+#[derive(Debug, Clone, Copy)]
+enum Framing {
+    Prost,
+    Raw,
+}
+
+/// 在 seed 确定的环境中运行 f，失败时 panic 并打印 seed
+pub fn simulate<F, Fut, T>(seed: u64, f: F) -> T
+where
+    F: FnOnce(Sim) -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .start_paused(true)
+        .build()
+        .unwrap();
+    let sim = Sim {
+        rng: StdRng::seed_from_u64(seed),
+    };
+    match rt.block_on(f(sim)) {
+        Ok(v) => v,
+        Err(e) => panic!("simulation failed with SIM_SEED={}: {:?}", seed, e),
+    }
+}
+
+/// 需要运行的 seed：设置了 SIM_SEED 时只运行它，否则是 0..count
+pub fn seeds(count: u64) -> Vec<u64> {
+    match env::var("SIM_SEED") {
+        Ok(seed) => vec![seed.parse().expect("SIM_SEED must be a number")],
+        Err(_) => (0..count).collect(),
+    }
+}
+
+impl Sim {
+    pub fn rng(&mut self) -> &mut StdRng {
+        &mut self.rng
+    }
+
+    /// 传输 prost frame 的连接，可以丢弃、延迟 frame 或者断开
+    pub fn link(&mut self, faults: Faults) -> (DuplexStream, DuplexStream) {
+        self.connect(faults, Framing::Prost)
+    }
+
+    /// 传输任意字节的连接（yamux），只能延迟或者断开
+    pub fn raw_link(&mut self, faults: Faults) -> (DuplexStream, DuplexStream) {
+        assert!(faults.drop == 0.0, "raw link can't drop bytes");
+        self.connect(faults, Framing::Raw)
+    }
+
+    /// 在 stream 上运行 ProstServerStream
+    pub fn serve<Store: Storage>(&self, service: Service<Store>, stream: DuplexStream) {
+        tokio::spawn(ProstServerStream::new(stream, service).process());
+    }
+
+    /// 在 stream 上运行 yamux server，每个 yamux stream 交给 ProstServerStream 处理
+    pub fn serve_yamux<Store: Storage>(
+        &self,
+        service: Service<Store>,
+        stream: DuplexStream,
+    ) -> YamuxCtrl<DuplexStream> {
+        YamuxCtrl::new_server(stream, None, move |stream| {
+            let svc = service.clone();
+            async move {
+                let _ = ProstServerStream::new(stream.compat(), svc).process().await;
+                Ok(())
+            }
+        })
+    }
+
+    // 两端之间运行一个转发的 task，在转发时注入故障
+    fn connect(&mut self, faults: Faults, framing: Framing) -> (DuplexStream, DuplexStream) {
+        let (client, client_end) = io::duplex(BUF_SIZE);
+        let (server, server_end) = io::duplex(BUF_SIZE);
+        let link = Link { faults, framing };
+        let seeds: [u64; 2] = self.rng.gen();
+        tokio::spawn(async move { link.run(client_end, server_end, seeds).await });
+        (client, server)
+    }
+}
+
+struct Link {
+    faults: Faults,
+    framing: Framing,
+}
+
+impl Link {
+    // 两个方向各自转发，任何一个方向结束（断开或者对端关闭）时关闭两端
+    async fn run(&self, a: DuplexStream, b: DuplexStream, seeds: [u64; 2]) {
+        let (a_read, a_write) = io::split(a);
+        let (b_read, b_write) = io::split(b);
+        let forwarded = AtomicUsize::new(0);
+        let up = self.forward(a_read, b_write, &forwarded, seeds[0]);
+        let down = self.forward(b_read, a_write, &forwarded, seeds[1]);
+        // biased 让 select 按固定的顺序 poll，保证调度是确定的
+        tokio::select! {
+            biased;
+            _ = up => {},
+            _ = down => {},
+        }
+    }
+
+    async fn forward(
+        &self,
+        mut from: ReadHalf<DuplexStream>,
+        mut to: WriteHalf<DuplexStream>,
+        forwarded: &AtomicUsize,
+        seed: u64,
+    ) -> Result<()> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut buf = vec![];
+        loop {
+            // 一端关闭写入时只关闭这个方向，另一个方向继续转发
+            if !read_unit(&mut from, &mut buf, self.framing).await? {
+                to.shutdown().await?;
+                return future::pending().await;
+            }
+            let n = forwarded.fetch_add(1, Ordering::SeqCst);
+            if matches!(self.faults.disconnect_after, Some(limit) if n >= limit) {
+                return Ok(());
+            }
+            if !self.faults.delay.is_zero() {
+                time::sleep(rng.gen_range(Duration::ZERO..self.faults.delay)).await;
+            }
+            if self.faults.drop > 0.0 && rng.gen_bool(self.faults.drop) {
+                continue;
+            }
+            to.write_all(&buf).await?;
+        }
+    }
+}
+
+// 读出一个转发的单位，对端关闭时返回 false
+async fn read_unit(
+    from: &mut ReadHalf<DuplexStream>,
+    buf: &mut Vec<u8>,
+    framing: Framing,
+) -> Result<bool> {
+    buf.clear();
+    match framing {
+        Framing::Prost => {
+            let header = match from.read_u32().await {
+                Ok(header) => header,
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(false),
+                Err(e) => return Err(e.into()),
+            };
+            let len = header as usize & MAX_FRAME;
+            buf.resize(LEN_LEN + len, 0);
+            buf[..LEN_LEN].copy_from_slice(&header.to_be_bytes());
+            from.read_exact(&mut buf[LEN_LEN..]).await?;
+        }
+        Framing::Raw => {
+            buf.resize(BUF_SIZE, 0);
+            let n = from.read(buf).await?;
+            if n == 0 {
+                return Ok(false);
+            }
+            buf.truncate(n);
+        }
+    }
+    Ok(true)
+}
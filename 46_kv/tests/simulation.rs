@@ -0,0 +1,257 @@
+mod sim;
+
+use anyhow::Result;
+use futures::StreamExt;
+use kv6::{
+    CommandRequest, CommandResponse, KvError, MemTable, ProstClientStream, Service, ServiceInner,
+    Value, YamuxCtrl,
+};
+use rand::Rng;
+use sim::{seeds, simulate, Faults, Sim};
+use std::{collections::HashMap, time::Duration};
+use tokio::{
+    io::DuplexStream,
+    time::{self, Instant},
+};
+
+const TABLE: &str = "t";
+const KEYS: usize = 5;
+
+/// 随机生成的命令
+#[derive(Debug, Clone)]
+enum Op {
+    Set(String, i64),
+    Get(String),
+    Del(String),
+    Exist(String),
+}
+
+impl Op {
+    fn random(sim: &mut Sim) -> Self {
+        let rng = sim.rng();
+        let key = format!("k{}", rng.gen_range(0..KEYS));
+        match rng.gen_range(0..4) {
+            0 => Op::Set(key, rng.gen_range(0..100)),
+            1 => Op::Get(key),
+            2 => Op::Del(key),
+            _ => Op::Exist(key),
+        }
+    }
+
+    fn request(&self) -> CommandRequest {
+        match self {
+            Op::Set(key, value) => CommandRequest::new_hset(TABLE, key, (*value).into()),
+            Op::Get(key) => CommandRequest::new_hget(TABLE, key),
+            Op::Del(key) => CommandRequest::new_hdel(TABLE, key),
+            Op::Exist(key) => CommandRequest::new_hexist(TABLE, key),
+        }
+    }
+
+    // 在模型上执行，返回期望的状态码和值
+    fn apply(&self, model: &mut HashMap<String, Value>) -> (u32, Vec<Value>) {
+        let or_default = |v: Option<Value>| vec![v.unwrap_or_default()];
+        match self {
+            Op::Set(key, value) => (200, or_default(model.insert(key.clone(), (*value).into()))),
+            Op::Get(key) => match model.get(key) {
+                Some(v) => (200, vec![v.clone()]),
+                None => (404, vec![]),
+            },
+            Op::Del(key) => (200, or_default(model.remove(key))),
+            Op::Exist(key) => (200, vec![model.contains_key(key).into()]),
+        }
+    }
+}
+
+fn new_service() -> Service {
+    ServiceInner::new(MemTable::new()).into()
+}
+
+// 建立一个经过模拟连接的客户端
+fn connect(sim: &mut Sim, service: &Service, faults: Faults) -> ProstClientStream<DuplexStream> {
+    let (client, server) = sim.link(faults);
+    sim.serve(service.clone(), server);
+    ProstClientStream::new(client).with_timeout(Some(Duration::from_millis(100)))
+}
+
+#[test]
+fn commands_should_match_model() {
+    for seed in seeds(32) {
+        simulate(seed, |mut sim| async move {
+            let service = new_service();
+            let faults = Faults {
+                delay: Duration::from_millis(5),
+                ..Default::default()
+            };
+            let mut client = connect(&mut sim, &service, faults);
+            let mut model = HashMap::new();
+            for _ in 0..100 {
+                let op = Op::random(&mut sim);
+                let res = client.execute_unary(&op.request()).await?;
+                let (status, values) = op.apply(&mut model);
+                assert_eq!((res.status, res.values), (status, values), "{:?}", op);
+            }
+            Ok(())
+        });
+    }
+}
+
+/// 丢弃 frame 时的可能状态：写入超时后无法知道它是否生效，key 的值可能是其中任何一个
+type Possible = HashMap<String, Vec<Option<Value>>>;
+
+// 带丢包的一次运行，返回每个命令的结果和完成时的模拟时间
+async fn lossy_run(mut sim: Sim) -> Result<Vec<(u32, Duration)>> {
+    let service = new_service();
+    let faults = Faults {
+        drop: 0.1,
+        delay: Duration::from_millis(5),
+        ..Default::default()
+    };
+    let start = Instant::now();
+    let mut client = connect(&mut sim, &service, faults.clone());
+    let mut possible: Possible = HashMap::new();
+    let mut trace = vec![];
+
+    for _ in 0..100 {
+        let rng = sim.rng();
+        let key = format!("k{}", rng.gen_range(0..KEYS));
+        let set = rng
+            .gen_bool(0.5)
+            .then(|| Value::from(rng.gen_range(0..100)));
+        let values = possible.entry(key.clone()).or_insert_with(|| vec![None]);
+        let cmd = match &set {
+            Some(v) => CommandRequest::new_hset(TABLE, &key, v.clone()),
+            None => CommandRequest::new_hget(TABLE, &key),
+        };
+
+        let res = match client.execute_unary(&cmd).await {
+            Ok(res) => res,
+            Err(KvError::Timeout) => {
+                // 超时的写入可能已经生效；超时之后这个 stream 不能再使用
+                values.extend(set.map(Some));
+                client = connect(&mut sim, &service, faults.clone());
+                trace.push((504, start.elapsed()));
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        trace.push((res.status, start.elapsed()));
+
+        // hset 返回之前的值（不存在时是空值），hget 返回当前的值，都必须是模型中可能的值
+        let current = match (res.status, &set) {
+            (404, None) => None,
+            (200, Some(_)) if res.values[0] == Value::default() => None,
+            (200, _) => Some(res.values[0].clone()),
+            _ => anyhow::bail!("unexpected response: {:?}", res),
+        };
+        assert!(
+            values.contains(&current),
+            "{} = {:?} not in {:?}",
+            key,
+            current,
+            values
+        );
+        *values = vec![set.or(current)];
+    }
+    Ok(trace)
+}
+
+#[test]
+fn dropped_frames_should_not_corrupt_state() {
+    let mut timeouts = 0;
+    for seed in seeds(16) {
+        let trace = simulate(seed, lossy_run);
+        timeouts += trace.iter().filter(|(status, _)| *status == 504).count();
+    }
+    // 确认故障确实被注入了
+    assert!(timeouts > 0);
+}
+
+#[test]
+fn same_seed_should_replay_same_trace() {
+    let trace = simulate(7, lossy_run);
+    assert_eq!(simulate(7, lossy_run), trace);
+    assert_ne!(simulate(8, lossy_run), trace);
+}
+
+#[test]
+fn disconnect_should_release_locks() {
+    simulate(0, |mut sim| async move {
+        let service = new_service();
+        // lock 的请求和响应之后断开
+        let faults = Faults {
+            disconnect_after: Some(2),
+            ..Default::default()
+        };
+        let mut client = connect(&mut sim, &service, faults);
+        let res = client
+            .execute_unary(&CommandRequest::new_lock("l1", "a", 60_000))
+            .await?;
+        assert_eq!(res.status, 200);
+        let res = client
+            .execute_unary(&CommandRequest::new_hget(TABLE, "k0"))
+            .await;
+        assert!(res.is_err());
+
+        // 服务器发现连接断开后释放 a 持有的锁
+        time::sleep(Duration::from_millis(10)).await;
+        let mut client = connect(&mut sim, &service, Faults::default());
+        let res = client
+            .execute_unary(&CommandRequest::new_lock("l1", "b", 60_000))
+            .await?;
+        assert_eq!(res.status, 200);
+        Ok(())
+    });
+}
+
+#[test]
+fn yamux_pubsub_should_survive_delays() {
+    for seed in seeds(8) {
+        simulate(seed, |mut sim| async move {
+            let service = new_service();
+            let faults = Faults {
+                delay: Duration::from_millis(2),
+                ..Default::default()
+            };
+            let (client, server) = sim.raw_link(faults);
+            let _server = sim.serve_yamux(service, server);
+            let mut ctrl = YamuxCtrl::new_client(client, None);
+
+            let stream = ctrl.open_stream().await?;
+            let mut messages = stream
+                .execute_streaming(&CommandRequest::new_subscribe("lobby"))
+                .await?;
+
+            // 多个 stream 同时写入和发布
+            let mut handles = vec![];
+            for i in 0..4i64 {
+                let mut stream = ctrl.open_stream().await?;
+                handles.push(tokio::spawn(async move {
+                    let cmd = CommandRequest::new_hset(TABLE, format!("k{}", i), i.into());
+                    stream.execute_unary(&cmd).await?;
+                    let cmd = CommandRequest::new_publish("lobby", vec![i.into()]);
+                    stream.execute_unary(&cmd).await
+                }));
+            }
+            for handle in handles {
+                assert_eq!(handle.await??.status, 200);
+            }
+
+            let mut received: Vec<i64> = vec![];
+            for _ in 0..4 {
+                let res: CommandResponse = messages.next().await.unwrap()?;
+                received.push((&res.values[0]).try_into()?);
+            }
+            received.sort_unstable();
+            assert_eq!(received, [0, 1, 2, 3]);
+
+            let mut stream = ctrl.open_stream().await?;
+            for i in 0..4i64 {
+                let res = stream
+                    .execute_unary(&CommandRequest::new_hget(TABLE, format!("k{}", i)))
+                    .await?;
+                assert_eq!(res.values, [i.into()]);
+            }
+            Ok(())
+        });
+    }
+}